@@ -0,0 +1,101 @@
+use std::fmt;
+
+/// The kinds of mistakes the assembler can find in a source line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownInstruction,        // The mnemonic is not a 6502 instruction
+    InvalidOperand,            // The operand is malformed or missing
+    InvalidNumber,             // A numeric value is not valid decimal/hex or does not fit
+    InvalidLabel,              // A label name is not a valid identifier
+    DuplicateLabel,            // A label is defined more than once
+    UndefinedLabel,            // An operand refers to a label that is never defined
+    UnsupportedAddressingMode, // The instruction has no form for the operand given
+    BranchOutOfRange,          // A branch target is more than -128/+127 bytes away
+    OperandSizeChanged,        // An operand flipped between zero page and absolute across passes
+    AddressOverflow,           // Code or data runs past $FFFF
+    Cmos65C02Only,             // The mnemonic is a 65C02 instruction the NMOS 6502 lacks
+}
+
+/// An error found while assembling a program.
+///
+/// Syntax errors carry the 1-based line and column of the offending text, so callers can point at
+/// the exact spot in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    Io {
+        path: String,
+        message: String,
+    },
+    Syntax {
+        kind: AsmErrorKind,
+        line: usize,
+        column: usize,
+        text: String,
+    },
+}
+
+impl AsmError {
+    /// Creates a syntax error for `text` whose position is filled in later by `at_line`.
+    pub(crate) fn syntax(kind: AsmErrorKind, text: &str) -> Self {
+        AsmError::Syntax {
+            kind,
+            line: 0,
+            column: 0,
+            text: text.to_string(),
+        }
+    }
+
+    /// Records the line a syntax error was found on, locating its text within `source_line`.
+    pub(crate) fn at_line(self, line_number: usize, source_line: &str) -> Self {
+        match self {
+            AsmError::Syntax { kind, text, .. } => {
+                let column = match source_line.find(text.as_str()) {
+                    Some(index) => index + 1,
+                    None => 1,
+                };
+                AsmError::Syntax {
+                    kind,
+                    line: line_number,
+                    column,
+                    text,
+                }
+            }
+            io => io,
+        }
+    }
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            AsmErrorKind::UnknownInstruction => "unknown instruction",
+            AsmErrorKind::InvalidOperand => "invalid operand",
+            AsmErrorKind::InvalidNumber => "invalid number",
+            AsmErrorKind::InvalidLabel => "invalid label name",
+            AsmErrorKind::DuplicateLabel => "duplicate label",
+            AsmErrorKind::UndefinedLabel => "undefined label",
+            AsmErrorKind::UnsupportedAddressingMode => "unsupported addressing mode",
+            AsmErrorKind::BranchOutOfRange => "branch target out of range",
+            AsmErrorKind::OperandSizeChanged => "operand size changed between passes",
+            AsmErrorKind::AddressOverflow => "code runs past $FFFF",
+            AsmErrorKind::Cmos65C02Only => "65C02-only instruction, not on the NMOS 6502",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::Io { path, message } => write!(f, "{}: {}", path, message),
+            AsmError::Syntax {
+                kind,
+                line,
+                column,
+                text,
+            } => write!(f, "{}:{}: {} `{}`", line, column, kind, text),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
@@ -1,93 +1,547 @@
+use crate::asm_error::{AsmError, AsmErrorKind};
+use crate::debug_info::DebugInfo;
+use crate::expr::evaluate;
 use crate::memory::Memory;
-use crate::token::Token;
-use crate::util::{self, convert_hex_string_to_u8, is_zero_page};
-use std::collections::HashMap;
+use crate::opcode_table::{self, find_opcode, OpcodeInfo};
+use crate::token::{AddressingMode, Token};
+use crate::util::{self, convert_hex_string_to_u16, convert_hex_string_to_u8, is_zero_page};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
-/// Populates a `HashMap` mapping assembly instruction mnemonics to their corresponding `Token` variants.
+/// Builds the default mnemonic aliases used by other 6502 assemblers.
 ///
-/// This function creates a `HashMap` where each entry associates a string representation of a
-/// 6502 assembly instruction (e.g., "LDA", "LDX", "STA") with a corresponding `Token` variant
-/// representing the instruction. This can be useful for parsing or interpreting assembly code
-/// in the context of a 6502 emulator or assembler.
+/// `BGE` (branch if greater or equal) is `BCS` and `BLT` (branch if less than) is `BCC`, since
+/// after a `CMP` the carry is set exactly when the register is greater than or equal to the operand.
+/// The data directives `.db` and `.dw` are `.byte` and `.word`.
 ///
-/// # Returns
-/// A `HashMap<&'static str, Token>` mapping instruction mnemonics to their `Token` representations.
+/// `DEA` and `INA`, which 65C02 assemblers take for `DEC A` and `INC A`, are deliberately not
+/// aliased: the assembler targets the NMOS 6502, which has no accumulator `INC` or `DEC`, so they
+/// are reported as `Cmos65C02Only` errors instead.
+fn populate_default_aliases() -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    map.insert(String::from("BGE"), String::from("BCS"));
+    map.insert(String::from("BLT"), String::from("BCC"));
+    map.insert(String::from(".db"), String::from(".byte"));
+    map.insert(String::from(".dw"), String::from(".word"));
+    map
+}
+
+/// How the assembler encodes label and expression operands, such as `LDA ptr` or `STA table+1`,
+/// of instructions that have a zero-page form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZeroPageLabels {
+    #[default]
+    Off, // Always absolute, so no line's size depends on the value of a label
+    On, // Zero page when the value fits, widening lines whose size oscillates (see `widenings`)
+    Strict, // Like `On`, but a widened line is an `OperandSizeChanged` error
+}
+
+/// A line whose operand was kept absolute because its size changed between passes.
+///
+/// With `ZeroPageLabels::On`, label addresses are worked out again until no line changes size.
+/// Making one operand zero page moves the labels after it down, which can push another operand,
+/// say `LDA top-end`, out of the zero page, and that can move the labels back up. To converge, a
+/// line that goes from zero page back to absolute stays absolute, and is reported here with the
+/// symbols its operand uses, since those are the ones that forced the widening.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Widening {
+    pub line: usize,          // 1-based line of the instruction
+    pub operand: String,      // The operand, as written
+    pub symbols: Vec<String>, // Labels and constants the operand uses, in order
+}
+
+impl fmt::Display for Widening {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: `{}` kept absolute because its size changed between passes",
+            self.line, self.operand
+        )?;
+        if !self.symbols.is_empty() {
+            write!(f, " (symbols: {})", self.symbols.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Assembles 6502 source code into memory.
+///
+/// The assembler works in two passes. The first pass (`collect_labels`) walks every non-empty line,
+/// tracking the address each instruction will occupy and recording label definitions (e.g. `loop:`)
+/// into a symbol table. The second pass uses the `parse_line` function to process each line, resolving
+/// label operands against that symbol table. The symbol table of the last assembled program stays
+/// available through `symbol_table`.
+///
+/// Code is placed from the address passed in, or from the address given by an `.org $8000` (or
+/// `* = $8000`) directive. The address the first instruction was placed at and the number of bytes
+/// emitted are available through `entry_point` and `size`, which is what a runner needs to execute
+/// the program. Code can run up to and including `$FFFF`; a line that would go past it, rather than
+/// wrap around to `$0000`, is an `AddressOverflow` error.
+///
+/// Operands can be constant expressions over numbers and labels (see `expr::evaluate`), with `<`
+/// and `>` taking the low and high byte. An address given by an expression rather than a plain `$`
+/// number is always encoded in absolute form. Expressions cannot contain spaces.
+///
+/// Branches take a label or address as their target and are encoded as the signed offset from
+/// the next instruction. A target further than -128/+127 bytes away is an error.
+///
+/// Constants are defined with `SCREEN = $0400` or `SCREEN EQU $0400` and share the symbol table
+/// with labels, so they can be used in any operand. A constant's value can only refer to
+/// constants and labels defined above it.
+///
+/// Tables and strings are embedded with data directives: `.byte` takes comma-separated byte
+/// values, `.word` 16-bit values stored low byte first, and `.text` a string in double quotes.
+/// `.textz` ends the string with a zero byte and `.texth` sets bit 7 of its last character.
+///
+/// Mnemonics, directives, index registers and hex digits can be written in any case; labels and
+/// constants are case-sensitive.
+///
+/// Lines can be indented and words separated by any number of spaces or tabs, and a label can
+/// share its line with an instruction or directive.
+///
+/// The shifts and rotates work on the accumulator when written with an operand of `A`, or with
+/// none.
+///
+/// Mnemonics and directives written for other assemblers can be accepted through an alias table,
+/// see `add_alias`.
+///
+/// Label and expression operands are encoded as absolute addresses, unless zero-page encoding is
+/// turned on with `set_zero_page_labels`.
 ///
 /// # Example
 /// ```rust
-/// let instruction_map = populate_string_to_token_table();
-/// let token = instruction_map.get("LDA");
-/// assert_eq!(token, Some(&Token::LDA));
+/// use r_6502::{Assembler, Memory};
+///
+/// let mut memory = Memory::new();
+/// let mut current_mem_addr: u16 = 0x0600;
+/// let mut assembler = Assembler::new();
+/// assembler
+///     .assemble("start:\nLDA #$01\nJMP start", &mut memory, &mut current_mem_addr)
+///     .unwrap();
+/// assert_eq!(current_mem_addr, 0x0605);
+/// assert_eq!(assembler.symbol_table().get("start"), Some(&0x0600));
 /// ```
-fn populate_string_to_token_table() -> HashMap<&'static str, Token> {
-    let mut map = HashMap::new();
-    map.insert("LDA", Token::LDA);
-    map.insert("LDX", Token::LDX);
-    map.insert("LDY", Token::LDY);
-    map.insert("ADC", Token::ADC);
-    map.insert("STA", Token::STA);
-    map.insert("STX", Token::STX);
-    map.insert("STY", Token::STY);
-    map.insert("JMP", Token::JMP);
-    map.insert("JSR", Token::JSR);
-    map.insert("AND", Token::AND);
-    map.insert("ASL", Token::ASL);
-    map.insert("BCC", Token::BCC);
-    map.insert("BCS", Token::BCS);
-    map.insert("BEQ", Token::BEQ);
-    map.insert("BIT", Token::BIT);
-    map.insert("BMI", Token::BMI);
-    map.insert("BNE", Token::BNE);
-    map.insert("BPL", Token::BPL);
-    map.insert("BRK", Token::BRK);
-    map.insert("BVC", Token::BVC);
-    map.insert("BVS", Token::BVS);
-    map.insert("CLC", Token::CLC);
-    map.insert("CLD", Token::CLD);
-    map.insert("CLI", Token::CLI);
-    map.insert("CLV", Token::CLV);
-    map.insert("CMP", Token::CMP);
-    map.insert("CPX", Token::CPX);
-    map.insert("CPY", Token::CPY);
-    map.insert("DEC", Token::DEC);
-    map.insert("DEX", Token::DEX);
-    map.insert("DEY", Token::DEY);
-    map.insert("EOR", Token::EOR);
-    map.insert("INC", Token::INC);
-    map.insert("INX", Token::INX);
-    map.insert("INY", Token::INY);
-    map.insert("LSR", Token::LSR);
-    map.insert("NOP", Token::NOP);
-    map.insert("ORA", Token::ORA);
-    map.insert("PHA", Token::PHA);
-    map.insert("PHP", Token::PHP);
-    map.insert("PLA", Token::PLA);
-    map.insert("PLP", Token::PLP);
-    map.insert("ROL", Token::ROL);
-    map.insert("ROR", Token::ROR);
-    map.insert("RTI", Token::RTI);
-    map.insert("RTS", Token::RTS);
-    map.insert("SBC", Token::SBC);
-    map.insert("SEC", Token::SEC);
-    map.insert("SED", Token::SED);
-    map.insert("SEI", Token::SEI);
-    map.insert("TAX", Token::TAX);
-    map.insert("TAY", Token::TAY);
-    map.insert("TSX", Token::TSX);
-    map.insert("TXA", Token::TXA);
-    map.insert("TXS", Token::TXS);
-    map.insert("TYA", Token::TYA);
-    map
+pub struct Assembler {
+    aliases: HashMap<String, String>, // Alternative mnemonics and the names they stand for
+    symbol_table: HashMap<String, u16>,
+    entry_point: Option<u16>,    // Address of the first byte emitted
+    size: u32,                   // Number of bytes emitted
+    markers: Vec<(u16, String)>, // `.marker` names and the addresses they mark
+    debug_info: DebugInfo,
+    zero_page_labels: ZeroPageLabels,
+    widenings: Vec<Widening>, // Lines of the last program kept absolute to converge
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler {
+            aliases: populate_default_aliases(),
+            symbol_table: HashMap::new(),
+            entry_point: None,
+            size: 0,
+            markers: Vec::new(),
+            debug_info: DebugInfo::new(None),
+            zero_page_labels: ZeroPageLabels::Off,
+            widenings: Vec::new(),
+        }
+    }
+
+    /// Sets how label and expression operands are encoded, `ZeroPageLabels::Off` by default.
+    pub fn set_zero_page_labels(&mut self, mode: ZeroPageLabels) {
+        self.zero_page_labels = mode;
+    }
+
+    /// Returns the lines of the last assembled program whose operand was kept absolute because its
+    /// size changed between passes, in source order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::asm_parser::ZeroPageLabels;
+    /// use r_6502::asm_error::{AsmError, AsmErrorKind};
+    /// use r_6502::{Assembler, Memory};
+    ///
+    /// // `gap` only fits the zero page while `LDA gap` is absolute
+    /// let source = "LDA gap\nNOP\nend:\ngap = $0103-end";
+    /// let mut assembler = Assembler::new();
+    /// assembler.set_zero_page_labels(ZeroPageLabels::On);
+    /// assembler.assemble(source, &mut Memory::new(), &mut 0).unwrap();
+    /// assert_eq!(assembler.widenings()[0].line, 1);
+    /// assert_eq!(assembler.widenings()[0].symbols, ["gap"]);
+    ///
+    /// assembler.set_zero_page_labels(ZeroPageLabels::Strict);
+    /// let error = assembler.assemble(source, &mut Memory::new(), &mut 0).unwrap_err();
+    /// assert!(matches!(error, AsmError::Syntax { kind: AsmErrorKind::OperandSizeChanged, line: 1, .. }));
+    /// ```
+    pub fn widenings(&self) -> &[Widening] {
+        &self.widenings
+    }
+
+    /// Returns the labels defined by the last assembled program and their addresses.
+    pub fn symbol_table(&self) -> &HashMap<String, u16> {
+        &self.symbol_table
+    }
+
+    /// Returns the address the first instruction of the last assembled program was placed at, or
+    /// `None` if it emitted nothing.
+    pub fn entry_point(&self) -> Option<u16> {
+        self.entry_point
+    }
+
+    /// Returns the number of bytes the last assembled program emitted.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns the `.marker` directives of the last assembled program, in source order, with the
+    /// address of the instruction each one marks.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::{Assembler, Memory};
+    ///
+    /// let mut memory = Memory::new();
+    /// let mut current_mem_addr: u16 = 0x0600;
+    /// let mut assembler = Assembler::new();
+    /// assembler
+    ///     .assemble("LDA #$01\n.marker \"loop\"\nINX", &mut memory, &mut current_mem_addr)
+    ///     .unwrap();
+    /// assert_eq!(assembler.markers(), &[(0x0602, String::from("loop"))]);
+    /// ```
+    pub fn markers(&self) -> &[(u16, String)] {
+        &self.markers
+    }
+
+    /// Returns the mapping between addresses and source lines of the last assembled program.
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    /// Builds a binary image of the last assembled program, as it was written to `mem`.
+    ///
+    /// The image runs from `start` (or the lowest address code was emitted at, if `None`) to the
+    /// last byte emitted. Bytes in between that no line emitted, such as the gap left by an `.org`
+    /// directive, are set to `fill`. The image is empty if the program emitted nothing.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::{Assembler, Memory};
+    ///
+    /// let mut memory = Memory::new();
+    /// let mut current_mem_addr: u16 = 0x8000;
+    /// let mut assembler = Assembler::new();
+    /// assembler
+    ///     .assemble("INX\n.org $8003\nRTS", &mut memory, &mut current_mem_addr)
+    ///     .unwrap();
+    /// assert_eq!(assembler.image(&memory, None, 0xFF), vec![0xE8, 0xFF, 0xFF, 0x60]);
+    /// assert_eq!(assembler.image(&memory, Some(0x7FFF), 0x00), vec![0x00, 0xE8, 0x00, 0x00, 0x60]);
+    /// ```
+    pub fn image(&self, mem: &Memory, start: Option<u16>, fill: u8) -> Vec<u8> {
+        let ranges: Vec<(u16, u16)> = self.debug_info.ranges().collect();
+        let end = match ranges
+            .iter()
+            .map(|(address, length)| *address as usize + *length as usize)
+            .max()
+        {
+            Some(end) => end.min(mem.data.len()),
+            None => return Vec::new(),
+        };
+        let start = start.unwrap_or_else(|| {
+            ranges
+                .iter()
+                .map(|(address, _)| *address)
+                .min()
+                .unwrap_or(0)
+        }) as usize;
+        let mut image = vec![fill; end.saturating_sub(start)];
+        for (address, length) in ranges {
+            for offset in 0..length as usize {
+                let address = address as usize + offset;
+                if (start..end).contains(&address) {
+                    image[address - start] = mem.data[address];
+                }
+            }
+        }
+        image
+    }
+
+    /// Makes `alias` stand for the mnemonic or directive `target`, replacing any previous alias.
+    ///
+    /// An alias is only recognised as the first word of a line, and is replaced by `target` before
+    /// the line is assembled, so the alias accepts exactly the operands `target` does. `BGE`,
+    /// `BLT`, `.db` and `.dw` are defined by default.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::{Assembler, Memory};
+    ///
+    /// let mut memory = Memory::new();
+    /// let mut current_mem_addr: u16 = 0x0600;
+    /// let mut assembler = Assembler::new();
+    /// assembler.add_alias("JUMP", "JMP");
+    /// assembler
+    ///     .assemble("loop:\nCMP #$10\nBLT loop\nJUMP $0600", &mut memory, &mut current_mem_addr)
+    ///     .unwrap();
+    /// assert_eq!(memory.data[0x0602], 0x90); // BCC
+    /// assert_eq!(memory.data[0x0604], 0x4C); // JMP absolute
+    ///
+    /// current_mem_addr = 0x0600;
+    /// assembler
+    ///     .assemble("table:\n.db $01,$02\n.dw table", &mut memory, &mut current_mem_addr)
+    ///     .unwrap();
+    /// assert_eq!(memory.data[0x0600..0x0604], [0x01, 0x02, 0x00, 0x06]);
+    /// ```
+    pub fn add_alias(&mut self, alias: &str, target: &str) {
+        self.aliases.insert(alias.to_string(), target.to_string());
+    }
+
+    /// Removes an alias, including one of the defaults.
+    ///
+    /// # Returns
+    /// - `true`: If the alias was defined.
+    /// - `false`: Otherwise.
+    pub fn remove_alias(&mut self, alias: &str) -> bool {
+        self.aliases.remove(alias).is_some()
+    }
+
+    /// Returns the aliases and the mnemonics or directives they stand for.
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+
+    /// Assembles source code held in a string.
+    ///
+    /// # Parameters
+    /// - `source`: The assembly source, one instruction, label or blank line per line.
+    /// - `mem`: A mutable reference to the `Memory` instance where the parsed instructions will be stored.
+    /// - `curr_mem_add`: A mutable reference to the current memory address, which is updated as instructions are added.
+    ///
+    /// # Errors
+    /// Returns an `AsmError::Syntax` with the line and column of the first syntax error, duplicate
+    /// label or undefined label found.
+    pub fn assemble(
+        &mut self,
+        source: &str,
+        mem: &mut Memory,
+        curr_mem_add: &mut u16,
+    ) -> Result<(), AsmError> {
+        let lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+        self.assemble_lines(&lines, mem, curr_mem_add, None)
+    }
+
+    /// Reads an assembly file and assembles it.
+    ///
+    /// # Parameters
+    /// - `file_path`: The path to the assembly file to be read.
+    /// - `mem`: A mutable reference to the `Memory` instance where the parsed instructions will be stored.
+    /// - `curr_mem_add`: A mutable reference to the current memory address, which is updated as instructions are added.
+    ///
+    /// # Errors
+    /// - `AsmError::Io`: If the file cannot be opened or read.
+    /// - `AsmError::Syntax`: If the source contains a syntax error, duplicate label or undefined label.
+    pub fn assemble_file(
+        &mut self,
+        file_path: &str,
+        mem: &mut Memory,
+        curr_mem_add: &mut u16,
+    ) -> Result<(), AsmError> {
+        let io_error = |e: std::io::Error| AsmError::Io {
+            path: file_path.to_string(),
+            message: e.to_string(),
+        };
+        let file = File::open(file_path).map_err(io_error)?;
+        let reader = BufReader::new(file);
+        let mut lines: Vec<String> = Vec::new();
+
+        for line in reader.lines() {
+            lines.push(line.map_err(io_error)?);
+        }
+        self.assemble_lines(&lines, mem, curr_mem_add, Some(file_path))
+    }
+
+    /// Runs both assembler passes over the source lines, skipping empty and comment-only ones.
+    ///
+    /// `file` is the path the lines were read from, recorded in the debug info.
+    fn assemble_lines(
+        &mut self,
+        lines: &[String],
+        mem: &mut Memory,
+        curr_mem_add: &mut u16,
+        file: Option<&str>,
+    ) -> Result<(), AsmError> {
+        let expanded: Vec<String> = lines
+            .iter()
+            .map(|line| expand_alias(line, &self.aliases))
+            .collect();
+        let zero_page_lines = self.size_operands(&expanded, lines, *curr_mem_add)?;
+        self.entry_point = None;
+        self.size = 0;
+        self.markers.clear();
+        self.debug_info = DebugInfo::new(file);
+        let mut past_end = false; // The last line ended at $FFFF, with no `.org` since
+        for (index, (line, source_line)) in expanded.iter().zip(lines).enumerate() {
+            let code = line_code(line);
+            if code.is_empty() {
+                continue;
+            }
+            if let Some(operand) = marker_directive(code) {
+                let name =
+                    parse_marker_name(operand).map_err(|e| e.at_line(index + 1, source_line))?;
+                self.markers.push((*curr_mem_add, name.to_string()));
+                continue;
+            }
+            let line_address = *curr_mem_add;
+            let zero_page = zero_page_lines.contains(&index);
+            parse_line(line, mem, curr_mem_add, &self.symbol_table, zero_page)
+                .map_err(|e| e.at_line(index + 1, source_line))?;
+            if origin_directive(code).is_some() {
+                past_end = false;
+            } else {
+                let emitted = curr_mem_add.wrapping_sub(line_address);
+                let end = line_address as u32 + emitted as u32;
+                if emitted > 0 && (past_end || end > 0x10000) {
+                    return Err(AsmError::syntax(AsmErrorKind::AddressOverflow, code)
+                        .at_line(index + 1, source_line));
+                }
+                past_end |= end == 0x10000;
+                if emitted > 0 && self.entry_point.is_none() {
+                    self.entry_point = Some(line_address);
+                }
+                self.size += emitted as u32;
+                self.debug_info.add(line_address, emitted, index + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the first pass until the label addresses settle, deciding which label and expression
+    /// operands are encoded in the zero page, and fills in the symbol table and `widenings`.
+    ///
+    /// With zero-page labels off, a single pass with every such operand absolute is enough.
+    /// Otherwise each pass sizes the operands by the label addresses of the one before, starting
+    /// from all absolute. An operand that was zero page in one pass and no longer fits in a later
+    /// one is widened for good, so every line changes size at most twice and the passes always end.
+    ///
+    /// # Returns
+    /// The indexes of the lines whose operand is encoded in the zero page.
+    ///
+    /// # Errors
+    /// - With `ZeroPageLabels::Strict`, `OperandSizeChanged` for the first widened line.
+    /// - Any error from `collect_labels`.
+    fn size_operands(
+        &mut self,
+        expanded: &[String],
+        lines: &[String],
+        start_address: u16,
+    ) -> Result<HashSet<usize>, AsmError> {
+        let mut zero_page_lines = HashSet::new();
+        let mut widened: BTreeSet<usize> = BTreeSet::new();
+        self.symbol_table = collect_labels(expanded, start_address, &zero_page_lines)?;
+        if self.zero_page_labels != ZeroPageLabels::Off {
+            loop {
+                let fitting: HashSet<usize> = (0..expanded.len())
+                    .filter(|index| {
+                        !widened.contains(index)
+                            && zero_page_operand(&expanded[*index], &self.symbol_table)
+                    })
+                    .collect();
+                widened.extend(zero_page_lines.difference(&fitting));
+                if fitting == zero_page_lines {
+                    break;
+                }
+                zero_page_lines = fitting;
+                self.symbol_table = collect_labels(expanded, start_address, &zero_page_lines)?;
+            }
+        }
+        self.widenings = widened
+            .into_iter()
+            .map(|index| {
+                let operand = split_instruction(line_code(&expanded[index]))
+                    .1
+                    .unwrap_or_default();
+                let symbols = operand_symbols(&operand, &self.symbol_table);
+                Widening {
+                    line: index + 1,
+                    operand,
+                    symbols,
+                }
+            })
+            .collect();
+        if self.zero_page_labels == ZeroPageLabels::Strict {
+            if let Some(widening) = self.widenings.first() {
+                return Err(
+                    AsmError::syntax(AsmErrorKind::OperandSizeChanged, &widening.operand)
+                        .at_line(widening.line, &lines[widening.line - 1]),
+                );
+            }
+        }
+        Ok(zero_page_lines)
+    }
+}
+
+/// Checks whether a line is an instruction whose label or expression operand can be encoded in
+/// the zero page, with the labels at the addresses in `symbol_table`.
+///
+/// Plain `$` numbers, immediates, indirect operands and branches are sized without labels, and
+/// an operand that refers to a label not in the table yet is taken as absolute.
+fn zero_page_operand(line: &str, symbol_table: &HashMap<String, u16>) -> bool {
+    let code = line_code(line);
+    if code.is_empty()
+        || origin_directive(code).is_some()
+        || marker_directive(code).is_some()
+        || data_directive(code).is_some()
+        || constant_definition(code).is_some()
+    {
+        return false;
+    }
+    let (mnemonic, operand) = split_instruction(code);
+    let (mnemonic, operand) = match (lookup_mnemonic(mnemonic), operand) {
+        (Ok(mnemonic), Some(operand)) => (mnemonic, operand),
+        _ => return false,
+    };
+    if is_branch(mnemonic)
+        || accumulator_opcode(mnemonic, &operand).is_some()
+        || operand.starts_with(['#', '('])
+    {
+        return false;
+    }
+    let (expression, index) = match operand.split_once(',') {
+        Some((expression, index)) => (expression, Some(index)),
+        None => (operand.as_str(), None),
+    };
+    if is_hex_number(expression) {
+        return false;
+    }
+    match evaluate_operand(expression, symbol_table) {
+        Ok(value) if (0..0x100).contains(&value) => {
+            memory_opcode(mnemonic, index, true, &operand).is_ok_and(|info| info.length == 2)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the labels and constants an operand expression uses, in order of first use.
+fn operand_symbols(operand: &str, symbol_table: &HashMap<String, u16>) -> Vec<String> {
+    let mut symbols: Vec<String> = Vec::new();
+    for word in operand.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')) {
+        if symbol_table.contains_key(word) && !symbols.iter().any(|symbol| symbol == word) {
+            symbols.push(word.to_string());
+        }
+    }
+    symbols
 }
+
 /// Reads an assembly file, parses each line, and stores the result in memory.
 ///
-/// This function opens the specified assembly file, reads it line by line, and uses the
-/// `parse_line` function to process each non-empty line. The parsed instructions are stored
+/// This is a shorthand for `Assembler::new().assemble_file(...)`. The parsed instructions are stored
 /// in the provided `Memory` instance starting at the memory address specified by `curr_mem_add`.
-/// The `token_table` is used to map assembly instruction mnemonics to their corresponding
-/// `Token` variants during the parsing process.
 ///
 /// # Parameters
 /// - `file_path`: The path to the assembly file to be read.
@@ -95,159 +549,648 @@ fn populate_string_to_token_table() -> HashMap<&'static str, Token> {
 /// - `curr_mem_add`: A mutable reference to the current memory address, which is updated as instructions are added.
 ///
 /// # Errors
-/// If the file cannot be opened, an error message is printed to `stderr`. If a line cannot be read,
-/// an error message is printed for that specific line.
+/// - `AsmError::Io`: If the file cannot be opened or read.
+/// - `AsmError::Syntax`: If the source contains a syntax error, duplicate label or undefined label.
 ///
 /// # Example
-/// ```rust
+/// ```rust,no_run
+/// use r_6502::asm_parser::read_asm_file;
+/// use r_6502::Memory;
+///
 /// let mut memory = Memory::new();
 /// let mut current_mem_addr = 0x8000;
-/// read_asm_file("program.asm".to_string(), &mut memory, &mut current_mem_addr);
+/// if let Err(e) = read_asm_file("program.asm".to_string(), &mut memory, &mut current_mem_addr) {
+///     eprintln!("{}", e);
+/// }
 /// ```
-pub fn read_asm_file(file_path: String, mem: &mut Memory, curr_mem_add: &mut u16) {
-    let file = match File::open(file_path) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Error opening file: {}", e);
-            return;
+pub fn read_asm_file(
+    file_path: String,
+    mem: &mut Memory,
+    curr_mem_add: &mut u16,
+) -> Result<(), AsmError> {
+    Assembler::new().assemble_file(&file_path, mem, curr_mem_add)
+}
+
+/// Runs the first assembler pass, collecting label definitions into a symbol table.
+///
+/// This function walks the given lines without writing anything to memory. A line consisting of a
+/// single token ending in `:` (e.g. `loop:`) defines a label at the current address, and an origin
+/// directive (`.org $8000`) moves the current address. A constant definition (`SCREEN = $0400`)
+/// adds the constant to the table with its value; every other line advances the address by the size
+/// its instruction will occupy once assembled, as computed by `instruction_size`. The resulting
+/// table is used by the second pass to resolve label operands.
+///
+/// # Parameters
+/// - `lines`: The non-empty lines of the assembly source, in order.
+/// - `start_address`: The address the first instruction will be assembled at.
+/// - `zero_page_lines`: The indexes of the lines whose label or expression operand is encoded in
+///   the zero page.
+///
+/// # Returns
+/// A `HashMap<String, u16>` mapping each label name to the address it was defined at.
+///
+/// # Errors
+/// - If the same label is defined more than once, or a label name is not a valid identifier.
+/// - If a line's size cannot be worked out because of a syntax error.
+fn collect_labels(
+    lines: &[String],
+    start_address: u16,
+    zero_page_lines: &HashSet<usize>,
+) -> Result<HashMap<String, u16>, AsmError> {
+    let mut symbol_table: HashMap<String, u16> = HashMap::new();
+    let mut address: u16 = start_address;
+
+    for (index, source_line) in lines.iter().enumerate() {
+        let (label, line) = split_label(strip_comment(source_line));
+        if let Some(label) = label {
+            define_label(&mut symbol_table, label, address)
+                .map_err(|e| e.at_line(index + 1, source_line))?;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(operand) = origin_directive(line) {
+            address = parse_origin(operand).map_err(|e| e.at_line(index + 1, line))?;
+            continue;
+        }
+        if marker_directive(line).is_some() {
+            continue;
+        }
+        if let Some((directive, operand)) = data_directive(line) {
+            let size = data_size(directive, operand).map_err(|e| e.at_line(index + 1, line))?;
+            address = address.wrapping_add(size);
+            continue;
+        }
+        if let Some((name, value)) = constant_definition(line) {
+            define_constant(&mut symbol_table, name, value)
+                .map_err(|e| e.at_line(index + 1, line))?;
+            continue;
+        }
+        let (mnemonic, operand) = split_instruction(line);
+        let tokens: Vec<&str> = std::iter::once(mnemonic)
+            .chain(operand.as_deref())
+            .collect();
+        let size = instruction_size(&tokens, zero_page_lines.contains(&index))
+            .map_err(|e| e.at_line(index + 1, line))?;
+        address = address.wrapping_add(size);
+    }
+    Ok(symbol_table)
+}
+
+/// Records a label definition in the symbol table.
+///
+/// # Errors
+/// - `InvalidLabel`: If the label name is not a valid identifier.
+/// - `DuplicateLabel`: If the label is already defined.
+fn define_label(
+    symbol_table: &mut HashMap<String, u16>,
+    label: &str,
+    address: u16,
+) -> Result<(), AsmError> {
+    if !is_valid_label(label) {
+        return Err(AsmError::syntax(AsmErrorKind::InvalidLabel, label));
+    }
+    if symbol_table.insert(label.to_string(), address).is_some() {
+        return Err(AsmError::syntax(AsmErrorKind::DuplicateLabel, label));
+    }
+    Ok(())
+}
+
+/// Records a constant definition in the symbol table, with the value of its expression.
+///
+/// The expression is evaluated during the first pass, so it can only use constants and labels
+/// defined above it.
+///
+/// # Errors
+/// - `InvalidNumber`: If the value is not a 16-bit value (`$0000-$FFFF`).
+/// - Any error from `evaluate_operand` or `define_label`.
+fn define_constant(
+    symbol_table: &mut HashMap<String, u16>,
+    name: &str,
+    value: &str,
+) -> Result<(), AsmError> {
+    let number = evaluate_operand(value, symbol_table)?;
+    match u16::try_from(number) {
+        Ok(number) => define_label(symbol_table, name, number),
+        Err(_) => Err(AsmError::syntax(AsmErrorKind::InvalidNumber, value)),
+    }
+}
+
+/// Returns the name and value expression of a constant definition, if the line is one.
+///
+/// Both the `SCREEN = $0400` form and the `SCREEN EQU $0400` form (with `EQU` in any case) are
+/// recognised. The name is checked when the constant is defined.
+fn constant_definition(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if let Some((name, value)) = line.split_once('=') {
+        let name = name.trim();
+        if !name.is_empty() && name != "*" && !name.contains(char::is_whitespace) {
+            return Some((name, value.trim()));
+        }
+    }
+    let (name, rest) = line.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    if rest.len() > 3
+        && rest[..3].eq_ignore_ascii_case("EQU")
+        && rest[3..].starts_with(char::is_whitespace)
+    {
+        return Some((name, rest[3..].trim()));
+    }
+    None
+}
+
+/// Splits the label a line starts with, if any, from the code after it.
+///
+/// A label is a name followed by `:`, either alone on its line (`loop:`) or before an
+/// instruction or directive (`loop: INX`). The name is checked when the label is defined.
+///
+/// # Returns
+/// The label name without its colon, if there is one, and the rest of the line without the
+/// whitespace around it.
+fn split_label(line: &str) -> (Option<&str>, &str) {
+    let line = line.trim();
+    if let Some((name, rest)) = line.split_once(':') {
+        if !name.is_empty() && !name.contains(char::is_whitespace) && !name.contains('"') {
+            return (Some(name), rest.trim());
+        }
+    }
+    (None, line)
+}
+
+/// Returns the instruction or directive of a source line, without its label, its comment and
+/// the whitespace around it.
+fn line_code(line: &str) -> &str {
+    split_label(strip_comment(line)).1
+}
+
+/// Splits an instruction into its mnemonic and its operand, if it has one.
+///
+/// The mnemonic and operand can be separated by any amount of spaces and tabs, and whitespace
+/// inside the operand is ignored, so `LDA ($10), Y` reads as `LDA ($10),Y`.
+fn split_instruction(code: &str) -> (&str, Option<String>) {
+    let mut words = code.split_whitespace();
+    let mnemonic = words.next().unwrap_or("");
+    let operand: String = words.collect();
+    if operand.is_empty() {
+        (mnemonic, None)
+    } else {
+        (mnemonic, Some(operand))
+    }
+}
+
+/// Replaces the first word of a line, after any label, by the mnemonic or directive it is an
+/// alias for, if it is one.
+///
+/// Aliases match in any case, like mnemonics.
+fn expand_alias(line: &str, aliases: &HashMap<String, String>) -> String {
+    // The alias is the first word after any label
+    let code_start = match split_label(strip_comment(line)) {
+        (Some(_), _) => line.find(':').map_or(0, |colon| colon + 1),
+        (None, _) => 0,
+    };
+    let start = line.len() - line[code_start..].trim_start().len();
+    let end = line[start..]
+        .find(|c: char| c.is_whitespace() || c == ';')
+        .map_or(line.len(), |offset| start + offset);
+    let word = &line[start..end];
+    let target = aliases.get(word).or_else(|| {
+        aliases
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(word))
+            .map(|(_, target)| target)
+    });
+    match target {
+        Some(target) => format!("{}{}{}", &line[..start], target, &line[end..]),
+        None => line.to_string(),
+    }
+}
+
+/// Removes a `;` comment and the whitespace before it from a source line.
+fn strip_comment(line: &str) -> &str {
+    match comment_start(line) {
+        Some(index) => line[..index].trim_end(),
+        None => line,
+    }
+}
+
+/// Returns the index of the `;` starting a line's comment, skipping any inside a quoted string.
+pub(crate) fn comment_start(line: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the operand of a `.marker` directive (in any case), if the line is one.
+fn marker_directive(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.len() > 7 && line[..7].eq_ignore_ascii_case(".marker") {
+        let operand = &line[7..];
+        if operand.starts_with(char::is_whitespace) {
+            return Some(operand.trim());
+        }
+    }
+    None
+}
+
+/// Extracts the name from the operand of a `.marker` directive, which must be a quoted string.
+///
+/// # Errors
+/// - `InvalidOperand`: If the operand is not a non-empty string in double quotes.
+fn parse_marker_name(operand: &str) -> Result<&str, AsmError> {
+    match operand
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(name) if !name.is_empty() && !name.contains('"') => Ok(name),
+        _ => Err(AsmError::syntax(AsmErrorKind::InvalidOperand, operand)),
+    }
+}
+
+/// Returns the address operand of an origin directive, if the line is one.
+///
+/// Both the `.org $8000` form (in any case) and the `* = $8000` form are recognised.
+fn origin_directive(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.len() > 4 && line[..4].eq_ignore_ascii_case(".org") {
+        let operand = &line[4..];
+        if operand.starts_with(char::is_whitespace) {
+            return Some(operand.trim());
         }
+    }
+    line.strip_prefix('*')
+        .and_then(|rest| rest.trim_start().strip_prefix('='))
+        .map(|operand| operand.trim())
+}
+
+/// Parses the address of an origin directive, either `$` hex or decimal.
+///
+/// # Errors
+/// - `InvalidNumber`: If the address is not a valid 16-bit value.
+fn parse_origin(operand: &str) -> Result<u16, AsmError> {
+    let address = match operand.strip_prefix('$') {
+        Some(hex) => convert_hex_string_to_u16(hex),
+        None => util::convert_string_to_u16(operand),
+    };
+    address.map_err(|_| AsmError::syntax(AsmErrorKind::InvalidNumber, operand))
+}
+
+/// A directive that emits literal data rather than an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DataDirective {
+    Byte,     // `.byte $01,$02`: one byte per value
+    Word,     // `.word $1234`: two bytes per value, low byte first
+    Text,     // `.text "HELLO"`: the characters of a string
+    TextZero, // `.textz "HELLO"`: the string followed by a $00 byte
+    TextHigh, // `.texth "HELLO"`: the string with bit 7 set on its last character
+}
+
+/// Returns the data directive (in any case) and its operand, if the line is one.
+fn data_directive(line: &str) -> Option<(DataDirective, &str)> {
+    let line = line.trim();
+    let (name, operand) = match line.split_once(char::is_whitespace) {
+        Some((name, operand)) => (name, operand.trim()),
+        None => (line, ""),
+    };
+    let directive = match name.to_ascii_lowercase().as_str() {
+        ".byte" => DataDirective::Byte,
+        ".word" => DataDirective::Word,
+        ".text" => DataDirective::Text,
+        ".textz" => DataDirective::TextZero,
+        ".texth" => DataDirective::TextHigh,
+        _ => return None,
+    };
+    Some((directive, operand))
+}
+
+/// Splits the operand of a `.byte` or `.word` directive into its comma-separated values.
+///
+/// # Errors
+/// - `InvalidOperand`: If the operand is empty or a value is missing (e.g. `$01,,$02`).
+fn data_values(operand: &str) -> Result<Vec<&str>, AsmError> {
+    let values: Vec<&str> = operand.split(',').map(str::trim).collect();
+    if values.iter().any(|value| value.is_empty()) {
+        return Err(AsmError::syntax(AsmErrorKind::InvalidOperand, operand));
+    }
+    Ok(values)
+}
+
+/// Decodes the quoted string operand of a text directive into its bytes.
+///
+/// The characters must be printable ASCII. `\"`, `\\`, `\n`, `\r` and `\0` stand for a quote, a
+/// backslash, a line feed, a carriage return and a zero byte.
+///
+/// # Errors
+/// - `InvalidOperand`: If the operand is not a single string in double quotes, or contains a
+///   character outside ASCII or an unknown escape.
+fn parse_text(operand: &str) -> Result<Vec<u8>, AsmError> {
+    let invalid = || AsmError::syntax(AsmErrorKind::InvalidOperand, operand);
+    let body = operand
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+    let mut bytes = Vec::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        let byte = match c {
+            '\\' => match chars.next() {
+                Some('"') => b'"',
+                Some('\\') => b'\\',
+                Some('n') => b'\n',
+                Some('r') => b'\r',
+                Some('0') => 0x00,
+                _ => return Err(invalid()),
+            },
+            '"' => return Err(invalid()),
+            c if c.is_ascii() && !c.is_ascii_control() => c as u8,
+            _ => return Err(invalid()),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Returns the bytes a text directive emits: the string, terminated as the directive asks.
+///
+/// # Errors
+/// - `InvalidOperand`: If a `.texth` string is empty, leaving no character to mark.
+/// - Any error from `parse_text`.
+fn text_bytes(directive: DataDirective, operand: &str) -> Result<Vec<u8>, AsmError> {
+    let mut bytes = parse_text(operand)?;
+    match directive {
+        DataDirective::TextZero => bytes.push(0x00),
+        DataDirective::TextHigh => match bytes.last_mut() {
+            Some(last) => *last |= 0x80,
+            None => return Err(AsmError::syntax(AsmErrorKind::InvalidOperand, operand)),
+        },
+        _ => {}
+    }
+    Ok(bytes)
+}
+
+/// Computes how many bytes a data directive will emit, without evaluating its values so they can
+/// refer to labels defined further down.
+///
+/// # Errors
+/// - Any error from `data_values` or `text_bytes`.
+fn data_size(directive: DataDirective, operand: &str) -> Result<u16, AsmError> {
+    let size = match directive {
+        DataDirective::Byte => data_values(operand)?.len(),
+        DataDirective::Word => data_values(operand)?.len() * 2,
+        _ => text_bytes(directive, operand)?.len(),
     };
-    let token_table = populate_string_to_token_table();
-    let reader = BufReader::new(file);
-
-    for line in reader.lines() {
-        match line {
-            Ok(line) => {
-                if line.is_empty() {
-                    continue;
-                } else {
-                    parse_line(&line, mem, curr_mem_add, &token_table)
+    Ok(size as u16)
+}
+
+/// Stores the data of a data directive at the current memory address, which is then advanced past
+/// it.
+///
+/// # Errors
+/// - `InvalidNumber`: If a `.byte` value does not fit in a byte or a `.word` value in 16 bits.
+/// - Any error from `data_values`, `text_bytes` or `evaluate_operand`.
+fn load_data(
+    directive: DataDirective,
+    operand: &str,
+    symbol_table: &HashMap<String, u16>,
+    mem: &mut Memory,
+    curr_mem_add: &mut u16,
+) -> Result<(), AsmError> {
+    let mut bytes = Vec::new();
+    match directive {
+        DataDirective::Byte => {
+            for value in data_values(operand)? {
+                let byte = evaluate_operand(value, symbol_table)?;
+                if !(-128..=0xFF).contains(&byte) {
+                    return Err(AsmError::syntax(AsmErrorKind::InvalidNumber, value));
+                }
+                bytes.push(byte as u8);
+            }
+        }
+        DataDirective::Word => {
+            for value in data_values(operand)? {
+                let word = evaluate_operand(value, symbol_table)?;
+                if !(-0x8000..=0xFFFF).contains(&word) {
+                    return Err(AsmError::syntax(AsmErrorKind::InvalidNumber, value));
                 }
+                bytes.extend_from_slice(&(word as u16).to_le_bytes());
             }
-            Err(e) => eprintln!("Error reading line {}", e),
         }
+        _ => bytes = text_bytes(directive, operand)?,
+    }
+    for byte in bytes {
+        mem.data[*curr_mem_add as usize] = byte;
+        *curr_mem_add = curr_mem_add.wrapping_add(1);
+    }
+    Ok(())
+}
+
+/// Checks whether a string is a valid label name.
+///
+/// Label names must start with an ASCII letter or underscore, followed by any number of ASCII
+/// letters, digits or underscores. This keeps them distinguishable from `#` immediates and `$`
+/// addresses when they appear as operands.
+///
+/// # Parameters
+/// - `name`: The candidate label name, without any trailing `:`.
+///
+/// # Returns
+/// - `true`: If `name` can be used as a label.
+/// - `false`: Otherwise.
+fn is_valid_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Computes how many bytes a line will occupy once assembled.
+///
+/// This mirrors the decisions made by the second pass so label addresses collected by
+/// `collect_labels` match where the code actually ends up:
+/// - A single mnemonic occupies one byte.
+/// - A branch with a label operand occupies two bytes (opcode and signed offset).
+/// - An immediate (`#`) operand occupies two bytes.
+/// - A `$` address, plain or indexed (`$10,X`), is sized by the opcode `memory_opcode` picks for
+///   it, since some instructions have no zero-page form.
+/// - An indirect operand occupies two bytes (`($10,X)`, `($10),Y`), or three for `JMP ($1234)`.
+/// - A label or other expression operand on any other instruction takes the absolute form, since
+///   the value of a label defined further down is not known during the first pass, unless
+///   `zero_page` says it is encoded in the zero page (see `ZeroPageLabels`).
+///
+/// # Parameters
+/// - `tokens`: The space-separated tokens of the line.
+/// - `zero_page`: Whether a label or expression operand is encoded in the zero page.
+///
+/// # Returns
+/// The number of bytes the line will emit.
+///
+/// # Errors
+/// - If the mnemonic is not a known instruction, or the operand is malformed.
+fn instruction_size(tokens: &[&str], zero_page: bool) -> Result<u16, AsmError> {
+    let mnemonic = lookup_mnemonic(tokens[0])?;
+    if tokens.len() == 1 {
+        return Ok(1);
+    }
+    if tokens.len() != 2 {
+        return Ok(0);
+    }
+    if is_branch(mnemonic) {
+        return Ok(2);
+    }
+    let command: &str = tokens[1];
+    if accumulator_opcode(mnemonic, command).is_some() {
+        return Ok(1);
+    }
+    if command.starts_with('(') {
+        let (_, mode) = split_indirect_operand(command)?;
+        return Ok(instruction_opcode(mnemonic, mode, command)?.length);
+    }
+    let (address, index) = match command.split_once(',') {
+        Some((address, index)) => (address, Some(index)),
+        None => (command, None),
+    };
+    if command.starts_with('#') {
+        return Ok(2);
+    }
+    let zero_page = if is_hex_number(address) {
+        check_zero_page(&address[1..])?
+    } else {
+        zero_page
+    };
+    Ok(memory_opcode(mnemonic, index, zero_page, command)?.length)
+}
+
+/// Mnemonics of the instructions the 65C02 added, which the NMOS 6502 does not have.
+const CMOS_ONLY_MNEMONICS: [&str; 10] = [
+    "BRA", "DEA", "INA", "PHX", "PHY", "PLX", "PLY", "STZ", "TRB", "TSB",
+];
+
+/// Looks up a mnemonic, written in any case, in the opcode table.
+///
+/// # Returns
+/// The mnemonic as the opcode table spells it, e.g. `LDA` for `lda`.
+///
+/// # Errors
+/// - `Cmos65C02Only`: If the mnemonic is one of the instructions only the 65C02 has.
+/// - `UnknownInstruction`: If no other instruction has that mnemonic.
+fn lookup_mnemonic(mnemonic: &str) -> Result<&'static str, AsmError> {
+    match opcode_table::canonical_mnemonic(mnemonic) {
+        Some(mnemonic) => Ok(mnemonic),
+        None if CMOS_ONLY_MNEMONICS
+            .iter()
+            .any(|cmos| cmos.eq_ignore_ascii_case(mnemonic)) =>
+        {
+            Err(AsmError::syntax(AsmErrorKind::Cmos65C02Only, mnemonic))
+        }
+        None => Err(AsmError::syntax(AsmErrorKind::UnknownInstruction, mnemonic)),
     }
 }
+
+/// Checks whether a mnemonic is one of the relative branch instructions.
+fn is_branch(mnemonic: &str) -> bool {
+    find_opcode(mnemonic, AddressingMode::Relative).is_some()
+}
+
 /// Parses a line of assembly code and processes it based on the number of tokens.
 ///
 /// This function splits the provided line into tokens and determines how to process it based on
 /// the number of tokens. If the line contains one token, it is handled by `handle_one_character_line`;
 /// if it contains two tokens, it is processed by `handle_two_character_line`. The function modifies
 /// the memory (`mem`) starting at the current memory address (`curr_mem_add`), updating the memory as
-/// instructions are parsed. The `token_table` is used to map assembly instruction mnemonics to their
-/// corresponding `Token` variants during parsing.
+/// instructions are parsed. Opcodes are looked up in the `opcode_table`.
 ///
 /// # Parameters
 /// - `line`: The line of assembly code to be parsed, typically in string form.
 /// - `mem`: A mutable reference to the `Memory` instance where the parsed instructions will be stored.
 /// - `curr_mem_add`: A mutable reference to the current memory address, which is updated as instructions
 ///   are parsed and stored.
-/// - `token_table`: A reference to a `HashMap` that maps instruction mnemonics to their respective `Token`
-///   variants for correct parsing.
+/// - `symbol_table`: A reference to the label addresses collected by the first pass (`collect_labels`).
+/// - `zero_page`: Whether a label or expression operand is encoded in the zero page.
 ///
 /// # Behavior
+/// - A `;` comment is removed first, along with the whitespace before it.
+/// - If the line is an origin directive (e.g. `.org $8000`), `curr_mem_add` is moved to its address.
+/// - If the line is a data directive (e.g. `.byte $01,$02`), its data is stored with `load_data`.
+/// - If the line defines a constant (e.g. `SCREEN = $0400`), nothing is emitted.
+/// - If the line is a label definition (e.g. `loop:`), nothing is emitted.
 /// - If the line contains one token, it is processed using the `handle_one_character_line` function.
 /// - If the line contains two tokens, it is processed using the `handle_two_character_line` function.
 ///
-/// # Example
-/// ```rust
-/// let mut memory = Memory::new();
-/// let mut current_mem_addr = 0x8000;
-/// let token_table = populate_string_to_token_table();
-/// parse_line("LDA #10", &mut memory, &mut current_mem_addr, &token_table);
-/// ```
+/// # Errors
+/// Returns an `AsmError::Syntax` describing the offending text if the line cannot be assembled. Its
+/// line number is filled in by the caller.
 fn parse_line(
     line: &str,
     mem: &mut Memory,
     curr_mem_add: &mut u16,
-    token_table: &HashMap<&str, Token>,
-) {
-    let tokens: Vec<&str> = line.split(" ").collect();
-    let amount_of_characters: usize = tokens.len();
-    if amount_of_characters == 1 {
-        handle_one_character_line(tokens[0], mem, token_table, curr_mem_add);
-    } else if amount_of_characters == 2 {
-        handle_two_character_line(tokens, mem, token_table, curr_mem_add);
+    symbol_table: &HashMap<String, u16>,
+    zero_page: bool,
+) -> Result<(), AsmError> {
+    let line = line_code(line);
+    if line.is_empty() {
+        return Ok(());
+    }
+    if let Some(operand) = origin_directive(line) {
+        *curr_mem_add = parse_origin(operand)?;
+        return Ok(());
+    }
+    if let Some((directive, operand)) = data_directive(line) {
+        return load_data(directive, operand, symbol_table, mem, curr_mem_add);
+    }
+    if constant_definition(line).is_some() {
+        return Ok(());
+    }
+    match split_instruction(line) {
+        (mnemonic, None) => handle_one_character_line(mnemonic, mem, curr_mem_add),
+        (mnemonic, Some(operand)) => handle_two_character_line(
+            vec![mnemonic, &operand],
+            mem,
+            curr_mem_add,
+            symbol_table,
+            zero_page,
+        ),
     }
 }
 
-/// Handles a single-token line by parsing the token and loading the corresponding instruction into memory.
+/// Handles a single-token line by loading the instruction's opcode into memory.
 ///
-/// This function takes a single assembly instruction token (e.g., "ASL", "BCC", "NOP") and attempts
-/// to look it up in the provided `token_table`. If the token is found, it processes the instruction
-/// by calling the `load_relative_value` function with the corresponding `Token` and stores it in memory.
-/// If the token is not found in the `token_table`, a panic with a syntax error message is triggered.
-///
-/// The function specifically handles instructions that are expected to have a relative value, updating
-/// the memory and current memory address (`curr_mem_add`) as the instructions are processed.
+/// The mnemonic (e.g. "INX", "ASL", "NOP") is looked up in the `opcode_table` in implied mode, or in
+/// accumulator mode for the shifts and rotates written without an operand, and its opcode is
+/// stored at the current memory address (`curr_mem_add`), which is then advanced.
 ///
 /// # Parameters
-/// - `token`: The assembly instruction token (e.g., "ASL", "BCC", etc.) to be processed.
+/// - `token`: The assembly instruction mnemonic (e.g., "ASL", "INX", etc.) to be processed.
 /// - `mem`: A mutable reference to the `Memory` instance where the parsed instruction will be stored.
-/// - `token_table`: A reference to the `HashMap` that maps instruction mnemonics to their respective `Token` variants.
 /// - `curr_mem_add`: A mutable reference to the current memory address, which is updated as the instruction is stored.
 ///
-/// # Panics
-/// - If the token is not found in the `token_table`, the function panics with a syntax error message.
-/// - If no token is found for the instruction requiring a relative value, it panics with an error message.
-///
-/// # Example
-/// ```rust
-/// let mut memory = Memory::new();
-/// let mut current_mem_addr = 0x8000;
-/// let token_table = populate_string_to_token_table();
-/// handle_one_character_line("LDA", &mut memory, &token_table, &mut current_mem_addr);
-/// ```
+/// # Errors
+/// - `UnknownInstruction`: If the mnemonic is not a known instruction.
+/// - `InvalidOperand`: If the instruction needs an operand.
 fn handle_one_character_line(
     token: &str,
     mem: &mut Memory,
-    token_table: &HashMap<&str, Token>,
     curr_mem_add: &mut u16,
-) {
-    let found_token: Token;
-
-    match token_table.get(token) {
-        Some(t) => found_token = t.clone(),
-        None => panic!("Syntax error {}", token),
-    }
-    match found_token {
-        Token::ASL => load_relative_value(Token::ASL, mem, curr_mem_add),
-        Token::BCC => load_relative_value(Token::BCC, mem, curr_mem_add),
-        Token::BCS => load_relative_value(Token::BCS, mem, curr_mem_add),
-        Token::BEQ => load_relative_value(Token::BEQ, mem, curr_mem_add),
-        Token::BMI => load_relative_value(Token::BMI, mem, curr_mem_add),
-        Token::BNE => load_relative_value(Token::BNE, mem, curr_mem_add),
-        Token::BPL => load_relative_value(Token::BPL, mem, curr_mem_add),
-        Token::BRK => load_relative_value(Token::BRK, mem, curr_mem_add),
-        Token::BVC => load_relative_value(Token::BVC, mem, curr_mem_add),
-        Token::BVS => load_relative_value(Token::BVS, mem, curr_mem_add),
-        Token::CLC => load_relative_value(Token::CLC, mem, curr_mem_add),
-        Token::CLD => load_relative_value(Token::CLD, mem, curr_mem_add),
-        Token::CLI => load_relative_value(Token::CLI, mem, curr_mem_add),
-        Token::CLV => load_relative_value(Token::CLV, mem, curr_mem_add),
-        Token::DEX => load_relative_value(Token::DEX, mem, curr_mem_add),
-        Token::DEY => load_relative_value(Token::DEY, mem, curr_mem_add),
-        Token::INX => load_relative_value(Token::INX, mem, curr_mem_add),
-        Token::INY => load_relative_value(Token::INY, mem, curr_mem_add),
-        Token::LSR => load_relative_value(Token::LSR, mem, curr_mem_add),
-        Token::NOP => load_relative_value(Token::NOP, mem, curr_mem_add),
-        Token::PHA => load_relative_value(Token::PHA, mem, curr_mem_add),
-        Token::PLA => load_relative_value(Token::PLA, mem, curr_mem_add),
-        Token::PLP => load_relative_value(Token::PLP, mem, curr_mem_add),
-        Token::ROL => load_relative_value(Token::ROL, mem, curr_mem_add),
-        Token::ROR => load_relative_value(Token::ROR, mem, curr_mem_add),
-        Token::RTI => load_relative_value(Token::RTI, mem, curr_mem_add),
-        Token::RTS => load_relative_value(Token::RTS, mem, curr_mem_add),
-        Token::SEC => load_relative_value(Token::SEC, mem, curr_mem_add),
-        Token::SED => load_relative_value(Token::SED, mem, curr_mem_add),
-        Token::SEI => load_relative_value(Token::SEI, mem, curr_mem_add),
-        Token::TAX => load_relative_value(Token::TAX, mem, curr_mem_add),
-        Token::TAY => load_relative_value(Token::TAY, mem, curr_mem_add),
-        Token::TSX => load_relative_value(Token::TSX, mem, curr_mem_add),
-        Token::TXA => load_relative_value(Token::TXA, mem, curr_mem_add),
-        Token::TXS => load_relative_value(Token::TXS, mem, curr_mem_add),
-        Token::TYA => load_relative_value(Token::TYA, mem, curr_mem_add),
-        _ => panic!("NO TOKEN FOUND FOR RELATIVE VALUE"),
+) -> Result<(), AsmError> {
+    let mnemonic = lookup_mnemonic(token)?;
+    let info = find_opcode(mnemonic, AddressingMode::Implied)
+        .or_else(|| find_opcode(mnemonic, AddressingMode::Accumulator));
+    match info {
+        Some(info) => {
+            load_opcode(info.token, mem, curr_mem_add);
+            Ok(())
+        }
+        None => Err(AsmError::syntax(AsmErrorKind::InvalidOperand, token)),
     }
 }
 
@@ -255,372 +1198,344 @@ fn handle_one_character_line(
 ///
 /// This function processes a line of assembly code consisting of two tokens: the first token is
 /// an instruction mnemonic (e.g., "LDA", "ADC"), and the second token contains additional data
-/// (e.g., immediate value or memory address). The function looks up the instruction token in the
-/// `token_table` and processes the command (e.g., immediate value or memory location) based on the
-/// first character of the second token. If the second token starts with `#`, the function treats it
-/// as an immediate value, and if it starts with `$`, it treats it as a memory location. The function
-/// calls the appropriate helper functions to load these values into memory and update the memory address.
+/// (e.g., immediate value, memory address or label). The function checks the mnemonic against the
+/// `opcode_table` and processes the command based on the first character of the second token. If the
+/// second token starts with `#`, the function treats it as an immediate value, if it is a `$` number,
+/// it treats it as a memory location, and otherwise it treats it as an address expression (such as a
+/// label or `table+2`) evaluated against the `symbol_table`. Branch instructions take an address
+/// expression, which is encoded as a relative offset. The function calls the appropriate helper
+/// functions to load these values into memory and update the memory address.
 ///
 /// # Parameters
 /// - `tokens`: A vector of two string slices, the first being the instruction mnemonic and the second being the command.
 /// - `mem`: A mutable reference to the `Memory` instance where the parsed instructions and values will be stored.
-/// - `token_table`: A reference to the `HashMap` mapping instruction mnemonics to their respective `Token` variants.
 /// - `curr_mem_add`: A mutable reference to the current memory address, which is updated as the instruction is stored.
+/// - `symbol_table`: A reference to the label addresses collected by the first pass.
+/// - `zero_page`: Whether an expression operand is encoded in the zero page.
 ///
-/// # Panics
-/// - If the instruction token is not found in the `token_table`, a syntax error panic is triggered.
-/// - If there is no character in the command string or the command does not start with a valid character, it panics.
+/// # Errors
+/// - `UnknownInstruction`: If the mnemonic is not a known instruction.
+/// - `InvalidOperand`: If the operand is empty or a malformed expression.
+/// - `UndefinedLabel`: If the operand refers to a label that is not in the `symbol_table`.
+/// - Any error from the function the operand is handed to.
 ///
 /// # Behavior
-/// - If the command starts with `#`, it is treated as an immediate value and passed to `load_immediate_command`.
-/// - If the command starts with `$`, it is treated as a memory location and passed to `load_mem_location_command`.
-/// - If the command does not start with `#` or `$`, it is ignored and a default message is printed.
-///
-/// # Example
-/// ```rust
-/// let mut memory = Memory::new();
-/// let mut current_mem_addr = 0x8000;
-/// let token_table = populate_string_to_token_table();
-/// let tokens = vec!["LDA", "#$10"];
-/// handle_two_character_line(tokens, &mut memory, &token_table, &mut current_mem_addr);
-/// ```
+/// - If the instruction is a branch, the command is passed to `load_branch_command`.
+/// - If the command starts with `#`, it is treated as an immediate value and passed to `load_immediate_value`.
+/// - If the command is a `$` number, it is treated as a memory location and passed to `load_memory_operand`
+///   with the opcode `memory_opcode` picks.
+/// - Any other command is evaluated as an expression and its value is passed to `load_memory_operand` as
+///   an absolute address, or a zero-page one if `zero_page` is set.
+/// - A `$` address or expression may be followed by `,X` or `,Y` for the indexed addressing modes.
+/// - If the command starts with `(`, it is treated as an indirect operand and passed to `load_indirect_command`.
 fn handle_two_character_line(
     tokens: Vec<&str>,
     mem: &mut Memory,
-    token_table: &HashMap<&str, Token>,
     curr_mem_add: &mut u16,
-) {
-    let token: &str = tokens[0];
-    let found_token: Token;
-    let command: &str = tokens[1];
-    match token_table.get(token) {
-        Some(t) => found_token = t.clone(),
-        None => panic!("Syntax error {}", token),
+    symbol_table: &HashMap<String, u16>,
+    zero_page: bool,
+) -> Result<(), AsmError> {
+    let operand: &str = tokens[1];
+    let mnemonic = lookup_mnemonic(tokens[0])?;
+
+    if is_branch(mnemonic) {
+        let info = instruction_opcode(mnemonic, AddressingMode::Relative, operand)?;
+        return load_branch_command(info.token, operand, symbol_table, mem, curr_mem_add);
+    }
+    if let Some(info) = accumulator_opcode(mnemonic, operand) {
+        load_opcode(info.token, mem, curr_mem_add);
+        return Ok(());
+    }
+
+    let special_character: char = match operand.chars().next() {
+        Some(c) => c,
+        None => return Err(AsmError::syntax(AsmErrorKind::InvalidOperand, operand)),
+    };
+    if special_character == '(' {
+        return load_indirect_command(mnemonic, operand, symbol_table, mem, curr_mem_add);
+    }
+    let (command, index) = match operand.split_once(',') {
+        Some((command, index)) => (command, Some(index)),
+        None => (operand, None),
+    };
+    if let Some(value) = command.strip_prefix('#') {
+        if index.is_some() {
+            return Err(AsmError::syntax(AsmErrorKind::InvalidOperand, operand));
+        }
+        let info = instruction_opcode(mnemonic, AddressingMode::Immediate, value)?;
+        return load_immediate_value(info.token, value, symbol_table, mem, curr_mem_add);
+    }
+    let (value, zero_page) = if is_hex_number(command) {
+        let value = &command[1..];
+        (value.to_string(), check_zero_page(value)?)
+    } else {
+        (address_value(command, symbol_table)?, zero_page)
+    };
+    let reported = if index.is_some() { operand } else { command };
+    let info = memory_opcode(mnemonic, index, zero_page, reported)?;
+    load_memory_operand(info, &value, mem, curr_mem_add)
+}
+
+/// Returns the accumulator-mode opcode of a shift or rotate whose operand is `A` (in any case), as
+/// in `ASL A`.
+///
+/// For any other instruction, `A` is left to be read as a label.
+fn accumulator_opcode(mnemonic: &str, operand: &str) -> Option<&'static OpcodeInfo> {
+    if operand.eq_ignore_ascii_case("A") {
+        find_opcode(mnemonic, AddressingMode::Accumulator)
+    } else {
+        None
+    }
+}
+
+/// Checks whether an operand is a plain `$` hex number rather than an expression.
+///
+/// Plain numbers below `$100` are encoded in the zero page; an expression is always encoded as an
+/// absolute address, since the labels in it may not be known yet when the first pass sizes the
+/// instruction.
+fn is_hex_number(operand: &str) -> bool {
+    match operand.strip_prefix('$') {
+        Some(digits) => digits.chars().all(|c| c.is_ascii_alphanumeric()),
+        None => false,
+    }
+}
+
+/// Evaluates an operand expression, such as `table+2` or `>message`, against the symbol table.
+///
+/// See `expr::evaluate` for the operators expressions can use.
+///
+/// # Errors
+/// - `UndefinedLabel`: If the expression refers to a label that is not defined anywhere in the
+///   source.
+/// - `InvalidOperand`: If the expression is malformed or divides by zero.
+fn evaluate_operand(
+    expression: &str,
+    symbol_table: &HashMap<String, u16>,
+) -> Result<i32, AsmError> {
+    evaluate(expression, symbol_table).map_err(|message| {
+        let undefined = message
+            .strip_prefix("undefined symbol `")
+            .and_then(|rest| rest.strip_suffix('`'));
+        match undefined {
+            Some(label) => AsmError::syntax(AsmErrorKind::UndefinedLabel, label),
+            None => AsmError::syntax(AsmErrorKind::InvalidOperand, expression),
+        }
+    })
+}
+
+/// Evaluates an address expression into the four hex digits `load_mem_page` expects.
+///
+/// # Errors
+/// - `InvalidNumber`: If the value is not an address (`$0000-$FFFF`).
+/// - Any error from `evaluate_operand`.
+fn address_value(
+    expression: &str,
+    symbol_table: &HashMap<String, u16>,
+) -> Result<String, AsmError> {
+    let address = evaluate_operand(expression, symbol_table)?;
+    match u16::try_from(address) {
+        Ok(address) => Ok(format!("{:04X}", address)),
+        Err(_) => Err(AsmError::syntax(AsmErrorKind::InvalidNumber, expression)),
+    }
+}
+
+/// Loads a branch instruction and its relative offset into memory.
+///
+/// Branch instructions encode their target as a signed 8-bit offset from the address of the next
+/// instruction. This function resolves the label operand through the `symbol_table`, computes that
+/// offset and stores the opcode followed by the offset byte.
+///
+/// # Parameters
+/// - `token`: The branch `Token` (e.g. `BNE`, `BEQ`).
+/// - `command`: The label or address expression the branch targets.
+/// - `symbol_table`: A reference to the label addresses collected by the first pass.
+/// - `mem`: A mutable reference to the `Memory` structure where the values are written to.
+/// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after each operation.
+///
+/// # Errors
+/// - `InvalidOperand`: If the operand is not a valid label name.
+/// - `UndefinedLabel`: If the label is undefined.
+/// - `BranchOutOfRange`: If the target is further than -128/+127 bytes from the next instruction.
+fn load_branch_command(
+    token: Token,
+    command: &str,
+    symbol_table: &HashMap<String, u16>,
+    mem: &mut Memory,
+    curr_mem_add: &mut u16,
+) -> Result<(), AsmError> {
+    let target = evaluate_operand(command, symbol_table)?;
+    let next_instruction = *curr_mem_add as i32 + 2;
+    let offset = target - next_instruction;
+    if !(-128..=127).contains(&offset) {
+        return Err(AsmError::syntax(AsmErrorKind::BranchOutOfRange, command));
     }
-    println!("{:?} is the token", found_token);
-    let special_character: char;
+    mem.data[*curr_mem_add as usize] = token as u8;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    mem.data[*curr_mem_add as usize] = offset as i8 as u8;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    Ok(())
+}
 
-    match command.chars().nth(0) {
-        Some(c) => special_character = c,
-        None => panic!("Error"),
-    }
-    let value: &str = &command[1..];
-    match special_character {
-        '#' => load_immediate_command(found_token, value, mem, curr_mem_add),
-        '$' => load_mem_location_command(found_token, value, mem, curr_mem_add),
-        _ => println!("default"),
-    }
+/// Looks up the opcode of an instruction in an addressing mode.
+///
+/// # Parameters
+/// - `mnemonic`: The instruction mnemonic (e.g. `LDA`).
+/// - `mode`: The `AddressingMode` the operand was written in.
+/// - `operand`: The operand text, reported if the instruction has no such mode.
+///
+/// # Errors
+/// - `UnsupportedAddressingMode`: If the instruction cannot be used with that addressing mode.
+fn instruction_opcode(
+    mnemonic: &str,
+    mode: AddressingMode,
+    operand: &str,
+) -> Result<&'static OpcodeInfo, AsmError> {
+    find_opcode(mnemonic, mode)
+        .ok_or_else(|| AsmError::syntax(AsmErrorKind::UnsupportedAddressingMode, operand))
 }
 
-/// Handles the execution of immediate value loading commands based on the given token.
+/// Picks the opcode for an instruction whose operand is an address, optionally indexed.
 ///
-/// This function matches the provided `token` to the appropriate instruction type (e.g., LDA, LDX,
-/// ADC, etc.), and then loads the immediate value into memory by calling `load_immediate_value`
-/// with the provided token, value, and memory reference. The function performs actions based on
-/// the specific command to load the immediate value accordingly.
+/// Instructions are only encoded with a zero-page opcode when `zero_page` is set and such an opcode
+/// exists; otherwise the absolute opcode is used (e.g. `LDA $10,Y` has to be encoded as
+/// `LDA $0010,Y` because there is no zero-page `LDA ,Y`, and `JMP $10` as `JMP $0010`).
 ///
 /// # Parameters
-/// - `token`: A `Token` representing the instruction type (e.g., `LDA`, `LDX`, `ADC`, etc.).
-/// - `value`: A string representing the immediate value to be loaded.
-/// - `mem`: A mutable reference to the `Memory` structure where the immediate value will be stored.
-/// - `curr_mem_add`: A mutable reference to the current memory address that will be updated during the operation.
-///
-/// # Panics
-/// This function will panic if an unsupported token is encountered that does not match any of the predefined
-/// command tokens (such as `LDA`, `LDX`, `ADC`, etc.).
+/// - `mnemonic`: The instruction mnemonic (e.g. `LDA`, `STA`).
+/// - `index`: The index register written after the comma (`"X"` or `"Y"`, in any case), if any.
+/// - `zero_page`: Whether the address is a zero-page address.
+/// - `operand`: The operand text, reported if there is no suitable opcode.
 ///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x00u16;
-/// load_immediate_command(Token::LDA, "0xFF", &mut mem, &mut curr_mem_add);
-/// ```
-fn load_immediate_command(token: Token, value: &str, mem: &mut Memory, curr_mem_add: &mut u16) {
-    match token {
-        Token::LDA => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::LDX => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::LDY => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::ADC => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::AND => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::CMP => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::CPX => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::CPY => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::EOR => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::ORA => {
-            load_immediate_value(token, value, mem, curr_mem_add);
-        }
-        Token::ROL => {
-            load_immediate_value(token, value, mem, curr_mem_add);
+/// # Errors
+/// - `UnsupportedAddressingMode`: If the instruction cannot take an address with that index, or the
+///   only available form is zero-page and the address does not fit in the zero page
+///   (`STX $1234,Y`).
+fn memory_opcode(
+    mnemonic: &str,
+    index: Option<&str>,
+    zero_page: bool,
+    operand: &str,
+) -> Result<&'static OpcodeInfo, AsmError> {
+    let (zero_page_mode, absolute_mode) = match index {
+        None => (AddressingMode::ZeroPage, AddressingMode::Absolute),
+        Some(index) if index.eq_ignore_ascii_case("X") => {
+            (AddressingMode::ZeroPageX, AddressingMode::AbsoluteX)
         }
-        Token::ROR => {
-            load_immediate_value(token, value, mem, curr_mem_add);
+        Some(index) if index.eq_ignore_ascii_case("Y") => {
+            (AddressingMode::ZeroPageY, AddressingMode::AbsoluteY)
         }
-        Token::SBC => {
-            load_immediate_value(token, value, mem, curr_mem_add);
+        Some(_) => {
+            return Err(AsmError::syntax(
+                AsmErrorKind::UnsupportedAddressingMode,
+                operand,
+            ))
         }
-        _ => panic!("NO FOUND TOKEN FOR IMMEDIATE COMMAND"),
+    };
+    let zero_page_opcode = if zero_page {
+        find_opcode(mnemonic, zero_page_mode)
+    } else {
+        None
+    };
+    match zero_page_opcode {
+        Some(info) => Ok(info),
+        None => instruction_opcode(mnemonic, absolute_mode, operand),
     }
 }
 
-/// Handles the execution of memory location loading commands based on the given token.
-///
-/// This function matches the provided `token` to the appropriate instruction type (e.g., LDA, STA,
-/// CMP, etc.) and loads or stores values from or to a memory location by calling `load_memory_location`
-/// with the provided token, value, current memory address, and memory reference. The function supports a
-/// wide variety of commands that involve memory locations.
+/// Stores an opcode and its address operand, which is a single byte for the zero-page modes and
+/// two bytes otherwise.
 ///
 /// # Parameters
-/// - `token`: A `Token` representing the instruction type (e.g., `LDA`, `STA`, `AND`, etc.).
-/// - `value`: A string representing the memory address or other relevant value to be used with the instruction.
-/// - `mem`: A mutable reference to the `Memory` structure where values will be loaded, stored, or processed.
-/// - `curr_mem_add`: A mutable reference to the current memory address, which may be updated during the operation.
+/// - `info`: The opcode picked for the instruction.
+/// - `value`: A string representing the hex address (e.g. `"2000"`).
+/// - `mem`: A mutable reference to the `Memory` structure where the values are written to.
+/// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after each operation.
 ///
-/// # Panics
-/// This function will panic if an unsupported token is encountered that does not match any of the predefined
-/// command tokens (such as `LDA`, `STA`, `ADC`, etc.).
+/// # Errors
+/// - `InvalidNumber`: If `value` is not a valid address.
+fn load_memory_operand(
+    info: &OpcodeInfo,
+    value: &str,
+    mem: &mut Memory,
+    curr_mem_add: &mut u16,
+) -> Result<(), AsmError> {
+    match info.mode.operand_size() {
+        1 => load_zero_page(info.token, value, curr_mem_add, mem),
+        _ => load_mem_page(info.token, value, curr_mem_add, mem),
+    }
+}
+
+/// Handles instructions using an indirect operand (`($10,X)`, `($10),Y` or `JMP ($1234)`).
 ///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x1000u16;
-/// load_mem_location_command(Token::LDA, "0xFF00", &mut mem, &mut curr_mem_add);
-/// ```
-fn load_mem_location_command(token: Token, value: &str, mem: &mut Memory, curr_mem_add: &mut u16) {
-    match token {
-        Token::LDA => load_memory_location(token, value, curr_mem_add, mem),
-        Token::LDX => load_memory_location(token, value, curr_mem_add, mem),
-        Token::LDY => load_memory_location(token, value, curr_mem_add, mem),
-        Token::ADC => load_memory_location(token, value, curr_mem_add, mem),
-        Token::STA => load_memory_location(token, value, curr_mem_add, mem),
-        Token::STX => load_memory_location(token, value, curr_mem_add, mem),
-        Token::STY => load_memory_location(token, value, curr_mem_add, mem),
-        Token::JMP => load_memory_location(token, value, curr_mem_add, mem),
-        Token::JSR => load_memory_location(token, value, curr_mem_add, mem),
-        Token::AND => load_memory_location(token, value, curr_mem_add, mem),
-        Token::ASL => load_memory_location(token, value, curr_mem_add, mem),
-        Token::BIT => load_memory_location(token, value, curr_mem_add, mem),
-        Token::CMP => load_memory_location(token, value, curr_mem_add, mem),
-        Token::CPX => load_memory_location(token, value, curr_mem_add, mem),
-        Token::CPY => load_memory_location(token, value, curr_mem_add, mem),
-        Token::DEC => load_memory_location(token, value, curr_mem_add, mem),
-        Token::EOR => load_memory_location(token, value, curr_mem_add, mem),
-        Token::INC => load_memory_location(token, value, curr_mem_add, mem),
-        Token::LSR => load_memory_location(token, value, curr_mem_add, mem),
-        Token::ORA => load_memory_location(token, value, curr_mem_add, mem),
-        Token::ROL => load_memory_location(token, value, curr_mem_add, mem),
-        Token::ROR => load_memory_location(token, value, curr_mem_add, mem),
-        Token::SBC => load_memory_location(token, value, curr_mem_add, mem),
-        _ => panic!("NO FOUND TOKEN FOR MEM LOCATION COMMAND"),
-    }
-}
-
-/// Handles the loading and storing of values from/to memory locations based on the given token.
-///
-/// This function processes the provided `token` (such as `LDA`, `STA`, `ADC`, etc.) and determines if
-/// the operation involves a zero-page memory address or a full memory page address. It then calls
-/// either `load_zero_page` or `load_mem_page` depending on whether the `value` refers to a zero-page
-/// address or a larger memory address.
+/// The pointer inside the parentheses may be a `$` address or an expression such as a label.
+/// Indexed indirect and indirect indexed operands point into the zero page and are stored as a
+/// single byte, while `JMP` takes a full 16-bit pointer.
 ///
 /// # Parameters
-/// - `token`: A `Token` representing the instruction type (e.g., `LDA`, `STA`, `ADC`, etc.) which dictates the operation.
-/// - `value`: A string representing the memory address or value to be used in the operation.
-/// - `curr_mem_add`: A mutable reference to the current memory address, which may be updated during the operation.
-/// - `mem`: A mutable reference to the `Memory` structure where the operation will be performed.
-///
-/// # Panics
-/// This function will panic if an unsupported token is encountered that does not match any of the predefined
-/// command tokens (such as `LDA`, `STA`, `ADC`, etc.) or if the token does not map to a valid operation for zero-page
-/// or full memory loading.
+/// - `mnemonic`: The instruction mnemonic (e.g. `LDA`, `JMP`).
+/// - `command`: The full operand, including the parentheses.
+/// - `symbol_table`: A reference to the label addresses collected by the first pass.
+/// - `mem`: A mutable reference to the `Memory` structure where the values are written to.
+/// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after each operation.
 ///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x1000u16;
-/// load_memory_location(Token::LDA, "0x00FF", &mut curr_mem_add, &mut mem);
-/// ```
-fn load_memory_location(token: Token, value: &str, curr_mem_add: &mut u16, mem: &mut Memory) {
-    match token {
-        Token::LDA => {
-            if is_zero_page(value) {
-                load_zero_page(Token::LdaZP, value, curr_mem_add, mem)
-            } else {
-                load_mem_page(Token::LdaAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::LDX => {
-            if is_zero_page(value) {
-                load_zero_page(Token::LdxZP, value, curr_mem_add, mem)
-            } else {
-                load_mem_page(Token::LdxAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::LDY => {
-            if is_zero_page(value) {
-                load_zero_page(Token::LdyZP, value, curr_mem_add, mem)
-            } else {
-                load_mem_page(Token::LdyAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::ADC => {
-            if is_zero_page(value) {
-                load_zero_page(Token::AdcZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::AdcAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::STA => {
-            if is_zero_page(value) {
-                load_zero_page(Token::STA, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::StaAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::STX => {
-            if is_zero_page(value) {
-                load_zero_page(Token::StxZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::StxAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::STY => {
-            if is_zero_page(value) {
-                load_zero_page(Token::StyZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::StyAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::JMP => {
-            if is_zero_page(value) {
-                load_zero_page(Token::JMP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::JMP, value, curr_mem_add, mem);
-            }
-        }
-        Token::JSR => {
-            if is_zero_page(value) {
-                load_zero_page(Token::JSR, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::JSR, value, curr_mem_add, mem);
-            }
-        }
-        Token::AND => {
-            if is_zero_page(value) {
-                load_zero_page(Token::AndZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::AndAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::ASL => {
-            if is_zero_page(value) {
-                load_zero_page(Token::AslZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::AslAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::BIT => {
-            if is_zero_page(value) {
-                load_zero_page(Token::BIT, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::BitAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::CMP => {
-            if is_zero_page(value) {
-                load_zero_page(Token::CmpZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::CmpAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::CPX => {
-            if is_zero_page(value) {
-                load_zero_page(Token::CpxZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::CpxAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::CPY => {
-            if is_zero_page(value) {
-                load_zero_page(Token::CpyZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::CpyAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::DEC => {
-            if is_zero_page(value) {
-                load_zero_page(Token::DEC, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::DecAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::EOR => {
-            if is_zero_page(value) {
-                load_zero_page(Token::EorZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::EorAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::INC => {
-            if is_zero_page(value) {
-                load_zero_page(Token::INC, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::IncAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::LSR => {
-            if is_zero_page(value) {
-                load_zero_page(Token::LsrZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::LsrAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::ORA => {
-            if is_zero_page(value) {
-                load_zero_page(Token::OraZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::OraAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::ROL => {
-            if is_zero_page(value) {
-                load_zero_page(Token::RolZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::RolAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::ROR => {
-            if is_zero_page(value) {
-                load_zero_page(Token::RorZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::RorAP, value, curr_mem_add, mem);
-            }
-        }
-        Token::SBC => {
-            if is_zero_page(value) {
-                load_zero_page(Token::SbcZP, value, curr_mem_add, mem);
-            } else {
-                load_mem_page(Token::SbcAP, value, curr_mem_add, mem);
+/// # Errors
+/// - `InvalidOperand`: If the operand is not one of the indirect forms, or a `($10,X)` or `($10),Y`
+///   pointer is not in the zero page.
+/// - `UnsupportedAddressingMode`: If the instruction has no such addressing mode.
+/// - `UndefinedLabel`: If the pointer is a label that is never defined.
+fn load_indirect_command(
+    mnemonic: &str,
+    command: &str,
+    symbol_table: &HashMap<String, u16>,
+    mem: &mut Memory,
+    curr_mem_add: &mut u16,
+) -> Result<(), AsmError> {
+    let (pointer, mode) = split_indirect_operand(command)?;
+    let info = instruction_opcode(mnemonic, mode, command)?;
+    let value: String = if is_hex_number(pointer) {
+        pointer[1..].to_string()
+    } else {
+        address_value(pointer, symbol_table)?
+    };
+    match mode {
+        AddressingMode::Indirect => load_mem_page(info.token, &value, curr_mem_add, mem),
+        _ => {
+            if !check_zero_page(&value)? {
+                return Err(AsmError::syntax(AsmErrorKind::InvalidOperand, pointer));
             }
+            load_zero_page(info.token, &value, curr_mem_add, mem)
         }
+    }
+}
 
-        _ => panic!("NO FOUND TOKEN FOR ZERO PAGE LOADING"),
+/// Splits an indirect operand into the pointer inside the parentheses and its addressing mode.
+///
+/// # Parameters
+/// - `command`: The full operand, e.g. `($10,X)`, `($10),Y` or `($1234)`, with the index register
+///   in any case.
+///
+/// # Returns
+/// A tuple of the pointer text (e.g. `$10` or a label name) and the `AddressingMode` it denotes.
+///
+/// # Errors
+/// - `InvalidOperand`: If the operand is not one of the three indirect forms.
+fn split_indirect_operand(command: &str) -> Result<(&str, AddressingMode), AsmError> {
+    let inner = &command[1..];
+    let strip_index = |suffix: &str| {
+        let start = inner.len().checked_sub(suffix.len())?;
+        match inner.get(start..) {
+            Some(end) if end.eq_ignore_ascii_case(suffix) => Some(&inner[..start]),
+            _ => None,
+        }
+    };
+    if let Some(pointer) = strip_index(",X)") {
+        Ok((pointer, AddressingMode::IndexedIndirect))
+    } else if let Some(pointer) = strip_index("),Y") {
+        Ok((pointer, AddressingMode::IndirectIndexed))
+    } else if let Some(pointer) = inner.strip_suffix(')') {
+        Ok((pointer, AddressingMode::Indirect))
+    } else {
+        Err(AsmError::syntax(AsmErrorKind::InvalidOperand, command))
     }
 }
 
@@ -638,20 +1553,21 @@ fn load_memory_location(token: Token, value: &str, curr_mem_add: &mut u16, mem:
 /// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after each operation.
 /// - `mem`: A mutable reference to the `Memory` structure where the values are written to.
 ///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x1000u16;
-/// load_zero_page(Token::LDA, "FF", &mut curr_mem_add, &mut mem);
-/// ```
-///
-/// This will store the byte corresponding to the `LDA` token in `mem.data[0x1000]`,
-/// and the value `0xFF` (from the hex string `"FF"`) in `mem.data[0x1001]`.
-fn load_zero_page(token: Token, value: &str, curr_mem_add: &mut u16, mem: &mut Memory) {
+/// # Errors
+/// - `InvalidNumber`: If `value` is not a valid one-byte hex value.
+fn load_zero_page(
+    token: Token,
+    value: &str,
+    curr_mem_add: &mut u16,
+    mem: &mut Memory,
+) -> Result<(), AsmError> {
+    let byte = convert_hex_string_to_u8(value)
+        .map_err(|_| AsmError::syntax(AsmErrorKind::InvalidNumber, value))?;
     mem.data[*curr_mem_add as usize] = token as u8;
-    *curr_mem_add += 1;
-    mem.data[*curr_mem_add as usize] = convert_hex_string_to_u8(value);
-    *curr_mem_add += 1;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    mem.data[*curr_mem_add as usize] = byte;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    Ok(())
 }
 
 /// Loads a value from a memory address page based on the provided token and value.
@@ -668,133 +1584,528 @@ fn load_zero_page(token: Token, value: &str, curr_mem_add: &mut u16, mem: &mut M
 /// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after each operation.
 /// - `mem`: A mutable reference to the `Memory` structure where the values are written to.
 ///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x1000u16;
-/// load_mem_page(Token::LDA, "FF01", &mut curr_mem_add, &mut mem);
-/// ```
-///
-/// This will store the byte corresponding to the `LDA` token in `mem.data[0x1000]`,
-/// the low byte `0x01` in `mem.data[0x1001]`, and the high byte `0xFF` in `mem.data[0x1002]`.
-///
-/// # Note
-/// The `value` string is expected to have at least four characters, as it represents a 16-bit value (two bytes).
-fn load_mem_page(token: Token, value: &str, curr_mem_add: &mut u16, mem: &mut Memory) {
+/// # Errors
+/// - `InvalidNumber`: If `value` is not a valid 16-bit hex value.
+fn load_mem_page(
+    token: Token,
+    value: &str,
+    curr_mem_add: &mut u16,
+    mem: &mut Memory,
+) -> Result<(), AsmError> {
+    let address = convert_hex_string_to_u16(value)
+        .map_err(|_| AsmError::syntax(AsmErrorKind::InvalidNumber, value))?;
     mem.data[*curr_mem_add as usize] = token as u8;
-    *curr_mem_add += 1;
-    let h_byte: u8 = convert_hex_string_to_u8(&value[0..2]);
-    let l_byte: u8 = convert_hex_string_to_u8(&value[2..4]);
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    let h_byte: u8 = (address >> 8) as u8;
+    let l_byte: u8 = address as u8;
     mem.data[*curr_mem_add as usize] = l_byte;
-    *curr_mem_add += 1;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
     mem.data[*curr_mem_add as usize] = h_byte;
-    *curr_mem_add += 1;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    Ok(())
 }
 
-/// Loads an immediate value into memory based on the provided token and value.
-///
-/// This function stores a byte value corresponding to the provided `token` at the current memory address
-/// in the `Memory` structure, then increments the current memory address. It also stores the immediate `value`
-/// after converting it, depending on whether the value is in hexadecimal or string format.
+/// Loads an instruction with an immediate operand into memory.
 ///
-/// - If the `value` is a valid hex string (starting with `0x`), it is converted and stored as a `u8` byte.
-/// - Otherwise, the `value` is interpreted as a regular string and converted to a `u8` using a custom conversion function.
+/// The opcode of `token` is stored at the current memory address, followed by the value of the
+/// operand expression (e.g. `$FF`, `10`, `<message` or `COUNT-1`), which must fit in a byte. Negative
+/// values down to -128 are stored as their two's complement.
 ///
 /// # Parameters
 /// - `token`: A `Token` representing an operation (such as `LDA`, `ADC`, etc.). The token is cast to a `u8`
 ///   value and stored in the current memory location.
-/// - `value`: A string representing the immediate value to be loaded into memory. If the value starts with `0x`,
-///   it is treated as a hexadecimal string, otherwise, it is treated as a regular string.
+/// - `value`: The operand expression, without the `#`.
+/// - `symbol_table`: A reference to the label addresses collected by the first pass.
 /// - `mem`: A mutable reference to the `Memory` structure where the values are written to.
 /// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after each operation.
 ///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x1000u16;
-/// load_immediate_value(Token::LDA, "0xFF", &mut mem, &mut curr_mem_add);
-/// ```
-/// This will store the byte corresponding to the `LDA` token in `mem.data[0x1000]`, and the value `0xFF`
-/// in `mem.data[0x1001]` after converting it from hexadecimal.
-///
-/// # Notes
-/// - The `value` string must either be in hexadecimal (starting with `0x`) or a regular string. The function handles both cases.
-/// - If the value is in hexadecimal, it is expected to be prefixed by `0x` (e.g., `0xFF`).
-fn load_immediate_value(token: Token, value: &str, mem: &mut Memory, curr_mem_add: &mut u16) {
-    if is_hex(value) {
-        mem.data[*curr_mem_add as usize] = token as u8;
-        *curr_mem_add += 1;
-        mem.data[*curr_mem_add as usize] = util::convert_hex_string_to_u8(&value[1..]);
-        *curr_mem_add += 1;
-    } else {
-        mem.data[*curr_mem_add as usize] = token as u8;
-        *curr_mem_add += 1;
-        mem.data[*curr_mem_add as usize] = util::convert_string_to_u8(value);
-        *curr_mem_add += 1;
+/// # Errors
+/// - `InvalidNumber`: If the value does not fit in a byte.
+/// - Any error from `evaluate_operand`.
+fn load_immediate_value(
+    token: Token,
+    value: &str,
+    symbol_table: &HashMap<String, u16>,
+    mem: &mut Memory,
+    curr_mem_add: &mut u16,
+) -> Result<(), AsmError> {
+    let byte = evaluate_operand(value, symbol_table)?;
+    if !(-128..=0xFF).contains(&byte) {
+        return Err(AsmError::syntax(AsmErrorKind::InvalidNumber, value));
     }
+    mem.data[*curr_mem_add as usize] = token as u8;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    mem.data[*curr_mem_add as usize] = byte as u8;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
+    Ok(())
 }
 
-/// Loads a relative value into memory based on the provided token.
+/// Loads the opcode of an instruction that has no operand into memory.
 ///
 /// This function stores the byte corresponding to the provided `token` at the current memory address
-/// in the `Memory` structure and increments the current memory address.
+/// in the `Memory` structure and increments the current memory address. Branches are never
+/// emitted this way, as they always take an offset (see `load_branch_command`).
 ///
 /// # Parameters
-/// - `token`: A `Token` representing an operation (such as `BEQ`, `BNE`, etc.). The token is cast to a `u8`
-///   value and stored in the current memory location.
+/// - `token`: A `Token` representing an implied or accumulator instruction (such as `INX` or
+///   `ASL`). The token is cast to a `u8` value and stored in the current memory location.
 /// - `mem`: A mutable reference to the `Memory` structure where the token is written to.
 /// - `curr_mem_add`: A mutable reference to the current memory address. It is updated after the operation.
-///
-/// # Example
-/// ```rust
-/// let mut mem = Memory::new();
-/// let mut curr_mem_add = 0x1000u16;
-/// load_relative_value(Token::BEQ, &mut mem, &mut curr_mem_add);
-/// ```
-/// This will store the byte corresponding to the `BEQ` token in `mem.data[0x1000]`, and increment the
-/// current memory address to `0x1001`.
-///
-/// # Notes
-/// - This function is typically used for operations that involve relative addressing (like branch instructions),
-///   where only the token is stored and the actual relative value will be added in a later step.
-fn load_relative_value(token: Token, mem: &mut Memory, curr_mem_add: &mut u16) {
+fn load_opcode(token: Token, mem: &mut Memory, curr_mem_add: &mut u16) {
     mem.data[*curr_mem_add as usize] = token as u8;
-    *curr_mem_add += 1;
+    *curr_mem_add = curr_mem_add.wrapping_add(1);
 }
 
-/// Checks if the provided string represents a hexadecimal value.
-///
-/// This function checks whether the first character of the string is a dollar sign (`$`),
-/// which is a common prefix used to denote hexadecimal numbers in some assembly languages.
-///
-/// # Parameters
-/// - `value`: A string reference to the value that needs to be checked for hexadecimal format.
-///
-/// # Returns
-/// - `true`: If the string starts with a `$`, indicating it is a hexadecimal value.
-/// - `false`: If the string does not start with a `$`, indicating it is not considered hexadecimal.
-///
-/// # Panics
-/// - This function will panic with a `"Syntax error for hex"` message if the input string is empty.
-///
-/// # Example
-/// ```rust
-/// let hex_value = "$FF";
-/// let non_hex_value = "FF";
-///
-/// assert!(is_hex(hex_value));  // returns true
-/// assert!(!is_hex(non_hex_value));  // returns false
-/// ```
+/// Checks whether a hex address (without the `$`) fits in the zero page.
 ///
-/// # Notes
-/// - This function is used to identify whether a string represents a hexadecimal value based on the `$` prefix.
-/// - It does not check if the rest of the string is a valid hexadecimal number; it only checks the prefix.
-fn is_hex(value: &str) -> bool {
-    match value.chars().nth(0) {
-        Some(c) if c == '$' => {
-            return true;
+/// # Errors
+/// - `InvalidNumber`: If `value` is not a valid 16-bit hex value.
+fn check_zero_page(value: &str) -> Result<bool, AsmError> {
+    is_zero_page(value).map_err(|_| AsmError::syntax(AsmErrorKind::InvalidNumber, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles `source` from `$0000` into fresh memory.
+    fn assemble(source: &str) -> (Result<(), AsmError>, Memory) {
+        assemble_with(&mut Assembler::new(), source, 0x0000)
+    }
+
+    /// Assembles `source` with `assembler` from `origin` into fresh memory.
+    fn assemble_with(
+        assembler: &mut Assembler,
+        source: &str,
+        origin: u16,
+    ) -> (Result<(), AsmError>, Memory) {
+        let mut mem = Memory::new();
+        let mut curr_mem_add = origin;
+        let result = assembler.assemble(source, &mut mem, &mut curr_mem_add);
+        (result, mem)
+    }
+
+    /// Assembles `source` from `$0600` and returns the bytes from there up to `end`.
+    fn bytes_at_0600(source: &str, end: usize) -> Vec<u8> {
+        let (result, mem) = assemble_with(&mut Assembler::new(), source, 0x0600);
+        assert_eq!(result, Ok(()), "{}", source);
+        mem.data[0x0600..end].to_vec()
+    }
+
+    #[test]
+    fn records_labels_entry_point_and_size() {
+        let mut assembler = Assembler::new();
+        let (result, _) = assemble_with(&mut assembler, "start:\nLDA #$01\nJMP start", 0x0600);
+        assert_eq!(result, Ok(()));
+        assert_eq!(assembler.symbol_table().get("start"), Some(&0x0600));
+
+        let (result, _) = assemble_with(&mut assembler, ".org $8000\nLDA #$01", 0x0600);
+        assert_eq!(result, Ok(()));
+        assert_eq!(assembler.entry_point(), Some(0x8000));
+        assert_eq!(assembler.size(), 2);
+    }
+
+    #[test]
+    fn evaluates_operand_expressions() {
+        let source = "LDA #>message\nLDX #<(message+2)\nLDY message+4*2\nmessage:";
+        assert_eq!(
+            bytes_at_0600(source, 0x0607),
+            [0xA9, 0x06, 0xA2, 0x09, 0xAC, 0x0F, 0x06]
+        );
+    }
+
+    #[test]
+    fn encodes_branches_as_offsets() {
+        let source = "loop:\nBNE loop\nBEQ $0600\nBCC done\nNOP\ndone:";
+        assert_eq!(
+            bytes_at_0600(source, 0x0606),
+            [0xD0, 0xFE, 0xF0, 0xFC, 0x90, 0x01]
+        );
+
+        let (far, _) = assemble_with(&mut Assembler::new(), "BNE $0700", 0x0600);
+        assert!(matches!(
+            far,
+            Err(AsmError::Syntax {
+                kind: AsmErrorKind::BranchOutOfRange,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn defines_constants() {
+        let mut assembler = Assembler::new();
+        let source = "SCREEN = $0400\nVALUE EQU 10\nLDA #VALUE+1\nSTA SCREEN";
+        let (result, mem) = assemble_with(&mut assembler, source, 0x0600);
+        assert_eq!(result, Ok(()));
+        assert_eq!(mem.data[0x0600..0x0605], [0xA9, 0x0B, 0x8D, 0x00, 0x04]);
+        assert_eq!(assembler.symbol_table().get("SCREEN"), Some(&0x0400));
+    }
+
+    #[test]
+    fn embeds_data_directives() {
+        let source = "table:\n.byte $01,2,<table\n.word table,$1234\n.textz \"HI\"\n.texth \"OK\"";
+        assert_eq!(
+            bytes_at_0600(source, 0x060E),
+            [
+                0x01,
+                0x02,
+                0x00,
+                0x00,
+                0x06,
+                0x34,
+                0x12,
+                b'H',
+                b'I',
+                0x00,
+                b'O',
+                b'K' | 0x80,
+                0,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_any_case_and_spacing() {
+        let source = "ldx #$0a\nloop:\nsta $c0,x\nlda ($fb),y\nDex\nbne loop";
+        assert_eq!(
+            bytes_at_0600(source, 0x0609),
+            [0xA2, 0x0A, 0x95, 0xC0, 0xB1, 0xFB, 0xCA, 0xD0, 0xF9]
+        );
+
+        let source =
+            "\tLDX   #$03\nloop:\tSTA $c0, X  ; fill\n    DEX\n    BNE loop\ndone: .byte 1";
+        assert_eq!(
+            bytes_at_0600(source, 0x0608),
+            [0xA2, 0x03, 0x95, 0xC0, 0xCA, 0xD0, 0xFB, 0x01]
+        );
+    }
+
+    #[test]
+    fn reads_an_operand_of_a_as_the_accumulator_for_shifts() {
+        assert_eq!(
+            bytes_at_0600("ASL A\nLSR a\nROL A\nROR\nLDA #$01", 0x0606),
+            [0x0A, 0x4A, 0x2A, 0x6A, 0xA9, 0x01]
+        );
+        assert!(matches!(
+            assemble("LDA A").0,
+            Err(AsmError::Syntax {
+                kind: AsmErrorKind::UndefinedLabel,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn encodes_labels_in_the_zero_page_when_turned_on() {
+        let mut assembler = Assembler::new();
+        assembler.set_zero_page_labels(ZeroPageLabels::On);
+        let source = "ptr = $FB\nLDA ptr\nSTA ptr+1,X\nJMP done\ndone:\nLDA table,Y\ntable:";
+        let (result, mem) = assemble_with(&mut assembler, source, 0x0600);
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            mem.data[0x0600..0x060A],
+            [0xA5, 0xFB, 0x95, 0xFC, 0x4C, 0x07, 0x06, 0xB9, 0x0A, 0x06]
+        );
+        assert!(assembler.widenings().is_empty());
+    }
+
+    #[test]
+    fn assembles_at_the_last_address() {
+        let (result, mem) = assemble(".org $FFFF\nNOP");
+        assert_eq!(result, Ok(()));
+        assert_eq!(mem.data[0xFFFF], 0xEA);
+
+        let (result, mem) = assemble(".org $FFFE\n.word $1234");
+        assert_eq!(result, Ok(()));
+        assert_eq!(mem.data[0xFFFE..], [0x34, 0x12]);
+
+        for (source, line) in [
+            (".org $FFFF\nNOP\nNOP", 3),
+            (".org $FFFF\nLDA #$01", 2),
+            (".org $FFFE\nJMP $1234", 2),
+            (".org $FFFF\nLDA $10", 2),
+            (".org $FFFF\n.byte 1,2", 2),
+            ("* = $FFFF\nNOP\nlater:\nINX", 4),
+        ] {
+            match assemble(source).0 {
+                Err(AsmError::Syntax {
+                    kind: AsmErrorKind::AddressOverflow,
+                    line: error_line,
+                    ..
+                }) => assert_eq!(error_line, line, "{}", source),
+                other => panic!("{}: {:?}", source, other),
+            }
+        }
+
+        // An `.org` after the end starts over
+        let (result, mem) = assemble(".org $FFFF\nNOP\n.org $0200\nINX");
+        assert_eq!(result, Ok(()));
+        assert_eq!(mem.data[0x0200], 0xE8);
+    }
+
+    #[test]
+    fn strips_full_line_and_inline_comments() {
+        assert_eq!(strip_comment("LDA #$01 ; load one"), "LDA #$01");
+        assert_eq!(strip_comment("; a whole-line comment"), "");
+        assert_eq!(strip_comment("INX;next"), "INX");
+        assert_eq!(strip_comment(".text \"A;B\" ; text"), ".text \"A;B\"");
+
+        let (result, mem) = assemble(
+            "; set up\n  ; indented\nloop: ; the top\nLDA #$01 ; load one\nINX;next\nBNE loop ;;\n",
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(mem.data[0..6], [0xA9, 0x01, 0xE8, 0xD0, 0xFB, 0x00]);
+
+        // A `;` inside a string is text, not a comment
+        let (result, mem) = assemble(".text \"A;B\" ; three bytes");
+        assert_eq!(result, Ok(()));
+        assert_eq!(mem.data[0..4], [b'A', b';', b'B', 0x00]);
+
+        // A line that is only a comment takes no space, so labels after it are not moved
+        let mut memory = Memory::new();
+        let mut current_mem_addr: u16 = 0x0600;
+        let mut assembler = Assembler::new();
+        let result = assembler.assemble(
+            "; header\nstart: ; entry\n; between\nNOP\nend:",
+            &mut memory,
+            &mut current_mem_addr,
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(assembler.symbol_table().get("start"), Some(&0x0600));
+        assert_eq!(assembler.symbol_table().get("end"), Some(&0x0601));
+        assert_eq!(assembler.size(), 1);
+    }
+
+    #[test]
+    fn collects_labels_in_the_first_pass() {
+        let lines = ["loop:", "INX", "BNE loop", "done: RTS"].map(String::from);
+        let symbols = collect_labels(&lines, 0x0600, &HashSet::new()).unwrap();
+        assert_eq!(symbols.get("loop"), Some(&0x0600));
+        assert_eq!(symbols.get("done"), Some(&0x0603));
+    }
+
+    #[test]
+    fn splits_definitions_labels_and_instructions() {
+        assert_eq!(
+            constant_definition("SCREEN = $0400"),
+            Some(("SCREEN", "$0400"))
+        );
+        assert_eq!(constant_definition("VALUE equ 10"), Some(("VALUE", "10")));
+        assert_eq!(constant_definition("* = $8000"), None);
+        assert_eq!(constant_definition("LDA VALUE"), None);
+
+        assert_eq!(split_label("loop:"), (Some("loop"), ""));
+        assert_eq!(split_label("  loop:\tINX"), (Some("loop"), "INX"));
+        assert_eq!(split_label("  LDA #$01"), (None, "LDA #$01"));
+        assert_eq!(split_label(".text \"A: B\""), (None, ".text \"A: B\""));
+
+        assert_eq!(
+            split_instruction("LDA\t #$10"),
+            ("LDA", Some(String::from("#$10")))
+        );
+        assert_eq!(
+            split_instruction("STA $10, X"),
+            ("STA", Some(String::from("$10,X")))
+        );
+        assert_eq!(split_instruction("INX"), ("INX", None));
+    }
+
+    #[test]
+    fn expands_default_aliases() {
+        let aliases = populate_default_aliases();
+        assert_eq!(
+            expand_alias("  BGE done ; taken", &aliases),
+            "  BCS done ; taken"
+        );
+        assert_eq!(expand_alias("bge done", &aliases), "BCS done");
+        assert_eq!(
+            expand_alias("check:\tBGE done", &aliases),
+            "check:\tBCS done"
+        );
+        assert_eq!(expand_alias("BNE done", &aliases), "BNE done");
+        assert_eq!(expand_alias("DEA", &aliases), "DEA");
+    }
+
+    #[test]
+    fn reports_65c02_instructions_as_such() {
+        for (source, line) in [("DEA", 1), ("NOP\nina", 2), ("STZ $10", 1)] {
+            match assemble(source).0 {
+                Err(AsmError::Syntax {
+                    kind: AsmErrorKind::Cmos65C02Only,
+                    line: error_line,
+                    ..
+                }) => assert_eq!(error_line, line, "{}", source),
+                other => panic!("{}: {:?}", source, other),
+            }
         }
-        Some(_) => return false,
-        None => panic!("Syntax error for hex"),
+        let error = assemble("INX\nDEA").0.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "2:1: 65C02-only instruction, not on the NMOS 6502 `DEA`"
+        );
+        assert!(matches!(
+            assemble("DEY\nFOO").0,
+            Err(AsmError::Syntax {
+                kind: AsmErrorKind::UnknownInstruction,
+                line: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn recognises_directives() {
+        assert_eq!(
+            marker_directive(".marker \"frame_start\""),
+            Some("\"frame_start\"")
+        );
+        assert_eq!(marker_directive("LDA #$01"), None);
+
+        assert_eq!(origin_directive(".org $8000"), Some("$8000"));
+        assert_eq!(origin_directive("*=$8000"), Some("$8000"));
+        assert_eq!(origin_directive("LDA #$01"), None);
+
+        assert_eq!(
+            data_directive(".byte $01,$02"),
+            Some((DataDirective::Byte, "$01,$02"))
+        );
+        assert_eq!(
+            data_directive(".TEXT \"HI THERE\""),
+            Some((DataDirective::Text, "\"HI THERE\""))
+        );
+        assert_eq!(data_directive("LDA #$01"), None);
+    }
+
+    #[test]
+    fn loads_data_directives() {
+        assert_eq!(parse_text("\"HI\\r\""), Ok(vec![b'H', b'I', 0x0D]));
+
+        let mut mem = Memory::new();
+        let mut curr_mem_add: u16 = 0x0600;
+        let symbol_table = HashMap::new();
+        load_data(
+            DataDirective::Word,
+            "$1234",
+            &symbol_table,
+            &mut mem,
+            &mut curr_mem_add,
+        )
+        .unwrap();
+        assert_eq!(mem.data[0x0600..0x0602], [0x34, 0x12]);
+        assert_eq!(curr_mem_add, 0x0602);
+    }
+
+    #[test]
+    fn sizes_instructions_and_checks_names() {
+        assert!(is_valid_label("loop_1"));
+        assert!(!is_valid_label("1loop"));
+
+        assert_eq!(instruction_size(&["LDA", "$0200"], false), Ok(3));
+        assert_eq!(instruction_size(&["BNE", "loop"], false), Ok(2));
+        assert_eq!(instruction_size(&["LDA", "ptr"], true), Ok(2));
+
+        assert!(is_branch("BNE"));
+        assert!(!is_branch("JMP"));
+
+        assert!(is_hex_number("$10"));
+        assert!(!is_hex_number("$10+1"));
+        assert!(!is_hex_number("table"));
+    }
+
+    #[test]
+    fn parses_lines() {
+        let mut mem = Memory::new();
+        let mut curr_mem_add: u16 = 0x8000;
+        let symbol_table = HashMap::new();
+        parse_line("LDA #10", &mut mem, &mut curr_mem_add, &symbol_table, false).unwrap();
+        assert_eq!(mem.data[0x8000..0x8002], [0xA9, 10]);
+
+        handle_one_character_line("INX", &mut mem, &mut curr_mem_add).unwrap();
+        assert_eq!(mem.data[0x8002], 0xE8);
+
+        handle_two_character_line(
+            vec!["LDA", "#$10"],
+            &mut mem,
+            &mut curr_mem_add,
+            &symbol_table,
+            false,
+        )
+        .unwrap();
+        assert_eq!(mem.data[0x8003..0x8005], [0xA9, 0x10]);
+        assert_eq!(curr_mem_add, 0x8005);
+    }
+
+    #[test]
+    fn picks_opcodes_for_operands() {
+        assert_eq!(
+            accumulator_opcode("ROR", "a").map(|info| info.token),
+            Some(Token::ROR)
+        );
+        assert!(accumulator_opcode("LDA", "A").is_none());
+
+        let mut symbol_table = HashMap::new();
+        symbol_table.insert(String::from("table"), 0x0600);
+        assert_eq!(evaluate_operand("table+2*3", &symbol_table), Ok(0x0606));
+
+        let info = instruction_opcode("JMP", AddressingMode::Indirect, "($1234)").unwrap();
+        assert_eq!(info.token, Token::JmpID);
+
+        let info = memory_opcode("LDA", Some("X"), true, "$10,X").unwrap();
+        assert_eq!(info.token, Token::LdaZPX);
+        let info = memory_opcode("LDA", Some("Y"), true, "$10,Y").unwrap();
+        assert_eq!(info.token, Token::LdaAPY);
+
+        assert_eq!(
+            split_indirect_operand("($10),Y"),
+            Ok(("$10", AddressingMode::IndirectIndexed))
+        );
+    }
+
+    #[test]
+    fn loads_operands() {
+        let mut mem = Memory::new();
+        let mut curr_mem_add: u16 = 0x0602;
+        let mut symbol_table = HashMap::new();
+        symbol_table.insert(String::from("loop"), 0x0600);
+        load_branch_command(
+            Token::BNE,
+            "loop",
+            &symbol_table,
+            &mut mem,
+            &mut curr_mem_add,
+        )
+        .unwrap();
+        assert_eq!(mem.data[0x0602..0x0604], [0xD0, 0xFC]);
+
+        let mut curr_mem_add: u16 = 0x1000;
+        let info = opcode_table::opcode_info(0xBD).unwrap();
+        load_memory_operand(info, "2000", &mut mem, &mut curr_mem_add).unwrap();
+        assert_eq!(mem.data[0x1000..0x1003], [0xBD, 0x00, 0x20]);
+
+        load_indirect_command("LDA", "($20),Y", &symbol_table, &mut mem, &mut curr_mem_add)
+            .unwrap();
+        assert_eq!(mem.data[0x1003..0x1005], [0xB1, 0x20]);
+
+        load_zero_page(Token::LdaZP, "FF", &mut curr_mem_add, &mut mem).unwrap();
+        assert_eq!(mem.data[0x1005..0x1007], [0xA5, 0xFF]);
+
+        load_mem_page(Token::LdaAP, "FF01", &mut curr_mem_add, &mut mem).unwrap();
+        assert_eq!(mem.data[0x1007..0x100A], [0xAD, 0x01, 0xFF]);
+
+        load_immediate_value(
+            Token::LDA,
+            "$FF",
+            &symbol_table,
+            &mut mem,
+            &mut curr_mem_add,
+        )
+        .unwrap();
+        assert_eq!(mem.data[0x100A..0x100C], [0xA9, 0xFF]);
+
+        load_opcode(Token::INX, &mut mem, &mut curr_mem_add);
+        assert_eq!(mem.data[0x100C], 0xE8);
+        assert_eq!(curr_mem_add, 0x100D);
     }
 }
@@ -0,0 +1,2081 @@
+use crate::breakpoint::{Breakpoint, Condition, FlagBreakpoint};
+use crate::bus::Bus;
+use crate::cosim::Retirement;
+use crate::cpu::{
+    EndOfMemory, UnknownOpcode, BREAK, CARRY, CPU, DECIMAL, INTERRUPT_DISABLE, NEGATIVE, OVERFLOW,
+    ZERO,
+};
+use crate::event_log::EventKind;
+use crate::limits::Limit;
+#[cfg(feature = "metrics")]
+use crate::metrics::PUBLISH_INTERVAL;
+use crate::opcode_table::opcode_info;
+use crate::token::{AddressingMode, Token};
+use crate::trace::write_trace;
+
+/// Why a run of the program stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Finished,               // The program ran through
+    Breakpoint(Breakpoint), // One of `cpu.breakpoints` was hit
+    IllegalOpcode {
+        address: u16,
+        opcode: u8,
+    }, // The byte at `address` is not a documented opcode
+    GuardHit {
+        pc: u16,
+        address: u16,
+        write: bool,
+    }, // The instruction at `pc` accessed a guard region
+    FlagChanged {
+        pc: u16,
+        breakpoint: FlagBreakpoint,
+    }, // The instruction at `pc` hit a flag breakpoint
+    Watchpoint {
+        pc: u16,
+        address: u16,
+        value: u8,
+        write: bool,
+    }, // The instruction at `pc` hit a watchpoint
+    RanOffEnd {
+        pc: u16,
+    }, // The instruction at `pc` runs past $FFFF, see `cpu.end_of_memory`
+    DeviceFetch {
+        pc: u16,
+    }, // The program counter reached `pc`, which is mapped to a device rather than memory
+    LimitExceeded(Limit),   // The program reached one of `cpu.limits`
+    Brk {
+        pc: u16,
+    }, // The program counter reached a `BRK` at `pc`, see `EndConditions::brk`
+    Trapped {
+        pc: u16,
+    }, // The instruction at `pc` jumped or branched to itself, see `EndConditions::trap`
+    EndAddress {
+        pc: u16,
+    }, // The program counter reached `EndConditions::end_address`
+    BudgetSpent,            // The run used up `EndConditions::cycle_budget` or `instruction_budget`
+}
+
+impl StopReason {
+    /// Returns whether the run stopped at a problem with the program, rather than at its end, a
+    /// breakpoint or one of the `EndConditions`.
+    ///
+    /// These are the reasons `run_until` panics on, unless `cpu.panic_free` is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::asm_runner::StopReason;
+    ///
+    /// assert!(StopReason::RanOffEnd { pc: 0xFFFF }.is_fault());
+    /// assert!(!StopReason::Brk { pc: 0x0600 }.is_fault());
+    /// ```
+    pub fn is_fault(&self) -> bool {
+        matches!(
+            self,
+            StopReason::IllegalOpcode { .. }
+                | StopReason::GuardHit { .. }
+                | StopReason::RanOffEnd { .. }
+                | StopReason::DeviceFetch { .. }
+                | StopReason::LimitExceeded(_)
+        )
+    }
+
+    /// Returns whether the run ended where it was meant to, at the end of the program or one of
+    /// the `EndConditions`, rather than at a problem or breakpoint.
+    pub fn is_end(&self) -> bool {
+        matches!(
+            self,
+            StopReason::Finished
+                | StopReason::Brk { .. }
+                | StopReason::Trapped { .. }
+                | StopReason::EndAddress { .. }
+                | StopReason::BudgetSpent
+        )
+    }
+}
+
+/// When `try_run_until` ends a run, besides the problems and breakpoints it always stops at.
+///
+/// Every condition is checked between instructions, against the CPU's own cycle and instruction
+/// counters, so an instruction always runs to completion and nothing is counted down that could
+/// run out part way through one. Conditions left at their defaults are not checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EndConditions {
+    pub brk: bool,  // Stop before executing a `BRK`, the usual end of small programs
+    pub trap: bool, // Stop after an instruction that jumps or branches to itself (`JMP *`)
+    pub end_address: Option<u16>, // Stop when the program counter reaches this address
+    pub cycle_budget: Option<u64>, // Stop once the run has taken this many cycles
+    pub instruction_budget: Option<u64>, // Stop once the run has executed this many instructions
+}
+
+impl EndConditions {
+    /// Ends a run at the end of a program assembled up to `end_address`, or at a `BRK`, which is
+    /// also what a program that jumps off its end into cleared memory reaches.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+    /// use r_6502::{Assembler, CPU};
+    ///
+    /// let mut cpu = CPU::new();
+    /// let mut end_address: u16 = 0;
+    /// Assembler::new()
+    ///     .assemble("LDX #$00\nloop:\nINX\nCPX #$10\nBNE loop", &mut cpu.memory, &mut end_address)
+    ///     .unwrap();
+    /// let end = EndConditions::program_end(end_address);
+    /// assert_eq!(try_run_until(&mut cpu, &end), StopReason::EndAddress { pc: 0x0007 });
+    /// assert_eq!(cpu.x, 0x10);
+    /// ```
+    pub fn program_end(end_address: u16) -> Self {
+        EndConditions {
+            brk: true,
+            end_address: Some(end_address),
+            ..EndConditions::default()
+        }
+    }
+}
+
+/// Executes the program from the program counter until one of `end` is met, like `try_run_until`.
+///
+/// Frontends that run a program indefinitely, or in time with a clock, should use `CPU::step` or
+/// `CPU::run_for_cycles` instead.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` whose registers, flags and memory are updated.
+/// - `end`: When the run ends, usually `EndConditions::program_end`.
+///
+/// # Returns
+/// - `Some(breakpoint)`: If execution stopped on one of `cpu.breakpoints`. The program counter is
+///   left on the instruction the breakpoint is set on, and calling `run_until` again resumes from
+///   there without stopping on the same breakpoint first. After a flag breakpoint, the breakpoint
+///   returned is at the instruction following the one that changed the flag, with the flag's new
+///   value as its condition. After a watchpoint, it is at the instruction following the one that
+///   accessed the watched address.
+/// - `None`: If the run reached one of `end`.
+///
+/// When `cpu.trace` or `cpu.compact_trace` is set, every instruction is traced to it before it
+/// runs (see `write_trace`).
+///
+/// # Panics
+/// - If a byte that is not a documented opcode is executed, an instruction accesses one of
+///   `cpu.guards`, the program runs off the end of memory or into a device's registers, or it
+///   reaches one of `cpu.limits`. Use `try_run_until` to get a `StopReason` instead, or set
+///   `cpu.panic_free` to have the reason kept in `cpu.fault` and `None` returned.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// Assembler::new()
+///     .assemble("LDX #$02\nINX", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert_eq!(cpu.x, 3);
+/// ```
+pub fn run_until(cpu: &mut CPU, end: &EndConditions) -> Option<Breakpoint> {
+    let reason = try_run_until(cpu, end);
+    stopped_at(cpu, reason)
+}
+
+/// Executes the program like `run_until`, until `data_cycle_count` program bytes were fetched.
+///
+/// Every byte fetched from the instruction stream (opcodes and operands) decrements the counter,
+/// which ends loops early and a program that jumps over its last bytes late.
+///
+/// # Panics
+/// - Where `run_until` does.
+#[deprecated(note = "counting program bytes ends loops early; use `run_until` instead")]
+pub fn run_memory(cpu: &mut CPU, data_cycle_count: &mut u32) -> Option<Breakpoint> {
+    let reason = run(cpu, Some(data_cycle_count), &EndConditions::default());
+    stopped_at(cpu, reason)
+}
+
+/// Turns why a run stopped into what `run_until` returns, keeping a fault in `cpu.fault` when
+/// `cpu.panic_free` is set and panicking on it otherwise.
+fn stopped_at(cpu: &mut CPU, reason: StopReason) -> Option<Breakpoint> {
+    if cpu.panic_free && reason.is_fault() {
+        cpu.fault = Some(reason);
+        return None;
+    }
+    match reason {
+        StopReason::Finished
+        | StopReason::Brk { .. }
+        | StopReason::Trapped { .. }
+        | StopReason::EndAddress { .. }
+        | StopReason::BudgetSpent => None,
+        StopReason::Breakpoint(breakpoint) => Some(breakpoint),
+        StopReason::Watchpoint { .. } => Some(Breakpoint {
+            address: cpu.pc,
+            bank: None,
+            condition: None,
+        }),
+        StopReason::FlagChanged { breakpoint, .. } => Some(Breakpoint {
+            address: cpu.pc,
+            bank: None,
+            condition: Some(Condition::Flag(
+                breakpoint.flag,
+                cpu.flag(breakpoint.flag.mask()),
+            )),
+        }),
+        StopReason::IllegalOpcode { address, opcode } => {
+            panic!("Unknown opcode {:02X} at {:04X}", opcode, address)
+        }
+        StopReason::RanOffEnd { pc } => panic!("Instruction at {:04X} runs past $FFFF", pc),
+        StopReason::DeviceFetch { pc } => panic!("Executing device registers at {:04X}", pc),
+        StopReason::LimitExceeded(limit) => panic!("Program reached its {} limit", limit),
+        StopReason::GuardHit { pc, address, write } => panic!(
+            "Guard region {} at {:04X} by the instruction at {:04X}",
+            if write { "written" } else { "read" },
+            address,
+            pc
+        ),
+    }
+}
+
+/// Executes the program like `run_memory`, but never panics.
+///
+/// # Example
+/// ```rust
+/// # #![allow(deprecated)]
+/// use r_6502::asm_runner::{try_run_memory, StopReason};
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..2].copy_from_slice(&[0xE8, 0xFF]);
+/// let mut data_cycle_count = 2;
+/// let reason = try_run_memory(&mut cpu, &mut data_cycle_count);
+/// assert_eq!(reason, StopReason::IllegalOpcode { address: 1, opcode: 0xFF });
+/// assert_eq!(cpu.pc, 1);
+/// ```
+#[deprecated(note = "counting program bytes ends loops early; use `try_run_until` instead")]
+pub fn try_run_memory(cpu: &mut CPU, data_cycle_count: &mut u32) -> StopReason {
+    run(cpu, Some(data_cycle_count), &EndConditions::default())
+}
+
+/// Executes the program from the program counter until one of `end` is met, but never panics.
+///
+/// This is the entry point for hosts that must not abort, such as GUIs or WebAssembly builds: any
+/// problem with the program being run is reported as a `StopReason`, with the CPU left in the state
+/// it was in before the offending instruction.
+///
+/// The run goes on until the program itself ends, however it is written: with `end.brk` at a
+/// `BRK` (which is also what running off the end of a program into cleared memory reaches), with
+/// `end.trap` at a `JMP *`, at `end.end_address`, or once a budget is spent. It also stops at a
+/// problem or one of `cpu.breakpoints`, so with no conditions set it only ends at one of those.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// Assembler::new()
+///     .assemble("LDX #$00\nloop:\nINX\nCPX #$10\nBNE loop\nBRK", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// let end = EndConditions { brk: true, ..EndConditions::default() };
+/// assert_eq!(try_run_until(&mut cpu, &end), StopReason::Brk { pc: 0x0007 });
+/// assert_eq!(cpu.x, 0x10);
+///
+/// cpu.pc = 0;
+/// let end = EndConditions { end_address: Some(0x0005), ..end };
+/// assert_eq!(try_run_until(&mut cpu, &end), StopReason::EndAddress { pc: 0x0005 });
+/// assert_eq!(cpu.x, 0x01);
+///
+/// let end = EndConditions { cycle_budget: Some(100), ..EndConditions::default() };
+/// let start = cpu.cycles;
+/// assert_eq!(try_run_until(&mut cpu, &end), StopReason::BudgetSpent);
+/// assert!((100..107).contains(&(cpu.cycles - start)));
+///
+/// cpu.pc = 0;
+/// cpu.memory.data[0] = 0xFF;
+/// let reason = try_run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert_eq!(reason, StopReason::IllegalOpcode { address: 0, opcode: 0xFF });
+/// ```
+pub fn try_run_until(cpu: &mut CPU, end: &EndConditions) -> StopReason {
+    run(cpu, None, end)
+}
+
+/// Runs instructions for `try_run_until` and the deprecated byte-counting runs, until
+/// `data_cycle_count` (if any) reaches zero, one of `end` is met, or the program stops for another
+/// reason.
+///
+/// A `cpu.panic_free` CPU with a `cpu.fault` does not run, and stops at the fault again.
+fn run(cpu: &mut CPU, mut data_cycle_count: Option<&mut u32>, end: &EndConditions) -> StopReason {
+    if cpu.panic_free {
+        if let Some(fault) = cpu.fault {
+            return fault;
+        }
+    }
+    let start_cycles = cpu.cycles;
+    let start_instructions = cpu.instructions;
+    let mut first = true;
+    while data_cycle_count.as_deref() != Some(&0) {
+        if !first {
+            if let Some(breakpoint) = cpu.breakpoints.hit(cpu) {
+                return StopReason::Breakpoint(breakpoint);
+            }
+        }
+        first = false;
+        let pc = cpu.pc;
+        if let Some(reason) = end_reached(cpu, end, start_cycles, start_instructions) {
+            return reason;
+        }
+        write_trace(cpu);
+        let status = cpu.p;
+        // Only accesses made by this instruction count, not those of a debugger or a trace
+        cpu.memory.watchpoints.take_hit();
+        let mut unlimited = u32::MAX;
+        let count = match data_cycle_count.as_deref_mut() {
+            Some(count) => count,
+            None => &mut unlimited,
+        };
+        if let Err(reason) = try_execute_instruction(cpu, count) {
+            return reason;
+        }
+        if let Some(access) = cpu.guards.take_access() {
+            let reason = StopReason::GuardHit {
+                pc,
+                address: access.address,
+                write: access.write,
+            };
+            log_trap(cpu, reason);
+            return reason;
+        }
+        if let Some(hit) = cpu.memory.watchpoints.take_hit() {
+            return StopReason::Watchpoint {
+                pc,
+                address: hit.address,
+                value: hit.value,
+                write: hit.write,
+            };
+        }
+        if let Some(breakpoint) = cpu.breakpoints.flag_hit(status, cpu.p) {
+            return StopReason::FlagChanged { pc, breakpoint };
+        }
+        if end.trap && cpu.pc == pc {
+            log_trap(cpu, StopReason::Trapped { pc });
+            return StopReason::Trapped { pc };
+        }
+    }
+    StopReason::Finished
+}
+
+/// Checks the end conditions that apply before the instruction at the program counter runs.
+fn end_reached(
+    cpu: &CPU,
+    end: &EndConditions,
+    start_cycles: u64,
+    start_instructions: u64,
+) -> Option<StopReason> {
+    let pc = cpu.pc;
+    let spent = |budget: Option<u64>, used: u64| budget.is_some_and(|budget| used >= budget);
+    if end.end_address == Some(pc) {
+        Some(StopReason::EndAddress { pc })
+    } else if end.brk && cpu.memory.data[pc as usize] == Token::BRK as u8 {
+        Some(StopReason::Brk { pc })
+    } else if spent(end.cycle_budget, cpu.cycles - start_cycles)
+        || spent(
+            end.instruction_budget,
+            cpu.instructions - start_instructions,
+        )
+    {
+        Some(StopReason::BudgetSpent)
+    } else {
+        None
+    }
+}
+
+/// Executes one instruction like `execute_instruction`, but never panics.
+///
+/// # Errors
+/// Nothing is executed and the CPU is left unchanged when an error is returned.
+/// - `StopReason::LimitExceeded`: If the program has reached one of `cpu.limits`.
+/// - `StopReason::DeviceFetch`: If the program counter is on an attached device's registers.
+/// - `StopReason::IllegalOpcode`: If the byte at the program counter is not a documented opcode,
+///   nor an undocumented one the CPU is configured to execute (see `cpu.illegal_opcodes` and
+///   `cpu.unknown_opcode`) or `cpu.opcode_handler` handles.
+/// - `StopReason::RanOffEnd`: If the instruction's operand runs past `$FFFF` and
+///   `cpu.end_of_memory` is `EndOfMemory::Stop`.
+pub fn try_execute_instruction(
+    cpu: &mut CPU,
+    data_cycle_count: &mut u32,
+) -> Result<(), StopReason> {
+    if let Err(reason) = check_instruction(cpu) {
+        log_trap(cpu, reason);
+        return Err(reason);
+    }
+    execute(cpu, data_cycle_count);
+    Ok(())
+}
+
+/// Adds a run stopping at `reason` to `cpu.events`, as a trap.
+fn log_trap(cpu: &mut CPU, reason: StopReason) {
+    let (pc, detail) = match reason {
+        StopReason::IllegalOpcode { address, opcode } => {
+            (address, format!("unknown opcode ${:02X}", opcode))
+        }
+        StopReason::GuardHit { pc, address, write } => (
+            pc,
+            format!(
+                "guard region {} at ${:04X}",
+                if write { "written" } else { "read" },
+                address
+            ),
+        ),
+        StopReason::RanOffEnd { pc } => (pc, String::from("ran past $FFFF")),
+        StopReason::DeviceFetch { pc } => (pc, String::from("executed device registers")),
+        StopReason::LimitExceeded(limit) => (cpu.pc, format!("reached the {} limit", limit)),
+        StopReason::Trapped { pc } => (pc, String::from("jumped to itself")),
+        _ => return,
+    };
+    cpu.log_event(pc, EventKind::Trap, || detail);
+}
+
+/// Checks that the instruction at the program counter can be executed, for
+/// `try_execute_instruction`.
+fn check_instruction(cpu: &CPU) -> Result<(), StopReason> {
+    if let Some(limit) = cpu.limits.check(cpu) {
+        return Err(StopReason::LimitExceeded(limit));
+    }
+    let pc = cpu.pc;
+    if cpu.memory.is_device(pc) {
+        return Err(StopReason::DeviceFetch { pc });
+    }
+    let opcode = cpu.memory.data[pc as usize];
+    if Token::from_opcode(opcode).is_none() && !executes_undocumented(cpu, opcode) {
+        return Err(StopReason::IllegalOpcode {
+            address: pc,
+            opcode,
+        });
+    }
+    if cpu.end_of_memory == EndOfMemory::Stop
+        && pc as u32 + instruction_length(cpu, opcode) as u32 > 0x10000
+    {
+        return Err(StopReason::RanOffEnd { pc });
+    }
+    Ok(())
+}
+
+/// Returns the number of bytes, opcode included, the instruction an opcode starts occupies.
+fn instruction_length(cpu: &CPU, opcode: u8) -> u16 {
+    match opcode_info(opcode) {
+        Some(info) => info.length,
+        None => match decode_undocumented(opcode) {
+            Some((_, mode, _)) if cpu.illegal_opcodes => 1 + mode.operand_size(),
+            _ => 1, // Skipped as a one-byte NOP, or the opcode of a handled instruction
+        },
+    }
+}
+
+/// Runs programs on a `CPU` until the end conditions it was created with.
+///
+/// This wraps `run_until` and `execute_instruction` for embedders that would rather hold on to
+/// where the program ends, and whether it got there, than pass it around.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::EndConditions;
+/// use r_6502::{Assembler, Runner, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// Assembler::new()
+///     .assemble("LDA #$10\nADC #$20", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// let mut runner = Runner::until(EndConditions::program_end(end_address));
+/// runner.step(&mut cpu);
+/// assert!(!runner.is_finished());
+/// runner.run(&mut cpu);
+/// assert_eq!(cpu.a, 0x30);
+/// assert!(runner.is_finished());
+/// ```
+pub struct Runner {
+    pub data_cycle_count: u32, // Program bytes left to execute, for a runner created with `new`
+    pub end: Option<EndConditions>, // Where runs end, for a runner created with `until`
+    finished: bool,            // Whether the last run or step reached one of `end`
+}
+
+impl Runner {
+    /// Creates a runner that stops once `data_cycle_count` program bytes were fetched.
+    #[deprecated(note = "counting program bytes ends loops early; use `Runner::until` instead")]
+    pub fn new(data_cycle_count: u32) -> Self {
+        Runner {
+            data_cycle_count,
+            end: None,
+            finished: false,
+        }
+    }
+
+    /// Creates a runner whose runs end at one of `end`, usually `EndConditions::program_end`.
+    pub fn until(end: EndConditions) -> Self {
+        Runner {
+            data_cycle_count: 0,
+            end: Some(end),
+            finished: false,
+        }
+    }
+
+    /// Executes instructions until the program ends or a breakpoint is hit, see `run_until`.
+    pub fn run(&mut self, cpu: &mut CPU) -> Option<Breakpoint> {
+        let reason = self.try_run(cpu);
+        stopped_at(cpu, reason)
+    }
+
+    /// Executes instructions like `run`, reporting problems as a `StopReason` instead of panicking.
+    pub fn try_run(&mut self, cpu: &mut CPU) -> StopReason {
+        let reason = match &self.end {
+            Some(end) => try_run_until(cpu, end),
+            None => run(
+                cpu,
+                Some(&mut self.data_cycle_count),
+                &EndConditions::default(),
+            ),
+        };
+        self.finished = reason.is_end();
+        reason
+    }
+
+    /// Executes a single instruction, see `execute_instruction`.
+    ///
+    /// A step spends none of the end conditions' budgets, but can reach their end address, a
+    /// `BRK` or a trap.
+    pub fn step(&mut self, cpu: &mut CPU) {
+        match &self.end {
+            Some(end) => {
+                let pc = cpu.pc;
+                let mut unlimited = u32::MAX;
+                execute_instruction(cpu, &mut unlimited);
+                self.finished = (end.trap && cpu.pc == pc)
+                    || end_reached(cpu, end, cpu.cycles, cpu.instructions).is_some();
+            }
+            None => execute_instruction(cpu, &mut self.data_cycle_count),
+        }
+    }
+
+    /// Returns whether the program has ended: for a runner created with `until`, whether the
+    /// last run or step reached one of its end conditions, and otherwise whether every byte of
+    /// the program has been executed.
+    pub fn is_finished(&self) -> bool {
+        match self.end {
+            Some(_) => self.finished,
+            None => self.data_cycle_count == 0,
+        }
+    }
+}
+
+/// Fetches, decodes and executes the instruction at the program counter.
+///
+/// The opcode byte is looked up in the `opcode_table` and executed through the function its entry
+/// names, in the entry's addressing mode. The instruction's cycle count, including any penalty
+/// for taken branches or for indexed reads crossing a page boundary, is added to `cpu.cycles`.
+///
+/// Markers on the instruction are logged before it runs, and writes it makes to its own bytes
+/// are recorded in `cpu.self_writes`. When `cpu.taint` is set, its marks are updated for the
+/// instruction first, and when `cpu.mmu` is set, writes the instruction made to the MMU registers
+/// are applied once it has run. When `cpu.cosim` is set, the model is told about the instruction
+/// last, and can raise an interrupt.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` executing the instruction.
+/// - `data_cycle_count`: A mutable reference to the number of program bytes left to execute.
+///
+/// Undocumented opcodes are executed according to `cpu.illegal_opcodes`, `cpu.opcode_handler` and
+/// `cpu.unknown_opcode`, see `execute_undocumented`.
+///
+/// When `cpu.panic_free` is set, the instruction is executed through `try_execute_instruction`
+/// instead: a problem it reports is kept in `cpu.fault`, and nothing more is executed until that
+/// is cleared.
+///
+/// # Panics
+/// - If the byte at the program counter is not a documented opcode, and the CPU is not configured
+///   to execute it, unless `cpu.panic_free` is set.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::execute_instruction;
+/// use r_6502::token::Token;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0] = Token::INX as u8;
+/// let mut data_cycle_count = 1;
+/// execute_instruction(&mut cpu, &mut data_cycle_count);
+/// assert_eq!(cpu.x, 1);
+/// ```
+pub fn execute_instruction(cpu: &mut CPU, data_cycle_count: &mut u32) {
+    if cpu.panic_free {
+        if cpu.fault.is_none() {
+            if let Err(reason) = try_execute_instruction(cpu, data_cycle_count) {
+                cpu.fault = Some(reason);
+            }
+        }
+        return;
+    }
+    execute(cpu, data_cycle_count);
+}
+
+/// Executes the instruction at the program counter, once `try_execute_instruction` or the caller
+/// of `execute_instruction` has made sure it can be.
+fn execute(cpu: &mut CPU, data_cycle_count: &mut u32) {
+    cpu.memory.record_device_writes(cpu.events.is_some());
+    if let Some(events) = cpu.events.as_mut() {
+        for name in cpu.markers.names_at(cpu.pc) {
+            events.record(cpu.cycles, cpu.pc, EventKind::Marker, name.clone());
+        }
+    }
+    if let Some(mut taint) = cpu.taint.take() {
+        taint.propagate(cpu);
+        cpu.taint = Some(taint);
+    }
+    let opcode_address = cpu.pc;
+    let opcode = cpu.fetch_address_value(data_cycle_count);
+    let length = instruction_length(cpu, opcode);
+    cpu.self_writes.begin(opcode_address, length);
+    let cycles = match opcode_info(opcode) {
+        Some(info) => info.cycles + (info.execute)(cpu, info.mode, data_cycle_count),
+        None => execute_undocumented(cpu, opcode, opcode_address, data_cycle_count),
+    };
+    cpu.self_writes.end();
+    finish_instruction(cpu, opcode_address, opcode, cycles);
+}
+
+/// Accounts for an executed instruction's cycles, clocking the devices and taking an IRQ they
+/// raise, applies the writes it made to the MMU and the continuous patches, reports it to the
+/// co-simulation model, and lets `cpu.clock` hold execution back to its speed.
+fn finish_instruction(cpu: &mut CPU, pc: u16, opcode: u8, cycles: u64) {
+    cpu.cycles += cycles;
+    cpu.instructions += 1;
+    for write in cpu.memory.take_device_writes() {
+        cpu.log_event(pc, EventKind::DeviceWrite, || {
+            format!(
+                "{} ${:04X} = ${:02X}",
+                write.name, write.address, write.value
+            )
+        });
+    }
+    cpu.memory.tick(cycles);
+    if cpu.memory.irq() {
+        let start = cpu.cycles;
+        cpu.trigger_irq();
+        cpu.memory.tick(cpu.cycles - start);
+    }
+    if let Some(mmu) = cpu.mmu.as_mut() {
+        let before = mmu.mapping();
+        mmu.sync(&mut cpu.memory);
+        let after = mmu.mapping();
+        for page in (0..before.len()).filter(|page| before[*page] != after[*page]) {
+            cpu.log_event(pc, EventKind::BankSwitch, || match after[page] {
+                Some(bank) => format!("page ${:X} -> bank {}", page, bank),
+                None => format!("page ${:X} -> RAM", page),
+            });
+        }
+    }
+    cpu.patches.apply_always(&mut cpu.memory);
+    if let Some(mut model) = cpu.cosim.take() {
+        let signals = model.retire(&Retirement {
+            pc,
+            opcode,
+            cycles,
+            total_cycles: cpu.cycles,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.p,
+            sp: cpu.sp as u8,
+        });
+        cpu.cosim = Some(model);
+        let start = cpu.cycles;
+        if signals.nmi {
+            cpu.trigger_nmi();
+        } else if signals.irq {
+            cpu.trigger_irq();
+        }
+        cpu.memory.tick(cpu.cycles - start);
+    }
+    if let Some(clock) = cpu.clock.as_mut() {
+        clock.tick(cpu.cycles);
+    }
+    #[cfg(feature = "metrics")]
+    if cpu.instructions.is_multiple_of(PUBLISH_INTERVAL) {
+        if let Some(metrics) = &cpu.metrics {
+            metrics.publish(cpu);
+        }
+    }
+}
+
+/// The stable undocumented NMOS instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Undocumented {
+    Slo, // ASL the operand, then ORA it
+    Rla, // ROL the operand, then AND it
+    Sre, // LSR the operand, then EOR it
+    Rra, // ROR the operand, then ADC it
+    Sax, // Store A AND X
+    Lax, // Load A and X
+    Dcp, // DEC the operand, then CMP it
+    Isc, // INC the operand, then SBC it
+    Anc, // AND, then copy N into C
+    Alr, // AND, then LSR A
+    Arr, // AND, then ROR A with odd C and V results
+    Sbx, // X = (A AND X) - operand, with flags like CMP
+    Sbc, // Same as the documented immediate SBC
+    Nop, // Reads its operand, if any, and does nothing
+}
+
+impl Undocumented {
+    /// Returns the instruction's usual mnemonic.
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Undocumented::Slo => "SLO",
+            Undocumented::Rla => "RLA",
+            Undocumented::Sre => "SRE",
+            Undocumented::Rra => "RRA",
+            Undocumented::Sax => "SAX",
+            Undocumented::Lax => "LAX",
+            Undocumented::Dcp => "DCP",
+            Undocumented::Isc => "ISC",
+            Undocumented::Anc => "ANC",
+            Undocumented::Alr => "ALR",
+            Undocumented::Arr => "ARR",
+            Undocumented::Sbx => "SBX",
+            Undocumented::Sbc => "SBC",
+            Undocumented::Nop => "NOP",
+        }
+    }
+}
+
+/// Decodes a stable undocumented opcode.
+///
+/// # Returns
+/// The instruction, its addressing mode and its base cycle count, or `None` for opcodes that are
+/// documented, unstable (`ANE`, `SHA`, ...) or halt the CPU (`JAM`).
+fn decode_undocumented(opcode: u8) -> Option<(Undocumented, AddressingMode, u64)> {
+    use AddressingMode::*;
+    let read_modify_write = [
+        (0x00, Undocumented::Slo),
+        (0x20, Undocumented::Rla),
+        (0x40, Undocumented::Sre),
+        (0x60, Undocumented::Rra),
+        (0xC0, Undocumented::Dcp),
+        (0xE0, Undocumented::Isc),
+    ];
+    for (base, instruction) in read_modify_write {
+        let decoded = match opcode.wrapping_sub(base) {
+            0x03 => (IndexedIndirect, 8),
+            0x07 => (ZeroPage, 5),
+            0x0F => (Absolute, 6),
+            0x13 => (IndirectIndexed, 8),
+            0x17 => (ZeroPageX, 6),
+            0x1B => (AbsoluteY, 7),
+            0x1F => (AbsoluteX, 7),
+            _ => continue,
+        };
+        return Some((instruction, decoded.0, decoded.1));
+    }
+    let decoded = match opcode {
+        0x83 => (Undocumented::Sax, IndexedIndirect, 6),
+        0x87 => (Undocumented::Sax, ZeroPage, 3),
+        0x8F => (Undocumented::Sax, Absolute, 4),
+        0x97 => (Undocumented::Sax, ZeroPageY, 4),
+        0xA3 => (Undocumented::Lax, IndexedIndirect, 6),
+        0xA7 => (Undocumented::Lax, ZeroPage, 3),
+        0xAF => (Undocumented::Lax, Absolute, 4),
+        0xB3 => (Undocumented::Lax, IndirectIndexed, 5),
+        0xB7 => (Undocumented::Lax, ZeroPageY, 4),
+        0xBF => (Undocumented::Lax, AbsoluteY, 4),
+        0x0B | 0x2B => (Undocumented::Anc, Immediate, 2),
+        0x4B => (Undocumented::Alr, Immediate, 2),
+        0x6B => (Undocumented::Arr, Immediate, 2),
+        0xCB => (Undocumented::Sbx, Immediate, 2),
+        0xEB => (Undocumented::Sbc, Immediate, 2),
+        0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => (Undocumented::Nop, Implied, 2),
+        0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => (Undocumented::Nop, Immediate, 2),
+        0x04 | 0x44 | 0x64 => (Undocumented::Nop, ZeroPage, 3),
+        0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => (Undocumented::Nop, ZeroPageX, 4),
+        0x0C => (Undocumented::Nop, Absolute, 4),
+        0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => (Undocumented::Nop, AbsoluteX, 4),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/// Returns the addressing mode of a stable undocumented opcode, or `None` for opcodes that are
+/// documented, unstable or halt the CPU.
+pub(crate) fn undocumented_mode(opcode: u8) -> Option<AddressingMode> {
+    decode_undocumented(opcode).map(|(_, mode, _)| mode)
+}
+
+/// Returns the mnemonic of a stable undocumented opcode, or `None` for opcodes that are
+/// documented, unstable or halt the CPU.
+pub(crate) fn undocumented_mnemonic(opcode: u8) -> Option<&'static str> {
+    decode_undocumented(opcode).map(|(instruction, _, _)| instruction.mnemonic())
+}
+
+/// Checks whether the CPU is configured to execute an undocumented opcode.
+fn executes_undocumented(cpu: &CPU, opcode: u8) -> bool {
+    cpu.unknown_opcode == UnknownOpcode::Nop
+        || (cpu.illegal_opcodes && decode_undocumented(opcode).is_some())
+        || cpu
+            .opcode_handler
+            .as_ref()
+            .is_some_and(|handler| handler.handles(opcode))
+}
+
+/// Executes an undocumented opcode, whose byte has already been fetched.
+///
+/// With `cpu.illegal_opcodes` set, the stable undocumented NMOS instructions run as they do on
+/// the real chip: the combined read-modify-write instructions (`SLO`, `RLA`, `SRE`, `RRA`, `DCP`,
+/// `ISC`), `LAX`, `SAX`, the immediate `ANC`, `ALR`, `ARR`, `SBX` and `SBC`, and the multi-byte
+/// `NOP`s. `ARR` is only implemented for binary mode. Anything else, including the unstable
+/// opcodes and the `JAM`s that halt the chip, goes to `cpu.opcode_handler` if it handles it, and
+/// is handled as `cpu.unknown_opcode` says otherwise.
+///
+/// # Returns
+/// The number of cycles the instruction took.
+///
+/// # Panics
+/// - If the opcode is not executed, nor handled, and `cpu.unknown_opcode` is `UnknownOpcode::Trap`.
+fn execute_undocumented(
+    cpu: &mut CPU,
+    opcode: u8,
+    opcode_address: u16,
+    data_cycle_count: &mut u32,
+) -> u64 {
+    let (instruction, mode, cycles) = match decode_undocumented(opcode) {
+        Some(decoded) if cpu.illegal_opcodes => decoded,
+        _ => {
+            if let Some(mut handler) = cpu.opcode_handler.take() {
+                let cycles = if handler.handles(opcode) {
+                    Some(handler.execute(cpu, opcode, data_cycle_count))
+                } else {
+                    None
+                };
+                cpu.opcode_handler = Some(handler);
+                if let Some(cycles) = cycles {
+                    return cycles;
+                }
+            }
+            match cpu.unknown_opcode {
+                UnknownOpcode::Nop => return 2,
+                UnknownOpcode::Trap => {
+                    panic!("Unknown opcode {:02X} at {:04X}", opcode, opcode_address)
+                }
+            }
+        }
+    };
+    match instruction {
+        Undocumented::Slo => modify_operand(cpu, mode, data_cycle_count, slo),
+        Undocumented::Rla => modify_operand(cpu, mode, data_cycle_count, rla),
+        Undocumented::Sre => modify_operand(cpu, mode, data_cycle_count, sre),
+        Undocumented::Rra => modify_operand(cpu, mode, data_cycle_count, rra),
+        Undocumented::Dcp => modify_operand(cpu, mode, data_cycle_count, dcp),
+        Undocumented::Isc => modify_operand(cpu, mode, data_cycle_count, isc),
+        Undocumented::Sax => {
+            let (address, _) = operand_address(cpu, mode, data_cycle_count);
+            write_byte(cpu, address, cpu.a & cpu.x);
+        }
+        Undocumented::Lax => {
+            let value = read_operand(cpu, mode, data_cycle_count);
+            cpu.a = transfer(cpu, value);
+            cpu.x = value;
+        }
+        Undocumented::Anc => {
+            let value = read_operand(cpu, mode, data_cycle_count);
+            cpu.a = transfer(cpu, cpu.a & value);
+            cpu.set_flag(CARRY, cpu.flag(NEGATIVE));
+        }
+        Undocumented::Alr => {
+            let value = read_operand(cpu, mode, data_cycle_count);
+            cpu.a = shift_right(cpu, cpu.a & value);
+        }
+        Undocumented::Arr => {
+            let value = read_operand(cpu, mode, data_cycle_count);
+            let result = ((cpu.a & value) >> 1) | ((cpu.flag(CARRY) as u8) << 7);
+            cpu.a = transfer(cpu, result);
+            cpu.set_flag(CARRY, result & 0x40 != 0);
+            cpu.set_flag(OVERFLOW, ((result >> 6) ^ (result >> 5)) & 1 != 0);
+        }
+        Undocumented::Sbx => {
+            let value = read_operand(cpu, mode, data_cycle_count);
+            let masked = cpu.a & cpu.x;
+            compare(cpu, masked, value);
+            cpu.x = masked.wrapping_sub(value);
+        }
+        Undocumented::Sbc => {
+            sbc(cpu, mode, data_cycle_count);
+        }
+        Undocumented::Nop => {
+            if mode != AddressingMode::Implied {
+                read_operand(cpu, mode, data_cycle_count);
+            }
+        }
+    }
+    cycles
+}
+
+/// Shifts a value left, then ORs it into the accumulator (`SLO`).
+fn slo(cpu: &mut CPU, value: u8) -> u8 {
+    let result = shift_left(cpu, value);
+    cpu.a = transfer(cpu, cpu.a | result);
+    result
+}
+
+/// Rotates a value left, then ANDs it into the accumulator (`RLA`).
+fn rla(cpu: &mut CPU, value: u8) -> u8 {
+    let result = rotate_left(cpu, value);
+    cpu.a = transfer(cpu, cpu.a & result);
+    result
+}
+
+/// Shifts a value right, then exclusive-ORs it into the accumulator (`SRE`).
+fn sre(cpu: &mut CPU, value: u8) -> u8 {
+    let result = shift_right(cpu, value);
+    cpu.a = transfer(cpu, cpu.a ^ result);
+    result
+}
+
+/// Rotates a value right, then adds it to the accumulator with the carry it rotated out (`RRA`).
+fn rra(cpu: &mut CPU, value: u8) -> u8 {
+    let result = rotate_right(cpu, value);
+    if cpu.flag(DECIMAL) {
+        decimal_add(cpu, result);
+    } else {
+        add_with_carry(cpu, result);
+    }
+    result
+}
+
+/// Decrements a value, then compares the accumulator with it (`DCP`).
+fn dcp(cpu: &mut CPU, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    compare(cpu, cpu.a, result);
+    result
+}
+
+/// Increments a value, then subtracts it from the accumulator (`ISC`).
+fn isc(cpu: &mut CPU, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    if cpu.flag(DECIMAL) {
+        decimal_subtract(cpu, result);
+    } else {
+        add_with_carry(cpu, !result);
+    }
+    result
+}
+
+/// Reads a byte from memory.
+fn read_byte(cpu: &mut CPU, address: u16) -> u8 {
+    cpu.guards.check(address, false);
+    cpu.memory.read(address)
+}
+
+/// Writes a byte to memory.
+fn write_byte(cpu: &mut CPU, address: u16, value: u8) {
+    cpu.guards.check(address, true);
+    cpu.self_writes.check(address, value);
+    cpu.memory.write(address, value);
+}
+
+/// Fetches a little-endian 16-bit word from the instruction stream.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` whose program counter is advanced by two.
+/// - `data_cycle_count`: A mutable reference to the number of program bytes left to execute.
+///
+/// # Returns
+/// The word formed by the low byte at the program counter and the high byte after it.
+fn fetch_word(cpu: &mut CPU, data_cycle_count: &mut u32) -> u16 {
+    let l_byte = cpu.fetch_address_value(data_cycle_count) as u16;
+    let h_byte = cpu.fetch_address_value(data_cycle_count) as u16;
+    (h_byte << 8) | l_byte
+}
+
+/// Reads a little-endian 16-bit word the way `JMP ($xxxx)` does on an NMOS 6502.
+///
+/// The high byte is read from the same page as the low byte, so a pointer at `$xxFF` takes its high
+/// byte from `$xx00` rather than from the next page. Zero-page pointers used by `($10,X)` and
+/// `($10),Y` wrap around the zero page in the same way.
+///
+/// # Parameters
+/// - `cpu`: A reference to the `CPU` whose memory is read.
+/// - `pointer`: The address of the low byte of the word.
+///
+/// # Returns
+/// The word stored at `pointer`, with the high byte fetched within the same page.
+fn read_word_page_wrapped(cpu: &mut CPU, pointer: u16) -> u16 {
+    let h_pointer = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+    let l_byte = read_byte(cpu, pointer) as u16;
+    let h_byte = read_byte(cpu, h_pointer) as u16;
+    (h_byte << 8) | l_byte
+}
+
+/// Resolves the memory address an instruction operates on.
+///
+/// For immediate operands this is the address of the operand byte itself, which lets every
+/// addressing mode read its value the same way. Zero-page indexed addresses and zero-page pointers
+/// wrap around within the zero page, as they do on hardware.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` whose program counter is advanced past the operand.
+/// - `mode`: The `AddressingMode` of the instruction being executed.
+/// - `data_cycle_count`: A mutable reference to the number of program bytes left to execute.
+///
+/// # Returns
+/// A tuple of the effective address of the operand and whether adding an index register to an
+/// absolute address crossed into a different page.
+///
+/// # Panics
+/// - If called for a mode without a memory operand (`Implied`, `Accumulator`, `Relative`).
+fn operand_address(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> (u16, bool) {
+    match mode {
+        AddressingMode::Immediate => {
+            let address = cpu.pc;
+            cpu.fetch_address_value(data_cycle_count);
+            (address, false)
+        }
+        AddressingMode::ZeroPage => (cpu.fetch_address_value(data_cycle_count) as u16, false),
+        AddressingMode::ZeroPageX => {
+            let base = cpu.fetch_address_value(data_cycle_count);
+            (base.wrapping_add(cpu.x) as u16, false)
+        }
+        AddressingMode::ZeroPageY => {
+            let base = cpu.fetch_address_value(data_cycle_count);
+            (base.wrapping_add(cpu.y) as u16, false)
+        }
+        AddressingMode::Absolute => (fetch_word(cpu, data_cycle_count), false),
+        AddressingMode::AbsoluteX => {
+            let base = fetch_word(cpu, data_cycle_count);
+            indexed_address(base, cpu.x)
+        }
+        AddressingMode::AbsoluteY => {
+            let base = fetch_word(cpu, data_cycle_count);
+            indexed_address(base, cpu.y)
+        }
+        AddressingMode::Indirect => {
+            let pointer = fetch_word(cpu, data_cycle_count);
+            (read_word_page_wrapped(cpu, pointer), false)
+        }
+        AddressingMode::IndexedIndirect => {
+            let pointer = cpu
+                .fetch_address_value(data_cycle_count)
+                .wrapping_add(cpu.x);
+            (read_word_page_wrapped(cpu, pointer as u16), false)
+        }
+        AddressingMode::IndirectIndexed => {
+            let pointer = cpu.fetch_address_value(data_cycle_count);
+            let base = read_word_page_wrapped(cpu, pointer as u16);
+            indexed_address(base, cpu.y)
+        }
+        AddressingMode::Implied | AddressingMode::Accumulator | AddressingMode::Relative => {
+            panic!("{:?} addressing has no memory operand", mode)
+        }
+    }
+}
+
+/// Adds an index register to an absolute address, reporting whether a page boundary was crossed.
+fn indexed_address(base: u16, index: u8) -> (u16, bool) {
+    let address = base.wrapping_add(index as u16);
+    (address, address & 0xFF00 != base & 0xFF00)
+}
+
+/// Reads the value an instruction operates on.
+///
+/// Indexed reads that cross a page boundary take one extra cycle, which is added to `cpu.cycles`
+/// here. Stores and read-modify-write instructions always take their fixed cycle count instead.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` executing the instruction.
+/// - `mode`: The `AddressingMode` of the instruction being executed.
+/// - `data_cycle_count`: A mutable reference to the number of program bytes left to execute.
+///
+/// # Returns
+/// The accumulator for `AddressingMode::Accumulator`, otherwise the byte at the operand's address.
+fn read_operand(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u8 {
+    match mode {
+        AddressingMode::Accumulator => cpu.a,
+        _ => {
+            let (address, page_crossed) = operand_address(cpu, mode, data_cycle_count);
+            if page_crossed {
+                cpu.cycles += 1;
+            }
+            read_byte(cpu, address)
+        }
+    }
+}
+
+/// Applies a read-modify-write operation to an instruction's operand.
+///
+/// The operand is read, passed through `operation`, and the result written back to the same place:
+/// the accumulator for `AddressingMode::Accumulator`, otherwise the operand's memory address.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` executing the instruction.
+/// - `mode`: The `AddressingMode` of the instruction being executed.
+/// - `data_cycle_count`: A mutable reference to the number of program bytes left to execute.
+/// - `operation`: The function computing the new value and updating flags (e.g. `shift_left`, `increment`).
+fn modify_operand(
+    cpu: &mut CPU,
+    mode: AddressingMode,
+    data_cycle_count: &mut u32,
+    operation: fn(&mut CPU, u8) -> u8,
+) {
+    match mode {
+        AddressingMode::Accumulator => {
+            cpu.a = operation(cpu, cpu.a);
+        }
+        _ => {
+            let (address, _) = operand_address(cpu, mode, data_cycle_count);
+            let value = read_byte(cpu, address);
+            let result = operation(cpu, value);
+            write_byte(cpu, address, result);
+        }
+    }
+}
+
+/// Loads the operand into the accumulator (`LDA`).
+pub(crate) fn lda(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    cpu.a = read_operand(cpu, mode, data_cycle_count);
+    cpu.update_nz(cpu.a);
+    0
+}
+
+/// Loads the operand into the X register (`LDX`).
+pub(crate) fn ldx(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    cpu.x = read_operand(cpu, mode, data_cycle_count);
+    cpu.update_nz(cpu.x);
+    0
+}
+
+/// Loads the operand into the Y register (`LDY`).
+pub(crate) fn ldy(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    cpu.y = read_operand(cpu, mode, data_cycle_count);
+    cpu.update_nz(cpu.y);
+    0
+}
+
+/// Stores the accumulator at the operand's address (`STA`).
+///
+/// Like `STX` and `STY`, the write goes through `write_byte`, so it reaches any device mapped at
+/// the address. Stores always take their table cycle count: the real chip spends the extra cycle
+/// of an indexed access whether or not the index crosses a page, so there is no penalty to add.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0x0600;
+/// let source = "LDA #$42\nLDX #$FF\nLDY #$10\nSTA $02F0,X\nSTX $80,Y\nSTY $1234\nSTA ($FB),Y";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// cpu.memory.data[0xFB..=0xFC].copy_from_slice(&[0x00, 0x04]); // Pointer to $0400
+/// cpu.pc = 0x0600;
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert_eq!(cpu.memory.data[0x03EF], 0x42); // Across a page, in 5 cycles
+/// assert_eq!(cpu.memory.data[0x0090], 0xFF); // Zero page indexed, in 4
+/// assert_eq!(cpu.memory.data[0x1234], 0x10); // Absolute, in 4
+/// assert_eq!(cpu.memory.data[0x0410], 0x42); // Indirect indexed, in 6
+/// assert_eq!(cpu.cycles, 3 * 2 + 5 + 4 + 4 + 6);
+/// ```
+pub(crate) fn sta(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let (address, _) = operand_address(cpu, mode, data_cycle_count);
+    write_byte(cpu, address, cpu.a);
+    0
+}
+
+/// Stores the X register at the operand's address (`STX`).
+pub(crate) fn stx(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let (address, _) = operand_address(cpu, mode, data_cycle_count);
+    write_byte(cpu, address, cpu.x);
+    0
+}
+
+/// Stores the Y register at the operand's address (`STY`).
+pub(crate) fn sty(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let (address, _) = operand_address(cpu, mode, data_cycle_count);
+    write_byte(cpu, address, cpu.y);
+    0
+}
+
+/// Adds a value and the carry flag to the accumulator, updating C, V, Z and N.
+///
+/// The overflow flag is set when both inputs have the same sign and the result's sign differs,
+/// i.e. when the signed result does not fit in -128..=127.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` whose accumulator and flags are updated.
+/// - `value`: The value added to the accumulator.
+fn add_with_carry(cpu: &mut CPU, value: u8) {
+    let sum: u16 = cpu.a as u16 + value as u16 + cpu.flag(CARRY) as u16;
+    let result = sum as u8;
+    cpu.set_flag(CARRY, sum > 0xFF);
+    cpu.set_flag(OVERFLOW, (cpu.a ^ result) & (value ^ result) & 0x80 != 0);
+    cpu.a = result;
+    cpu.update_nz(cpu.a);
+}
+
+/// Adds the operand and carry to the accumulator (`ADC`), in decimal if the D flag is set.
+pub(crate) fn adc(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let value = read_operand(cpu, mode, data_cycle_count);
+    if cpu.flag(DECIMAL) {
+        decimal_add(cpu, value);
+    } else {
+        add_with_carry(cpu, value);
+    }
+    0
+}
+
+/// Subtracts the operand and the borrow (inverted carry) from the accumulator (`SBC`), in decimal
+/// if the D flag is set.
+///
+/// In binary mode `A - M - (1 - C)` is the same as `A + !M + C`, so this reuses `add_with_carry`.
+pub(crate) fn sbc(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let value = read_operand(cpu, mode, data_cycle_count);
+    if cpu.flag(DECIMAL) {
+        decimal_subtract(cpu, value);
+    } else {
+        add_with_carry(cpu, !value);
+    }
+    0
+}
+
+/// Adds a packed BCD value and the carry to the accumulator, the way the NMOS 6502 does.
+///
+/// Each nibble is corrected by adding 6 when it goes past 9, and C is set when the result goes
+/// past 99. The other flags follow the NMOS quirks: Z comes from the binary sum, while N and V come
+/// from the intermediate result after the low nibble is corrected but before the high nibble is.
+/// Nibbles that are not decimal digits give the same results as the real chip.
+fn decimal_add(cpu: &mut CPU, value: u8) {
+    let carry = cpu.flag(CARRY) as u16;
+    let binary = (cpu.a as u16 + value as u16 + carry) as u8;
+    let mut low = (cpu.a & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+    if low > 0x09 {
+        low += 0x06;
+    }
+    let mut high = (cpu.a >> 4) as u16 + (value >> 4) as u16 + (low > 0x0F) as u16;
+    let intermediate = ((high << 4) as u8) | (low as u8 & 0x0F);
+    cpu.check_z_flag(binary);
+    cpu.check_n_flag(intermediate);
+    cpu.set_flag(
+        OVERFLOW,
+        (cpu.a ^ intermediate) & !(cpu.a ^ value) & 0x80 != 0,
+    );
+    if high > 0x09 {
+        high += 0x06;
+    }
+    cpu.set_flag(CARRY, high > 0x0F);
+    cpu.a = ((high << 4) as u8) | (low as u8 & 0x0F);
+}
+
+/// Subtracts a packed BCD value and the borrow from the accumulator, the way the NMOS 6502 does.
+///
+/// Each nibble is corrected by subtracting 6 when it borrows. Unlike `ADC`, all the flags are
+/// those of the equivalent binary subtraction, so only the accumulator differs from binary mode.
+fn decimal_subtract(cpu: &mut CPU, value: u8) {
+    let borrow = !cpu.flag(CARRY) as i16;
+    let mut low = (cpu.a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+    if low < 0 {
+        low = ((low - 0x06) & 0x0F) - 0x10;
+    }
+    let mut result = (cpu.a & 0xF0) as i16 - (value & 0xF0) as i16 + low;
+    if result < 0 {
+        result -= 0x60;
+    }
+    add_with_carry(cpu, !value); // Sets every flag
+    cpu.a = result as u8;
+}
+
+/// Bitwise ANDs the operand into the accumulator (`AND`).
+pub(crate) fn and(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    cpu.a &= read_operand(cpu, mode, data_cycle_count);
+    cpu.update_nz(cpu.a);
+    0
+}
+
+/// Bitwise ORs the operand into the accumulator (`ORA`).
+pub(crate) fn ora(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    cpu.a |= read_operand(cpu, mode, data_cycle_count);
+    cpu.update_nz(cpu.a);
+    0
+}
+
+/// Bitwise exclusive-ORs the operand into the accumulator (`EOR`).
+pub(crate) fn eor(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    cpu.a ^= read_operand(cpu, mode, data_cycle_count);
+    cpu.update_nz(cpu.a);
+    0
+}
+
+/// Tests accumulator bits against the operand (`BIT`).
+///
+/// Z is set from `A & M`, while N and V are copied from bits 7 and 6 of the operand.
+pub(crate) fn bit(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let value = read_operand(cpu, mode, data_cycle_count);
+    cpu.check_z_flag(cpu.a & value);
+    cpu.check_n_flag(value);
+    cpu.set_flag(OVERFLOW, value & 0x40 != 0);
+    0
+}
+
+/// Compares a register with a value, updating C, Z and N as if `register - value` was computed.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` whose flags are updated.
+/// - `register`: The value of the register being compared.
+/// - `value`: The value it is compared against.
+fn compare(cpu: &mut CPU, register: u8, value: u8) {
+    let result = register.wrapping_sub(value);
+    cpu.set_flag(CARRY, register >= value);
+    cpu.update_nz(result);
+}
+
+/// Compares the accumulator with the operand (`CMP`).
+///
+/// `CPX` and `CPY` compare the index registers the same way. The carry is set when the register
+/// is greater than or equal to the operand as an unsigned number, Z when they are equal, and N is
+/// bit 7 of the difference, so `BCS`/`BCC` branch on unsigned order and `BEQ`/`BNE` on equality.
+pub(crate) fn cmp(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let value = read_operand(cpu, mode, data_cycle_count);
+    compare(cpu, cpu.a, value);
+    0
+}
+
+/// Compares the X register with the operand (`CPX`).
+pub(crate) fn cpx(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let value = read_operand(cpu, mode, data_cycle_count);
+    compare(cpu, cpu.x, value);
+    0
+}
+
+/// Compares the Y register with the operand (`CPY`).
+pub(crate) fn cpy(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let value = read_operand(cpu, mode, data_cycle_count);
+    compare(cpu, cpu.y, value);
+    0
+}
+
+/// Shifts a value left one bit, moving bit 7 into the carry (`ASL`).
+fn shift_left(cpu: &mut CPU, value: u8) -> u8 {
+    let result = value << 1;
+    cpu.set_flag(CARRY, value & 0x80 != 0);
+    cpu.update_nz(result);
+    result
+}
+
+/// Shifts a value right one bit, moving bit 0 into the carry (`LSR`).
+fn shift_right(cpu: &mut CPU, value: u8) -> u8 {
+    let result = value >> 1;
+    cpu.set_flag(CARRY, value & 1 != 0);
+    cpu.update_nz(result);
+    result
+}
+
+/// Rotates a value left one bit through the carry (`ROL`).
+fn rotate_left(cpu: &mut CPU, value: u8) -> u8 {
+    let result = (value << 1) | cpu.flag(CARRY) as u8;
+    cpu.set_flag(CARRY, value & 0x80 != 0);
+    cpu.update_nz(result);
+    result
+}
+
+/// Rotates a value right one bit through the carry (`ROR`).
+fn rotate_right(cpu: &mut CPU, value: u8) -> u8 {
+    let result = (value >> 1) | ((cpu.flag(CARRY) as u8) << 7);
+    cpu.set_flag(CARRY, value & 1 != 0);
+    cpu.update_nz(result);
+    result
+}
+
+/// Increments a value by one, wrapping at `$FF` (`INC`, `INX`, `INY`).
+fn increment(cpu: &mut CPU, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    cpu.update_nz(result);
+    result
+}
+
+/// Decrements a value by one, wrapping at `$00` (`DEC`, `DEX`, `DEY`).
+fn decrement(cpu: &mut CPU, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    cpu.update_nz(result);
+    result
+}
+
+/// Shifts the accumulator or the operand left one bit (`ASL`).
+pub(crate) fn asl(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    modify_operand(cpu, mode, data_cycle_count, shift_left);
+    0
+}
+
+/// Shifts the accumulator or the operand right one bit (`LSR`).
+pub(crate) fn lsr(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    modify_operand(cpu, mode, data_cycle_count, shift_right);
+    0
+}
+
+/// Rotates the accumulator or the operand left through the carry (`ROL`).
+pub(crate) fn rol(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    modify_operand(cpu, mode, data_cycle_count, rotate_left);
+    0
+}
+
+/// Rotates the accumulator or the operand right through the carry (`ROR`).
+pub(crate) fn ror(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    modify_operand(cpu, mode, data_cycle_count, rotate_right);
+    0
+}
+
+/// Increments the operand (`INC`).
+///
+/// `INC` and `DEC` read the byte and write the result back in a fixed 5 (zero page), 6 (zero page,X
+/// and absolute) or 7 (absolute,X) cycles; like the stores, absolute,X never adds a page-crossing
+/// cycle. The result wraps at $FF and $00, and sets Z and N like the register forms `INX`, `INY`,
+/// `DEX` and `DEY`, which take 2 cycles.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::cpu::{NEGATIVE, ZERO};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0x0600;
+/// let source = "LDX #$01\nINC $10\nINC $10,X\nDEC $0300\nDEC $02FF,X";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// cpu.memory.data[0x10] = 0xFF;
+/// cpu.pc = 0x0600;
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert_eq!(cpu.memory.data[0x10], 0x00); // Wrapped, in 5 cycles
+/// assert_eq!(cpu.memory.data[0x11], 0x01); // Zero page indexed, in 6
+/// assert_eq!(cpu.memory.data[0x0300], 0xFE); // Absolute, then absolute,X across a page, 6 and 7
+/// assert!(cpu.flag(NEGATIVE) && !cpu.flag(ZERO));
+/// assert_eq!(cpu.cycles, 2 + 5 + 6 + 6 + 7);
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// Assembler::new().assemble("LDY #$01\nDEY\nINX\nDEX\nDEX", &mut cpu.memory, &mut end_address).unwrap();
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert_eq!((cpu.x, cpu.y), (0xFF, 0x00));
+/// assert!(cpu.flag(NEGATIVE) && !cpu.flag(ZERO));
+/// assert_eq!(cpu.cycles, 5 * 2);
+/// ```
+pub(crate) fn inc(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    modify_operand(cpu, mode, data_cycle_count, increment);
+    0
+}
+
+/// Decrements the operand (`DEC`).
+pub(crate) fn dec(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    modify_operand(cpu, mode, data_cycle_count, decrement);
+    0
+}
+
+/// Increments the X register (`INX`).
+pub(crate) fn inx(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.x = increment(cpu, cpu.x);
+    0
+}
+
+/// Increments the Y register (`INY`).
+pub(crate) fn iny(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.y = increment(cpu, cpu.y);
+    0
+}
+
+/// Decrements the X register (`DEX`).
+pub(crate) fn dex(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.x = decrement(cpu, cpu.x);
+    0
+}
+
+/// Decrements the Y register (`DEY`).
+pub(crate) fn dey(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.y = decrement(cpu, cpu.y);
+    0
+}
+
+/// Copies a register value, updating Z and N for the destination (`TAX`, `TXA`, ...).
+fn transfer(cpu: &mut CPU, value: u8) -> u8 {
+    cpu.update_nz(value);
+    value
+}
+
+/// Copies the accumulator to the X register (`TAX`).
+pub(crate) fn tax(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.x = transfer(cpu, cpu.a);
+    0
+}
+
+/// Copies the accumulator to the Y register (`TAY`).
+pub(crate) fn tay(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.y = transfer(cpu, cpu.a);
+    0
+}
+
+/// Copies the X register to the accumulator (`TXA`).
+pub(crate) fn txa(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.a = transfer(cpu, cpu.x);
+    0
+}
+
+/// Copies the Y register to the accumulator (`TYA`).
+pub(crate) fn tya(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.a = transfer(cpu, cpu.y);
+    0
+}
+
+/// Copies the stack pointer to the X register (`TSX`).
+pub(crate) fn tsx(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.x = transfer(cpu, cpu.sp as u8);
+    0
+}
+
+/// Copies the X register to the stack pointer, leaving the flags alone (`TXS`).
+pub(crate) fn txs(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.sp = cpu.x as u16;
+    0
+}
+
+/// Executes a relative branch.
+///
+/// The signed offset byte is always fetched. If `condition` holds, it is added to the program
+/// counter, which now points at the next instruction.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` executing the branch.
+/// - `condition`: Whether the branch is taken.
+/// - `data_cycle_count`: A mutable reference to the number of program bytes left to execute.
+///
+/// # Returns
+/// The extra cycles spent: 0 if not taken, 1 if taken, 2 if taken to a different page.
+fn branch(cpu: &mut CPU, condition: bool, data_cycle_count: &mut u32) -> u64 {
+    let offset = cpu.fetch_address_value(data_cycle_count) as i8;
+    if !condition {
+        return 0;
+    }
+    let target = cpu.pc.wrapping_add(offset as u16);
+    let extra_cycles = if target & 0xFF00 != cpu.pc & 0xFF00 {
+        2
+    } else {
+        1
+    };
+    cpu.pc = target;
+    extra_cycles
+}
+
+/// Branches if the carry is clear (`BCC`).
+pub(crate) fn bcc(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, !cpu.flag(CARRY), data_cycle_count)
+}
+
+/// Branches if the carry is set (`BCS`).
+pub(crate) fn bcs(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, cpu.flag(CARRY), data_cycle_count)
+}
+
+/// Branches if the result was zero (`BEQ`).
+pub(crate) fn beq(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, cpu.flag(ZERO), data_cycle_count)
+}
+
+/// Branches if the result was not zero (`BNE`).
+pub(crate) fn bne(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, !cpu.flag(ZERO), data_cycle_count)
+}
+
+/// Branches if the result was negative (`BMI`).
+pub(crate) fn bmi(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, cpu.flag(NEGATIVE), data_cycle_count)
+}
+
+/// Branches if the result was positive (`BPL`).
+pub(crate) fn bpl(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, !cpu.flag(NEGATIVE), data_cycle_count)
+}
+
+/// Branches if the overflow flag is set (`BVS`).
+pub(crate) fn bvs(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, cpu.flag(OVERFLOW), data_cycle_count)
+}
+
+/// Branches if the overflow flag is clear (`BVC`).
+pub(crate) fn bvc(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    branch(cpu, !cpu.flag(OVERFLOW), data_cycle_count)
+}
+
+/// Jumps to the operand's address, read through a pointer for `AddressingMode::Indirect` (`JMP`).
+pub(crate) fn jmp(cpu: &mut CPU, mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let (target, _) = operand_address(cpu, mode, data_cycle_count);
+    cpu.pc = target;
+    0
+}
+
+/// Jumps to a subroutine, pushing the address of the last byte of the `JSR` instruction (`JSR`).
+pub(crate) fn jsr(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let target = fetch_word(cpu, data_cycle_count);
+    cpu.push_word(cpu.pc.wrapping_sub(1));
+    cpu.pc = target;
+    0
+}
+
+/// Returns from a subroutine to the instruction after the matching `JSR` (`RTS`).
+pub(crate) fn rts(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.pc = cpu.pop_word().wrapping_add(1);
+    0
+}
+
+/// Returns from an interrupt, restoring the status register and program counter (`RTI`).
+pub(crate) fn rti(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.interrupts.leave();
+    let status = cpu.pop();
+    cpu.set_status(status);
+    cpu.pc = cpu.pop_word();
+    0
+}
+
+/// Forces a software interrupt (`BRK`).
+///
+/// The byte after the opcode is skipped as padding, the return address and the status register
+/// (with the break bit set) are pushed, interrupts are disabled and execution continues at the
+/// address stored in the IRQ/BRK vector at `$FFFE`/`$FFFF`.
+pub(crate) fn brk(cpu: &mut CPU, _mode: AddressingMode, data_cycle_count: &mut u32) -> u64 {
+    let pc = cpu.pc.wrapping_sub(1);
+    cpu.log_event(pc, EventKind::Interrupt, || String::from("BRK"));
+    cpu.interrupts.enter(pc, cpu.sp);
+    cpu.fetch_address_value(data_cycle_count);
+    cpu.push_word(cpu.pc);
+    cpu.push(cpu.get_status() | BREAK);
+    cpu.set_flag(INTERRUPT_DISABLE, true);
+    let l_byte = read_byte(cpu, 0xFFFE) as u16;
+    let h_byte = read_byte(cpu, 0xFFFF) as u16;
+    cpu.pc = (h_byte << 8) | l_byte;
+    0
+}
+
+/// Pushes the accumulator (`PHA`).
+pub(crate) fn pha(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.push(cpu.a);
+    0
+}
+
+/// Pushes the status register, with the break bit set (`PHP`).
+pub(crate) fn php(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.push(cpu.get_status() | BREAK);
+    0
+}
+
+/// Pulls the accumulator (`PLA`).
+pub(crate) fn pla(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.a = cpu.pop();
+    cpu.update_nz(cpu.a);
+    0
+}
+
+/// Pulls the status register (`PLP`).
+pub(crate) fn plp(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    let status = cpu.pop();
+    cpu.set_status(status);
+    0
+}
+
+/// Clears the `CARRY` flag (`CLC`).
+pub(crate) fn clc(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(CARRY, false);
+    0
+}
+
+/// Clears the `DECIMAL` flag (`CLD`).
+pub(crate) fn cld(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(DECIMAL, false);
+    0
+}
+
+/// Clears the `INTERRUPT_DISABLE` flag (`CLI`).
+pub(crate) fn cli(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(INTERRUPT_DISABLE, false);
+    0
+}
+
+/// Clears the `OVERFLOW` flag (`CLV`).
+pub(crate) fn clv(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(OVERFLOW, false);
+    0
+}
+
+/// Sets the `CARRY` flag (`SEC`).
+pub(crate) fn sec(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(CARRY, true);
+    0
+}
+
+/// Sets the `DECIMAL` flag (`SED`).
+pub(crate) fn sed(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(DECIMAL, true);
+    0
+}
+
+/// Sets the `INTERRUPT_DISABLE` flag (`SEI`).
+pub(crate) fn sei(cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    cpu.set_flag(INTERRUPT_DISABLE, true);
+    0
+}
+
+/// Does nothing (`NOP`).
+pub(crate) fn nop(_cpu: &mut CPU, _mode: AddressingMode, _data_cycle_count: &mut u32) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bcd::binary_to_bcd;
+    use crate::Assembler;
+    use std::fs;
+    use std::path::Path;
+
+    /// The panics left in the library, by file and a fragment of the line, each with why none of
+    /// them is reachable from `CPU::step`, `CPU::run_for_cycles`, `execute_instruction`,
+    /// `run_until` and `Runner` when `cpu.panic_free` is set, nor from `Assembler::assemble`.
+    const ALLOWED_PANICS: &[(&str, &str)] = &[
+        // Only on problem stop reasons, which `panic_free` keeps in `cpu.fault` instead
+        (
+            "asm_runner.rs",
+            "Unknown opcode {:02X} at {:04X}\", opcode, address",
+        ),
+        ("asm_runner.rs", "runs past $FFFF\", pc"),
+        ("asm_runner.rs", "Executing device registers"),
+        ("asm_runner.rs", "Program reached its {} limit"),
+        ("asm_runner.rs", "GuardHit { pc, address, write } => panic!"),
+        // `try_execute_instruction` reports these opcodes as `IllegalOpcode` before they run
+        (
+            "asm_runner.rs",
+            "Unknown opcode {:02X} at {:04X}\", opcode, opcode_address",
+        ),
+        // The opcode tables only pair these modes with instructions that have no memory operand
+        ("asm_runner.rs", "addressing has no memory operand"),
+        ("token.rs", "is missing from the opcode table"),
+        ("memory.rs", "the vector has exactly MAX_MEMORY bytes"),
+        // Helpers and tools the CPU and assembler do not call
+        ("bcd.rs", "does not fit in a BCD byte"),
+        ("bcd.rs", "Invalid BCD value"),
+        ("util.rs", "Cannot encode"),
+        ("mmu.rs", "straddle two pages"),
+        ("mmu.rs", "can have at most"),
+        // Only `Mmu::bank` and `Mmu::bank_mut` pass a bank without checking it first
+        ("mmu.rs", "is not in the {}-bank backing store"),
+        ("functional_test.rs", "panic!(\"{}\", e)"),
+        // Test helpers, which report a failed test by panicking
+        ("frame_hash.rs", "Frame test failed"),
+        ("frame_hash.rs", "panic!(\"{}\", mismatch)"),
+        ("random_program.rs", "assert!("),
+        ("random_program.rs", "is never generated"),
+    ];
+
+    const PANICS: &[&str] = &[
+        "panic!(",
+        "unreachable!(",
+        "todo!(",
+        "unimplemented!(",
+        "assert!(",
+        "assert_eq!(",
+        "assert_ne!(",
+        ".unwrap()",
+        ".expect(",
+    ];
+
+    #[test]
+    fn panics_are_unreachable_in_panic_free_mode() {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut unexpected = Vec::new();
+        let mut used = vec![false; ALLOWED_PANICS.len()];
+        for entry in fs::read_dir(src).unwrap() {
+            let path = entry.unwrap().path();
+            let file = path.file_name().unwrap().to_string_lossy().into_owned();
+            if file == "main.rs" {
+                continue; // The command line tool reports its errors by exiting
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            // Tests sit in a module at the end of the file
+            let code = source.split("#[cfg(test)]\nmod tests").next().unwrap();
+            for (index, line) in code.lines().enumerate() {
+                let trimmed = line.trim_start();
+                if trimmed.starts_with("//") || !PANICS.iter().any(|panic| line.contains(panic)) {
+                    continue;
+                }
+                let allowed = ALLOWED_PANICS
+                    .iter()
+                    .position(|(name, fragment)| *name == file && line.contains(fragment));
+                match allowed {
+                    Some(entry) => used[entry] = true,
+                    None => unexpected.push(format!("{}:{}: {}", file, index + 1, trimmed)),
+                }
+            }
+        }
+        assert!(
+            unexpected.is_empty(),
+            "unreviewed panics:\n{}",
+            unexpected.join("\n")
+        );
+        // An entry that matches nothing would let a later panic with the same text through
+        let stale: Vec<_> = ALLOWED_PANICS
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| !used)
+            .collect();
+        assert!(stale.is_empty(), "allowed panics not found: {:?}", stale);
+    }
+
+    #[test]
+    fn panic_free_cpu_keeps_its_fault() {
+        let mut cpu = CPU::new();
+        cpu.panic_free = true;
+        cpu.memory.data[0..2].copy_from_slice(&[0xE8, 0xFF]); // INX, then an unknown opcode
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 0);
+        assert_eq!(
+            cpu.fault,
+            Some(StopReason::IllegalOpcode {
+                address: 1,
+                opcode: 0xFF
+            })
+        );
+        assert_eq!((cpu.pc, cpu.x), (1, 1));
+
+        let mut runner = Runner::until(EndConditions::program_end(2));
+        runner.step(&mut cpu);
+        assert_eq!(runner.run(&mut cpu), None);
+        assert_eq!(cpu.pc, 1);
+
+        cpu.fault = None;
+        cpu.memory.data[1] = 0xE8;
+        assert_eq!(runner.run(&mut cpu), None);
+        assert_eq!((cpu.pc, cpu.x), (2, 2));
+        assert!(runner.is_finished());
+    }
+
+    #[test]
+    fn panic_free_run_until_reports_instead_of_panicking() {
+        let mut cpu = CPU::new();
+        cpu.panic_free = true;
+        cpu.end_of_memory = EndOfMemory::Stop;
+        let mut end_address: u16 = 0;
+        Assembler::new()
+            .assemble(
+                ".org $FFFE\nNOP\n.byte $AD",
+                &mut cpu.memory,
+                &mut end_address,
+            )
+            .unwrap();
+        cpu.pc = 0xFFFE;
+        run_until(&mut cpu, &EndConditions::default());
+        assert_eq!(cpu.fault, Some(StopReason::RanOffEnd { pc: 0xFFFF }));
+        assert_eq!(cpu.pc, 0xFFFF);
+    }
+
+    /// Assembles `source` from `$0000` and runs it to its end.
+    fn run_program(source: &str) -> CPU {
+        let mut cpu = CPU::new();
+        let mut end_address: u16 = 0;
+        Assembler::new()
+            .assemble(source, &mut cpu.memory, &mut end_address)
+            .unwrap();
+        run_until(&mut cpu, &EndConditions::program_end(end_address));
+        cpu
+    }
+
+    /// Returns the C, V, Z and N flags of `cpu`.
+    fn flags(cpu: &CPU) -> [bool; 4] {
+        [CARRY, OVERFLOW, ZERO, NEGATIVE].map(|flag| cpu.flag(flag))
+    }
+
+    #[test]
+    fn adc_and_sbc_set_carry_and_overflow() {
+        // (source, A, [C, V, Z, N])
+        let cases = [
+            // 127 + 1 overflows into the sign bit
+            ("CLC\nLDA #$7F\nADC #$01", 0x80, [false, true, false, true]),
+            // Unsigned wrap-around: carry, but no signed overflow
+            ("CLC\nLDA #$FF\nADC #$01", 0x00, [true, false, true, false]),
+            // -128 - 1 overflows into the positive range
+            ("SEC\nLDA #$80\nSBC #$01", 0x7F, [true, true, false, false]),
+            // A borrow clears the carry
+            ("SEC\nLDA #$00\nSBC #$01", 0xFF, [false, false, false, true]),
+        ];
+        for (source, a, expected) in cases {
+            let cpu = run_program(source);
+            assert_eq!((cpu.a, flags(&cpu)), (a, expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn decimal_mode_programs_work_on_packed_bcd() {
+        for (source, a, carry) in [
+            ("SED\nCLC\nLDA #$58\nADC #$46", 0x04, true), // 58 + 46 = 104
+            ("SED\nSEC\nLDA #$40\nSBC #$13", 0x27, true), // 40 - 13 = 27
+            ("SED\nSEC\nLDA #$00\nSBC #$01", 0x99, false), // 0 - 1 = 99 with a borrow
+        ] {
+            let cpu = run_program(source);
+            assert_eq!((cpu.a, cpu.flag(CARRY)), (a, carry), "{}", source);
+        }
+    }
+
+    #[test]
+    fn shifts_and_rotates_move_the_accumulator_through_the_carry() {
+        for (source, a, expected) in [
+            ("LDA #$81\nASL A", 0x02, [true, false, false, false]),
+            ("LDA #$01\nLSR a", 0x00, [true, false, true, false]),
+            ("SEC\nLDA #$40\nROL A", 0x81, [false, false, false, true]),
+            ("SEC\nLDA #$02\nROR", 0x81, [false, false, false, true]),
+        ] {
+            let cpu = run_program(source);
+            assert_eq!((cpu.a, flags(&cpu)), (a, expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn compares_set_the_flags_branches_test() {
+        // (source, [C, Z, N])
+        for (source, expected) in [
+            ("LDA #$40\nCMP #$40", [true, true, false]),   // Equal
+            ("LDX #$40\nCPX #$41", [false, false, true]),  // Less
+            ("LDY #$90\nCPY #$01", [true, false, true]),   // Greater, $8F
+            ("LDA #$01\nCMP #$90", [false, false, false]), // Less, $71
+        ] {
+            let cpu = run_program(source);
+            let [carry, _, zero, negative] = flags(&cpu);
+            assert_eq!([carry, zero, negative], expected, "{}", source);
+        }
+
+        // Counting Y up to 5 with CPY and BNE
+        let cpu = run_program("LDY #$00\nloop:\nINY\nCPY #$05\nBNE loop");
+        assert_eq!((cpu.y, cpu.instructions), (5, 16));
+    }
+
+    /// Runs `opcode #value` with `a` and the carry in, returning A and the C, V, Z and N flags.
+    fn arithmetic(opcode: u8, a: u8, carry: bool, value: u8) -> (u8, [bool; 4]) {
+        let mut cpu = CPU::new();
+        cpu.memory.data[0..2].copy_from_slice(&[opcode, value]);
+        cpu.a = a;
+        cpu.set_flag(CARRY, carry);
+        cpu.step();
+        let flags = [CARRY, OVERFLOW, ZERO, NEGATIVE].map(|flag| cpu.flag(flag));
+        (cpu.a, flags)
+    }
+
+    #[test]
+    fn adc_sets_flags_at_the_boundaries() {
+        // (A, carry in, operand) => (A, [C, V, Z, N])
+        let cases = [
+            ((0x7F, false, 0x01), (0x80, [false, true, false, true])),
+            ((0xFF, false, 0x01), (0x00, [true, false, true, false])),
+            ((0x80, false, 0x80), (0x00, [true, true, true, false])),
+            ((0x80, false, 0xFF), (0x7F, [true, true, false, false])),
+            ((0x7F, true, 0x00), (0x80, [false, true, false, true])),
+            ((0xFF, true, 0xFF), (0xFF, [true, false, false, true])),
+            ((0x00, false, 0x00), (0x00, [false, false, true, false])),
+            ((0x3F, true, 0x40), (0x80, [false, true, false, true])),
+        ];
+        for ((a, carry, value), expected) in cases {
+            let result = arithmetic(0x69, a, carry, value);
+            assert_eq!(
+                result, expected,
+                "ADC ${:02X}+${:02X}+{}",
+                a, value, carry as u8
+            );
+        }
+    }
+
+    #[test]
+    fn sbc_sets_flags_at_the_boundaries() {
+        // (A, carry in, operand) => (A, [C, V, Z, N]); a clear carry borrows one more
+        let cases = [
+            ((0x80, true, 0x01), (0x7F, [true, true, false, false])),
+            ((0x00, true, 0x01), (0xFF, [false, false, false, true])),
+            ((0x7F, true, 0xFF), (0x80, [false, true, false, true])),
+            ((0x01, true, 0x01), (0x00, [true, false, true, false])),
+            ((0x00, false, 0x00), (0xFF, [false, false, false, true])),
+            ((0x80, false, 0x00), (0x7F, [true, true, false, false])),
+            ((0xFF, true, 0x7F), (0x80, [true, false, false, true])),
+            ((0x40, true, 0x40), (0x00, [true, false, true, false])),
+        ];
+        for ((a, carry, value), expected) in cases {
+            let result = arithmetic(0xE9, a, carry, value);
+            assert_eq!(
+                result, expected,
+                "SBC ${:02X}-${:02X}, C={}",
+                a, value, carry as u8
+            );
+        }
+    }
+
+    #[test]
+    fn lax_loads_a_and_x_and_sets_the_flags_from_them() {
+        let mut cpu = CPU::new();
+        cpu.illegal_opcodes = true;
+        cpu.memory.data[0..4].copy_from_slice(&[0xA7, 0x10, 0xA7, 0x11]); // LAX $10; LAX $11
+        cpu.memory.data[0x10] = 0x80;
+        cpu.step();
+        assert_eq!((cpu.a, cpu.x), (0x80, 0x80));
+        assert!(cpu.flag(NEGATIVE) && !cpu.flag(ZERO));
+        cpu.step();
+        assert_eq!((cpu.a, cpu.x), (0x00, 0x00));
+        assert!(cpu.flag(ZERO) && !cpu.flag(NEGATIVE));
+    }
+
+    #[test]
+    fn decimal_mode_adds_and_subtracts_every_pair_of_digits() {
+        for a in 0..=99u8 {
+            for value in 0..=99u8 {
+                for carry in [false, true] {
+                    let mut cpu = CPU::new();
+                    cpu.a = binary_to_bcd(a);
+                    cpu.set_flag(CARRY, carry);
+                    decimal_add(&mut cpu, binary_to_bcd(value));
+                    let sum = a as u16 + value as u16 + carry as u16;
+                    assert_eq!(
+                        cpu.a,
+                        binary_to_bcd((sum % 100) as u8),
+                        "{}+{}+{}",
+                        a,
+                        value,
+                        carry
+                    );
+                    assert_eq!(cpu.flag(CARRY), sum > 99, "{}+{}+{}: C", a, value, carry);
+
+                    cpu.a = binary_to_bcd(a);
+                    cpu.set_flag(CARRY, carry);
+                    decimal_subtract(&mut cpu, binary_to_bcd(value));
+                    let difference = a as i16 - value as i16 - !carry as i16;
+                    let expected = binary_to_bcd(difference.rem_euclid(100) as u8);
+                    assert_eq!(cpu.a, expected, "{}-{}, C={}", a, value, carry);
+                    assert_eq!(
+                        cpu.flag(CARRY),
+                        difference >= 0,
+                        "{}-{}, C={}: C",
+                        a,
+                        value,
+                        carry
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decimal_mode_runs_through_adc_and_sbc() {
+        // SED; SEC; LDA #$58; ADC #$46 (58 + 46 + 1 = 105); SBC #$21 (05 - 21 = -16, i.e. 84)
+        let mut cpu = CPU::new();
+        cpu.memory.data[0..8].copy_from_slice(&[0xF8, 0x38, 0xA9, 0x58, 0x69, 0x46, 0xE9, 0x21]);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.a, 0x05);
+        assert!(cpu.flag(CARRY));
+        cpu.step();
+        assert_eq!(cpu.a, 0x84);
+        assert!(!cpu.flag(CARRY));
+    }
+}
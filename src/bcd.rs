@@ -0,0 +1,111 @@
+/// Checks whether a byte is a valid packed BCD value (both nibbles in `0..=9`).
+///
+/// # Example
+/// ```rust
+/// use r_6502::bcd::*;
+///
+/// assert!(is_valid_bcd(0x42));
+/// assert!(!is_valid_bcd(0x4A));
+/// ```
+pub fn is_valid_bcd(value: u8) -> bool {
+    value >> 4 <= 9 && value & 0x0F <= 9
+}
+
+/// Converts a binary value in `0..=99` to packed BCD.
+///
+/// # Panics
+/// - If `value` is greater than 99 and so does not fit in one BCD byte.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bcd::*;
+///
+/// assert_eq!(binary_to_bcd(42), 0x42);
+/// ```
+pub fn binary_to_bcd(value: u8) -> u8 {
+    if value > 99 {
+        panic!("Value {} does not fit in a BCD byte", value);
+    }
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Converts a packed BCD byte to its binary value.
+///
+/// # Panics
+/// - If either nibble of `value` is not a decimal digit.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bcd::*;
+///
+/// assert_eq!(bcd_to_binary(0x42), 42);
+/// ```
+pub fn bcd_to_binary(value: u8) -> u8 {
+    if !is_valid_bcd(value) {
+        panic!("Invalid BCD value {:02X}", value);
+    }
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Formats a run of packed BCD bytes as decimal digits, most significant byte first.
+///
+/// This is how multi-byte BCD values such as scores or clock readings are usually laid out in
+/// memory by 6502 programs. Nibbles that are not decimal digits are shown as `?` so corrupted
+/// values stand out in dumps.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bcd::*;
+///
+/// assert_eq!(format_bcd(&[0x01, 0x23, 0x45]), "012345");
+/// assert_eq!(format_bcd(&[0x1F]), "1?");
+/// ```
+pub fn format_bcd(data: &[u8]) -> String {
+    let mut digits = String::with_capacity(data.len() * 2);
+    for value in data {
+        for nibble in [value >> 4, value & 0x0F] {
+            match nibble {
+                0..=9 => digits.push((b'0' + nibble) as char),
+                _ => digits.push('?'),
+            }
+        }
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_every_decimal_value_both_ways() {
+        for value in 0..=99 {
+            let bcd = binary_to_bcd(value);
+            assert!(is_valid_bcd(bcd), "{}", value);
+            assert_eq!(bcd_to_binary(bcd), value);
+            assert_eq!(format_bcd(&[bcd]), format!("{:02}", value));
+        }
+        assert_eq!((0..=255).filter(|&value| is_valid_bcd(value)).count(), 100);
+    }
+
+    #[test]
+    fn flags_nibbles_that_are_not_digits() {
+        for value in [0x0A, 0x0F, 0xA0, 0xF9, 0xFF] {
+            assert!(!is_valid_bcd(value), "{:02X}", value);
+        }
+        assert_eq!(format_bcd(&[0xA0, 0x99]), "?099");
+        assert_eq!(format_bcd(&[]), "");
+    }
+
+    #[test]
+    #[should_panic(expected = "Value 100 does not fit in a BCD byte")]
+    fn binary_to_bcd_rejects_values_over_99() {
+        binary_to_bcd(100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid BCD value 1A")]
+    fn bcd_to_binary_rejects_invalid_values() {
+        bcd_to_binary(0x1A);
+    }
+}
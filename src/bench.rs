@@ -0,0 +1,83 @@
+use crate::asm_runner::{try_run_until, EndConditions, StopReason};
+use crate::cpu::CPU;
+use std::time::{Duration, Instant};
+
+/// Why a benchmarked program halted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    Trap { pc: u16 },    // Jumped or branched to itself (`JMP *`, `BNE *`, ...) at `pc`
+    Brk { pc: u16 },     // Reached a `BRK` at `pc`, which is not executed
+    Stopped(StopReason), // Could not go on, e.g. at an unknown opcode or one of `cpu.limits`
+    TimedOut,            // Still running after the instruction limit
+}
+
+/// What a benchmark run measured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchResult {
+    pub halt: Halt,
+    pub cycles: u64,       // Clock cycles the program took
+    pub instructions: u64, // Instructions it executed
+    pub elapsed: Duration, // Host time taken to emulate them
+}
+
+impl BenchResult {
+    /// Returns the millions of emulated instructions the host executed per second.
+    pub fn mips(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64().max(1e-9) / 1e6
+    }
+
+    /// Returns the clock speed the emulation reached, in MHz.
+    pub fn mhz(&self) -> f64 {
+        self.cycles as f64 / self.elapsed.as_secs_f64().max(1e-9) / 1e6
+    }
+}
+
+/// Runs the program loaded in the CPU's memory until it halts, measuring its cycles and
+/// instructions and how long the host took to execute them.
+///
+/// The program is run with `try_run_until`, and halts when it traps by jumping or branching to the
+/// instruction itself, when it reaches a `BRK` (as it does when it runs off its end into cleared
+/// memory), or when it cannot go on. The cycle count is the one to compare when optimising 6502
+/// code; `mips` tracks the emulator's own speed, so leave `cpu.trace` unset while measuring it.
+///
+/// # Parameters
+/// - `cpu`: A mutable reference to the `CPU` to run, left in the state the program halted in.
+/// - `max_instructions`: Instructions to execute before giving up.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bench::{run_benchmark, Halt};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// Assembler::new()
+///     .assemble("LDX #$00\nloop:\nDEX\nBNE loop\ndone:\nJMP done", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// let result = run_benchmark(&mut cpu, 1_000_000);
+/// assert_eq!(result.halt, Halt::Trap { pc: 0x0005 });
+/// assert_eq!((result.instructions, result.cycles), (514, 1284));
+/// ```
+pub fn run_benchmark(cpu: &mut CPU, max_instructions: u64) -> BenchResult {
+    let start_cycles = cpu.cycles;
+    let start_instructions = cpu.instructions;
+    let started = Instant::now();
+    let end = EndConditions {
+        brk: true,
+        trap: true,
+        instruction_budget: Some(max_instructions),
+        ..EndConditions::default()
+    };
+    let halt = match try_run_until(cpu, &end) {
+        StopReason::Trapped { pc } => Halt::Trap { pc },
+        StopReason::Brk { pc } => Halt::Brk { pc },
+        StopReason::BudgetSpent => Halt::TimedOut,
+        reason => Halt::Stopped(reason),
+    };
+    BenchResult {
+        halt,
+        cycles: cpu.cycles - start_cycles,
+        instructions: cpu.instructions - start_instructions,
+        elapsed: started.elapsed(),
+    }
+}
@@ -0,0 +1,276 @@
+use crate::cpu::{
+    Register, BREAK, CARRY, CPU, DECIMAL, INTERRUPT_DISABLE, NEGATIVE, OVERFLOW, ZERO,
+};
+use crate::mmu::BankedAddress;
+use serde::{Deserialize, Serialize};
+
+/// Processor status flags a breakpoint condition can test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Flag {
+    C, // Carry
+    Z, // Zero
+    I, // Interrupt Disable
+    D, // Decimal Mode
+    B, // Break Command, which never reads as set since only pushed copies of P hold it
+    V, // Overflow
+    N, // Negative
+}
+
+impl Flag {
+    /// Returns the bit of the status register holding the flag.
+    pub fn mask(self) -> u8 {
+        match self {
+            Flag::C => CARRY,
+            Flag::Z => ZERO,
+            Flag::I => INTERRUPT_DISABLE,
+            Flag::D => DECIMAL,
+            Flag::B => BREAK,
+            Flag::V => OVERFLOW,
+            Flag::N => NEGATIVE,
+        }
+    }
+
+    /// Parses a flag from its letter, e.g. `V` or `d`.
+    pub fn parse(name: &str) -> Option<Flag> {
+        match name.to_ascii_uppercase().as_str() {
+            "C" => Some(Flag::C),
+            "Z" => Some(Flag::Z),
+            "I" => Some(Flag::I),
+            "D" => Some(Flag::D),
+            "B" => Some(Flag::B),
+            "V" => Some(Flag::V),
+            "N" => Some(Flag::N),
+            _ => None,
+        }
+    }
+}
+
+/// A condition that must hold for a conditional breakpoint to stop execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+    Register(Register, u16), // The register holds exactly this value
+    Flag(Flag, bool),        // The flag is set (`true`) or clear (`false`)
+}
+
+impl Condition {
+    /// Checks whether the condition holds for the current CPU state.
+    pub fn matches(&self, cpu: &CPU) -> bool {
+        match *self {
+            Condition::Register(register, value) => cpu.get(register) == value,
+            Condition::Flag(flag, set) => cpu.flag(flag.mask()) == set,
+        }
+    }
+}
+
+/// A breakpoint on the address of an instruction, optionally guarded by a condition.
+///
+/// A breakpoint with a `bank` only stops execution while that bank is mapped over its address by
+/// the CPU's MMU, so it does not fire for code in another bank sharing the same address range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub bank: Option<u8>,
+    pub condition: Option<Condition>,
+}
+
+/// The transition of a status flag that a `FlagBreakpoint` stops on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlagChange {
+    Set,     // The flag goes from clear to set
+    Cleared, // The flag goes from set to clear
+    Either,  // The flag changes either way
+}
+
+/// A breakpoint on a status flag changing, wherever in the program the change happens.
+///
+/// Useful for bugs that are found far from their cause, such as arithmetic going wrong because
+/// something executed `SED` or restored a status byte with D set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlagBreakpoint {
+    pub flag: Flag,
+    pub change: FlagChange,
+}
+
+impl FlagBreakpoint {
+    /// Checks whether going from status `before` to status `after` is the change watched for.
+    pub fn matches(&self, before: u8, after: u8) -> bool {
+        let mask = self.flag.mask();
+        let (was_set, is_set) = (before & mask != 0, after & mask != 0);
+        match self.change {
+            FlagChange::Set => !was_set && is_set,
+            FlagChange::Cleared => was_set && !is_set,
+            FlagChange::Either => was_set != is_set,
+        }
+    }
+}
+
+/// The breakpoints set on a `CPU`.
+///
+/// `run_until` checks these before every instruction it executes and stops when one is hit,
+/// leaving the program counter on the instruction the breakpoint is set on.
+///
+/// # Example
+/// ```rust
+/// use r_6502::breakpoint::{Condition, Flag};
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.breakpoints.add(0x0600);
+/// cpu.breakpoints.add_conditional(0x0610, Condition::Flag(Flag::Z, true));
+/// assert_eq!(cpu.breakpoints.list().len(), 2);
+/// assert!(cpu.breakpoints.remove(0x0600));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    flag_breakpoints: Vec<FlagBreakpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Breakpoints {
+            breakpoints: Vec::new(),
+            flag_breakpoints: Vec::new(),
+        }
+    }
+
+    /// Adds an unconditional breakpoint at `address`.
+    pub fn add(&mut self, address: u16) {
+        self.breakpoints.push(Breakpoint {
+            address,
+            bank: None,
+            condition: None,
+        });
+    }
+
+    /// Adds an unconditional breakpoint at a banked address, e.g. `3:$8000`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::mmu::{BankedAddress, Mmu};
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.pc = 0x8000;
+    /// cpu.mmu = Some(Mmu::new(0xFE00, 4));
+    /// cpu.breakpoints.add_banked(BankedAddress::parse("3:$8000").unwrap());
+    /// if let Some(mmu) = cpu.mmu.as_mut() {
+    ///     mmu.map(&mut cpu.memory, 0x8, Some(1));
+    /// }
+    /// assert_eq!(cpu.breakpoints.hit(&cpu), None);
+    /// if let Some(mmu) = cpu.mmu.as_mut() {
+    ///     mmu.map(&mut cpu.memory, 0x8, Some(3));
+    /// }
+    /// assert!(cpu.breakpoints.hit(&cpu).is_some());
+    /// ```
+    pub fn add_banked(&mut self, location: BankedAddress) {
+        self.breakpoints.push(Breakpoint {
+            address: location.address,
+            bank: location.bank,
+            condition: None,
+        });
+    }
+
+    /// Adds a breakpoint as it is, e.g. one listed by another `Breakpoints`.
+    pub fn insert(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// Adds a breakpoint at `address` that only stops execution while `condition` holds.
+    pub fn add_conditional(&mut self, address: u16, condition: Condition) {
+        self.breakpoints.push(Breakpoint {
+            address,
+            bank: None,
+            condition: Some(condition),
+        });
+    }
+
+    /// Removes every breakpoint at `address`, whatever its bank.
+    ///
+    /// # Returns
+    /// - `true`: If at least one breakpoint was removed.
+    /// - `false`: If there was no breakpoint at `address`.
+    pub fn remove(&mut self, address: u16) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.address != address);
+        self.breakpoints.len() != count
+    }
+
+    /// Returns all breakpoints in the order they were added.
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Returns the first breakpoint hit by the CPU in its current state, if any.
+    pub fn hit(&self, cpu: &CPU) -> Option<Breakpoint> {
+        self.breakpoints.iter().copied().find(|b| {
+            b.address == cpu.pc
+                && BankedAddress {
+                    bank: b.bank,
+                    address: b.address,
+                }
+                .is_active(cpu.mmu.as_ref())
+                && match b.condition {
+                    Some(condition) => condition.matches(cpu),
+                    None => true,
+                }
+        })
+    }
+
+    /// Adds a breakpoint that stops execution after any instruction changing `flag` as given by
+    /// `change`.
+    ///
+    /// `try_run_until` compares the status register before and after every instruction it
+    /// executes and stops with `StopReason::FlagChanged`, leaving the program counter on the
+    /// instruction after the one that changed the flag.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+    /// use r_6502::breakpoint::{Flag, FlagChange};
+    /// use r_6502::{Assembler, CPU};
+    ///
+    /// let mut cpu = CPU::new();
+    /// let mut end_address: u16 = 0;
+    /// Assembler::new()
+    ///     .assemble("LDA #$50\nADC #$50\nSED\nINX", &mut cpu.memory, &mut end_address)
+    ///     .unwrap();
+    /// cpu.breakpoints.add_flag(Flag::D, FlagChange::Set);
+    /// cpu.breakpoints.add_flag(Flag::V, FlagChange::Set);
+    /// let end = EndConditions::program_end(end_address);
+    /// // $50 + $50 overflows into the sign bit
+    /// let reason = try_run_until(&mut cpu, &end);
+    /// assert!(matches!(reason, StopReason::FlagChanged { pc: 0x0002, .. }));
+    /// let reason = try_run_until(&mut cpu, &end);
+    /// assert!(matches!(reason, StopReason::FlagChanged { pc: 0x0004, .. }));
+    /// assert_eq!(cpu.pc, 0x0005);
+    /// ```
+    pub fn add_flag(&mut self, flag: Flag, change: FlagChange) {
+        self.flag_breakpoints.push(FlagBreakpoint { flag, change });
+    }
+
+    /// Removes every breakpoint on `flag` changing.
+    ///
+    /// # Returns
+    /// - `true`: If at least one breakpoint was removed.
+    /// - `false`: If there was no breakpoint on `flag`.
+    pub fn remove_flag(&mut self, flag: Flag) -> bool {
+        let count = self.flag_breakpoints.len();
+        self.flag_breakpoints.retain(|b| b.flag != flag);
+        self.flag_breakpoints.len() != count
+    }
+
+    /// Returns all flag breakpoints in the order they were added.
+    pub fn flag_list(&self) -> &[FlagBreakpoint] {
+        &self.flag_breakpoints
+    }
+
+    /// Returns the first flag breakpoint hit by an instruction that changed the status register
+    /// from `before` to `after`, if any.
+    pub fn flag_hit(&self, before: u8, after: u8) -> Option<FlagBreakpoint> {
+        self.flag_breakpoints
+            .iter()
+            .copied()
+            .find(|b| b.matches(before, after))
+    }
+}
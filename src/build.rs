@@ -0,0 +1,101 @@
+use crate::asm_error::AsmError;
+use crate::asm_parser::Assembler;
+use crate::memory::Memory;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Assembles a source file into a binary image.
+///
+/// Code starts at `origin` unless the source moves it with `.org`. The image runs from the lowest
+/// address assembled to through the last byte emitted, with any gaps between `.org` blocks filled
+/// with zeros, so it can be loaded with `Memory::load_bytes` at that lowest address.
+///
+/// # Errors
+/// - `AsmError::Io`: If the file cannot be opened or read.
+/// - `AsmError::Syntax`: If the source contains a syntax error, duplicate label or undefined label.
+///
+/// # Example
+/// ```rust,no_run
+/// let rom = r_6502::build::assemble_image("asm/firmware.asm", 0x8000).unwrap();
+/// ```
+pub fn assemble_image(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let mut mem = Memory::new();
+    let mut assembler = Assembler::new();
+    let mut starting_add = origin;
+    assembler.assemble_file(source, &mut mem, &mut starting_add)?;
+    Ok(assembler.image(&mem, None, 0x00))
+}
+
+/// Assembles a source file from a `build.rs` script into `$OUT_DIR/<output>`.
+///
+/// Cargo is told to rerun the build script when the source changes. The image is built as by
+/// `assemble_image`, with code starting at `$0000` unless the source sets an `.org`.
+///
+/// # Returns
+/// The path the image was written to.
+///
+/// # Errors
+/// - `AsmError::Io`: If the source cannot be read, `OUT_DIR` is not set (i.e. this is not called
+///   from a build script) or the image cannot be written.
+/// - `AsmError::Syntax`: If the source does not assemble.
+///
+/// # Example
+/// In the `main` of `build.rs`:
+/// ```rust,no_run
+/// if let Err(e) = r_6502::build::assemble("asm/firmware.asm", "firmware.bin") {
+///     panic!("{}", e);
+/// }
+/// ```
+/// Then in the crate, where Cargo sets `OUT_DIR`:
+/// ```text
+/// static FIRMWARE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/firmware.bin"));
+/// ```
+pub fn assemble(source: &str, output: &str) -> Result<PathBuf, AsmError> {
+    println!("cargo:rerun-if-changed={}", source);
+    let image = assemble_image(source, 0x0000)?;
+    let out_dir = env::var("OUT_DIR").map_err(|e| AsmError::Io {
+        path: String::from("OUT_DIR"),
+        message: e.to_string(),
+    })?;
+    let path = PathBuf::from(out_dir).join(output);
+    fs::write(&path, image).map_err(|e| AsmError::Io {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_a_source_file_into_out_dir() {
+        let dir = env::temp_dir().join(format!("r_6502_build_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("firmware.asm");
+        fs::write(
+            &source,
+            ".org $0002\nLDA #$01 ; gap before\n.org $0006\nRTS\n",
+        )
+        .unwrap();
+        let source = source.to_str().unwrap();
+
+        assert_eq!(
+            assemble_image(source, 0x0000).unwrap(),
+            [0xA9, 0x01, 0x00, 0x00, 0x60]
+        );
+
+        env::set_var("OUT_DIR", &dir);
+        let path = assemble(source, "firmware.bin").unwrap();
+        assert_eq!(path, dir.join("firmware.bin"));
+        assert_eq!(fs::read(&path).unwrap(), [0xA9, 0x01, 0x00, 0x00, 0x60]);
+
+        assert!(matches!(
+            assemble_image(dir.join("missing.asm").to_str().unwrap(), 0),
+            Err(AsmError::Io { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+/// The address and data bus the CPU makes every memory access through.
+///
+/// `Memory` is the bus the CPU uses: plain RAM by default, with ROM regions and memory-mapped
+/// `Device`s layered on top. Reads take `&mut self` because reading a device register can have side
+/// effects, such as a UART clearing its "byte received" flag.
+pub trait Bus {
+    /// Reads the byte at `address`, as the CPU does.
+    fn read(&mut self, address: u16) -> u8;
+
+    /// Writes `value` to `address`, as the CPU does.
+    fn write(&mut self, address: u16, value: u8);
+}
+
+/// An access made through the bus, as recorded by `Memory::record_bus`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: u16,
+    pub value: u8,   // Value read, or value written
+    pub write: bool, // `true` for a write, `false` for a read
+}
+
+/// A peripheral mapped into a range of the address space, such as a UART or a video chip stub.
+///
+/// Devices are attached with `Memory::attach` and see addresses relative to the start of the range
+/// they are mapped at, so the same device can be mapped anywhere.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bus::{Bus, Device};
+/// use r_6502::Memory;
+///
+/// /// A write-only output port collecting the bytes written to it.
+/// struct Output(Vec<u8>);
+///
+/// impl Device for Output {
+///     fn read(&mut self, _offset: u16) -> u8 {
+///         0
+///     }
+///     fn write(&mut self, _offset: u16, value: u8) {
+///         self.0.push(value);
+///     }
+/// }
+///
+/// let mut memory = Memory::new();
+/// memory.attach(0xD000, 0xD000, "Output", Box::new(Output(Vec::new())));
+/// memory.write(0xD000, b'A');
+/// assert_eq!(memory.read(0xD000), 0);
+/// assert_eq!(memory.data[0xD000], 0); // The RAM underneath is untouched
+/// ```
+pub trait Device {
+    /// Reads the register at `offset` from the start of the device's range.
+    fn read(&mut self, offset: u16) -> u8;
+
+    /// Writes `value` to the register at `offset` from the start of the device's range.
+    fn write(&mut self, offset: u16, value: u8);
+
+    /// Advances the device's clock by `cycles` CPU clock cycles, after every instruction.
+    ///
+    /// Devices with timers count them down here; the default does nothing.
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Returns whether the device is pulling the IRQ line low.
+    ///
+    /// The line is level-triggered: the CPU takes an interrupt after every instruction while a
+    /// device asserts it and interrupts are enabled. The default never asserts it.
+    fn irq(&self) -> bool {
+        false
+    }
+}
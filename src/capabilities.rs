@@ -0,0 +1,93 @@
+use crate::asm_runner::{undocumented_mnemonic, undocumented_mode};
+use crate::machine::MachineProfile;
+use crate::opcode_table::opcode_info;
+use serde::Serialize;
+
+/// An opcode the emulator executes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct OpcodeCapability {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: String, // Addressing mode, as named by `AddressingMode`
+}
+
+/// What this build of the emulator supports, for front-ends and scripts that need to adapt to the
+/// installed binary rather than assume the newest one.
+///
+/// # Example
+/// ```rust
+/// use r_6502::capabilities::Capabilities;
+///
+/// let capabilities = Capabilities::of_this_build();
+/// assert_eq!(capabilities.documented_opcodes.len(), 151);
+/// assert!(capabilities.undocumented_opcodes.iter().any(|op| op.mnemonic == "LAX"));
+/// assert!(capabilities.machines.contains(&"apple1"));
+/// assert!(capabilities.to_json().contains(r#""loaders":["assembly","binary""#));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub variants: Vec<&'static str>, // CPUs emulated
+    pub documented_opcodes: Vec<OpcodeCapability>,
+    pub undocumented_opcodes: Vec<OpcodeCapability>, // Executed with `cpu.illegal_opcodes` set
+    pub machines: Vec<&'static str>,                 // Built-in machine profiles
+    pub devices: Vec<&'static str>,                  // Device kinds a machine profile can attach
+    pub loaders: Vec<&'static str>,                  // Formats programs and states load from
+    pub features: Vec<&'static str>,                 // Cargo features this build was made with
+}
+
+impl Capabilities {
+    /// Describes the running build.
+    pub fn of_this_build() -> Self {
+        let mut documented_opcodes = Vec::new();
+        let mut undocumented_opcodes = Vec::new();
+        for opcode in 0..=0xFF {
+            if let Some(info) = opcode_info(opcode) {
+                documented_opcodes.push(OpcodeCapability {
+                    opcode,
+                    mnemonic: info.mnemonic,
+                    mode: format!("{:?}", info.mode),
+                });
+            } else if let (Some(mnemonic), Some(mode)) =
+                (undocumented_mnemonic(opcode), undocumented_mode(opcode))
+            {
+                undocumented_opcodes.push(OpcodeCapability {
+                    opcode,
+                    mnemonic,
+                    mode: format!("{:?}", mode),
+                });
+            }
+        }
+        let mut features = Vec::new();
+        if cfg!(feature = "metrics") {
+            features.push("metrics");
+        }
+        if cfg!(feature = "server") {
+            features.push("server");
+        }
+        Capabilities {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            variants: vec!["nmos6502"],
+            documented_opcodes,
+            undocumented_opcodes,
+            machines: MachineProfile::builtin_names().to_vec(),
+            devices: vec!["console", "via", "lcd", "pia"],
+            loaders: vec![
+                "assembly",
+                "binary",
+                "intel-hex",
+                "project",
+                "relocatable",
+                "save-state",
+            ],
+            features,
+        }
+    }
+
+    /// Serializes the capabilities as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
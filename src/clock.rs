@@ -0,0 +1,297 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Wall-clock time executed between two checks of the clock.
+const BATCH: Duration = Duration::from_millis(10);
+
+/// How far the emulation may fall behind real time before it stops trying to catch up, e.g. after
+/// the debugger paused it or the host was busy.
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+/// The slowest speed a clock can be scaled to, as a fraction of its `ClockSpeed`.
+pub const MIN_SCALE: f64 = 0.1;
+
+/// The fastest speed a clock can be scaled to, as a multiple of its `ClockSpeed`.
+pub const MAX_SCALE: f64 = 100.0;
+
+/// The speed a CPU's clock runs at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClockSpeed {
+    #[default]
+    Unlimited, // As fast as the host can go
+    Hz(u64), // Cycles per second of wall-clock time
+}
+
+impl ClockSpeed {
+    /// Parses a speed such as `1MHz`, `1.79mhz`, `500kHz`, `1000000` (Hz) or `unlimited`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::clock::ClockSpeed;
+    ///
+    /// assert_eq!(ClockSpeed::parse("1MHz"), Some(ClockSpeed::Hz(1_000_000)));
+    /// assert_eq!(ClockSpeed::parse("1.79MHz"), Some(ClockSpeed::Hz(1_790_000)));
+    /// assert_eq!(ClockSpeed::parse("32768"), Some(ClockSpeed::Hz(32_768)));
+    /// assert_eq!(ClockSpeed::parse("unlimited"), Some(ClockSpeed::Unlimited));
+    /// assert_eq!(ClockSpeed::parse("0MHz"), None);
+    /// ```
+    pub fn parse(text: &str) -> Option<ClockSpeed> {
+        let text = text.trim().to_ascii_lowercase();
+        if text == "unlimited" {
+            return Some(ClockSpeed::Unlimited);
+        }
+        let (number, multiplier) = if let Some(number) = text.strip_suffix("mhz") {
+            (number, 1_000_000.0)
+        } else if let Some(number) = text.strip_suffix("khz") {
+            (number, 1_000.0)
+        } else {
+            (text.strip_suffix("hz").unwrap_or(&text), 1.0)
+        };
+        let hz = (number.trim().parse::<f64>().ok()? * multiplier).round();
+        if hz >= 1.0 && hz <= u64::MAX as f64 {
+            Some(ClockSpeed::Hz(hz as u64))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ClockSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClockSpeed::Unlimited => write!(f, "unlimited"),
+            ClockSpeed::Hz(hz) if hz % 1_000_000 == 0 => write!(f, "{}MHz", hz / 1_000_000),
+            ClockSpeed::Hz(hz) if hz % 1_000 == 0 => write!(f, "{}kHz", hz / 1_000),
+            ClockSpeed::Hz(hz) => write!(f, "{}Hz", hz),
+        }
+    }
+}
+
+/// Throttles a CPU to a clock speed, so timing-sensitive programs and demos run as fast as they
+/// would on the real machine.
+///
+/// Set as `cpu.clock`, it is told the cycle count after every instruction and, every 10 ms of
+/// emulated time, sleeps until the wall clock has caught up with the cycles executed. The
+/// instructions in between run at full speed, so the timing is exact on average rather than per
+/// instruction. If the emulation falls well behind, e.g. while the debugger has it stopped, it
+/// carries on from there instead of racing to make up the time.
+///
+/// The clock can be paused, stepped, scaled and put into turbo through its `ClockControls`, see
+/// `controls`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::clock::{Clock, ClockSpeed};
+/// use r_6502::CPU;
+/// use std::time::{Duration, Instant};
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..3].copy_from_slice(&[0x4C, 0x00, 0x00]); // JMP $0000, forever
+/// cpu.clock = Some(Clock::new(ClockSpeed::Hz(100_000)));
+/// let started = Instant::now();
+/// cpu.run_for_cycles(5_000); // 50 ms at 100 kHz
+/// assert!(started.elapsed() >= Duration::from_millis(40));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Clock {
+    speed: ClockSpeed,
+    controls: ClockControls,
+    batch_cycles: u64,             // Cycles between two checks of the wall clock
+    start: Option<(Instant, u64)>, // When the cycle count was last in step, and the count then
+    next_check: u64,               // Cycle count at which to check the wall clock next
+    changes: u64,                  // The `controls` changes already taken into account
+}
+
+impl Clock {
+    pub fn new(speed: ClockSpeed) -> Self {
+        Clock {
+            speed,
+            controls: ClockControls::new(),
+            batch_cycles: u64::MAX,
+            start: None,
+            next_check: 0,
+            changes: 0,
+        }
+    }
+
+    /// Returns the speed the clock runs at, before any scaling.
+    pub fn speed(&self) -> ClockSpeed {
+        self.speed
+    }
+
+    /// Returns a handle that pauses, steps, scales and speeds up the clock.
+    ///
+    /// The handle can be cloned and moved to another thread, such as a front-end reading hotkeys,
+    /// and its changes take effect at the next instruction.
+    pub fn controls(&self) -> ClockControls {
+        self.controls.clone()
+    }
+
+    /// Accounts for the CPU having reached `cycles`, sleeping if it is ahead of real time.
+    pub fn tick(&mut self, cycles: u64) {
+        let changes = self.controls.0.changes.load(Ordering::Acquire);
+        if changes != self.changes {
+            self.changes = changes;
+            self.start = None; // Measure afresh at the new speed
+        }
+        let rate = match self.speed {
+            ClockSpeed::Hz(hz) if !self.controls.is_turbo() => hz as f64 * self.controls.scale(),
+            _ => return,
+        };
+        let (started, start_cycles) = match self.start {
+            Some(start) if cycles >= start.1 => start,
+            _ => {
+                self.rebase(cycles, rate);
+                return;
+            }
+        };
+        if cycles < self.next_check {
+            return;
+        }
+        self.next_check = cycles.saturating_add(self.batch_cycles);
+        let emulated = cycles - start_cycles;
+        let due = Duration::from_secs_f64(emulated as f64 / rate);
+        let elapsed = started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        } else if elapsed - due > MAX_LAG {
+            self.rebase(cycles, rate);
+        }
+    }
+
+    /// Starts measuring again from the current time and `cycles`, at `rate` cycles per second.
+    fn rebase(&mut self, cycles: u64, rate: f64) {
+        self.batch_cycles = (rate * BATCH.as_secs_f64()).max(1.0) as u64;
+        self.start = Some((Instant::now(), cycles));
+        self.next_check = cycles.saturating_add(self.batch_cycles);
+    }
+}
+
+/// Shared state behind `ClockControls`.
+#[derive(Debug)]
+struct Controls {
+    paused: AtomicBool,
+    turbo: AtomicBool,
+    steps: AtomicU64,   // Instructions still to let through while paused
+    scale: AtomicU64,   // The speed factor, as the bits of an `f64`
+    changes: AtomicU64, // Bumped whenever the speed changes, so the clock measures afresh
+}
+
+/// Speed controls for a `Clock`, obtained with `Clock::controls`.
+///
+/// While paused, `CPU::step` and `CPU::run_for_cycles` execute nothing, except for the
+/// instructions let through one at a time with `step`. The scale runs the clock from `MIN_SCALE`
+/// (0.1x) to `MAX_SCALE` (100x) of its speed, to watch a routine execute or get through a slow
+/// stretch sooner, and turbo runs it as fast as the host can go until turned off, e.g. to
+/// fast-forward through a boot sequence. None of them change what the program does, only how
+/// fast it does it in wall-clock time.
+///
+/// # Example
+/// ```rust
+/// use r_6502::clock::{Clock, ClockSpeed};
+/// use r_6502::CPU;
+/// use std::time::{Duration, Instant};
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..3].copy_from_slice(&[0x4C, 0x00, 0x00]); // JMP $0000, forever
+/// let clock = Clock::new(ClockSpeed::Hz(1_000));
+/// let controls = clock.controls();
+/// cpu.clock = Some(clock);
+///
+/// controls.pause();
+/// assert_eq!(cpu.run_for_cycles(100), 0);
+/// controls.step();
+/// assert_eq!(cpu.run_for_cycles(100), 3);
+///
+/// controls.resume();
+/// controls.set_scale(1000.0); // Clamped to 100x, so 100 kHz
+/// assert_eq!(controls.scale(), 100.0);
+/// controls.set_turbo(true);
+/// let started = Instant::now();
+/// cpu.run_for_cycles(100_000); // 100 s at 1 kHz, a second at 100x
+/// assert!(started.elapsed() < Duration::from_millis(500));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClockControls(Arc<Controls>);
+
+impl ClockControls {
+    fn new() -> Self {
+        ClockControls(Arc::new(Controls {
+            paused: AtomicBool::new(false),
+            turbo: AtomicBool::new(false),
+            steps: AtomicU64::new(0),
+            scale: AtomicU64::new(1.0f64.to_bits()),
+            changes: AtomicU64::new(0),
+        }))
+    }
+
+    /// Stops the CPU before its next instruction.
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::Release);
+    }
+
+    /// Lets the CPU run again, dropping any steps not yet taken.
+    pub fn resume(&self) {
+        self.0.steps.store(0, Ordering::Release);
+        self.0.paused.store(false, Ordering::Release);
+        self.changed();
+    }
+
+    /// Returns whether the clock is paused.
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::Acquire)
+    }
+
+    /// Lets one more instruction run while paused.
+    pub fn step(&self) {
+        self.0.steps.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Sets the speed factor, clamped to `MIN_SCALE..=MAX_SCALE`; 1.0 is the clock's own speed.
+    pub fn set_scale(&self, scale: f64) {
+        let scale = if scale.is_nan() {
+            1.0
+        } else {
+            scale.clamp(MIN_SCALE, MAX_SCALE)
+        };
+        self.0.scale.store(scale.to_bits(), Ordering::Release);
+        self.changed();
+    }
+
+    /// Returns the speed factor.
+    pub fn scale(&self) -> f64 {
+        f64::from_bits(self.0.scale.load(Ordering::Acquire))
+    }
+
+    /// Runs as fast as the host can go while `on`, whatever the clock's speed and scale.
+    pub fn set_turbo(&self, on: bool) {
+        self.0.turbo.store(on, Ordering::Release);
+        self.changed();
+    }
+
+    /// Returns whether turbo is on.
+    pub fn is_turbo(&self) -> bool {
+        self.0.turbo.load(Ordering::Acquire)
+    }
+
+    /// Returns whether the CPU may execute its next instruction, taking one of the steps let
+    /// through while paused.
+    pub(crate) fn take_turn(&self) -> bool {
+        !self.is_paused()
+            || self
+                .0
+                .steps
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |steps| {
+                    steps.checked_sub(1)
+                })
+                .is_ok()
+    }
+
+    /// Makes the clock measure afresh from its next tick.
+    fn changed(&self) {
+        self.0.changes.fetch_add(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::disassembler::encoded_length;
+use crate::symbol_map::SymbolMap;
+use crate::trace::TraceRecord;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// First bytes of a compact trace.
+pub const COMPACT_TRACE_MAGIC: &[u8; 4] = b"R6T\x01";
+
+// Bits of the flags byte that starts every record
+const A_CHANGED: u8 = 0x01;
+const X_CHANGED: u8 = 0x02;
+const Y_CHANGED: u8 = 0x04;
+const P_CHANGED: u8 = 0x08;
+const SP_CHANGED: u8 = 0x10;
+const JUMPED: u8 = 0x20; // The PC is not the address after the previous instruction
+const KNOWN_BYTES: u8 = 0x40; // The instruction bytes are the ones last traced at this PC
+
+/// Writes traces in a compact binary format, for runs too long to trace as text.
+///
+/// A trace starts with `COMPACT_TRACE_MAGIC` and the symbol table of the program, so the text
+/// conversion can show labels: the number of symbols, then each symbol's address (16-bit
+/// little-endian) and name (length and UTF-8 bytes). Each instruction traced is then one record:
+///
+/// - A flags byte, saying which registers changed since the previous record, whether the PC
+///   jumped rather than following on from the previous instruction, and whether the instruction
+///   bytes are the same as the last time the PC was traced.
+/// - The PC (16-bit little-endian), only if it jumped.
+/// - The opcode and operand bytes, only if they are not the same as last time.
+/// - The new value of each register that changed, in the order A, X, Y, P and SP.
+/// - The cycles elapsed since the previous record.
+///
+/// Counts, lengths and cycles are unsigned LEB128 numbers. In a loop, a record is typically two
+/// to four bytes, against some 80 for a line of text.
+///
+/// # Example
+/// ```rust
+/// use r_6502::compact_trace::{CompactTraceReader, CompactTraceWriter};
+/// use r_6502::trace::{trace_line, TraceRecord};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// let mut assembler = Assembler::new();
+/// assembler
+///     .assemble("LDX #$00\nloop:\nINX\nBNE loop", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// let mut writer = CompactTraceWriter::new(Vec::new(), assembler.symbol_table()).unwrap();
+/// let mut lines = Vec::new();
+/// while cpu.pc < end_address {
+///     writer.record(&TraceRecord::capture(&cpu)).unwrap();
+///     lines.push(trace_line(&cpu));
+///     cpu.step();
+/// }
+/// let bytes = writer.into_inner();
+/// assert_eq!(lines.len(), 513);
+/// assert!(bytes.len() < 2000);
+///
+/// let reader = CompactTraceReader::new(&bytes[..]).unwrap();
+/// assert_eq!(reader.symbols(), &[(0x0002, String::from("loop"))]);
+/// let decoded: Vec<String> = reader.map(|record| record.unwrap().to_string()).collect();
+/// assert_eq!(decoded, lines);
+/// ```
+pub struct CompactTraceWriter<W: Write> {
+    output: W,
+    bytes_written: u64,
+    previous: Option<TraceRecord>,
+    known: HashMap<u16, Vec<u8>>, // Instruction bytes last traced at each PC
+}
+
+impl<W: Write> CompactTraceWriter<W> {
+    /// Creates a writer, writing the header with `symbols` to `output`.
+    ///
+    /// # Errors
+    /// Returns any error writing to `output`.
+    pub fn new(mut output: W, symbols: &HashMap<String, u16>) -> io::Result<Self> {
+        let mut sorted: Vec<(&String, &u16)> = symbols.iter().collect();
+        sorted.sort_by_key(|(name, address)| (**address, name.as_str()));
+        let mut header = COMPACT_TRACE_MAGIC.to_vec();
+        write_number(&mut header, sorted.len() as u64);
+        for (name, address) in sorted {
+            header.extend_from_slice(&address.to_le_bytes());
+            write_number(&mut header, name.len() as u64);
+            header.extend_from_slice(name.as_bytes());
+        }
+        output.write_all(&header)?;
+        Ok(CompactTraceWriter {
+            output,
+            bytes_written: header.len() as u64,
+            previous: None,
+            known: HashMap::new(),
+        })
+    }
+
+    /// Appends a record to the trace.
+    ///
+    /// # Errors
+    /// Returns any error writing to the output.
+    pub fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let mut flags = 0;
+        let mut bytes = vec![0];
+        let registers = [record.a, record.x, record.y, record.p, record.sp];
+        let (previous_registers, next_pc, previous_cycles) = match &self.previous {
+            Some(previous) => (
+                Some([previous.a, previous.x, previous.y, previous.p, previous.sp]),
+                Some(previous.pc.wrapping_add(previous.bytes.len() as u16)),
+                previous.cycles,
+            ),
+            None => (None, None, 0),
+        };
+        if next_pc != Some(record.pc) {
+            flags |= JUMPED;
+            bytes.extend_from_slice(&record.pc.to_le_bytes());
+        }
+        if self.known.get(&record.pc) == Some(&record.bytes) {
+            flags |= KNOWN_BYTES;
+        } else {
+            bytes.extend_from_slice(&record.bytes);
+            self.known.insert(record.pc, record.bytes.clone());
+        }
+        for (index, flag) in [A_CHANGED, X_CHANGED, Y_CHANGED, P_CHANGED, SP_CHANGED]
+            .into_iter()
+            .enumerate()
+        {
+            if previous_registers.map(|previous| previous[index]) != Some(registers[index]) {
+                flags |= flag;
+                bytes.push(registers[index]);
+            }
+        }
+        write_number(&mut bytes, record.cycles.wrapping_sub(previous_cycles));
+        bytes[0] = flags;
+        self.output.write_all(&bytes)?;
+        self.bytes_written += bytes.len() as u64;
+        self.previous = Some(record.clone());
+        Ok(())
+    }
+
+    /// Returns the size of the trace written so far, header included.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Flushes the output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    /// Returns the output, with everything written so far.
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+/// Reads back a trace written by `CompactTraceWriter`, record by record.
+///
+/// The reader is an iterator over the records. Reading from a file is best done through a
+/// `BufReader`, as the records are read a few bytes at a time.
+pub struct CompactTraceReader<R: Read> {
+    input: R,
+    symbols: Vec<(u16, String)>,
+    previous: Option<TraceRecord>,
+    known: HashMap<u16, Vec<u8>>,
+}
+
+impl<R: Read> CompactTraceReader<R> {
+    /// Creates a reader, reading the header from `input`.
+    ///
+    /// # Errors
+    /// Returns an `InvalidData` error if `input` is not a compact trace, or any error reading it.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != COMPACT_TRACE_MAGIC {
+            return Err(invalid_data("not a compact trace"));
+        }
+        let count = read_number(&mut input)?;
+        let mut symbols = Vec::new();
+        for _ in 0..count {
+            let mut address = [0; 2];
+            input.read_exact(&mut address)?;
+            let mut name = vec![0; read_number(&mut input)? as usize];
+            input.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid_data("invalid symbol name"))?;
+            symbols.push((u16::from_le_bytes(address), name));
+        }
+        Ok(CompactTraceReader {
+            input,
+            symbols,
+            previous: None,
+            known: HashMap::new(),
+        })
+    }
+
+    /// Returns the symbols of the traced program, by address.
+    pub fn symbols(&self) -> &[(u16, String)] {
+        &self.symbols
+    }
+
+    /// Reads the next record, or `None` at the end of the trace.
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut flags = [0];
+        if self.input.read(&mut flags)? == 0 {
+            return Ok(None);
+        }
+        let flags = flags[0];
+        let mut record = match &self.previous {
+            Some(previous) => TraceRecord {
+                pc: previous.pc.wrapping_add(previous.bytes.len() as u16),
+                ..previous.clone()
+            },
+            None if flags & JUMPED != 0 => TraceRecord {
+                pc: 0,
+                bytes: Vec::new(),
+                a: 0,
+                x: 0,
+                y: 0,
+                p: 0,
+                sp: 0,
+                cycles: 0,
+            },
+            None => return Err(invalid_data("first record has no PC")),
+        };
+        if flags & JUMPED != 0 {
+            let mut pc = [0; 2];
+            self.input.read_exact(&mut pc)?;
+            record.pc = u16::from_le_bytes(pc);
+        }
+        if flags & KNOWN_BYTES != 0 {
+            record.bytes = match self.known.get(&record.pc) {
+                Some(bytes) => bytes.clone(),
+                None => return Err(invalid_data("instruction bytes missing")),
+            };
+        } else {
+            let mut opcode = [0];
+            self.input.read_exact(&mut opcode)?;
+            let mut bytes = vec![0; encoded_length(opcode[0]) as usize];
+            bytes[0] = opcode[0];
+            self.input.read_exact(&mut bytes[1..])?;
+            self.known.insert(record.pc, bytes.clone());
+            record.bytes = bytes;
+        }
+        for (flag, register) in [
+            (A_CHANGED, &mut record.a),
+            (X_CHANGED, &mut record.x),
+            (Y_CHANGED, &mut record.y),
+            (P_CHANGED, &mut record.p),
+            (SP_CHANGED, &mut record.sp),
+        ] {
+            if flags & flag != 0 {
+                let mut value = [0];
+                self.input.read_exact(&mut value)?;
+                *register = value[0];
+            }
+        }
+        record.cycles = record.cycles.wrapping_add(read_number(&mut self.input)?);
+        self.previous = Some(record.clone());
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read> Iterator for CompactTraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Converts a compact trace to text, one `trace_line` per record, with a `label:` line before
+/// every record at the address of a symbol and the addresses instructions use shown by name.
+///
+/// # Returns
+/// The number of records converted.
+///
+/// # Errors
+/// Returns any error reading the trace or writing the text.
+pub fn write_text<R: Read, W: Write>(
+    reader: CompactTraceReader<R>,
+    output: &mut W,
+) -> io::Result<u64> {
+    let mut symbols = SymbolMap {
+        labels: reader.symbols().to_vec(),
+    };
+    symbols.labels.sort();
+    let mut count = 0;
+    for record in reader {
+        let record = record?;
+        for (_, name) in symbols
+            .labels
+            .iter()
+            .filter(|(address, _)| *address == record.pc)
+        {
+            writeln!(output, "{}:", name)?;
+        }
+        writeln!(output, "{}", record.format(&symbols))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Appends an unsigned LEB128 number: seven bits per byte, lowest first, with bit 7 set on every
+/// byte but the last.
+fn write_number(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 number.
+fn read_number<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7F) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("number too long"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,628 @@
+use crate::bus::Device;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// ANSI sequence clearing the terminal and moving the cursor to the top left.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// How the bytes a program writes to the console map to text on the host terminal.
+///
+/// Every byte maps to a string: printable characters to themselves (or their Unicode equivalent),
+/// control codes to the ANSI escape sequence doing the same on the terminal, and anything else to
+/// nothing. `ascii` and `petscii` cover the common machines; `set` adjusts a table or builds a
+/// custom one from `Encoding::empty`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::console::Encoding;
+///
+/// let petscii = Encoding::petscii();
+/// assert_eq!(petscii.decode(&[0x48, 0x49, 0xC1]), "hiA");
+/// assert_eq!(petscii.translate(0x93), "\x1b[2J\x1b[H"); // Clear screen
+///
+/// let mut custom = Encoding::ascii();
+/// custom.set(0x00, "\n");
+/// assert_eq!(custom.decode(b"OK\0"), "OK\n");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encoding {
+    table: Vec<String>, // Text for each of the 256 byte values
+}
+
+impl Encoding {
+    /// Returns an encoding mapping every byte to nothing, to fill in with `set`.
+    pub fn empty() -> Self {
+        Encoding {
+            table: vec![String::new(); 256],
+        }
+    }
+
+    /// Returns 7-bit ASCII: printable characters, with tab, line feed, carriage return, backspace
+    /// and bell passed through, form feed clearing the screen and DEL erasing the last character.
+    /// Bytes with bit 7 set are dropped.
+    pub fn ascii() -> Self {
+        let mut encoding = Self::empty();
+        for byte in 0x20..=0x7E {
+            encoding.set(byte, &(byte as char).to_string());
+        }
+        for byte in [0x07, 0x08, 0x09, 0x0A, 0x0D] {
+            encoding.set(byte, &(byte as char).to_string());
+        }
+        encoding.set(0x0C, CLEAR_SCREEN);
+        encoding.set(0x7F, "\x08 \x08");
+        encoding
+    }
+
+    /// Returns the PETSCII of the Commodore 8-bit machines, in the text (lowercase) character set:
+    /// `$41`-`$5A` are lowercase letters and `$61`-`$7A` and `$C1`-`$DA` uppercase.
+    ///
+    /// Return starts a new line, and the cursor, clear, home, delete, reverse and colour codes
+    /// become the ANSI sequences doing the same. Graphics characters are dropped.
+    pub fn petscii() -> Self {
+        let mut encoding = Self::empty();
+        for byte in 0x20..=0x40 {
+            encoding.set(byte, &(byte as char).to_string());
+        }
+        for letter in 0..26 {
+            let lower = (b'a' + letter) as char;
+            let upper = (b'A' + letter) as char;
+            encoding.set(0x41 + letter, &lower.to_string());
+            encoding.set(0x61 + letter, &upper.to_string());
+            encoding.set(0xC1 + letter, &upper.to_string());
+        }
+        for (byte, text) in [
+            (0x5B, "["),
+            (0x5C, "£"),
+            (0x5D, "]"),
+            (0x5E, "↑"),
+            (0x5F, "←"),
+            (0xA0, " "),
+            (0x0D, "\n"),
+            (0x8D, "\n"),
+            (0x93, CLEAR_SCREEN),
+            (0x13, "\x1b[H"),
+            (0x11, "\x1b[B"),
+            (0x91, "\x1b[A"),
+            (0x1D, "\x1b[C"),
+            (0x9D, "\x1b[D"),
+            (0x14, "\x08 \x08"),
+            (0x12, "\x1b[7m"),
+            (0x92, "\x1b[27m"),
+        ] {
+            encoding.set(byte, text);
+        }
+        // Colours, approximated by the nearest ANSI foreground colour
+        for (byte, colour) in [
+            (0x05, 97), // White
+            (0x1C, 31), // Red
+            (0x1E, 32), // Green
+            (0x1F, 34), // Blue
+            (0x81, 33), // Orange
+            (0x90, 30), // Black
+            (0x95, 33), // Brown
+            (0x96, 91), // Light red
+            (0x97, 90), // Dark grey
+            (0x98, 37), // Grey
+            (0x99, 92), // Light green
+            (0x9A, 94), // Light blue
+            (0x9B, 37), // Light grey
+            (0x9C, 35), // Purple
+            (0x9E, 93), // Yellow
+            (0x9F, 36), // Cyan
+        ] {
+            encoding.set(byte, &format!("\x1b[{}m", colour));
+        }
+        encoding
+    }
+
+    /// Returns the encoding called `name` (`ascii` or `petscii`, in any case), if there is one.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ascii" => Some(Self::ascii()),
+            "petscii" => Some(Self::petscii()),
+            _ => None,
+        }
+    }
+
+    /// Maps `byte` to `text`.
+    pub fn set(&mut self, byte: u8, text: &str) {
+        self.table[byte as usize] = text.to_string();
+    }
+
+    /// Returns the text `byte` maps to, which is empty if it has no meaning in this encoding.
+    pub fn translate(&self, byte: u8) -> &str {
+        &self.table[byte as usize]
+    }
+
+    /// Translates a sequence of bytes.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| self.translate(*byte)).collect()
+    }
+}
+
+/// How a console passes the keys typed on the host to the program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputMode {
+    #[default]
+    None, // No input: reads return $00
+    Raw,    // Every byte as it arrives, as monitors like Wozmon expect
+    Cooked, // Whole lines, once edited and entered (see `LineEditor`)
+}
+
+impl InputMode {
+    /// Returns the mode called `name` (`none`, `raw` or `cooked`, in any case), if there is one.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(InputMode::None),
+            "raw" => Some(InputMode::Raw),
+            "cooked" => Some(InputMode::Cooked),
+            _ => None,
+        }
+    }
+}
+
+/// The bytes a cooked console ends each entered line with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Newline {
+    #[default]
+    Cr, // Carriage return, as the Apple and Commodore machines use
+    Lf,   // Line feed
+    CrLf, // Carriage return then line feed
+}
+
+impl Newline {
+    /// Returns the newline called `name` (`cr`, `lf` or `crlf`, in any case), if there is one.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cr" => Some(Newline::Cr),
+            "lf" => Some(Newline::Lf),
+            "crlf" => Some(Newline::CrLf),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of the newline.
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            Newline::Cr => b"\r",
+            Newline::Lf => b"\n",
+            Newline::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// The input settings of a console.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConsoleInput {
+    pub mode: InputMode,
+    pub newline: Newline, // Ending of cooked lines
+    pub echo: bool,       // Show cooked lines as they are edited, for hosts that do not echo keys
+}
+
+/// Erases the character before the cursor on the terminal.
+const RUBOUT: &str = "\x08 \x08";
+
+/// A line discipline with readline-style editing, turning the keys typed on the host into whole
+/// lines for a program to read.
+///
+/// Keys are collected into a line until return (a carriage return, a line feed, or both) enters
+/// it; the program then reads the line followed by the `Newline`, and nothing before. While a line
+/// is being typed:
+///
+/// - Backspace and DEL erase the last character, Ctrl-U the whole line and Ctrl-W the last word.
+/// - The up and down arrows step back and forth through the lines entered before.
+/// - Other control characters are ignored.
+///
+/// `key` returns the text that shows the edit on a terminal, for consoles that echo.
+///
+/// # Example
+/// ```rust
+/// use r_6502::console::{LineEditor, Newline};
+///
+/// let mut editor = LineEditor::new(Newline::Cr);
+/// for key in b"PRINT 1\x08\x082\r\n" {
+///     editor.key(*key);
+/// }
+/// assert_eq!(editor.take_line(), b"PRINT2\r");
+///
+/// for key in b"LIST\x15\x1b[A\x7f3\n" {
+///     editor.key(*key); // Clear the line, recall PRINT2 and change its 2 to a 3
+/// }
+/// assert_eq!(editor.take_line(), b"PRINT3\r");
+/// assert_eq!(editor.history(), &[b"PRINT2".to_vec(), b"PRINT3".to_vec()]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct LineEditor {
+    newline: Newline,
+    line: Vec<u8>,           // Line being edited
+    history: Vec<Vec<u8>>,   // Lines entered, oldest first
+    recalled: Option<usize>, // Index of the history line being shown, if one is
+    escape: Option<Vec<u8>>, // Escape sequence being received
+    after_cr: bool,          // The last key was a carriage return, so a line feed is skipped
+    entered: VecDeque<u8>,   // Entered lines not read yet
+}
+
+impl LineEditor {
+    /// Creates an editor ending each line with `newline`.
+    pub fn new(newline: Newline) -> Self {
+        LineEditor {
+            newline,
+            ..LineEditor::default()
+        }
+    }
+
+    /// Returns the lines entered so far, oldest first, without repeats of the line before.
+    pub fn history(&self) -> &[Vec<u8>] {
+        &self.history
+    }
+
+    /// Takes the next byte of the entered lines, if there is one.
+    pub fn next_byte(&mut self) -> Option<u8> {
+        self.entered.pop_front()
+    }
+
+    /// Takes all the bytes of the entered lines.
+    pub fn take_line(&mut self) -> Vec<u8> {
+        self.entered.drain(..).collect()
+    }
+
+    /// Passes the line being typed on to be read as it is, without a newline and without adding
+    /// it to the history, as when the console switches to raw input.
+    pub fn flush_line(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        self.entered.extend(line);
+        self.recalled = None;
+        self.escape = None;
+        self.after_cr = false;
+    }
+
+    /// Handles a key typed on the host.
+    ///
+    /// # Returns
+    /// The text that shows the change on a terminal.
+    pub fn key(&mut self, key: u8) -> String {
+        let after_cr = std::mem::replace(&mut self.after_cr, key == b'\r');
+        if let Some(mut escape) = self.escape.take() {
+            escape.push(key);
+            return match escape[..] {
+                [b'[' | b'O'] => {
+                    self.escape = Some(escape);
+                    String::new()
+                }
+                [b'[' | b'O', b'A'] => self.recall_older(),
+                [b'[' | b'O', b'B'] => self.recall_newer(),
+                // Keep collecting the parameters of other sequences, to ignore them whole
+                [b'[', .., 0x20..=0x3F] => {
+                    self.escape = Some(escape);
+                    String::new()
+                }
+                _ => String::new(),
+            };
+        }
+        match key {
+            b'\n' if after_cr => String::new(),
+            b'\r' | b'\n' => {
+                let line = std::mem::take(&mut self.line);
+                self.entered.extend(&line);
+                self.entered.extend(self.newline.bytes());
+                if !line.is_empty() && self.history.last() != Some(&line) {
+                    self.history.push(line);
+                }
+                self.recalled = None;
+                String::from("\n")
+            }
+            0x08 | 0x7F => match self.line.pop() {
+                Some(_) => String::from(RUBOUT),
+                None => String::new(),
+            },
+            0x15 => self.replace_line(Vec::new()),
+            0x17 => {
+                let kept = self.line.trim_ascii_end().len();
+                let word = self.line[..kept]
+                    .iter()
+                    .rposition(u8::is_ascii_whitespace)
+                    .map_or(0, |space| space + 1);
+                let erased = self.line.len() - word;
+                self.line.truncate(word);
+                RUBOUT.repeat(erased)
+            }
+            0x1B => {
+                self.escape = Some(Vec::new());
+                String::new()
+            }
+            0x09 | 0x20.. => {
+                self.line.push(key);
+                String::from_utf8_lossy(&[key]).into_owned()
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Shows the history line before the one shown, if there is one.
+    fn recall_older(&mut self) -> String {
+        let index = match self.recalled {
+            Some(0) => return String::new(),
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return String::new(),
+            None => self.history.len() - 1,
+        };
+        self.recalled = Some(index);
+        self.replace_line(self.history[index].clone())
+    }
+
+    /// Shows the history line after the one shown, or an empty line after the newest.
+    fn recall_newer(&mut self) -> String {
+        match self.recalled {
+            Some(index) if index + 1 < self.history.len() => {
+                self.recalled = Some(index + 1);
+                self.replace_line(self.history[index + 1].clone())
+            }
+            Some(_) => {
+                self.recalled = None;
+                self.replace_line(Vec::new())
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Replaces the line being edited, returning the text that redraws it.
+    fn replace_line(&mut self, line: Vec<u8>) -> String {
+        let erase = RUBOUT.repeat(self.line.len());
+        self.line = line;
+        erase + &String::from_utf8_lossy(&self.line)
+    }
+}
+
+/// Returns the bytes typed on stdin, read on a background thread so a program keeps running while
+/// no key is pressed.
+pub fn stdin_keys() -> Receiver<u8> {
+    let (keys, input) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in std::io::stdin().lock().bytes() {
+            match byte {
+                Ok(byte) if keys.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+    input
+}
+
+/// A character device: every byte written to its register is translated with an `Encoding` and
+/// written to the host, and reading it returns the next byte of input.
+///
+/// The device occupies a single address. Without input, or while none is waiting, reading it
+/// returns `$00`. With `InputMode::Raw` the program reads the host's bytes as they arrive, and
+/// with `InputMode::Cooked` whole lines once they are entered, edited by a `LineEditor` on the
+/// way. `set_input` switches between them while the program runs.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bus::Device;
+/// use r_6502::console::{Console, ConsoleInput, Encoding, InputMode, Newline};
+/// use r_6502::Memory;
+/// use std::sync::mpsc;
+///
+/// let mut memory = Memory::new();
+/// let console = Console::new(Encoding::ascii(), Box::new(std::io::stdout()));
+/// memory.attach(0xF001, 0xF001, "Console", Box::new(console));
+///
+/// let (keys, input) = mpsc::channel();
+/// let cooked = ConsoleInput {
+///     mode: InputMode::Cooked,
+///     newline: Newline::Lf,
+///     echo: false,
+/// };
+/// let mut console =
+///     Console::new(Encoding::ascii(), Box::new(std::io::sink())).with_input(input, cooked);
+/// for key in b"RUM\x7fN" {
+///     keys.send(*key).unwrap();
+/// }
+/// assert_eq!(console.read(0), 0x00); // Not entered yet
+/// keys.send(b'\r').unwrap();
+/// let line: Vec<u8> = (0..5).map(|_| console.read(0)).collect();
+/// assert_eq!(line, b"RUN\n\0");
+/// ```
+pub struct Console {
+    encoding: Encoding,
+    output: Box<dyn Write>,
+    input: Option<Receiver<u8>>, // Keys from the host, if the console was given any
+    mode: InputMode,
+    editor: LineEditor, // Line discipline of cooked input, kept across modes for its history
+    echo: bool,         // Write the edits of cooked input to `output`
+}
+
+impl Console {
+    /// Creates a console translating with `encoding` and writing to `output`, without input.
+    pub fn new(encoding: Encoding, output: Box<dyn Write>) -> Self {
+        Console {
+            encoding,
+            output,
+            input: None,
+            mode: InputMode::None,
+            editor: LineEditor::default(),
+            echo: false,
+        }
+    }
+
+    /// Gives the console the keys from `input`, passed to the program as `settings` says.
+    pub fn with_input(mut self, input: Receiver<u8>, settings: ConsoleInput) -> Self {
+        self.input = Some(input);
+        self.set_input(settings);
+        self
+    }
+
+    /// Changes how the console passes the keys from `with_input` to the program.
+    ///
+    /// Switching from cooked to raw input passes on the line being typed as it is, after any
+    /// entered lines the program has not read; switching from raw to cooked starts editing a new
+    /// line with the keys not read yet. The history of entered lines is kept.
+    pub fn set_input(&mut self, settings: ConsoleInput) {
+        if self.mode == InputMode::Cooked && settings.mode != InputMode::Cooked {
+            self.editor.flush_line();
+        }
+        self.mode = settings.mode;
+        self.editor.newline = settings.newline;
+        self.echo = settings.echo;
+    }
+
+    /// Writes the echo of edited input to the host.
+    fn print_echo(&mut self, text: &str) {
+        if !text.is_empty() {
+            let _ = self.output.write_all(text.as_bytes());
+            let _ = self.output.flush();
+        }
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, _offset: u16) -> u8 {
+        let input = match (&self.input, self.mode) {
+            (Some(input), InputMode::Raw | InputMode::Cooked) => input,
+            _ => return 0,
+        };
+        if self.mode == InputMode::Raw {
+            // Bytes left from cooked input come first
+            return match self.editor.next_byte() {
+                Some(byte) => byte,
+                None => input.try_recv().unwrap_or(0),
+            };
+        }
+        let mut echoed = String::new();
+        while let Ok(key) = input.try_recv() {
+            echoed += &self.editor.key(key);
+        }
+        if self.echo {
+            self.print_echo(&echoed);
+        }
+        self.editor.next_byte().unwrap_or(0)
+    }
+
+    fn write(&mut self, _offset: u16, value: u8) {
+        let text = self.encoding.translate(value);
+        if !text.is_empty() {
+            // Output the host can no longer take should not stop the program
+            let _ = self.output.write_all(text.as_bytes());
+            let _ = self.output.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    /// Types `keys` into `editor`, returning the echo.
+    fn type_keys(editor: &mut LineEditor, keys: &[u8]) -> String {
+        keys.iter().map(|key| editor.key(*key)).collect()
+    }
+
+    /// Returns a console taking keys as `mode` says, and the sender of its keys.
+    fn console(mode: InputMode) -> (Console, Sender<u8>) {
+        let (keys, input) = mpsc::channel();
+        let console = Console::new(Encoding::ascii(), Box::new(std::io::sink()))
+            .with_input(input, input_mode(mode));
+        (console, keys)
+    }
+
+    /// Returns the settings of `mode`, ending cooked lines with carriage returns.
+    fn input_mode(mode: InputMode) -> ConsoleInput {
+        ConsoleInput {
+            mode,
+            ..ConsoleInput::default()
+        }
+    }
+
+    /// Reads `count` bytes from the console.
+    fn read(console: &mut Console, count: usize) -> Vec<u8> {
+        (0..count).map(|_| console.read(0)).collect()
+    }
+
+    #[test]
+    fn backspace_at_the_start_of_a_line_does_nothing() {
+        let mut editor = LineEditor::new(Newline::Cr);
+        assert_eq!(type_keys(&mut editor, b"\x08\x7f\x17\x15"), "");
+        assert_eq!(type_keys(&mut editor, b"A\x08\x08B\r"), "A\x08 \x08B\n");
+        assert_eq!(editor.take_line(), b"B\r");
+
+        // An entered line is out of reach
+        assert_eq!(type_keys(&mut editor, b"\x08C\r"), "C\n");
+        assert_eq!(editor.take_line(), b"C\r");
+    }
+
+    #[test]
+    fn history_stops_at_either_end() {
+        let mut editor = LineEditor::new(Newline::Lf);
+        assert_eq!(type_keys(&mut editor, b"\x1b[A\x1b[B"), "");
+        type_keys(&mut editor, b"ONE\rTWO\r");
+        editor.take_line();
+
+        // Past the oldest line
+        assert_eq!(type_keys(&mut editor, b"\x1b[A"), "TWO");
+        assert_eq!(
+            type_keys(&mut editor, b"\x1b[A"),
+            format!("{}ONE", RUBOUT.repeat(3))
+        );
+        assert_eq!(type_keys(&mut editor, b"\x1b[A"), "");
+        type_keys(&mut editor, b"\n");
+        assert_eq!(editor.take_line(), b"ONE\n");
+
+        // Past the newest line, to an empty line and no further
+        type_keys(&mut editor, b"\x1bOA\x1bOA");
+        assert_eq!(
+            type_keys(&mut editor, b"\x1b[B"),
+            format!("{}ONE", RUBOUT.repeat(3))
+        );
+        assert_eq!(type_keys(&mut editor, b"\x1b[B"), RUBOUT.repeat(3));
+        assert_eq!(type_keys(&mut editor, b"\x1b[B"), "");
+        type_keys(&mut editor, b"\r");
+        assert_eq!(editor.take_line(), b"\n");
+        assert_eq!(
+            editor.history(),
+            &[b"ONE".to_vec(), b"TWO".to_vec(), b"ONE".to_vec()]
+        );
+    }
+
+    #[test]
+    fn switching_to_raw_passes_on_a_half_typed_line() {
+        let (mut console, keys) = console(InputMode::Cooked);
+        for key in b"AB\rCD" {
+            keys.send(*key).unwrap();
+        }
+        assert_eq!(read(&mut console, 1), b"A");
+
+        console.set_input(input_mode(InputMode::Raw));
+        assert_eq!(read(&mut console, 5), b"B\rCD\0");
+        keys.send(0x08).unwrap();
+        assert_eq!(read(&mut console, 2), b"\x08\0");
+    }
+
+    #[test]
+    fn switching_to_cooked_edits_the_keys_not_read() {
+        let (mut console, keys) = console(InputMode::Raw);
+        for key in b"XY" {
+            keys.send(*key).unwrap();
+        }
+        assert_eq!(read(&mut console, 1), b"X");
+
+        console.set_input(input_mode(InputMode::Cooked));
+        assert_eq!(read(&mut console, 1), b"\0");
+        for key in b"\x7fZ\r" {
+            keys.send(*key).unwrap();
+        }
+        assert_eq!(read(&mut console, 3), b"Z\r\0");
+
+        // Switching off input and back keeps the history
+        console.set_input(input_mode(InputMode::None));
+        keys.send(b'Q').unwrap();
+        assert_eq!(read(&mut console, 1), b"\0");
+        console.set_input(input_mode(InputMode::Cooked));
+        for key in b"\x7f\x1b[A\r" {
+            keys.send(*key).unwrap();
+        }
+        assert_eq!(read(&mut console, 2), b"Z\r");
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// An instruction the CPU has finished executing, as reported to a co-simulation model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retirement {
+    pub pc: u16, // Address of the instruction
+    pub opcode: u8,
+    pub cycles: u64,       // Clock cycles the instruction took
+    pub total_cycles: u64, // Value of `cpu.cycles` after the instruction
+    // Registers after the instruction
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+}
+
+/// The lines a co-simulation model drives into the CPU after an instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Signals {
+    pub irq: bool, // Request a maskable interrupt, taken unless interrupts are disabled
+    pub nmi: bool, // Signal a non-maskable interrupt
+}
+
+/// An external model simulated alongside the CPU, such as the rest of a system-level emulator or
+/// an FPGA verification bench checking its own CPU against this one.
+///
+/// Set as `cpu.cosim`, the model is told about every instruction the CPU retires, after its
+/// cycles are counted and the devices are clocked, and advances itself by the instruction's
+/// cycles. What it returns is applied before the next instruction. Any `FnMut(&Retirement) ->
+/// Signals` closure is a model; `ChannelModel` runs one on another thread.
+///
+/// # Example
+/// ```rust
+/// use r_6502::cosim::{Retirement, Signals};
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..3].copy_from_slice(&[0xE8, 0xE8, 0xE8]); // INX x3
+/// cpu.memory.data[0xFFFA..=0xFFFB].copy_from_slice(&[0x00, 0x80]); // NMI handler at $8000
+/// let mut elapsed = 0;
+/// cpu.cosim = Some(Box::new(move |retired: &Retirement| {
+///     elapsed += retired.cycles;
+///     Signals { irq: false, nmi: elapsed == 4 } // After the second instruction
+/// }));
+/// cpu.step();
+/// cpu.step();
+/// assert_eq!(cpu.pc, 0x8000);
+/// ```
+pub trait CoSimulation {
+    /// Advances the model past an instruction the CPU retired.
+    fn retire(&mut self, retired: &Retirement) -> Signals;
+}
+
+impl<F: FnMut(&Retirement) -> Signals> CoSimulation for F {
+    fn retire(&mut self, retired: &Retirement) -> Signals {
+        self(retired)
+    }
+}
+
+/// A model on the other end of a pair of channels, for simulations running on their own thread.
+///
+/// Every retirement is sent on one channel, and the CPU then waits for the signals to come back
+/// on the other, so both sides stay in lockstep. If the other side hangs up, the CPU carries on
+/// without signals.
+///
+/// # Example
+/// ```rust
+/// use r_6502::cosim::{ChannelModel, Signals};
+/// use r_6502::CPU;
+/// use std::thread;
+///
+/// let (model, retirements, signals) = ChannelModel::new();
+/// let bench = thread::spawn(move || {
+///     let mut pcs = Vec::new();
+///     for retired in retirements {
+///         pcs.push(retired.pc);
+///         signals.send(Signals::default()).unwrap();
+///     }
+///     pcs
+/// });
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..2].copy_from_slice(&[0xE8, 0xC8]); // INX, INY
+/// cpu.cosim = Some(Box::new(model));
+/// cpu.step();
+/// cpu.step();
+/// cpu.cosim = None; // Hangs up, ending the bench
+/// assert_eq!(bench.join().unwrap(), [0x0000, 0x0001]);
+/// ```
+pub struct ChannelModel {
+    retirements: Sender<Retirement>,
+    signals: Receiver<Signals>,
+}
+
+impl ChannelModel {
+    /// Creates a model, with the receiver of its retirements and the sender of its signals for
+    /// the simulation to use.
+    pub fn new() -> (Self, Receiver<Retirement>, Sender<Signals>) {
+        let (retirement_sender, retirement_receiver) = mpsc::channel();
+        let (signal_sender, signal_receiver) = mpsc::channel();
+        let model = ChannelModel {
+            retirements: retirement_sender,
+            signals: signal_receiver,
+        };
+        (model, retirement_receiver, signal_sender)
+    }
+}
+
+impl CoSimulation for ChannelModel {
+    fn retire(&mut self, retired: &Retirement) -> Signals {
+        if self.retirements.send(*retired).is_err() {
+            return Signals::default();
+        }
+        self.signals.recv().unwrap_or_default()
+    }
+}
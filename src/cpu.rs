@@ -1,44 +1,744 @@
+use crate::asm_runner::{execute_instruction, StopReason};
+use crate::breakpoint::Breakpoints;
+use crate::bus::Bus;
+use crate::clock::Clock;
+use crate::compact_trace::CompactTraceWriter;
+use crate::cosim::CoSimulation;
+use crate::event_log::{EventKind, EventLog};
+use crate::guard::GuardRegions;
+use crate::interrupts::InterruptMonitor;
+use crate::limits::ResourceLimits;
+use crate::markers::Markers;
 use crate::memory::{self, Memory};
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::mmu::Mmu;
+use crate::patch::PatchSet;
+use crate::self_write::SelfWriteMonitor;
+use crate::symbol_map::SymbolMap;
+use crate::taint::TaintTracker;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 
+// Bits of the processor status register (`NV-BDIZC`)
+pub const CARRY: u8 = 0x01;
+pub const ZERO: u8 = 0x02;
+pub const INTERRUPT_DISABLE: u8 = 0x04;
+pub const DECIMAL: u8 = 0x08;
+pub const BREAK: u8 = 0x10; // Only exists in copies of P pushed by `PHP` and `BRK`
+pub const UNUSED: u8 = 0x20; // Always reads back as set
+pub const OVERFLOW: u8 = 0x40;
+pub const NEGATIVE: u8 = 0x80;
+
+/// A CPU register, for code that picks registers at run time, such as debuggers, pre-state files
+/// and breakpoint conditions.
+///
+/// `CPU::get` and `CPU::set` read and write any register through it as a 16-bit value, so the
+/// 8-bit registers and the 16-bit program counter are handled alike.
+///
+/// # Example
+/// ```rust
+/// use r_6502::cpu::Register;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// let register = Register::parse("y").unwrap();
+/// cpu.set(register, 0x42);
+/// assert_eq!(cpu.y, 0x42);
+/// cpu.set(Register::PC, 0x8000);
+/// assert_eq!(cpu.get(Register::PC), 0x8000);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    P, // Processor status, as `CPU::get_status` returns it
+    PC,
+}
+
+impl Register {
+    /// Every register, in the order the debugger and traces show them.
+    pub const ALL: [Register; 6] = [
+        Register::PC,
+        Register::SP,
+        Register::A,
+        Register::X,
+        Register::Y,
+        Register::P,
+    ];
+
+    /// Parses a register from its name, e.g. `A` or `sp`.
+    pub fn parse(name: &str) -> Option<Register> {
+        match name.to_ascii_uppercase().as_str() {
+            "A" => Some(Register::A),
+            "X" => Some(Register::X),
+            "Y" => Some(Register::Y),
+            "SP" => Some(Register::SP),
+            "P" => Some(Register::P),
+            "PC" => Some(Register::PC),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the register holds 8 bits, as every register but the program counter does.
+    pub fn is_8_bit(self) -> bool {
+        self != Register::PC
+    }
+}
+
+/// What the CPU does with an opcode it has no implementation for.
+///
+/// Undocumented opcodes trap by default. Setting `cpu.illegal_opcodes` executes the stable
+/// undocumented NMOS instructions that test ROMs and some games rely on, and `cpu.unknown_opcode`
+/// decides what happens with the rest.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::cpu::UnknownOpcode;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.illegal_opcodes = true;
+/// cpu.unknown_opcode = UnknownOpcode::Nop;
+/// // LAX $10, then a JAM that would halt the real chip, then INX
+/// cpu.memory.data[0..4].copy_from_slice(&[0xA7, 0x10, 0x02, 0xE8]);
+/// cpu.memory.data[0x10] = 0x42;
+/// run_until(&mut cpu, &EndConditions::program_end(4));
+/// assert_eq!((cpu.a, cpu.x), (0x42, 0x43));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownOpcode {
+    #[default]
+    Trap, // Stop with `StopReason::IllegalOpcode` (`execute_instruction` panics)
+    Nop, // Skip the opcode byte, taking 2 cycles
+}
+
+/// Executes opcodes the CPU has no implementation for, such as custom trap instructions or
+/// operating system hooks, without patching the decode loop.
+///
+/// Set as `cpu.opcode_handler`, the handler is offered every opcode that is neither documented nor
+/// an undocumented one the CPU is configured to execute (see `cpu.illegal_opcodes`). The opcodes it
+/// declines are handled as `cpu.unknown_opcode` says, so by default they still stop the run with
+/// `StopReason::IllegalOpcode`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+/// use r_6502::cpu::OpcodeHandler;
+/// use r_6502::CPU;
+///
+/// /// `$02 nn`: loads A with the version number of service `nn`.
+/// struct Services;
+///
+/// impl OpcodeHandler for Services {
+///     fn handles(&self, opcode: u8) -> bool {
+///         opcode == 0x02
+///     }
+///
+///     fn execute(&mut self, cpu: &mut CPU, _opcode: u8, data_cycle_count: &mut u32) -> u64 {
+///         let service = cpu.fetch_address_value(data_cycle_count);
+///         cpu.a = service + 0x10;
+///         6
+///     }
+/// }
+///
+/// let mut cpu = CPU::new();
+/// cpu.opcode_handler = Some(Box::new(Services));
+/// cpu.memory.data[0..4].copy_from_slice(&[0x02, 0x01, 0xE8, 0x03]); // Service 1, INX, then $03
+/// let reason = try_run_until(&mut cpu, &EndConditions::program_end(4));
+/// assert_eq!((cpu.a, cpu.x, cpu.cycles), (0x11, 1, 8));
+/// assert_eq!(reason, StopReason::IllegalOpcode { address: 3, opcode: 0x03 });
+/// ```
+pub trait OpcodeHandler {
+    /// Returns whether the handler executes `opcode`.
+    fn handles(&self, opcode: u8) -> bool;
+
+    /// Executes an opcode the handler handles, with the program counter on the byte after it.
+    /// Operands are read with `cpu.fetch_address_value(data_cycle_count)`, as the documented
+    /// instructions read theirs.
+    ///
+    /// # Returns
+    /// The number of cycles the instruction took.
+    fn execute(&mut self, cpu: &mut CPU, opcode: u8, data_cycle_count: &mut u32) -> u64;
+}
+
+/// What `try_run_until` does with an instruction whose bytes run past `$FFFF`.
+///
+/// The real chip wraps the program counter round to `$0000`, but a program that gets there has
+/// almost always run off the end of its code, so by default the run stops with
+/// `StopReason::RanOffEnd` instead. `CPU::step` and `execute_instruction` always wrap.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+/// use r_6502::cpu::EndOfMemory;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0xFFFE..=0xFFFF].copy_from_slice(&[0xE8, 0xA9]); // INX, then LDA # cut short
+/// cpu.memory.data[0x0000] = 0x05;
+/// cpu.pc = 0xFFFE;
+/// let end = EndConditions::program_end(0x0001);
+/// assert_eq!(try_run_until(&mut cpu, &end), StopReason::RanOffEnd { pc: 0xFFFF });
+/// assert_eq!((cpu.pc, cpu.x), (0xFFFF, 1));
+///
+/// cpu.end_of_memory = EndOfMemory::Wrap;
+/// assert_eq!(try_run_until(&mut cpu, &end), StopReason::EndAddress { pc: 0x0001 });
+/// assert_eq!((cpu.pc, cpu.a), (0x0001, 0x05));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EndOfMemory {
+    #[default]
+    Stop, // Stop with `StopReason::RanOffEnd` (`run_until` panics)
+    Wrap, // Carry on from `$0000`, as the real chip does
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub pc: u16,
     pub sp: u16,
 
+    pub a: u8, // Accumulator
+    pub x: u8, // X Index Register
+    pub y: u8, // Y Index Register
+
     pub memory: Memory,
 
-    pub c: u8, // Carry Flag
-    pub z: u8, // Zero Flag
-    pub i: u8, // Interrupt Disable
-    pub d: u8, // Decimal Mode Flag
-    pub b: u8, // Break Command
-    pub v: u8, // Overflow Flag
-    pub n: u8, // Negative Flag
+    pub p: u8, // Processor status (`NV-BDIZC`), see `flag` and `set_flag`
+
+    pub cycles: u64,       // Clock cycles elapsed since the CPU was created
+    pub instructions: u64, // Instructions executed since the CPU was created
+
+    pub breakpoints: Breakpoints,
+    pub guards: GuardRegions,
+    pub interrupts: InterruptMonitor,
+    pub markers: Markers,
+    pub patches: PatchSet, // Patches marked `always` are reapplied after every instruction
+    pub self_writes: SelfWriteMonitor,
+
+    pub trace: Option<Box<dyn Write>>, // Receives a `trace_line` for every instruction executed
+    pub compact_trace: Option<CompactTraceWriter<Box<dyn Write>>>, // Records them in binary
+    pub trace_bytes: u64,              // Bytes written to `trace` so far
+    pub symbols: SymbolMap,            // Labels shown in place of addresses in `trace`
+    pub taint: Option<TaintTracker>,   // Tracks values derived from input when set
+    pub mmu: Option<Mmu>,              // Remaps 4K pages after every instruction when set
+    pub cosim: Option<Box<dyn CoSimulation>>, // Told about every instruction retired when set
+    pub clock: Option<Clock>,          // Throttles execution to a clock speed when set
+    pub events: Option<EventLog>,      // Records interrupts, device writes, traps and bank switches
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<Metrics>, // Published to every few thousand instructions when set
+
+    pub illegal_opcodes: bool, // Executes the stable undocumented NMOS opcodes (LAX, SAX, DCP, ...)
+    pub unknown_opcode: UnknownOpcode, // Handling of every other undocumented opcode
+    pub opcode_handler: Option<Box<dyn OpcodeHandler>>, // Offered those opcodes first when set
+    pub end_of_memory: EndOfMemory, // Handling of instructions running past $FFFF
+    pub limits: ResourceLimits, // Checked before every instruction by `try_execute_instruction`
+    pub panic_free: bool, // Keeps problems in `fault` rather than panicking, see `execute_instruction`
+    pub fault: Option<StopReason>, // The problem a `panic_free` CPU stopped at, until cleared
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
+    /// Creates a CPU with its registers and memory cleared.
+    ///
+    /// Memory is allocated on the heap, so the CPU itself is small enough to create on the short
+    /// thread stacks of WASM and embedded hosts.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::CPU;
+    /// use std::thread;
+    ///
+    /// assert!(std::mem::size_of::<CPU>() < 1024);
+    /// let cleared = thread::Builder::new()
+    ///     .stack_size(32 * 1024)
+    ///     .spawn(|| CPU::new().memory.data.iter().all(|byte| *byte == 0))
+    ///     .unwrap();
+    /// assert!(cleared.join().unwrap());
+    /// ```
     pub fn new() -> Self {
-        let mut cpu = CPU {
+        CPU {
             pc: 0x0,
             sp: 0x0,
+            a: 0,
+            x: 0,
+            y: 0,
             memory: memory::Memory::new(),
-            c: 0,
-            z: 0,
-            i: 0,
-            d: 0,
-            b: 0,
-            v: 0,
-            n: 0,
-        };
-        cpu.memory.initialise();
+            p: UNUSED,
+            cycles: 0,
+            instructions: 0,
+            breakpoints: Breakpoints::new(),
+            guards: GuardRegions::new(),
+            interrupts: InterruptMonitor::new(),
+            markers: Markers::new(),
+            patches: PatchSet::new(),
+            self_writes: SelfWriteMonitor::new(),
+            trace: None,
+            compact_trace: None,
+            trace_bytes: 0,
+            symbols: SymbolMap::default(),
+            taint: None,
+            mmu: None,
+            cosim: None,
+            clock: None,
+            events: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            illegal_opcodes: false,
+            unknown_opcode: UnknownOpcode::Trap,
+            opcode_handler: None,
+            end_of_memory: EndOfMemory::Stop,
+            limits: ResourceLimits::default(),
+            panic_free: false,
+            fault: None,
+        }
+    }
+
+    /// Reads the byte at the program counter and advances the program counter past it.
+    ///
+    /// Every byte fetched also consumes one unit of `cycles`, which the runner uses to know how much
+    /// of the loaded program is left to execute. The count stops at zero rather than wrapping when an
+    /// instruction reads past the end of the program, and the program counter wraps from `$FFFF` to
+    /// `$0000` like it does on the real chip.
+    pub fn fetch_address_value(&mut self, cycles: &mut u32) -> u8 {
+        let value: u8 = self.memory.read(self.pc);
+
+        self.pc = self.pc.wrapping_add(1);
+
+        *cycles = cycles.saturating_sub(1);
+
+        value
+    }
+
+    /// Returns the value of a register.
+    pub fn get(&self, register: Register) -> u16 {
+        match register {
+            Register::A => self.a as u16,
+            Register::X => self.x as u16,
+            Register::Y => self.y as u16,
+            Register::SP => self.sp & 0x00FF,
+            Register::P => self.get_status() as u16,
+            Register::PC => self.pc,
+        }
+    }
+
+    /// Sets a register, keeping only the low byte of `value` for the 8-bit registers.
+    ///
+    /// The status register is loaded like `set_status` does, ignoring the B and unused bits.
+    pub fn set(&mut self, register: Register, value: u16) {
+        match register {
+            Register::A => self.a = value as u8,
+            Register::X => self.x = value as u8,
+            Register::Y => self.y = value as u8,
+            Register::SP => self.sp = value & 0x00FF,
+            Register::P => self.set_status(value as u8),
+            Register::PC => self.pc = value,
+        }
+    }
+
+    /// Returns whether a status flag is set, e.g. `cpu.flag(CARRY)`.
+    pub fn flag(&self, flag: u8) -> bool {
+        self.p & flag != 0
+    }
+
+    /// Sets or clears a status flag, e.g. `cpu.set_flag(CARRY, true)`.
+    pub fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    /// Sets the zero and negative flags from a value just loaded, transferred or computed, as
+    /// every instruction that updates both from its result does.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::cpu::{NEGATIVE, ZERO};
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.update_nz(0x80);
+    /// assert!(cpu.flag(NEGATIVE) && !cpu.flag(ZERO));
+    ///
+    /// // LDA #$01; LDX #$80: the flags follow X, the register just written
+    /// cpu.memory.data[0..4].copy_from_slice(&[0xA9, 0x01, 0xA2, 0x80]);
+    /// cpu.step();
+    /// cpu.step();
+    /// assert!(cpu.flag(NEGATIVE) && !cpu.flag(ZERO));
+    ///
+    /// // LDY #$00; TXA: Y sets Z, then the transfer sets N from the value copied
+    /// cpu.memory.data[4..7].copy_from_slice(&[0xA0, 0x00, 0x8A]);
+    /// cpu.step();
+    /// assert!(cpu.flag(ZERO) && !cpu.flag(NEGATIVE));
+    /// cpu.step();
+    /// assert_eq!(cpu.a, 0x80);
+    /// assert!(cpu.flag(NEGATIVE) && !cpu.flag(ZERO));
+    /// ```
+    pub fn update_nz(&mut self, value: u8) {
+        self.check_z_flag(value);
+        self.check_n_flag(value);
+    }
+
+    /// Sets the zero flag if `value` is zero and clears it otherwise.
+    pub fn check_z_flag(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+    }
+
+    /// Sets the negative flag from bit 7 of `value`.
+    pub fn check_n_flag(&mut self, value: u8) {
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+    }
+
+    /// Returns the processor status byte (`NV-BDIZC`) as an interrupt pushes it.
+    ///
+    /// The unused bit 5 always reads back as set. The B flag has no storage in the register: it
+    /// only appears in the copies pushed by `PHP` and `BRK`, which set it on top of this value.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::cpu::{BREAK, CARRY};
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.set_status(0xFF);
+    /// assert_eq!(cpu.get_status(), 0xFF & !BREAK);
+    /// assert!(cpu.flag(CARRY));
+    /// ```
+    pub fn get_status(&self) -> u8 {
+        (self.p | UNUSED) & !BREAK
+    }
+
+    /// Loads the processor status from a byte, as `PLP` and `RTI` do.
+    ///
+    /// Bits 4 (B) and 5 (unused) of `status` are ignored, since the register has no storage for them.
+    pub fn set_status(&mut self, status: u8) {
+        self.p = (status & !BREAK) | UNUSED;
+    }
+
+    /// Executes the instruction at the program counter.
+    ///
+    /// Unlike `run_until`, stepping does not check for the end of the program: it runs whatever
+    /// the program counter points to, so a frontend can drive the CPU indefinitely.
+    /// Breakpoints are not checked. While `clock` is paused (see `ClockControls`), nothing is
+    /// executed unless a step was let through.
+    ///
+    /// # Returns
+    /// The clock cycles the instruction took, including the extra cycles for taken branches and
+    /// indexed reads crossing a page boundary, or 0 if it was not executed.
+    ///
+    /// # Panics
+    /// - If the byte at the program counter is not a documented opcode, unless `panic_free` is set.
+    ///   The instruction is then left unexecuted, its problem kept in `fault`, and no cycles pass.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// // LDA $10FF,X with X = 1 crosses into the next page
+    /// cpu.memory.data[0..3].copy_from_slice(&[0xBD, 0xFF, 0x10]);
+    /// cpu.x = 1;
+    /// assert_eq!(cpu.step(), 5);
+    /// ```
+    pub fn step(&mut self) -> u64 {
+        self.try_step().unwrap_or(0)
+    }
+
+    /// Executes the instruction at the program counter for `step`, unless the clock is paused.
+    ///
+    /// # Returns
+    /// The clock cycles the instruction took, or `None` if the CPU did not run, because the clock
+    /// is paused or the CPU stopped at a `fault`.
+    fn try_step(&mut self) -> Option<u64> {
+        if self.fault.is_some() {
+            return None;
+        }
+        if let Some(clock) = &self.clock {
+            if !clock.controls().take_turn() {
+                return None;
+            }
+        }
+        let start = self.cycles;
+        let mut unlimited = u32::MAX;
+        execute_instruction(self, &mut unlimited);
+        match self.fault {
+            Some(_) => None,
+            None => Some(self.cycles - start),
+        }
+    }
+
+    /// Executes instructions until at least `cycles` clock cycles have passed.
+    ///
+    /// Instructions are never cut short, so the run can overshoot by a few cycles; frontends
+    /// syncing to a clock should carry the difference over to the next call. The run ends early
+    /// when `clock` is paused, after any steps let through.
+    ///
+    /// # Returns
+    /// The clock cycles actually executed.
+    ///
+    /// # Panics
+    /// - If an instruction executed is not a documented opcode, unless `panic_free` is set. The run
+    ///   then ends early at the instruction, with its problem kept in `fault`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::asm_runner::StopReason;
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.memory.data[0..3].copy_from_slice(&[0x4C, 0x00, 0x00]); // JMP $0000
+    /// assert_eq!(cpu.run_for_cycles(10), 12);
+    ///
+    /// cpu.memory.data[0] = 0xFF;
+    /// cpu.pc = 0;
+    /// cpu.panic_free = true;
+    /// assert_eq!(cpu.run_for_cycles(10), 0);
+    /// assert_eq!(cpu.fault, Some(StopReason::IllegalOpcode { address: 0, opcode: 0xFF }));
+    /// ```
+    pub fn run_for_cycles(&mut self, cycles: u64) -> u64 {
+        let start = self.cycles;
+        while self.cycles - start < cycles {
+            if self.try_step().is_none() {
+                break;
+            }
+        }
+        self.cycles - start
+    }
+
+    /// Performs the reset sequence, as when the chip's RESET line is released.
+    ///
+    /// The program counter is loaded from the reset vector at `$FFFC`/`$FFFD`, the stack pointer is
+    /// set to `$FD` and interrupts are disabled. Like on the real chip the sequence takes 7 cycles,
+    /// and the other registers and flags keep their values.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::cpu::INTERRUPT_DISABLE;
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.memory.set_reset_vector(0x0600);
+    /// cpu.reset();
+    /// assert_eq!(cpu.pc, 0x0600);
+    /// assert_eq!(cpu.sp, 0xFD);
+    /// assert!(cpu.flag(INTERRUPT_DISABLE));
+    /// assert_eq!(cpu.cycles, 7);
+    /// ```
+    pub fn reset(&mut self) {
+        self.log_event(self.pc, EventKind::Interrupt, || String::from("reset"));
+        let l_byte = self.memory.read(0xFFFC) as u16;
+        let h_byte = self.memory.read(0xFFFD) as u16;
+        self.pc = (h_byte << 8) | l_byte;
+        self.sp = 0xFD;
+        self.set_flag(INTERRUPT_DISABLE, true);
+        self.cycles += 7;
+    }
+
+    /// Signals a maskable interrupt request (IRQ).
+    ///
+    /// The request is ignored while the interrupt disable flag is set. Otherwise the program counter
+    /// and status (with the B flag clear) are pushed, interrupts are disabled and execution continues
+    /// at the address in the IRQ/BRK vector at `$FFFE`/`$FFFF`. The handler returns with `RTI`.
+    ///
+    /// # Returns
+    /// Whether the interrupt was taken.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.sp = 0xFF;
+    /// cpu.pc = 0x0600;
+    /// cpu.memory.data[0xFFFE..=0xFFFF].copy_from_slice(&[0x00, 0x80]);
+    /// assert!(cpu.trigger_irq());
+    /// assert_eq!(cpu.pc, 0x8000);
+    /// assert_eq!(cpu.memory.data[0x01FF], 0x06);
+    /// assert!(!cpu.trigger_irq()); // Masked while the handler runs
+    /// ```
+    pub fn trigger_irq(&mut self) -> bool {
+        if self.flag(INTERRUPT_DISABLE) {
+            return false;
+        }
+        self.interrupts.irqs += 1;
+        self.interrupt(0xFFFE);
+        true
+    }
+
+    /// Signals a non-maskable interrupt (NMI).
+    ///
+    /// NMIs are taken whatever the interrupt disable flag says. The program counter and status (with
+    /// the B flag clear) are pushed, interrupts are disabled and execution continues at the address
+    /// in the NMI vector at `$FFFA`/`$FFFB`.
+    pub fn trigger_nmi(&mut self) {
+        self.interrupts.nmis += 1;
+        self.interrupt(0xFFFA);
+    }
+
+    /// Enters an interrupt handler through the vector at `vector`.
+    ///
+    /// The reset vector at `$FFFC` is not entered this way, as a reset pushes nothing (see `reset`).
+    fn interrupt(&mut self, vector: u16) {
+        let name = if vector == 0xFFFA { "NMI" } else { "IRQ" };
+        self.log_event(self.pc, EventKind::Interrupt, || String::from(name));
+        self.interrupts.enter(self.pc, self.sp);
+        self.push_word(self.pc);
+        self.push(self.get_status());
+        self.set_flag(INTERRUPT_DISABLE, true);
+        let l_byte = self.memory.read(vector) as u16;
+        let h_byte = self.memory.read(vector + 1) as u16;
+        self.pc = (h_byte << 8) | l_byte;
+        self.cycles += 7;
+    }
+
+    /// Adds an event to `events` at the current cycle, if the log is set; `detail` is only
+    /// formatted then.
+    pub(crate) fn log_event(&mut self, pc: u16, kind: EventKind, detail: impl FnOnce() -> String) {
+        let cycle = self.cycles;
+        if let Some(events) = self.events.as_mut() {
+            events.record(cycle, pc, kind, detail());
+        }
+    }
+
+    /// Pushes a byte onto the hardware stack in page `$01` and decrements the stack pointer.
+    ///
+    /// The stack pointer wraps from `$00` to `$FF`, so an overflowing stack overwrites itself from
+    /// the top of the page like it does on the real chip.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// cpu.sp = 0x00;
+    /// cpu.push(0x42);
+    /// assert_eq!(cpu.memory.data[0x0100], 0x42);
+    /// assert_eq!(cpu.sp, 0xFF);
+    /// assert_eq!(cpu.pop(), 0x42);
+    /// assert_eq!(cpu.sp, 0x00);
+    /// ```
+    pub fn push(&mut self, value: u8) {
+        self.guards.check(0x0100 | (self.sp & 0x00FF), true);
+        self.self_writes.check(0x0100 | (self.sp & 0x00FF), value);
+        self.memory.write(0x0100 | (self.sp & 0x00FF), value);
+        self.sp = self.sp.wrapping_sub(1) & 0x00FF;
+    }
+
+    /// Increments the stack pointer, wrapping from `$FF` to `$00`, and pulls the byte it points to.
+    pub fn pop(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1) & 0x00FF;
+        self.guards.check(0x0100 | self.sp, false);
+        self.memory.read(0x0100 | self.sp)
+    }
+
+    /// Pushes a 16-bit address onto the stack, high byte first, as `JSR` and interrupts do.
+    pub fn push_word(&mut self, value: u16) {
+        self.push((value >> 8) as u8);
+        self.push(value as u8);
+    }
+
+    /// Pulls a 16-bit address pushed by `push_word`, low byte first.
+    pub fn pop_word(&mut self) -> u16 {
+        let l_byte = self.pop() as u16;
+        let h_byte = self.pop() as u16;
+        (h_byte << 8) | l_byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A name, a program, its setup, the register it writes and the value expected there.
+    type Case = (
+        &'static str,
+        &'static [u8],
+        fn(&mut CPU),
+        fn(&CPU) -> u8,
+        u8,
+    );
+
+    /// Runs `program` from `$0000` with A holding `$01`, so flags taken from A instead of the
+    /// register written would be neither Z nor N.
+    fn run(program: &[u8], setup: impl FnOnce(&mut CPU)) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.memory.data[..program.len()].copy_from_slice(program);
+        cpu.a = 0x01;
+        setup(&mut cpu);
+        while (cpu.pc as usize) < program.len() {
+            cpu.step();
+        }
         cpu
     }
-    pub fn fetch_address_value(mut self, cycles: &mut u32) -> u8 {
-        let value: u8 = self.memory.data[self.pc as usize];
 
-        self.pc += 1;
+    #[test]
+    fn x_and_y_writes_set_n_and_z_from_the_value_written() {
+        let cases: [Case; 14] = [
+            ("LDX #$80", &[0xA2, 0x80], |_| {}, |cpu| cpu.x, 0x80),
+            ("LDX #$00", &[0xA2, 0x00], |_| {}, |cpu| cpu.x, 0x00),
+            ("LDY #$80", &[0xA0, 0x80], |_| {}, |cpu| cpu.y, 0x80),
+            ("LDY #$00", &[0xA0, 0x00], |_| {}, |cpu| cpu.y, 0x00),
+            ("INX", &[0xE8], |cpu| cpu.x = 0xFF, |cpu| cpu.x, 0x00),
+            ("INX", &[0xE8], |cpu| cpu.x = 0x7F, |cpu| cpu.x, 0x80),
+            ("DEX", &[0xCA], |cpu| cpu.x = 0x00, |cpu| cpu.x, 0xFF),
+            ("INY", &[0xC8], |cpu| cpu.y = 0xFF, |cpu| cpu.y, 0x00),
+            ("DEY", &[0x88], |cpu| cpu.y = 0x01, |cpu| cpu.y, 0x00),
+            ("DEY", &[0x88], |cpu| cpu.y = 0x00, |cpu| cpu.y, 0xFF),
+            (
+                "LDA #$00; TAX",
+                &[0xA9, 0x00, 0xAA],
+                |_| {},
+                |cpu| cpu.x,
+                0x00,
+            ),
+            (
+                "LDA #$90; TAY",
+                &[0xA9, 0x90, 0xA8],
+                |_| {},
+                |cpu| cpu.y,
+                0x90,
+            ),
+            ("TSX", &[0xBA], |cpu| cpu.sp = 0xF0, |cpu| cpu.x, 0xF0),
+            (
+                "LDX $10,Y",
+                &[0xB6, 0x10],
+                |cpu| cpu.memory.data[0x10] = 0x80,
+                |cpu| cpu.x,
+                0x80,
+            ),
+        ];
+        for (name, program, setup, register, value) in cases {
+            let cpu = run(program, setup);
+            assert_eq!(register(&cpu), value, "{}", name);
+            assert_eq!(cpu.flag(ZERO), value == 0, "{}: Z", name);
+            assert_eq!(cpu.flag(NEGATIVE), value & 0x80 != 0, "{}: N", name);
+        }
+    }
+
+    #[test]
+    fn transfers_to_a_set_n_and_z_from_x_and_y() {
+        // TXA then TYA: the flags follow each value copied into A
+        let cpu = run(&[0x8A], |cpu| cpu.x = 0x00);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.flag(ZERO) && !cpu.flag(NEGATIVE));
 
-        *cycles -= 1;
+        let cpu = run(&[0x98], |cpu| cpu.y = 0xC0);
+        assert_eq!(cpu.a, 0xC0);
+        assert!(!cpu.flag(ZERO) && cpu.flag(NEGATIVE));
 
-        return value;
+        // TXS is the one transfer that leaves the flags alone
+        let cpu = run(&[0x9A], |cpu| cpu.x = 0x00);
+        assert_eq!(cpu.sp, 0x00);
+        assert!(!cpu.flag(ZERO) && !cpu.flag(NEGATIVE));
     }
 }
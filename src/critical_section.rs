@@ -0,0 +1,197 @@
+use crate::disassembler::{disassemble_instruction, find_code, JumpAnnotations};
+use crate::memory::Memory;
+use crate::token::{AddressingMode, Token};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// A write to part of a multi-byte value shared with an interrupt handler, made while interrupts
+/// may be enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CriticalSectionWarning {
+    pub address: u16,        // Address of the unprotected write
+    pub target: u16,         // Address it writes
+    pub value: (u16, u16),   // First and last address of the multi-byte value written
+    pub handler_access: u16, // Address of an instruction in a handler accessing the value
+}
+
+/// Finds multi-byte values the main program updates without masking interrupts, while an
+/// interrupt handler also accesses them.
+///
+/// Updating a 16-bit counter or pointer takes more than one instruction. If an interrupt arrives
+/// between the writes to its bytes, a handler reading it sees half the old value and half the new
+/// one, a bug that only shows up once in a long while. Such updates must be bracketed by `SEI` and
+/// `CLI`.
+///
+/// The analysis is static:
+/// - Code is found by following the control flow from `entry` and from each handler, as
+///   `find_code` does. Only zero-page and absolute operands are considered, since the address of
+///   an indexed or indirect access is not known before the program runs.
+/// - A multi-byte value is a run of consecutive addresses that the main program writes to (with a
+///   store or a read-modify-write instruction).
+/// - A write is protected if every path from `entry` reaches it after an `SEI` with no `CLI`,
+///   `PLP` or `RTI` in between. Calls are assumed to leave the interrupt disable flag unchanged.
+///
+/// # Returns
+/// One warning per unprotected write to a value a handler accesses, by address of the write.
+///
+/// # Example
+/// ```rust
+/// use r_6502::critical_section::check_critical_sections;
+/// use r_6502::{Assembler, Memory};
+///
+/// let mut memory = Memory::new();
+/// let mut end_address: u16 = 0x0600;
+/// let mut assembler = Assembler::new();
+/// let source = "main:\nLDA #$00\nSTA $10\nSTA $11\nSEI\nSTA $12\nSTA $13\nCLI\nJMP main\n\
+///               irq:\nLDA $10\nLDA $13\nRTI";
+/// assembler.assemble(source, &mut memory, &mut end_address).unwrap();
+/// let irq = assembler.symbol_table()["irq"];
+/// let warnings = check_critical_sections(&memory, 0x0600, &[irq]);
+/// // $10/$11 are written with interrupts enabled; $12/$13 are protected by SEI/CLI
+/// assert_eq!(warnings.len(), 2);
+/// assert_eq!(warnings[0].target, 0x10);
+/// assert_eq!(warnings[1].value, (0x10, 0x13));
+/// ```
+pub fn check_critical_sections(
+    mem: &Memory,
+    entry: u16,
+    handlers: &[u16],
+) -> Vec<CriticalSectionWarning> {
+    // Every address a handler accesses, with the first instruction found accessing it
+    let mut handler_accesses: BTreeMap<u16, u16> = BTreeMap::new();
+    for handler in handlers {
+        for address in find_code(mem, *handler, &JumpAnnotations::new()) {
+            if let Some((target, _)) = memory_operand(mem, address) {
+                handler_accesses.entry(target).or_insert(address);
+            }
+        }
+    }
+
+    let unmasked = unmasked_instructions(mem, entry);
+    let mut writes: BTreeMap<u16, Vec<u16>> = BTreeMap::new(); // Target -> writing instructions
+    for address in find_code(mem, entry, &JumpAnnotations::new()) {
+        if let Some((target, true)) = memory_operand(mem, address) {
+            writes.entry(target).or_default().push(address);
+        }
+    }
+
+    let mut warnings: Vec<CriticalSectionWarning> = Vec::new();
+    for (target, instructions) in &writes {
+        let (first, last) = value_extent(&writes, *target);
+        if first == last {
+            continue;
+        }
+        let handler_access = match handler_accesses.range(first..=last).next() {
+            Some((_, access)) => *access,
+            None => continue,
+        };
+        for address in instructions {
+            if unmasked.contains(address) {
+                warnings.push(CriticalSectionWarning {
+                    address: *address,
+                    target: *target,
+                    value: (first, last),
+                    handler_access,
+                });
+            }
+        }
+    }
+    warnings.sort_by_key(|warning| warning.address);
+    warnings
+}
+
+/// Formats a warning, naming addresses after the labels in `symbol_table` where there is one.
+///
+/// # Example
+/// ```text
+/// $0602: STA $10 updates $0010-$0011 with interrupts enabled, but the handler at irq accesses it
+/// ```
+pub fn format_warning(
+    mem: &Memory,
+    warning: &CriticalSectionWarning,
+    symbol_table: &HashMap<String, u16>,
+) -> String {
+    let name = |address: u16| {
+        symbol_table
+            .iter()
+            .filter(|(_, value)| **value == address)
+            .map(|(name, _)| name.clone())
+            .min()
+            .unwrap_or_else(|| format!("${:04X}", address))
+    };
+    format!(
+        "{}: {} updates ${:04X}-${:04X} with interrupts enabled, but the handler at {} accesses it",
+        name(warning.address),
+        disassemble_instruction(mem, warning.address).text,
+        warning.value.0,
+        warning.value.1,
+        name(warning.handler_access)
+    )
+}
+
+/// Returns the zero-page or absolute address an instruction accesses, and whether it writes it.
+fn memory_operand(mem: &Memory, address: u16) -> Option<(u16, bool)> {
+    let token = Token::from_opcode(mem.data[address as usize])?;
+    let low = mem.data[address.wrapping_add(1) as usize] as u16;
+    let high = mem.data[address.wrapping_add(2) as usize] as u16;
+    let target = match token.addressing_mode() {
+        AddressingMode::ZeroPage => low,
+        AddressingMode::Absolute if !matches!(token, Token::JMP | Token::JSR) => (high << 8) | low,
+        _ => return None,
+    };
+    let write = matches!(
+        token.mnemonic().as_str(),
+        "STA" | "STX" | "STY" | "INC" | "DEC" | "ASL" | "LSR" | "ROL" | "ROR"
+    );
+    Some((target, write))
+}
+
+/// Returns the first and last address of the run of consecutive written addresses around `target`.
+fn value_extent(writes: &BTreeMap<u16, Vec<u16>>, target: u16) -> (u16, u16) {
+    let mut first = target;
+    while first > 0 && writes.contains_key(&(first - 1)) {
+        first -= 1;
+    }
+    let mut last = target;
+    while last < 0xFFFF && writes.contains_key(&(last + 1)) {
+        last += 1;
+    }
+    (first, last)
+}
+
+/// Returns the instructions reachable from `entry` along some path with interrupts enabled.
+fn unmasked_instructions(mem: &Memory, entry: u16) -> HashSet<u16> {
+    let mut seen: BTreeSet<(u16, bool)> = BTreeSet::new();
+    let mut pending: Vec<(u16, bool)> = vec![(entry, false)];
+    while let Some((address, masked)) = pending.pop() {
+        if !seen.insert((address, masked)) {
+            continue;
+        }
+        let token = match Token::from_opcode(mem.data[address as usize]) {
+            Some(token) => token,
+            None => continue,
+        };
+        let instruction = disassemble_instruction(mem, address);
+        let next = address.wrapping_add(instruction.bytes.len() as u16);
+        let operand = || ((instruction.bytes[2] as u16) << 8) | instruction.bytes[1] as u16;
+        match (token, token.addressing_mode()) {
+            (_, AddressingMode::Relative) => {
+                let offset = instruction.bytes[1] as i8 as u16;
+                pending.push((next.wrapping_add(offset), masked));
+                pending.push((next, masked));
+            }
+            (Token::JMP, _) => pending.push((operand(), masked)),
+            (Token::JSR, _) => {
+                pending.push((operand(), masked));
+                pending.push((next, masked));
+            }
+            (Token::JmpID | Token::RTS | Token::RTI | Token::BRK, _) => {}
+            (Token::SEI, _) => pending.push((next, true)),
+            (Token::CLI | Token::PLP, _) => pending.push((next, false)),
+            _ => pending.push((next, masked)),
+        }
+    }
+    seen.into_iter()
+        .filter(|(_, masked)| !masked)
+        .map(|(address, _)| address)
+        .collect()
+}
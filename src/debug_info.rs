@@ -0,0 +1,118 @@
+use std::fmt;
+
+/// A position in assembly source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLoc {
+    pub file: Option<String>, // Path of the source file, `None` for source assembled from a string
+    pub line: usize,          // 1-based line number
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "line {}", self.line),
+        }
+    }
+}
+
+/// The bytes emitted by one source line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Span {
+    address: u16,
+    length: u16,
+    line: usize,
+}
+
+/// Maps addresses of an assembled program to the source lines they came from, and back.
+///
+/// The assembler records every line that emits bytes while it assembles a program; the result is
+/// available through `Assembler::debug_info`. Tools can then turn the `PC` of a crash or a
+/// breakpoint into a file and line with `lookup`, or find where a line ended up with `addresses`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::{Assembler, Memory};
+///
+/// let mut memory = Memory::new();
+/// let mut current_mem_addr: u16 = 0x0600;
+/// let mut assembler = Assembler::new();
+/// assembler
+///     .assemble("start:\nLDA #$01\nSTA $0200\nJMP start", &mut memory, &mut current_mem_addr)
+///     .unwrap();
+/// let debug_info = assembler.debug_info();
+/// assert_eq!(debug_info.lookup(0x0602).unwrap().line, 3);
+/// assert_eq!(debug_info.lookup(0x0604).unwrap().line, 3); // Operand bytes belong to the line too
+/// assert_eq!(debug_info.lookup(0x0608), None);
+/// assert_eq!(debug_info.addresses(4), vec![0x0605]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    file: Option<String>,
+    spans: Vec<Span>,
+}
+
+impl DebugInfo {
+    /// Creates empty debug info for a program assembled from `file`, or from a string if `None`.
+    pub fn new(file: Option<&str>) -> Self {
+        DebugInfo {
+            file: file.map(str::to_string),
+            spans: Vec::new(),
+        }
+    }
+
+    /// Records that `line` emitted `length` bytes starting at `address`.
+    pub fn add(&mut self, address: u16, length: u16, line: usize) {
+        if length > 0 {
+            self.spans.push(Span {
+                address,
+                length,
+                line,
+            });
+        }
+    }
+
+    /// Returns the path of the source file, if the program was assembled from one.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Returns the `(address, length)` of the code each line emitted, in the order it was emitted.
+    pub fn ranges(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.spans.iter().map(|span| (span.address, span.length))
+    }
+
+    /// Returns the `(line, address, length)` of the code each line emitted, in the order it was
+    /// emitted.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, u16, u16)> + '_ {
+        self.spans
+            .iter()
+            .map(|span| (span.line, span.address, span.length))
+    }
+
+    /// Returns the source line whose bytes include `address`, if any.
+    ///
+    /// If code was assembled over the same address more than once, the line assembled last wins,
+    /// since its bytes are the ones left in memory.
+    pub fn lookup(&self, address: u16) -> Option<SourceLoc> {
+        self.spans
+            .iter()
+            .rev()
+            .find(|span| address.wrapping_sub(span.address) < span.length)
+            .map(|span| SourceLoc {
+                file: self.file.clone(),
+                line: span.line,
+            })
+    }
+
+    /// Returns the addresses of the code `line` emitted, in the order it was emitted.
+    ///
+    /// Lines that emit nothing, such as labels and comments, have no addresses.
+    pub fn addresses(&self, line: usize) -> Vec<u16> {
+        self.spans
+            .iter()
+            .filter(|span| span.line == line)
+            .map(|span| span.address)
+            .collect()
+    }
+}
@@ -0,0 +1,470 @@
+use crate::disassembler::{
+    disassemble_with_symbols, format_annotated_disassembly, JumpAnnotations,
+};
+use crate::expr::{evaluate, format_value};
+use crate::memory::Memory;
+use crate::mmu::{BankedAddress, Mmu, PAGE_SIZE};
+use crate::symbol_map::SymbolMap;
+use crate::util::convert_hex_string_to_u16;
+use std::collections::HashMap;
+
+/// A 16-bit register made of two memory bytes, usually a zero-page pointer.
+///
+/// 6502 programs keep their pointers as little-endian byte pairs in the zero page, so viewing those
+/// pairs as named 16-bit values makes pointer-heavy code much easier to follow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VirtualRegister {
+    pub name: String,
+    pub low: u16,  // Address of the low byte
+    pub high: u16, // Address of the high byte
+}
+
+impl VirtualRegister {
+    /// Parses a definition of the form `NAME = $lo/$hi`, e.g. `PTR = $FB/$FC`.
+    ///
+    /// The high byte may be omitted (`PTR = $FB`), in which case it is taken to be the byte after
+    /// the low byte.
+    ///
+    /// # Errors
+    /// A message saying why, if the definition is not of the form above or an address is not
+    /// valid hex.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::debugger::VirtualRegister;
+    ///
+    /// let register = VirtualRegister::parse("PTR = $FB/$FC").unwrap();
+    /// assert_eq!(register.low, 0xFB);
+    /// assert_eq!(register.high, 0xFC);
+    /// assert!(VirtualRegister::parse("PTR $FB").is_err());
+    /// ```
+    pub fn parse(definition: &str) -> Result<Self, String> {
+        let (name, addresses) = match definition.split_once('=') {
+            Some((name, addresses)) => (name.trim(), addresses.trim()),
+            None => return Err(format!("Syntax error in virtual register {}", definition)),
+        };
+        if name.is_empty() {
+            return Err(format!("Virtual register has no name: {}", definition));
+        }
+        let (low, high) = match addresses.split_once('/') {
+            Some((low, high)) => (parse_address(low)?, parse_address(high)?),
+            None => {
+                let low = parse_address(addresses)?;
+                (low, low.wrapping_add(1))
+            }
+        };
+        Ok(VirtualRegister {
+            name: name.to_string(),
+            low,
+            high,
+        })
+    }
+
+    /// Reads the register's current value from memory.
+    pub fn read(&self, mem: &Memory) -> u16 {
+        let l_byte = mem.data[self.low as usize] as u16;
+        let h_byte = mem.data[self.high as usize] as u16;
+        (h_byte << 8) | l_byte
+    }
+
+    /// Writes a 16-bit value into the register's two memory bytes.
+    pub fn write(&self, mem: &mut Memory, value: u16) {
+        mem.data[self.low as usize] = value as u8;
+        mem.data[self.high as usize] = (value >> 8) as u8;
+    }
+
+    /// Formats the register and its current value for display, e.g. `PTR ($FB/$FC) = $0400`.
+    pub fn format(&self, mem: &Memory) -> String {
+        format!(
+            "{} (${:02X}/${:02X}) = ${:04X}",
+            self.name,
+            self.low,
+            self.high,
+            self.read(mem)
+        )
+    }
+}
+
+/// Parses a `$`-prefixed hex address.
+fn parse_address(value: &str) -> Result<u16, String> {
+    match value.trim().strip_prefix('$') {
+        Some(hex) => convert_hex_string_to_u16(hex)
+            .map_err(|_| format!("Failed to parse hex value: {}", hex)),
+        None => Err(format!("Expected a $ address, found {}", value)),
+    }
+}
+
+/// Follows a chain of zero-page pointers, starting with the one at `address`.
+///
+/// Each step reads the 16-bit little-endian pointer at the current address and moves to its
+/// target. Following stops after `depth` steps, or earlier once a target is outside the zero page
+/// and so cannot itself be a pointer.
+///
+/// # Returns
+/// The addresses visited after `address`, in order.
+///
+/// # Example
+/// ```rust
+/// use r_6502::debugger::follow_pointer;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// mem.data[0xFB] = 0x10;
+/// mem.data[0x10] = 0x00;
+/// mem.data[0x11] = 0x04;
+/// assert_eq!(follow_pointer(&mem, 0xFB, 4), vec![0x0010, 0x0400]);
+/// ```
+pub fn follow_pointer(mem: &Memory, address: u16, depth: usize) -> Vec<u16> {
+    let mut chain: Vec<u16> = Vec::new();
+    let mut current = address;
+    for _ in 0..depth {
+        if current > 0xFF {
+            break;
+        }
+        let l_byte = mem.data[current as usize] as u16;
+        let h_byte = mem.data[((current + 1) & 0xFF) as usize] as u16;
+        current = (h_byte << 8) | l_byte;
+        chain.push(current);
+    }
+    chain
+}
+
+/// Symbols whose addresses may belong to a bank, for debugging bank-switched programs.
+///
+/// Symbols defined with a bank only name their address while that bank is mapped there, so the
+/// debugger does not report a routine from bank 1 when bank 3 occupies the same address range.
+///
+/// # Example
+/// ```rust
+/// use r_6502::debugger::BankedSymbols;
+/// use r_6502::mmu::{BankedAddress, Mmu};
+/// use r_6502::Memory;
+/// use std::collections::HashMap;
+///
+/// let mut mem = Memory::new();
+/// let mut mmu = Mmu::new(0xFE00, 4);
+/// let mut symbols = BankedSymbols::new();
+/// symbols.add_symbol_table(&HashMap::from([(String::from("draw"), 0x8000)]), Some(1));
+/// symbols.add_symbol_table(&HashMap::from([(String::from("play"), 0x8000)]), Some(3));
+/// assert_eq!(symbols.resolve("play"), BankedAddress::parse("3:$8000"));
+///
+/// mmu.map(&mut mem, 0x8, Some(3));
+/// assert_eq!(symbols.name_at(0x8000, Some(&mmu)), Some("play"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BankedSymbols {
+    symbols: HashMap<String, BankedAddress>,
+}
+
+impl BankedSymbols {
+    pub fn new() -> Self {
+        BankedSymbols {
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Defines `name` at `location`, replacing any earlier definition.
+    pub fn define(&mut self, name: &str, location: BankedAddress) {
+        self.symbols.insert(name.to_string(), location);
+    }
+
+    /// Adds the labels of a program assembled to run in `bank` (`None` for unbanked code).
+    pub fn add_symbol_table(&mut self, symbol_table: &HashMap<String, u16>, bank: Option<u8>) {
+        for (name, address) in symbol_table {
+            self.define(
+                name,
+                BankedAddress {
+                    bank,
+                    address: *address,
+                },
+            );
+        }
+    }
+
+    /// Returns the location a symbol is defined at.
+    pub fn resolve(&self, name: &str) -> Option<BankedAddress> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Resolves a location typed by the user: a symbol name, `bank:$address` or `$address`.
+    pub fn parse_location(&self, text: &str) -> Option<BankedAddress> {
+        self.resolve(text.trim())
+            .or_else(|| BankedAddress::parse(text))
+    }
+
+    /// Returns the name of the symbol at `address` under the current mapping, if any.
+    ///
+    /// Banked symbols are preferred over unbanked ones, and the alphabetically first name is used
+    /// when several match.
+    pub fn name_at(&self, address: u16, mmu: Option<&Mmu>) -> Option<&str> {
+        self.symbols
+            .iter()
+            .filter(|(_, location)| location.address == address && location.is_active(mmu))
+            .min_by_key(|(name, location)| (location.bank.is_none(), name.as_str()))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Formats the pages an MMU has remapped, one per line, e.g. `$8000-$8FFF  bank 2`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::debugger::format_mmu_mapping;
+/// use r_6502::mmu::Mmu;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// let mut mmu = Mmu::new(0xFE00, 4);
+/// mmu.map(&mut mem, 0x8, Some(2));
+/// assert_eq!(format_mmu_mapping(&mmu), "$8000-$8FFF  bank 2");
+/// ```
+pub fn format_mmu_mapping(mmu: &Mmu) -> String {
+    let lines: Vec<String> = mmu
+        .mapping()
+        .iter()
+        .enumerate()
+        .filter_map(|(page, bank)| {
+            let start = page * PAGE_SIZE;
+            bank.map(|bank| {
+                format!(
+                    "${:04X}-${:04X}  bank {}",
+                    start,
+                    start + PAGE_SIZE - 1,
+                    bank
+                )
+            })
+        })
+        .collect();
+    if lines.is_empty() {
+        return String::from("No pages remapped");
+    }
+    lines.join("\n")
+}
+
+/// Runs a monitor command against memory and returns the text to display.
+///
+/// Supported commands:
+/// - `follow $FB`: Reads the pointer at `$FB`, shows its target and dumps the bytes there.
+/// - `follow $FB 3`: The same, following up to three pointers in a chain.
+/// - `map`: Prints the memory map (see `format_memory_map`).
+/// - `eval <expression>`: Evaluates an expression without symbols (see `expr::evaluate`) and shows
+///   the result in hex, decimal and binary.
+///
+/// # Errors
+/// A message to display instead, if the command is unknown or its arguments are malformed.
+///
+/// # Example
+/// ```rust
+/// use r_6502::debugger::run_monitor_command;
+/// use r_6502::Memory;
+///
+/// let mem = Memory::new();
+/// assert_eq!(run_monitor_command(&mem, "eval 10"), Ok(String::from("$0A  10  %00001010")));
+/// assert_eq!(
+///     run_monitor_command(&mem, "follow"),
+///     Err(String::from("Missing address in follow"))
+/// );
+/// ```
+pub fn run_monitor_command(mem: &Memory, command: &str) -> Result<String, String> {
+    let mut words = command.split_whitespace();
+    match words.next() {
+        Some("follow") => {
+            let address = match words.next() {
+                Some(address) => parse_address(address)?,
+                None => return Err(format!("Missing address in {}", command)),
+            };
+            let depth = match words.next() {
+                Some(depth) => match depth.parse::<usize>() {
+                    Ok(depth) => depth,
+                    Err(_) => return Err(format!("Invalid depth {}", depth)),
+                },
+                None => 1,
+            };
+            Ok(format_follow(mem, address, depth))
+        }
+        Some("map") => Ok(format_memory_map(mem)),
+        Some("eval") => {
+            let expression = command.trim_start()["eval".len()..].trim();
+            eval_command(expression, &HashMap::new())
+        }
+        _ => Err(format!("Unknown monitor command {}", command)),
+    }
+}
+
+/// A monitor session, holding state that commands build up over time.
+///
+/// Besides the stateless commands of `run_monitor_command`, a session supports:
+/// - `targets $0610 $0700 $0800`: Records that the computed jump at `$0610` can go to `$0700` or
+///   `$0800` (see `JumpAnnotations`).
+/// - `disasm $0600 $0620`: Disassembles the range, showing the recorded jump targets and the
+///   addresses of `symbols` by name.
+/// - `eval <expression>`: Evaluates an expression that may use the session's `symbols`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::debugger::Monitor;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// mem.data[0x0600..0x0603].copy_from_slice(&[0x6C, 0x00, 0x03]);
+/// let mut monitor = Monitor::new();
+/// monitor.run_command(&mem, "targets $0600 $0700").unwrap();
+/// let listing = monitor.run_command(&mem, "disasm $0600 $0600").unwrap();
+/// assert!(listing.ends_with("JMP ($0300)    ; -> $0700"));
+///
+/// monitor.symbols.insert(String::from("screen"), 0x0400);
+/// assert_eq!(monitor.run_command(&mem, "eval >screen").unwrap(), "$04  4  %00000100");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Monitor {
+    pub annotations: JumpAnnotations,
+    pub symbols: HashMap<String, u16>, // Symbols `eval` can refer to
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor {
+            annotations: JumpAnnotations::new(),
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Runs a monitor command and returns the text to display.
+    ///
+    /// # Errors
+    /// A message to display instead, if the command is unknown or its arguments are malformed.
+    pub fn run_command(&mut self, mem: &Memory, command: &str) -> Result<String, String> {
+        let (name, arguments) = match command.trim().split_once(' ') {
+            Some((name, arguments)) => (name, arguments.trim()),
+            None => (command.trim(), ""),
+        };
+        match name {
+            "targets" => match self.annotations.parse(arguments) {
+                Some(site) => {
+                    let targets: Vec<String> = self
+                        .annotations
+                        .targets(site)
+                        .iter()
+                        .map(|target| format!("${:04X}", target))
+                        .collect();
+                    Ok(format!("${:04X} -> {}", site, targets.join(", ")))
+                }
+                None => Err(format!("Syntax error in jump targets {}", arguments)),
+            },
+            "disasm" => {
+                let addresses: Vec<&str> = arguments.split_whitespace().collect();
+                if addresses.len() != 2 {
+                    return Err(format!(
+                        "Expected a start and end address, found {}",
+                        arguments
+                    ));
+                }
+                let start = parse_address(addresses[0])?;
+                let end = parse_address(addresses[1])?;
+                let symbols = SymbolMap::from_symbols(&self.symbols);
+                let instructions = disassemble_with_symbols(mem, start, end, &symbols);
+                Ok(format_annotated_disassembly(
+                    &instructions,
+                    &self.annotations,
+                ))
+            }
+            "eval" => eval_command(arguments, &self.symbols),
+            _ => run_monitor_command(mem, command),
+        }
+    }
+}
+
+/// Evaluates an expression for the `eval` command, e.g. `$0A  10  %00001010`.
+fn eval_command(expression: &str, symbols: &HashMap<String, u16>) -> Result<String, String> {
+    match evaluate(expression, symbols) {
+        Ok(value) => Ok(format_value(value)),
+        Err(e) => Err(format!("Cannot evaluate {}: {}", expression, e)),
+    }
+}
+
+/// Formats a followed pointer chain, e.g. `$FB -> $0400`, followed by a dump at the destination.
+fn format_follow(mem: &Memory, address: u16, depth: usize) -> String {
+    let chain = follow_pointer(mem, address, depth);
+    let mut output = format!("${:02X}", address);
+    for target in &chain {
+        output.push_str(&format!(" -> ${:04X}", target));
+    }
+    let destination = *chain.last().unwrap_or(&address);
+    output.push('\n');
+    output.push_str(&format!("{:04X}:", destination));
+    for offset in 0..16u16 {
+        let value = mem.data[destination.wrapping_add(offset) as usize];
+        output.push_str(&format!(" {:02X}", value));
+    }
+    output
+}
+
+/// Formats the memory map, one region per line, e.g. `$0000-$00FF  RAM  Zero page`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::debugger::format_memory_map;
+/// use r_6502::Memory;
+///
+/// let map = format_memory_map(&Memory::new());
+/// assert!(map.starts_with("$0000-$00FF  RAM  Zero page"));
+/// ```
+pub fn format_memory_map(mem: &Memory) -> String {
+    let lines: Vec<String> = mem
+        .map()
+        .iter()
+        .map(|region| {
+            format!(
+                "${:04X}-${:04X}  {}  {}",
+                region.start, region.end, region.kind, region.name
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_input_is_an_error_rather_than_a_panic() {
+        for definition in ["PTR $FB", " = $FB", "PTR = FB", "PTR = $FB/$XY"] {
+            assert!(
+                VirtualRegister::parse(definition).is_err(),
+                "{}",
+                definition
+            );
+        }
+        assert_eq!(
+            VirtualRegister::parse("PTR = $FB"),
+            Ok(VirtualRegister {
+                name: String::from("PTR"),
+                low: 0xFB,
+                high: 0xFC
+            })
+        );
+
+        let mem = Memory::new();
+        let mut monitor = Monitor::new();
+        for (command, error) in [
+            ("follow", "Missing address in follow"),
+            ("follow $FB deep", "Invalid depth deep"),
+            ("follow FB", "Expected a $ address, found FB"),
+            ("peek $FB", "Unknown monitor command peek $FB"),
+            ("", "Unknown monitor command "),
+            ("targets 0600", "Syntax error in jump targets 0600"),
+            (
+                "disasm $0600",
+                "Expected a start and end address, found $0600",
+            ),
+            ("eval 1+", "Cannot evaluate 1+: "),
+        ] {
+            match monitor.run_command(&mem, command) {
+                Err(e) => assert!(e.starts_with(error), "{}: {}", command, e),
+                Ok(output) => panic!("{}: {}", command, output),
+            }
+        }
+        assert!(monitor.run_command(&mem, "follow $FB 2").is_ok());
+    }
+}
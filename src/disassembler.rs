@@ -0,0 +1,389 @@
+use crate::memory::Memory;
+use crate::symbol_map::SymbolMap;
+use crate::token::{AddressingMode, Token};
+use crate::util::convert_hex_string_to_u16;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// One instruction decoded from memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>, // Opcode followed by its operand bytes
+    pub text: String,   // Assembly text, e.g. `LDA $2000`
+}
+
+/// Decodes the instruction at `address`.
+///
+/// Operands are written the way the assembler accepts them (`#$10`, `$10,X`, `($10),Y`, ...), with
+/// branch offsets shown as the absolute address they jump to. Bytes that are not a documented opcode
+/// decode to a one-byte `.byte $XX` line.
+///
+/// # Example
+/// ```rust
+/// use r_6502::disassembler::disassemble_instruction;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// mem.data[0x0600..0x0603].copy_from_slice(&[0xAD, 0x00, 0x20]);
+/// let instruction = disassemble_instruction(&mem, 0x0600);
+/// assert_eq!(instruction.text, "LDA $2000");
+/// assert_eq!(instruction.bytes, vec![0xAD, 0x00, 0x20]);
+/// ```
+pub fn disassemble_instruction(mem: &Memory, address: u16) -> DisassembledInstruction {
+    disassemble_bytes(address, &instruction_bytes(mem, address))
+}
+
+/// Reads the opcode at `address` and as many operand bytes as `encoded_length` gives.
+fn instruction_bytes(mem: &Memory, address: u16) -> Vec<u8> {
+    let opcode = mem.data[address as usize];
+    (0..encoded_length(opcode))
+        .map(|offset| mem.data[address.wrapping_add(offset) as usize])
+        .collect()
+}
+
+/// Returns the number of bytes `disassemble_instruction` decodes for an opcode: the opcode and its
+/// operand for a documented opcode, or the opcode alone otherwise.
+pub fn encoded_length(opcode: u8) -> u16 {
+    match Token::from_opcode(opcode) {
+        Some(token) => 1 + token.addressing_mode().operand_size(),
+        None => 1,
+    }
+}
+
+/// Decodes an instruction from its bytes, as if it were at `address`.
+///
+/// `bytes` holds the opcode followed by its operand, as many bytes as `encoded_length` gives;
+/// missing operand bytes are taken as zero.
+///
+/// # Example
+/// ```rust
+/// use r_6502::disassembler::disassemble_bytes;
+///
+/// assert_eq!(disassemble_bytes(0x0600, &[0xD0, 0xFE]).text, "BNE $0600");
+/// ```
+pub fn disassemble_bytes(address: u16, bytes: &[u8]) -> DisassembledInstruction {
+    disassemble_bytes_with_symbols(address, bytes, &SymbolMap::default())
+}
+
+/// Decodes an instruction like `disassemble_bytes`, showing the address it uses by name when
+/// `symbols` has a label there.
+///
+/// Every operand naming an address is substituted, branch targets and zero page addresses
+/// included; immediate values are not, as they are not addresses.
+///
+/// # Example
+/// ```rust
+/// use r_6502::disassembler::disassemble_bytes_with_symbols;
+/// use r_6502::symbol_map::SymbolMap;
+///
+/// let symbols = SymbolMap::parse("al C:8F3A .print_char\nal C:00FB .ptr").unwrap();
+/// let text = |bytes: &[u8]| disassemble_bytes_with_symbols(0x8000, bytes, &symbols).text;
+/// assert_eq!(text(&[0x20, 0x3A, 0x8F]), "JSR print_char");
+/// assert_eq!(text(&[0xB1, 0xFB]), "LDA (ptr),Y");
+/// assert_eq!(text(&[0xA9, 0xFB]), "LDA #$FB");
+/// assert_eq!(text(&[0x20, 0x00, 0x90]), "JSR $9000");
+/// ```
+pub fn disassemble_bytes_with_symbols(
+    address: u16,
+    bytes: &[u8],
+    symbols: &SymbolMap,
+) -> DisassembledInstruction {
+    let opcode = bytes.first().copied().unwrap_or(0);
+    let token = match Token::from_opcode(opcode) {
+        Some(token) => token,
+        None => {
+            return DisassembledInstruction {
+                address,
+                bytes: vec![opcode],
+                text: format!(".byte ${:02X}", opcode),
+            }
+        }
+    };
+    let mode = token.addressing_mode();
+    let bytes: Vec<u8> = bytes
+        .iter()
+        .copied()
+        .take(1 + mode.operand_size() as usize)
+        .collect();
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = ((bytes.get(2).copied().unwrap_or(0) as u16) << 8) | byte as u16;
+    // The address an operand names, by label if it has one
+    let zero_page = symbols
+        .name_at(byte as u16)
+        .map_or_else(|| format!("${:02X}", byte), String::from);
+    let absolute = |address: u16| {
+        symbols
+            .name_at(address)
+            .map_or_else(|| format!("${:04X}", address), String::from)
+    };
+
+    let operand = match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator => String::new(),
+        AddressingMode::Immediate => format!("#${:02X}", byte),
+        AddressingMode::ZeroPage => zero_page,
+        AddressingMode::ZeroPageX => format!("{},X", zero_page),
+        AddressingMode::ZeroPageY => format!("{},Y", zero_page),
+        AddressingMode::Absolute => absolute(word),
+        AddressingMode::AbsoluteX => format!("{},X", absolute(word)),
+        AddressingMode::AbsoluteY => format!("{},Y", absolute(word)),
+        AddressingMode::Indirect => format!("({})", absolute(word)),
+        AddressingMode::IndexedIndirect => format!("({},X)", zero_page),
+        AddressingMode::IndirectIndexed => format!("({}),Y", zero_page),
+        AddressingMode::Relative => {
+            absolute(address.wrapping_add(2).wrapping_add(byte as i8 as u16))
+        }
+    };
+    let text = if operand.is_empty() {
+        token.mnemonic()
+    } else {
+        format!("{} {}", token.mnemonic(), operand)
+    };
+    DisassembledInstruction {
+        address,
+        bytes,
+        text,
+    }
+}
+
+/// Decodes every instruction starting in the range `start..=end`.
+///
+/// The last instruction may extend past `end` if its operand does.
+pub fn disassemble(mem: &Memory, start: u16, end: u16) -> Vec<DisassembledInstruction> {
+    disassemble_with_symbols(mem, start, end, &SymbolMap::default())
+}
+
+/// Decodes every instruction starting in the range `start..=end`, like `disassemble`, with the
+/// addresses they use shown by name where `symbols` has a label.
+pub fn disassemble_with_symbols(
+    mem: &Memory,
+    start: u16,
+    end: u16,
+    symbols: &SymbolMap,
+) -> Vec<DisassembledInstruction> {
+    let mut instructions: Vec<DisassembledInstruction> = Vec::new();
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let bytes = instruction_bytes(mem, address as u16);
+        let instruction = disassemble_bytes_with_symbols(address as u16, &bytes, symbols);
+        address += instruction.bytes.len() as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Formats decoded instructions as a listing, e.g. `$0600  AD 00 20  LDA $2000`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::disassembler::{disassemble, format_disassembly};
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// mem.data[0x0600..0x0603].copy_from_slice(&[0xA9, 0x01, 0xE8]);
+/// let listing = format_disassembly(&disassemble(&mem, 0x0600, 0x0602));
+/// assert_eq!(listing, "$0600  A9 01     LDA #$01\n$0602  E8        INX");
+/// ```
+pub fn format_disassembly(instructions: &[DisassembledInstruction]) -> String {
+    let lines: Vec<String> = instructions
+        .iter()
+        .map(|instruction| {
+            let bytes: Vec<String> = instruction
+                .bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            format!(
+                "${:04X}  {:<8}  {}",
+                instruction.address,
+                bytes.join(" "),
+                instruction.text
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Formats decoded instructions like `format_disassembly`, with a `label:` line before every
+/// instruction at the address of a label in `symbols`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::disassembler::{disassemble_with_symbols, format_labeled_disassembly};
+/// use r_6502::symbol_map::SymbolMap;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// mem.data[0x0600..0x0603].copy_from_slice(&[0xE8, 0xD0, 0xFD]); // INX; BNE loop
+/// let symbols = SymbolMap::parse("al C:0600 .loop").unwrap();
+/// let instructions = disassemble_with_symbols(&mem, 0x0600, 0x0602, &symbols);
+/// assert_eq!(
+///     format_labeled_disassembly(&instructions, &symbols),
+///     "loop:\n$0600  E8        INX\n$0601  D0 FD     BNE loop"
+/// );
+/// ```
+pub fn format_labeled_disassembly(
+    instructions: &[DisassembledInstruction],
+    symbols: &SymbolMap,
+) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for instruction in instructions {
+        for (_, name) in symbols
+            .labels
+            .iter()
+            .filter(|(address, _)| *address == instruction.address)
+        {
+            lines.push(format!("{}:", name));
+        }
+        lines.push(format_disassembly(std::slice::from_ref(instruction)));
+    }
+    lines.join("\n")
+}
+
+/// Possible targets of computed jumps, keyed by the address of the jumping instruction.
+///
+/// The destination of a `JMP ($xxxx)` or of an `RTS` used as a jump (after pushing a target
+/// address) depends on data only known at run time, so code discovery cannot follow them on its
+/// own. Annotating these sites with the targets a jump table can dispatch to lets `find_code`
+/// and the annotated listing follow them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JumpAnnotations {
+    targets: BTreeMap<u16, Vec<u16>>,
+}
+
+impl JumpAnnotations {
+    pub fn new() -> Self {
+        JumpAnnotations {
+            targets: BTreeMap::new(),
+        }
+    }
+
+    /// Adds possible targets for the computed jump at `site`.
+    pub fn annotate(&mut self, site: u16, targets: &[u16]) {
+        let entry = self.targets.entry(site).or_default();
+        for target in targets {
+            if !entry.contains(target) {
+                entry.push(*target);
+            }
+        }
+    }
+
+    /// Parses and adds an annotation of the form `$site $target...`, e.g. `$0610 $0700 $0800`.
+    ///
+    /// # Returns
+    /// - `Some(site)`: The annotated site, if the annotation is well formed.
+    /// - `None`: If an address is missing, not `$`-prefixed or not valid hex.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::disassembler::JumpAnnotations;
+    ///
+    /// let mut annotations = JumpAnnotations::new();
+    /// assert_eq!(annotations.parse("$0610 $0700 $0800"), Some(0x0610));
+    /// assert_eq!(annotations.targets(0x0610), &[0x0700, 0x0800]);
+    /// ```
+    pub fn parse(&mut self, annotation: &str) -> Option<u16> {
+        let addresses: Option<Vec<u16>> = annotation
+            .split_whitespace()
+            .map(|word| {
+                word.strip_prefix('$')
+                    .and_then(|hex| convert_hex_string_to_u16(hex).ok())
+            })
+            .collect();
+        match addresses?.split_first() {
+            Some((site, targets)) if !targets.is_empty() => {
+                self.annotate(*site, targets);
+                Some(*site)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the annotated targets of the jump at `site`, if any.
+    pub fn targets(&self, site: u16) -> &[u16] {
+        match self.targets.get(&site) {
+            Some(targets) => targets,
+            None => &[],
+        }
+    }
+}
+
+/// Finds the addresses of every instruction reachable from `entry`.
+///
+/// Code is discovered by following the control flow: branches continue at both their target and
+/// the next instruction, `JMP`/`JSR` continue at their target (and `JSR` also after the call), and
+/// `RTS`, `RTI`, `BRK` and `JMP ($xxxx)` end the path unless `annotations` lists targets for them.
+/// Bytes that are not a documented opcode also end the path.
+///
+/// # Example
+/// ```rust
+/// use r_6502::disassembler::{find_code, JumpAnnotations};
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// // JMP ($0300) with a table entry the disassembler cannot see by itself
+/// mem.data[0x0600..0x0603].copy_from_slice(&[0x6C, 0x00, 0x03]);
+/// mem.data[0x0700] = 0x60; // RTS
+/// let mut annotations = JumpAnnotations::new();
+/// annotations.annotate(0x0600, &[0x0700]);
+/// assert_eq!(find_code(&mem, 0x0600, &annotations), vec![0x0600, 0x0700]);
+/// ```
+pub fn find_code(mem: &Memory, entry: u16, annotations: &JumpAnnotations) -> Vec<u16> {
+    let mut found: BTreeSet<u16> = BTreeSet::new();
+    let mut pending: Vec<u16> = vec![entry];
+    while let Some(address) = pending.pop() {
+        if !found.insert(address) {
+            continue;
+        }
+        let token = match Token::from_opcode(mem.data[address as usize]) {
+            Some(token) => token,
+            None => {
+                found.remove(&address);
+                continue;
+            }
+        };
+        let instruction = disassemble_instruction(mem, address);
+        let next = address.wrapping_add(instruction.bytes.len() as u16);
+        let operand = || ((instruction.bytes[2] as u16) << 8) | instruction.bytes[1] as u16;
+        pending.extend_from_slice(annotations.targets(address));
+        match (token, token.addressing_mode()) {
+            (_, AddressingMode::Relative) => {
+                let offset = instruction.bytes[1] as i8 as u16;
+                pending.push(next.wrapping_add(offset));
+                pending.push(next);
+            }
+            (Token::JMP, _) => pending.push(operand()),
+            (Token::JSR, _) => {
+                pending.push(operand());
+                pending.push(next);
+            }
+            (Token::JmpID | Token::RTS | Token::RTI | Token::BRK, _) => {}
+            _ => pending.push(next),
+        }
+    }
+    found.into_iter().collect()
+}
+
+/// Formats decoded instructions like `format_disassembly`, adding the annotated targets of
+/// computed jumps as a comment, e.g. `$0600  6C 00 03  JMP ($0300)    ; -> $0700, $0800`.
+pub fn format_annotated_disassembly(
+    instructions: &[DisassembledInstruction],
+    annotations: &JumpAnnotations,
+) -> String {
+    let lines: Vec<String> = instructions
+        .iter()
+        .map(|instruction| {
+            let line = format_disassembly(std::slice::from_ref(instruction));
+            let targets: Vec<String> = annotations
+                .targets(instruction.address)
+                .iter()
+                .map(|target| format!("${:04X}", target))
+                .collect();
+            if targets.is_empty() {
+                line
+            } else {
+                format!("{:<32}; -> {}", line, targets.join(", "))
+            }
+        })
+        .collect();
+    lines.join("\n")
+}
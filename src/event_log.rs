@@ -0,0 +1,217 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The kinds of event an `EventLog` records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    Interrupt,   // An IRQ, NMI, BRK or reset was taken
+    DeviceWrite, // An instruction wrote to an attached device's registers
+    Trap,        // The program stopped at a problem, or jumped to itself
+    BankSwitch,  // The MMU mapped a different bank into a page
+    Marker,      // A marker was reached: `detail` is its name and `pc` the marked address
+}
+
+impl EventKind {
+    /// Parses a kind as written in the `log` command, e.g. `device-write`.
+    pub fn parse(text: &str) -> Option<EventKind> {
+        match text {
+            "interrupt" => Some(EventKind::Interrupt),
+            "device-write" => Some(EventKind::DeviceWrite),
+            "trap" => Some(EventKind::Trap),
+            "bank-switch" => Some(EventKind::BankSwitch),
+            "marker" => Some(EventKind::Marker),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EventKind::Interrupt => "interrupt",
+            EventKind::DeviceWrite => "device-write",
+            EventKind::Trap => "trap",
+            EventKind::BankSwitch => "bank-switch",
+            EventKind::Marker => "marker",
+        };
+        f.pad(name)
+    }
+}
+
+/// An event in the log, stamped with the cycle it happened in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Event {
+    pub cycle: u64, // Value of `cpu.cycles` when the event happened
+    pub pc: u16,    // The instruction that caused the event, or the one an interrupt interrupted
+    pub kind: EventKind,
+    pub detail: String, // e.g. `IRQ`, `VIA $6004 = $FF` or `page $8 -> bank 2`
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>10}  ${:04X}  {:<12}  {}",
+            self.cycle, self.pc, self.kind, self.detail
+        )
+    }
+}
+
+/// Which events of an `EventLog` to show or export.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    pub kinds: Vec<EventKind>, // The kinds to include, or every kind when empty
+    pub from: Option<u64>,     // The first cycle to include
+    pub to: Option<u64>,       // The last cycle to include
+}
+
+impl EventFilter {
+    /// Parses a filter of event kinds and a cycle range, e.g. `interrupt trap from 1000 to 2000`.
+    ///
+    /// # Errors
+    /// A message naming the word that is neither a kind nor a valid `from`/`to` cycle.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::event_log::{EventFilter, EventKind};
+    ///
+    /// let filter = EventFilter::parse("device-write from 100").unwrap();
+    /// assert_eq!(filter.kinds, vec![EventKind::DeviceWrite]);
+    /// assert_eq!((filter.from, filter.to), (Some(100), None));
+    /// assert!(EventFilter::parse("writes").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<EventFilter, String> {
+        let mut filter = EventFilter::default();
+        let mut words = text.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "from" | "to" => {
+                    let cycle = words.next().and_then(|cycle| cycle.parse::<u64>().ok());
+                    match (word, cycle) {
+                        ("from", Some(cycle)) => filter.from = Some(cycle),
+                        (_, Some(cycle)) => filter.to = Some(cycle),
+                        (_, None) => return Err(format!("`{}` needs a cycle count", word)),
+                    }
+                }
+                _ => match EventKind::parse(word) {
+                    Some(kind) => filter.kinds.push(kind),
+                    None => return Err(format!("Unknown event kind `{}`", word)),
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    /// Returns whether `event` passes the filter.
+    pub fn matches(&self, event: &Event) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+            && self.from.is_none_or(|from| event.cycle >= from)
+            && self.to.is_none_or(|to| event.cycle <= to)
+    }
+}
+
+/// A cycle-stamped log of the notable things that happened while a CPU ran: interrupts taken,
+/// writes to device registers, traps, bank switches and markers reached.
+///
+/// Set as `cpu.events`, it ties together what the interrupt monitor, the devices and the MMU each
+/// see on their own, in the order it happened. Only the latest `capacity` events are kept, so the
+/// log can stay on for long runs; `dropped` counts those that were let go.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions};
+/// use r_6502::event_log::{EventFilter, EventKind, EventLog};
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.events = Some(EventLog::new(100));
+/// cpu.memory.data[0..3].copy_from_slice(&[0xE8, 0x00, 0xEA]); // INX, BRK
+/// cpu.memory.data[0xFFFE..=0xFFFF].copy_from_slice(&[0x00, 0x80]);
+/// cpu.memory.data[0x8000..0x8003].copy_from_slice(&[0x4C, 0x00, 0x80]); // JMP *
+/// let end = EndConditions { trap: true, ..EndConditions::default() };
+/// try_run_until(&mut cpu, &end);
+///
+/// let log = cpu.events.as_ref().unwrap();
+/// let lines: Vec<String> = log.events().map(|event| event.to_string()).collect();
+/// assert_eq!(lines[0], "         2  $0001  interrupt     BRK");
+/// assert_eq!(lines[1], "        12  $8000  trap          jumped to itself");
+/// let traps = EventFilter { kinds: vec![EventKind::Trap], ..EventFilter::default() };
+/// assert_eq!(log.query(&traps).count(), 1);
+/// assert!(log.to_json_lines(&traps).starts_with(r#"{"cycle":12,"pc":32768,"kind":"trap""#));
+/// ```
+#[derive(Clone, Debug)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    capacity: usize,
+    kinds: Vec<EventKind>, // The kinds recorded, or every kind when empty
+    dropped: u64,          // Events let go to stay within `capacity`
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self::of_kinds(capacity, &[])
+    }
+
+    /// Creates a log that only records events of `kinds`, e.g. the markers reached during a long
+    /// run, whose timings need every one of them.
+    pub fn of_kinds(capacity: usize, kinds: &[EventKind]) -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            capacity,
+            kinds: kinds.to_vec(),
+            dropped: 0,
+        }
+    }
+
+    /// Appends an event, letting go of the oldest one if the log is full. Events of a kind the log
+    /// does not record are ignored.
+    pub fn record(&mut self, cycle: u64, pc: u16, kind: EventKind, detail: String) {
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            return;
+        }
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(Event {
+            cycle,
+            pc,
+            kind,
+            detail,
+        });
+    }
+
+    /// Returns the events kept, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    /// Returns the events kept that pass `filter`, oldest first.
+    pub fn query<'a>(&'a self, filter: &'a EventFilter) -> impl Iterator<Item = &'a Event> {
+        self.events.iter().filter(|event| filter.matches(event))
+    }
+
+    /// Returns the number of events let go to stay within the capacity.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Forgets every event.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.dropped = 0;
+    }
+
+    /// Formats the events that pass `filter` as one JSON object per line, for exporting.
+    pub fn to_json_lines(&self, filter: &EventFilter) -> String {
+        self.query(filter)
+            .map(|event| serde_json::to_string(event).unwrap_or_default() + "\n")
+            .collect()
+    }
+}
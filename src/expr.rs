@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+/// Evaluates an assembler expression.
+///
+/// Expressions are made of:
+/// - Numbers in hex (`$FF`), binary (`%1010`) or decimal (`255`).
+/// - Symbols, looked up in `symbols` (usually an assembler's symbol table).
+/// - The unary operators `-` (negate), `~` (complement), `<` (low byte) and `>` (high byte).
+/// - The binary operators `*`, `/`, `%` (remainder), `+`, `-`, `&`, `^` and `|`, from highest to
+///   lowest precedence, and parentheses.
+///
+/// Arithmetic is done on 32-bit signed integers, so intermediate results can go past 16 bits.
+///
+/// # Errors
+/// Returns a message describing the problem if the expression is malformed, uses an undefined
+/// symbol or divides by zero.
+///
+/// # Example
+/// ```rust
+/// use r_6502::expr::evaluate;
+/// use std::collections::HashMap;
+///
+/// let symbols = HashMap::from([(String::from("table"), 0x1234)]);
+/// assert_eq!(evaluate("<table + 1", &symbols), Ok(0x35));
+/// assert_eq!(evaluate(">table", &symbols), Ok(0x12));
+/// assert_eq!(evaluate("(%1010 + $10) * 2", &symbols), Ok(52));
+/// assert!(evaluate("missing", &symbols).is_err());
+/// ```
+pub fn evaluate(text: &str, symbols: &HashMap<String, u16>) -> Result<i32, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        position: 0,
+        symbols,
+    };
+    let value = parser.parse_or()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(character) => Err(format!("unexpected `{}` in `{}`", character, text)),
+    }
+}
+
+/// Formats a value in hex, decimal and binary, e.g. `$0605  1541  %0000011000000101`.
+///
+/// Values that fit in a byte are shown with two hex and eight binary digits. Negative values are
+/// shown in decimal as they are and in hex and binary as a 16-bit two's complement.
+///
+/// # Example
+/// ```rust
+/// use r_6502::expr::format_value;
+///
+/// assert_eq!(format_value(10), "$0A  10  %00001010");
+/// assert_eq!(format_value(-1), "$FFFF  -1  %1111111111111111");
+/// ```
+pub fn format_value(value: i32) -> String {
+    if (0..=0xFF).contains(&value) {
+        format!("${:02X}  {}  %{:08b}", value, value, value)
+    } else {
+        let word = value as u16;
+        format!("${:04X}  {}  %{:016b}", word, value, word)
+    }
+}
+
+/// A recursive descent parser over the characters of an expression.
+struct Parser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    symbols: &'a HashMap<String, u16>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    /// Consumes `operator` if it is the next non-blank character.
+    fn accept(&mut self, operator: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(operator) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<i32, String> {
+        let mut value = self.parse_xor()?;
+        while self.accept('|') {
+            value |= self.parse_xor()?;
+        }
+        Ok(value)
+    }
+
+    fn parse_xor(&mut self) -> Result<i32, String> {
+        let mut value = self.parse_and()?;
+        while self.accept('^') {
+            value ^= self.parse_and()?;
+        }
+        Ok(value)
+    }
+
+    fn parse_and(&mut self) -> Result<i32, String> {
+        let mut value = self.parse_sum()?;
+        while self.accept('&') {
+            value &= self.parse_sum()?;
+        }
+        Ok(value)
+    }
+
+    fn parse_sum(&mut self) -> Result<i32, String> {
+        let mut value = self.parse_product()?;
+        loop {
+            if self.accept('+') {
+                value = value.wrapping_add(self.parse_product()?);
+            } else if self.accept('-') {
+                value = value.wrapping_sub(self.parse_product()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<i32, String> {
+        let mut value = self.parse_unary()?;
+        loop {
+            if self.accept('*') {
+                value = value.wrapping_mul(self.parse_unary()?);
+            } else if self.accept('/') {
+                let divisor = self.parse_unary()?;
+                value = value
+                    .checked_div(divisor)
+                    .ok_or_else(|| String::from("division by zero"))?;
+            } else if self.accept('%') {
+                let divisor = self.parse_unary()?;
+                value = value
+                    .checked_rem(divisor)
+                    .ok_or_else(|| String::from("division by zero"))?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<i32, String> {
+        if self.accept('-') {
+            Ok(self.parse_unary()?.wrapping_neg())
+        } else if self.accept('~') {
+            Ok(!self.parse_unary()?)
+        } else if self.accept('<') {
+            Ok(self.parse_unary()? & 0xFF)
+        } else if self.accept('>') {
+            Ok((self.parse_unary()? >> 8) & 0xFF)
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<i32, String> {
+        self.skip_whitespace();
+        if self.accept('(') {
+            let value = self.parse_or()?;
+            if !self.accept(')') {
+                return Err(String::from("missing `)`"));
+            }
+            return Ok(value);
+        }
+        match self.peek() {
+            Some('$') => {
+                self.position += 1;
+                self.parse_number(16)
+            }
+            Some('%') => {
+                self.position += 1;
+                self.parse_number(2)
+            }
+            Some(character) if character.is_ascii_digit() => self.parse_number(10),
+            Some(character) if character.is_alphabetic() || character == '_' => {
+                let name = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                match self.symbols.get(&name) {
+                    Some(value) => Ok(*value as i32),
+                    None => Err(format!("undefined symbol `{}`", name)),
+                }
+            }
+            Some(character) => Err(format!("unexpected `{}`", character)),
+            None => Err(String::from("unexpected end of expression")),
+        }
+    }
+
+    fn parse_number(&mut self, radix: u32) -> Result<i32, String> {
+        let digits = self.take_while(|c| c.is_ascii_alphanumeric());
+        i32::from_str_radix(&digits, radix).map_err(|_| format!("invalid number `{}`", digits))
+    }
+
+    fn take_while(&mut self, predicate: fn(char) -> bool) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(predicate) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+}
@@ -0,0 +1,173 @@
+use crate::asm_parser::comment_start;
+
+/// Column inline comments are aligned to, unless the code before them is longer.
+const COMMENT_COLUMN: usize = 24;
+
+/// One line of assembly source, split into its parts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct SourceLine {
+    label: Option<String>,
+    code: Option<String>, // Instruction or directive, already in canonical form
+    comment: Option<String>,
+}
+
+/// Formats assembly source in the canonical style.
+///
+/// - Labels go on a line of their own, so `loop: INX` becomes `loop:` followed by `INX`.
+/// - Mnemonics, index registers, the `A` of `ASL A` and hex digits are upper case, and the
+///   mnemonic and operand are separated by a single space.
+/// - Directives are lower case (`.org $8000`), with `* = $8000` written as `.org $8000`.
+/// - Constant definitions keep their name as written and are spaced as `SCREEN = $0400` or
+///   `SCREEN EQU $0400`.
+/// - Inline comments are aligned to column 25 and start with `; `; whole-line comments start at
+///   the beginning of the line.
+/// - Trailing whitespace is removed, runs of blank lines are collapsed to one, and the output ends
+///   with a single newline.
+///
+/// Formatting is idempotent and does not change what the source assembles to.
+///
+/// # Example
+/// ```rust
+/// use r_6502::formatter::format_source;
+///
+/// let source = "start:  lda #$0a   ;load\n\n\nloop: sta ($fb),y\n";
+/// assert_eq!(
+///     format_source(source),
+///     "start:\nLDA #$0A                ; load\n\nloop:\nSTA ($FB),Y\n"
+/// );
+/// ```
+pub fn format_source(source: &str) -> String {
+    let mut output = String::new();
+    let mut blank = true; // Drop blank lines at the start of the file
+    for line in source.lines() {
+        let parsed = split_line(line);
+        if parsed == SourceLine::default() {
+            if !blank {
+                output.push('\n');
+                blank = true;
+            }
+            continue;
+        }
+        blank = false;
+        if let Some(label) = &parsed.label {
+            output.push_str(label);
+            output.push_str(":\n");
+        }
+        match (&parsed.code, &parsed.comment) {
+            (Some(code), Some(comment)) => {
+                output.push_str(&format!(
+                    "{:<width$}; {}\n",
+                    code,
+                    comment,
+                    width = COMMENT_COLUMN.max(code.len() + 1)
+                ));
+            }
+            (Some(code), None) => {
+                output.push_str(code);
+                output.push('\n');
+            }
+            (None, Some(comment)) => output.push_str(&format!("; {}\n", comment)),
+            (None, None) => {}
+        }
+    }
+    // Drop a blank line at the end of the file
+    if output.ends_with("\n\n") {
+        output.pop();
+    }
+    output
+}
+
+/// Checks whether source is already in the canonical style.
+pub fn is_formatted(source: &str) -> bool {
+    format_source(source) == source
+}
+
+fn split_line(line: &str) -> SourceLine {
+    let (code, comment) = match comment_start(line) {
+        Some(index) => (&line[..index], Some(line[index + 1..].trim())),
+        None => (line, None),
+    };
+    let mut code = code.trim();
+    let mut label = None;
+    if let Some((name, rest)) = code.split_once(':') {
+        if !name.is_empty() && !name.contains(char::is_whitespace) && !name.contains('"') {
+            label = Some(name.to_string());
+            code = rest.trim();
+        }
+    }
+    SourceLine {
+        label,
+        code: if code.is_empty() {
+            None
+        } else {
+            Some(format_code(code))
+        },
+        comment: match comment {
+            Some(comment) if !comment.is_empty() => Some(comment.to_string()),
+            _ => None,
+        },
+    }
+}
+
+/// Formats an instruction or directive.
+fn format_code(code: &str) -> String {
+    if let Some(operand) = code.strip_prefix('*') {
+        if let Some(address) = operand.trim_start().strip_prefix('=') {
+            return format!(".org {}", format_operand(address.trim()));
+        }
+    }
+    if let Some((name, value)) = code.split_once('=') {
+        let name = name.trim();
+        if !name.contains(char::is_whitespace) {
+            return format!("{} = {}", name, format_operand(value.trim()));
+        }
+    }
+    let (mnemonic, operand) = match code.split_once(char::is_whitespace) {
+        Some((mnemonic, operand)) => (mnemonic, operand.trim()),
+        None => (code, ""),
+    };
+    if let Some((keyword, value)) = operand.split_once(char::is_whitespace) {
+        if keyword.eq_ignore_ascii_case("EQU") {
+            return format!("{} EQU {}", mnemonic, format_operand(value.trim()));
+        }
+    }
+    let mnemonic = if mnemonic.starts_with('.') {
+        mnemonic.to_ascii_lowercase()
+    } else {
+        mnemonic.to_ascii_uppercase()
+    };
+    if operand.is_empty() {
+        mnemonic
+    } else if operand.eq_ignore_ascii_case("A")
+        && matches!(mnemonic.as_str(), "ASL" | "LSR" | "ROL" | "ROR")
+    {
+        format!("{} A", mnemonic)
+    } else if operand.starts_with('"') {
+        format!("{} {}", mnemonic, operand)
+    } else {
+        format!("{} {}", mnemonic, format_operand(operand))
+    }
+}
+
+/// Formats an operand: removes spaces, and upper-cases hex digits and index registers.
+fn format_operand(operand: &str) -> String {
+    let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut output = String::new();
+    let mut in_hex = false;
+    let mut after_comma = false;
+    for character in operand.chars() {
+        if character == '$' {
+            in_hex = true;
+            output.push(character);
+        } else if (in_hex && character.is_ascii_hexdigit())
+            || (after_comma && matches!(character, 'x' | 'y' | 'X' | 'Y'))
+        {
+            output.push(character.to_ascii_uppercase());
+        } else {
+            in_hex = false;
+            output.push(character);
+        }
+        after_comma = character == ',';
+    }
+    output
+}
@@ -0,0 +1,266 @@
+use crate::asm_runner::{try_execute_instruction, StopReason};
+use crate::cpu::CPU;
+use crate::lcd::{LcdScreen, DDRAM_SIZE};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Hashes bytes with 64-bit FNV-1a.
+///
+/// Unlike the hashers in `std`, FNV-1a gives the same value on every platform and Rust version, so
+/// its values can be stored in tests as golden values.
+///
+/// # Example
+/// ```rust
+/// use r_6502::frame_hash::frame_hash;
+///
+/// assert_eq!(frame_hash(b""), 0xCBF29CE484222325);
+/// assert_ne!(frame_hash(b"Hello"), frame_hash(b"hello"));
+/// ```
+pub fn frame_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF29CE484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+/// Why a `FrameTest` run did not get to hash the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    TooFewRefreshes { refreshes: u64 }, // The cycle budget ran out after this many refreshes
+    Stopped(StopReason),                // The program stopped at a problem
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::TooFewRefreshes { refreshes } => write!(
+                f,
+                "the display was only refreshed {} times before the cycle budget ran out",
+                refreshes
+            ),
+            FrameError::Stopped(reason) => write!(f, "the program stopped: {:?}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// A visual regression test: runs a program for a number of display refreshes, or for a cycle
+/// budget, then hashes the display RAM of an LCD so it can be compared with a stored golden value.
+///
+/// Only the hash is stored, so demos that draw on the display can be checked in CI without keeping
+/// images of it. The screen comes from `Lcd::screen` or `MachineProfile::apply_headless`; a
+/// refresh is a change to the visible text.
+///
+/// # Example
+/// ```rust
+/// use r_6502::frame_hash::{frame_hash, FrameTest};
+/// use r_6502::machine::MachineProfile;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// let screens = MachineProfile::builtin("eater").unwrap().apply_headless(&mut cpu).unwrap();
+/// cpu.memory.data[0x8000..0x8003].copy_from_slice(&[0x4C, 0x00, 0x80]); // JMP $8000
+/// cpu.pc = 0x8000;
+///
+/// // Nothing is displayed, so the display RAM still holds the spaces it starts with
+/// let hash = FrameTest::cycles(1_000).run(&mut cpu, &screens[0]).unwrap();
+/// assert_eq!(hash, frame_hash(&[b' '; 0x80]));
+/// assert!(FrameTest::refreshes(1).with_max_cycles(1_000).run(&mut cpu, &screens[0]).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameTest {
+    pub refreshes: Option<u64>, // Refreshes to run for, or `None` to run for all of `max_cycles`
+    pub max_cycles: u64,        // Cycles to run before giving up, or stopping with no `refreshes`
+}
+
+impl FrameTest {
+    /// Creates a test that runs until the display has been refreshed `refreshes` times, giving up
+    /// after 10 million cycles.
+    pub fn refreshes(refreshes: u64) -> Self {
+        FrameTest {
+            refreshes: Some(refreshes),
+            max_cycles: 10_000_000,
+        }
+    }
+
+    /// Creates a test that runs for `cycles` cycles, however often the display is refreshed.
+    pub fn cycles(cycles: u64) -> Self {
+        FrameTest {
+            refreshes: None,
+            max_cycles: cycles,
+        }
+    }
+
+    /// Sets the number of cycles to run before giving up.
+    pub fn with_max_cycles(mut self, max_cycles: u64) -> Self {
+        self.max_cycles = max_cycles;
+        self
+    }
+
+    /// Runs the program from the current program counter, then hashes the display RAM.
+    ///
+    /// # Returns
+    /// The `frame_hash` of the display RAM once the run is over.
+    ///
+    /// # Errors
+    /// - `FrameError::TooFewRefreshes`: If the cycle budget ran out before the display was refreshed
+    ///   `refreshes` times.
+    /// - `FrameError::Stopped`: If the program stopped at a problem, such as an unknown opcode.
+    pub fn run(&self, cpu: &mut CPU, screen: &Rc<RefCell<LcdScreen>>) -> Result<u64, FrameError> {
+        let start_cycles = cpu.cycles;
+        let start_refreshes = screen.borrow().refreshes;
+        let mut data_cycle_count: u32 = u32::MAX;
+        loop {
+            let refreshes = screen.borrow().refreshes - start_refreshes;
+            if self.refreshes.is_some_and(|wanted| refreshes >= wanted) {
+                break;
+            }
+            if cpu.cycles - start_cycles >= self.max_cycles {
+                if self.refreshes.is_some() {
+                    return Err(FrameError::TooFewRefreshes { refreshes });
+                }
+                break;
+            }
+            try_execute_instruction(cpu, &mut data_cycle_count).map_err(FrameError::Stopped)?;
+            data_cycle_count = u32::MAX;
+        }
+        Ok(frame_hash(&screen.borrow().ddram))
+    }
+
+    /// Runs the program like `run` and checks the hash of the display RAM against `golden`.
+    ///
+    /// # Panics
+    /// - If the run fails, or the hash is not `golden`. The message shows the hash and the display
+    ///   RAM, so a deliberate change can be checked and its hash stored as the new golden value.
+    pub fn assert_hash(&self, cpu: &mut CPU, screen: &Rc<RefCell<LcdScreen>>, golden: u64) {
+        let hash = match self.run(cpu, screen) {
+            Ok(hash) => hash,
+            Err(e) => panic!("Frame test failed: {}", e),
+        };
+        if hash != golden {
+            let ddram = screen.borrow().ddram;
+            let line =
+                |start: usize| String::from_utf8_lossy(&ddram[start..start + 40]).into_owned();
+            let mismatch = format!(
+                "Frame hash {:#018X} is not the golden {:#018X}; display RAM:\n{}\n{}",
+                hash,
+                golden,
+                line(0),
+                line(DDRAM_SIZE / 2)
+            );
+            panic!("{}", mismatch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::MachineProfile;
+    use crate::Assembler;
+
+    /// Prints "Hello, world!" on the eater's LCD, the way Ben Eater's videos do, then loops.
+    const HELLO: &str = "
+PORTB = $6000
+PORTA = $6001
+DDRB = $6002
+DDRA = $6003
+E = $80
+RS = $20
+.org $8000
+reset:
+    LDX #$FF
+    TXS
+    LDA #$FF
+    STA DDRB
+    LDA #$E0
+    STA DDRA
+    LDA #$38 ; 8-bit, 2 lines
+    JSR instruction
+    LDA #$0E ; Display on
+    JSR instruction
+    LDA #$06 ; Increment
+    JSR instruction
+    LDA #$01 ; Clear
+    JSR instruction
+    LDX #0
+print:
+    LDA message,X
+    BEQ done
+    JSR character
+    INX
+    JMP print
+done:
+    JMP done
+message:
+    .textz \"Hello, world!\"
+instruction:
+    STA PORTB
+    LDA #0
+    STA PORTA
+    LDA #E
+    STA PORTA
+    LDA #0
+    STA PORTA
+    RTS
+character:
+    STA PORTB
+    LDA #RS
+    STA PORTA
+    LDA #RS+E
+    STA PORTA
+    LDA #RS
+    STA PORTA
+    RTS
+.org $FFFC
+.word reset
+.word $0000
+";
+
+    /// `frame_hash` of the display RAM once `HELLO` has printed its message.
+    const HELLO_GOLDEN: u64 = 0x6045B0353C0808DC;
+
+    /// Sets up the eater machine with `HELLO` loaded and reset into.
+    fn eater() -> (CPU, Rc<RefCell<LcdScreen>>) {
+        let mut cpu = CPU::new();
+        let mut screens = MachineProfile::builtin("eater")
+            .unwrap()
+            .apply_headless(&mut cpu)
+            .unwrap();
+        let mut address: u16 = 0x8000;
+        Assembler::new()
+            .assemble(HELLO, &mut cpu.memory, &mut address)
+            .unwrap();
+        cpu.reset();
+        (cpu, screens.remove(0))
+    }
+
+    #[test]
+    fn hello_world_matches_its_golden_hash() {
+        // One refresh for each character printed, but the space, which leaves the text as it was
+        let (mut cpu, screen) = eater();
+        FrameTest::refreshes(12).assert_hash(&mut cpu, &screen, HELLO_GOLDEN);
+        assert_eq!(&screen.borrow().ddram[0..13], b"Hello, world!");
+
+        // Running on in the final loop changes nothing
+        let (mut cpu, screen) = eater();
+        FrameTest::cycles(100_000).assert_hash(&mut cpu, &screen, HELLO_GOLDEN);
+        assert_eq!(screen.borrow().refreshes, 12);
+    }
+
+    #[test]
+    fn a_different_display_fails_the_golden_hash() {
+        let (mut cpu, screen) = eater();
+        let hash = FrameTest::refreshes(5).run(&mut cpu, &screen).unwrap();
+        assert_ne!(hash, HELLO_GOLDEN);
+        assert_eq!(&screen.borrow().ddram[0..6], b"Hello ");
+        assert_eq!(hash, frame_hash(&screen.borrow().ddram));
+
+        let (mut cpu, screen) = eater();
+        let result = FrameTest::refreshes(13)
+            .with_max_cycles(100_000)
+            .run(&mut cpu, &screen);
+        assert_eq!(result, Err(FrameError::TooFewRefreshes { refreshes: 12 }));
+    }
+}
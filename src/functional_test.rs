@@ -0,0 +1,142 @@
+use crate::asm_runner::{try_execute_instruction, StopReason};
+use crate::cpu::CPU;
+
+/// Entry point of Klaus Dormann's `6502_functional_test.bin`, assembled with its default options.
+pub const KLAUS_DORMANN_START: u16 = 0x0400;
+
+/// Address of the `JMP *` the same binary reaches once every test has passed.
+pub const KLAUS_DORMANN_SUCCESS: u16 = 0x3469;
+
+/// How a run of a self-checking test program ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed { instructions: u64, cycles: u64 }, // Trapped at the success address
+    Failed { pc: u16, instructions: u64 },     // Trapped anywhere else: the test at `pc` failed
+    IllegalOpcode { address: u16, opcode: u8 }, // Ran into a byte that is not an opcode
+    TimedOut { pc: u16 },                      // Still running after the instruction limit
+}
+
+/// A self-checking test program, such as Klaus Dormann's 6502 functional test.
+///
+/// Such programs report their result by trapping: they jump or branch to the instruction itself
+/// (`JMP *`, `BNE *`, ...) and spin there forever. Trapping at the success address means every
+/// test passed; trapping anywhere else means the test just before the trap failed, and the
+/// program's listing tells which one.
+///
+/// # Example
+/// ```rust
+/// use r_6502::functional_test::{FunctionalTest, TestOutcome};
+///
+/// // LDA #$01; CMP #$01; BNE * (fail); JMP * (success)
+/// let image = [0xA9, 0x01, 0xC9, 0x01, 0xD0, 0xFE, 0x4C, 0x06, 0x06];
+/// let test = FunctionalTest::new(0x0600, 0x0606);
+/// let outcome = test.run_image(&image, 0x0600);
+/// assert!(matches!(outcome, TestOutcome::Passed { instructions: 4, .. }));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FunctionalTest {
+    pub start: u16,            // Address execution starts at
+    pub success: u16,          // Address of the trap reached when every test passed
+    pub max_instructions: u64, // Instructions to execute before giving up
+}
+
+impl FunctionalTest {
+    /// Creates a test starting at `start` and passing when it traps at `success`, with a limit of
+    /// 100 million instructions.
+    pub fn new(start: u16, success: u16) -> Self {
+        FunctionalTest {
+            start,
+            success,
+            max_instructions: 100_000_000,
+        }
+    }
+
+    /// Creates a test for Klaus Dormann's `6502_functional_test.bin`, assembled with its default
+    /// options and loaded at `$0000`.
+    ///
+    /// The binary covers every documented opcode and addressing mode, including decimal mode,
+    /// and takes about 30 million instructions to pass.
+    pub fn klaus_dormann() -> Self {
+        Self::new(KLAUS_DORMANN_START, KLAUS_DORMANN_SUCCESS)
+    }
+
+    /// Loads `image` at `origin` into a fresh CPU and runs the test on it.
+    ///
+    /// # Panics
+    /// - If the image does not fit in memory when loaded at `origin`.
+    pub fn run_image(&self, image: &[u8], origin: u16) -> TestOutcome {
+        let mut cpu = CPU::new();
+        if let Err(e) = cpu.memory.load_bytes(image, origin) {
+            panic!("{}", e);
+        }
+        self.run(&mut cpu)
+    }
+
+    /// Runs the test program already loaded in the CPU's memory, from `start` until it traps.
+    ///
+    /// The CPU's undocumented opcode settings apply, and it is left in the state the program
+    /// trapped in, so the registers and memory can be inspected after a failure.
+    pub fn run(&self, cpu: &mut CPU) -> TestOutcome {
+        cpu.pc = self.start;
+        let start_cycles = cpu.cycles;
+        let mut data_cycle_count: u32 = u32::MAX;
+        for instructions in 1..=self.max_instructions {
+            let pc = cpu.pc;
+            if let Err(StopReason::IllegalOpcode { address, opcode }) =
+                try_execute_instruction(cpu, &mut data_cycle_count)
+            {
+                return TestOutcome::IllegalOpcode { address, opcode };
+            }
+            data_cycle_count = u32::MAX;
+            if cpu.pc == pc {
+                return if pc == self.success {
+                    TestOutcome::Passed {
+                        instructions,
+                        cycles: cpu.cycles - start_cycles,
+                    }
+                } else {
+                    TestOutcome::Failed { pc, instructions }
+                };
+            }
+        }
+        TestOutcome::TimedOut { pc: cpu.pc }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs Klaus Dormann's `6502_functional_test.bin` from the path in `KLAUS_FUNCTIONAL_TEST`.
+    ///
+    /// The binary isn't shipped with the crate, so run this with
+    /// `KLAUS_FUNCTIONAL_TEST=path/to/6502_functional_test.bin cargo test --release -- --ignored`.
+    #[test]
+    #[ignore = "needs KLAUS_FUNCTIONAL_TEST set to the path of 6502_functional_test.bin"]
+    fn klaus_dormann_functional_test_passes() {
+        let path = std::env::var("KLAUS_FUNCTIONAL_TEST")
+            .expect("KLAUS_FUNCTIONAL_TEST should name the functional test binary");
+        let image = std::fs::read(&path).expect("the functional test binary should be readable");
+        let outcome = FunctionalTest::klaus_dormann().run_image(&image, 0x0000);
+        assert!(
+            matches!(outcome, TestOutcome::Passed { .. }),
+            "{} did not pass: {:?}",
+            path,
+            outcome
+        );
+    }
+
+    #[test]
+    fn trapping_away_from_the_success_address_fails() {
+        // LDA #$01; CMP #$02; BNE * (fail); JMP * (success)
+        let image = [0xA9, 0x01, 0xC9, 0x02, 0xD0, 0xFE, 0x4C, 0x06, 0x06];
+        let outcome = FunctionalTest::new(0x0600, 0x0606).run_image(&image, 0x0600);
+        assert_eq!(
+            outcome,
+            TestOutcome::Failed {
+                pc: 0x0604,
+                instructions: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,82 @@
+/// An access to a guard region made by the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuardAccess {
+    pub address: u16,
+    pub write: bool, // `true` for a write, `false` for a read
+}
+
+/// Address ranges the program must never read or write.
+///
+/// Guard regions placed just past the end of a table or buffer catch indexed addressing that walks
+/// off its end, and a guard at the bottom of page `$01` catches the stack growing too deep. Reads
+/// and writes made by instructions, including pushes and pulls, are checked; fetching instructions
+/// is not. The first access is remembered until it is taken with `take_access`, which
+/// `try_run_until` does after every instruction to stop with `StopReason::GuardHit`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// // Fill a 4-byte table at $0300, but count the index down one step too far
+/// let source = "LDX #$04\nloop:\nSTA $02FF,X\nDEX\nBPL loop";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// cpu.guards.add(0x02FF, 0x02FF);
+/// let reason = try_run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert!(matches!(reason, StopReason::GuardHit { address: 0x02FF, write: true, .. }));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GuardRegions {
+    regions: Vec<(u16, u16)>,
+    access: Option<GuardAccess>,
+}
+
+impl GuardRegions {
+    pub fn new() -> Self {
+        GuardRegions {
+            regions: Vec::new(),
+            access: None,
+        }
+    }
+
+    /// Guards the addresses `start..=end`.
+    pub fn add(&mut self, start: u16, end: u16) {
+        self.regions.push((start, end));
+    }
+
+    /// Removes the guard region starting at `start`.
+    ///
+    /// # Returns
+    /// - `true`: If a region was removed.
+    /// - `false`: If no region starts at `start`.
+    pub fn remove(&mut self, start: u16) -> bool {
+        let count = self.regions.len();
+        self.regions
+            .retain(|(region_start, _)| *region_start != start);
+        self.regions.len() != count
+    }
+
+    /// Returns the guarded ranges as `(start, end)` pairs, in the order they were added.
+    pub fn list(&self) -> &[(u16, u16)] {
+        &self.regions
+    }
+
+    /// Checks an access made by the program, remembering it if it falls in a guard region.
+    pub fn check(&mut self, address: u16, write: bool) {
+        if self.access.is_none()
+            && self
+                .regions
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&address))
+        {
+            self.access = Some(GuardAccess { address, write });
+        }
+    }
+
+    /// Returns and forgets the first guarded access made since the last call, if any.
+    pub fn take_access(&mut self) -> Option<GuardAccess> {
+        self.access.take()
+    }
+}
@@ -0,0 +1,195 @@
+use crate::cpu::CPU;
+use crate::pins::step_pins;
+use std::fmt;
+use std::fs;
+
+/// The bus during one clock cycle of a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusCycle {
+    pub address: u16,
+    pub data: u8,
+    pub read: bool, // R/W high
+}
+
+impl fmt::Display for BusCycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = if self.read { "R" } else { "W" };
+        write!(f, "{} ${:04X} ${:02X}", direction, self.address, self.data)
+    }
+}
+
+/// An error found while reading a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureError {
+    pub line: usize, // 1-based line the error was found on, or 0 if the file could not be read
+    pub message: String,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// The first cycle where the emulator's bus differs from the capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceDivergence {
+    pub cycle: usize, // Index of the cycle in the capture, from 0
+    pub pc: u16,      // Address of the instruction the emulator was executing
+    pub expected: BusCycle,
+    pub actual: BusCycle,
+}
+
+impl fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cycle {} (instruction at ${:04X}): expected {}, emulator {}",
+            self.cycle, self.pc, self.expected, self.actual
+        )
+    }
+}
+
+/// A cycle-by-cycle recording of the bus of a real 6502 board, e.g. from a logic analyser.
+///
+/// Captures are CSV files with one cycle per line: the address, the data and the R/W line, as in
+/// `FFFC,00,R`. Address and data are hex, with an optional `$` or `0x`; R/W is `R` or `1` for a
+/// read and `W` or `0` for a write. A first line that is not a cycle (a header) is skipped, as are
+/// blank lines and lines starting with `#`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::hardware_trace::Capture;
+/// use r_6502::CPU;
+///
+/// let capture = Capture::parse_csv("address,data,rw\n0000,A9,R\n0001,05,R\n0002,85,R\n0003,10,R\n0010,05,W").unwrap();
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..4].copy_from_slice(&[0xA9, 0x05, 0x85, 0x10]); // LDA #$05, STA $10
+/// assert_eq!(capture.compare(&mut cpu), Ok(5));
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..4].copy_from_slice(&[0xA9, 0x06, 0x85, 0x10]);
+/// let divergence = capture.compare(&mut cpu).unwrap_err();
+/// assert_eq!(divergence.cycle, 1);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capture {
+    pub cycles: Vec<BusCycle>,
+}
+
+impl Capture {
+    /// Parses a capture in CSV.
+    ///
+    /// # Errors
+    /// Returns a `CaptureError` for the first line, other than a header, that is not a cycle.
+    pub fn parse_csv(text: &str) -> Result<Self, CaptureError> {
+        let mut cycles: Vec<BusCycle> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_cycle(line) {
+                Ok(cycle) => cycles.push(cycle),
+                Err(_) if cycles.is_empty() && index == 0 => {} // Header
+                Err(message) => {
+                    return Err(CaptureError {
+                        line: index + 1,
+                        message,
+                    })
+                }
+            }
+        }
+        Ok(Capture { cycles })
+    }
+
+    /// Reads and parses a capture file.
+    ///
+    /// # Errors
+    /// Returns a `CaptureError` with line 0 if the file cannot be read, or the first parse error.
+    pub fn load_file(path: &str) -> Result<Self, CaptureError> {
+        let text = fs::read_to_string(path).map_err(|e| CaptureError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        Self::parse_csv(&text)
+    }
+
+    /// Runs the CPU from its current state and compares its bus, cycle by cycle, with the
+    /// capture, which must start with the opcode fetch of the instruction at `cpu.pc`.
+    ///
+    /// The emulator's bus comes from `step_pins`. Its internal cycles only stand in for the dummy
+    /// accesses the chip makes, so they match any read in the capture.
+    ///
+    /// # Returns
+    /// The number of cycles compared, which is the length of the capture.
+    ///
+    /// # Errors
+    /// Returns the first `TraceDivergence`, with the CPU left after the instruction it occurred
+    /// in.
+    pub fn compare(&self, cpu: &mut CPU) -> Result<usize, TraceDivergence> {
+        let mut cycle = 0;
+        while cycle < self.cycles.len() {
+            let pc = cpu.pc;
+            for (pin, expected) in step_pins(cpu).iter().zip(&self.cycles[cycle..]) {
+                let actual = BusCycle {
+                    address: pin.address,
+                    data: pin.data,
+                    read: pin.read,
+                };
+                let matches = if pin.internal {
+                    expected.read
+                } else {
+                    actual == *expected
+                };
+                if !matches {
+                    return Err(TraceDivergence {
+                        cycle,
+                        pc,
+                        expected: *expected,
+                        actual,
+                    });
+                }
+                cycle += 1;
+            }
+        }
+        Ok(cycle)
+    }
+}
+
+/// Parses one line of a capture, e.g. `FFFC,00,R`.
+fn parse_cycle(line: &str) -> Result<BusCycle, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let (address, data, rw) = match fields[..] {
+        [address, data, rw] => (address, data, rw),
+        _ => return Err(format!("expected `address,data,rw`, found `{}`", line)),
+    };
+    let address = parse_hex(address)?;
+    let data = u8::try_from(parse_hex(data)?)
+        .map_err(|_| format!("data `{}` does not fit in a byte", data))?;
+    let read = match rw {
+        "R" | "r" | "1" => true,
+        "W" | "w" | "0" => false,
+        other => return Err(format!("invalid R/W `{}`", other)),
+    };
+    Ok(BusCycle {
+        address,
+        data,
+        read,
+    })
+}
+
+/// Parses a hex number with an optional `$` or `0x` prefix.
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix('$')
+        .or_else(|| value.strip_prefix("0x"))
+        .unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number `{}`", value))
+}
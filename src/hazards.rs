@@ -0,0 +1,229 @@
+use crate::asm_runner::execute_instruction;
+use crate::cpu::{CPU, DECIMAL};
+use crate::memory::Memory;
+use crate::token::Token;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A kind of behaviour that works in the emulator but is undefined or varies on real hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HazardKind {
+    /// A read of RAM nothing has written or loaded: open bus or power-on garbage on hardware,
+    /// where the emulator returns a well-defined value.
+    UninitialisedRead { address: u16 },
+    /// `ADC` or `SBC` in decimal mode with an operand or accumulator that is not valid BCD.
+    InvalidBcd { a: u8, operand: u8 },
+    /// A branch on N, V or Z straight after a decimal `ADC` or `SBC`, whose flags differ between
+    /// the NMOS 6502 and the 65C02.
+    DecimalFlags,
+    /// An undocumented opcode, which the 65C02 and other variants execute differently.
+    UndocumentedOpcode { opcode: u8 },
+}
+
+/// A portability hazard found while a program ran.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hazard {
+    pub pc: u16, // Address of the instruction that ran into it
+    pub kind: HazardKind,
+}
+
+impl fmt::Display for Hazard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            HazardKind::UninitialisedRead { address } => write!(
+                f,
+                "${:04X}: reads ${:04X}, which was never written (open bus or power-on RAM \
+                 contents on real hardware)",
+                self.pc, address
+            ),
+            HazardKind::InvalidBcd { a, operand } => write!(
+                f,
+                "${:04X}: decimal-mode arithmetic on ${:02X} and ${:02X}, which are not both valid \
+                 BCD (the result is undefined)",
+                self.pc, a, operand
+            ),
+            HazardKind::DecimalFlags => write!(
+                f,
+                "${:04X}: branches on N, V or Z after decimal-mode arithmetic (they differ \
+                 between the NMOS 6502 and the 65C02)",
+                self.pc
+            ),
+            HazardKind::UndocumentedOpcode { opcode } => write!(
+                f,
+                "${:04X}: executes undocumented opcode ${:02X} (not portable to the 65C02 and \
+                 other variants)",
+                self.pc, opcode
+            ),
+        }
+    }
+}
+
+/// Watches a running program for behaviour that is undefined or varies on real hardware, so
+/// authors targeting it learn about portability hazards early.
+///
+/// RAM counts as written once the program writes it through the bus; every nonzero byte in memory
+/// when the monitor is created counts as loaded, and `mark_initialised` marks ranges (such as the
+/// program image) that may contain zeros. ROMs and devices are always initialised.
+///
+/// Each hazard is reported once per instruction address and kind of hazard.
+///
+/// # Example
+/// ```rust
+/// use r_6502::hazards::{HazardKind, HazardMonitor};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// let source = "SED\nLDA #$0A\nADC #$01\nBEQ done\nLDX $0300\ndone:\nNOP";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// let mut monitor = HazardMonitor::new(&cpu.memory);
+/// monitor.mark_initialised(0x0000, end_address - 1);
+/// let mut data_cycle_count = end_address as u32;
+/// monitor.run(&mut cpu, &mut data_cycle_count);
+/// let kinds: Vec<HazardKind> = monitor.hazards().iter().map(|hazard| hazard.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [
+///         HazardKind::InvalidBcd { a: 0x0A, operand: 0x01 },
+///         HazardKind::DecimalFlags,
+///         HazardKind::UninitialisedRead { address: 0x0300 },
+///     ]
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct HazardMonitor {
+    initialised: Vec<bool>, // By address
+    hazards: Vec<Hazard>,
+    reported: BTreeSet<Hazard>,
+    after_decimal: bool, // The last instruction was `ADC` or `SBC` in decimal mode
+}
+
+impl HazardMonitor {
+    /// Creates a monitor for a program loaded into `mem`.
+    pub fn new(mem: &Memory) -> Self {
+        let mut initialised: Vec<bool> = mem.data.iter().map(|byte| *byte != 0).collect();
+        for region in mem.map().iter().filter(|region| region.kind != "RAM") {
+            initialised[region.start as usize..=region.end as usize].fill(true);
+        }
+        HazardMonitor {
+            initialised,
+            hazards: Vec::new(),
+            reported: BTreeSet::new(),
+            after_decimal: false,
+        }
+    }
+
+    /// Marks the addresses `start..=end` as loaded, so reading them is not a hazard.
+    pub fn mark_initialised(&mut self, start: u16, end: u16) {
+        self.initialised[start as usize..=end as usize].fill(true);
+    }
+
+    /// Executes the program like `run_memory`, checking every instruction.
+    pub fn run(&mut self, cpu: &mut CPU, data_cycle_count: &mut u32) {
+        while *data_cycle_count > 0 {
+            self.step(cpu, data_cycle_count);
+        }
+    }
+
+    /// Executes one instruction and records the hazards it runs into.
+    pub fn step(&mut self, cpu: &mut CPU, data_cycle_count: &mut u32) {
+        let pc = cpu.pc;
+        let a = cpu.a;
+        let decimal = cpu.flag(DECIMAL);
+        let opcode = cpu.memory.data[pc as usize];
+        let token = Token::from_opcode(opcode);
+        cpu.memory.record_bus();
+        execute_instruction(cpu, data_cycle_count);
+        let accesses = cpu.memory.take_bus_log();
+
+        for access in &accesses {
+            let address = access.address as usize;
+            if !access.write && !self.initialised[address] {
+                self.report(
+                    pc,
+                    HazardKind::UninitialisedRead {
+                        address: access.address,
+                    },
+                );
+            }
+            self.initialised[address] = true;
+        }
+        if token.is_none() {
+            self.report(pc, HazardKind::UndocumentedOpcode { opcode });
+        }
+        if self.after_decimal
+            && matches!(
+                token,
+                Some(Token::BMI | Token::BPL | Token::BVS | Token::BVC | Token::BEQ | Token::BNE)
+            )
+        {
+            self.report(pc, HazardKind::DecimalFlags);
+        }
+        self.after_decimal = decimal && is_arithmetic(token);
+        if self.after_decimal {
+            // The operand is the last byte read before any write (an IRQ taken after the
+            // instruction pushes before fetching its vector)
+            let operand = accesses
+                .iter()
+                .take_while(|access| !access.write)
+                .last()
+                .map_or(0, |access| access.value);
+            if !is_bcd(a) || !is_bcd(operand) {
+                self.report(pc, HazardKind::InvalidBcd { a, operand });
+            }
+        }
+    }
+
+    /// Returns the hazards found, in the order they were first run into.
+    pub fn hazards(&self) -> &[Hazard] {
+        &self.hazards
+    }
+
+    /// Records a hazard unless the instruction already ran into the same kind.
+    fn report(&mut self, pc: u16, kind: HazardKind) {
+        let key = Hazard {
+            pc,
+            kind: match kind {
+                // One report per instruction, whatever the values involved
+                HazardKind::UninitialisedRead { .. } => {
+                    HazardKind::UninitialisedRead { address: 0 }
+                }
+                HazardKind::InvalidBcd { .. } => HazardKind::InvalidBcd { a: 0, operand: 0 },
+                other => other,
+            },
+        };
+        if self.reported.insert(key) {
+            self.hazards.push(Hazard { pc, kind });
+        }
+    }
+}
+
+/// Checks whether an instruction is `ADC` or `SBC`.
+fn is_arithmetic(token: Option<Token>) -> bool {
+    matches!(
+        token,
+        Some(
+            Token::ADC
+                | Token::AdcZP
+                | Token::AdcAP
+                | Token::AdcZPX
+                | Token::AdcAPX
+                | Token::AdcAPY
+                | Token::AdcIDX
+                | Token::AdcIDY
+                | Token::SBC
+                | Token::SbcZP
+                | Token::SbcAP
+                | Token::SbcZPX
+                | Token::SbcAPX
+                | Token::SbcAPY
+                | Token::SbcIDX
+                | Token::SbcIDY
+        )
+    )
+}
+
+/// Checks whether both digits of a byte are decimal.
+fn is_bcd(value: u8) -> bool {
+    value & 0x0F <= 9 && value >> 4 <= 9
+}
@@ -0,0 +1,203 @@
+use crate::memory::Memory;
+use std::fmt;
+use std::fs;
+
+/// Number of data bytes written per record by `format`.
+const RECORD_LENGTH: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// An error found while reading an Intel HEX file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntelHexError {
+    pub line: usize, // 1-based line the error was found on, or 0 if the file could not be read
+    pub message: String,
+}
+
+impl fmt::Display for IntelHexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for IntelHexError {}
+
+/// The contents of an Intel HEX file: blocks of bytes and the addresses they go at.
+///
+/// Intel HEX is the text format most EEPROM programmers read and write. Each line is a record
+/// starting with `:`, holding a byte count, a 16-bit address, a record type, the data and a
+/// checksum, all in hex. Data (`00`) and end of file (`01`) records are all a 6502 image needs;
+/// extended address records (`02`, `04`) are accepted as long as they select the first 64K, and
+/// the start address records (`03`, `05`) give the program's entry point.
+///
+/// # Example
+/// ```rust
+/// use r_6502::intel_hex::IntelHex;
+/// use r_6502::Memory;
+///
+/// let text = IntelHex::format(&[0xA9, 0x01, 0x60], 0x8000);
+/// assert_eq!(text, ":03800000A9016073\n:00000001FF\n");
+///
+/// let hex = IntelHex::parse(&text).unwrap();
+/// let mut memory = Memory::new();
+/// assert_eq!(hex.load(&mut memory).unwrap(), 3);
+/// assert_eq!(memory.data[0x8001], 0x01);
+/// assert_eq!(hex.lowest_address(), Some(0x8000));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntelHex {
+    pub blocks: Vec<(u16, Vec<u8>)>, // Data records, in file order
+    pub start: Option<u16>,          // Entry point from a start address record
+}
+
+impl IntelHex {
+    /// Parses the text of an Intel HEX file.
+    ///
+    /// Blank lines are skipped and anything after the end of file record is ignored.
+    ///
+    /// # Errors
+    /// Returns an `IntelHexError` if a record is malformed, its checksum does not match, it has an
+    /// unknown type or it places data above `$FFFF`.
+    pub fn parse(text: &str) -> Result<Self, IntelHexError> {
+        let mut hex = IntelHex::default();
+        let mut base: u32 = 0; // Set by extended address records
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| IntelHexError {
+                line: index + 1,
+                message,
+            };
+            let bytes = parse_record(line).map_err(error)?;
+            let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+            let data = &bytes[4..bytes.len() - 1];
+            match bytes[3] {
+                DATA => {
+                    let start = base + address as u32;
+                    if start + data.len() as u32 > 0x10000 {
+                        return Err(error(format!("data at ${:X} is above $FFFF", start)));
+                    }
+                    hex.blocks.push((start as u16, data.to_vec()));
+                }
+                END_OF_FILE => break,
+                EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                    let value = u16::from_be_bytes([data[0], data[1]]) as u32;
+                    base = if bytes[3] == EXTENDED_SEGMENT_ADDRESS {
+                        value << 4
+                    } else {
+                        value << 16
+                    };
+                }
+                START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS if data.len() == 4 => {
+                    // Only the low 16 bits (the IP or the low half of the EIP) mean anything here
+                    hex.start = Some(u16::from_be_bytes([data[2], data[3]]));
+                }
+                record_type => {
+                    return Err(error(format!("invalid record of type {:02X}", record_type)))
+                }
+            }
+        }
+        Ok(hex)
+    }
+
+    /// Reads and parses an Intel HEX file.
+    ///
+    /// # Errors
+    /// Returns an `IntelHexError` with line 0 if the file cannot be read, or the error `parse`
+    /// found.
+    pub fn load_file(path: &str) -> Result<Self, IntelHexError> {
+        let text = fs::read_to_string(path).map_err(|e| IntelHexError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        Self::parse(&text)
+    }
+
+    /// Copies the data records into memory.
+    ///
+    /// # Returns
+    /// The number of bytes loaded.
+    ///
+    /// # Errors
+    /// Returns an `IntelHexError` with line 0 if a block runs past the end of memory.
+    pub fn load(&self, mem: &mut Memory) -> Result<usize, IntelHexError> {
+        let mut length = 0;
+        for (address, bytes) in &self.blocks {
+            mem.load_bytes(bytes, *address).map_err(|e| IntelHexError {
+                line: 0,
+                message: e.to_string(),
+            })?;
+            length += bytes.len();
+        }
+        Ok(length)
+    }
+
+    /// Returns the lowest address any data record loads to, or `None` if there is no data.
+    pub fn lowest_address(&self) -> Option<u16> {
+        self.blocks
+            .iter()
+            .filter(|(_, bytes)| !bytes.is_empty())
+            .map(|(address, _)| *address)
+            .min()
+    }
+
+    /// Formats `bytes`, placed at `origin`, as Intel HEX data records followed by an end of file
+    /// record.
+    pub fn format(bytes: &[u8], origin: u16) -> String {
+        let mut text = String::new();
+        for (index, chunk) in bytes.chunks(RECORD_LENGTH).enumerate() {
+            let address = origin.wrapping_add((index * RECORD_LENGTH) as u16);
+            text.push_str(&format_record(address, DATA, chunk));
+        }
+        text.push_str(&format_record(0, END_OF_FILE, &[]));
+        text
+    }
+}
+
+/// Decodes a record, checking its length and checksum.
+///
+/// # Returns
+/// The bytes of the record: count, address high, address low, type, data and checksum.
+fn parse_record(line: &str) -> Result<Vec<u8>, String> {
+    let digits = line
+        .strip_prefix(':')
+        .ok_or_else(|| String::from("record does not start with `:`"))?;
+    if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid record `{}`", line));
+    }
+    let bytes: Vec<u8> = (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).unwrap_or(0))
+        .collect();
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(format!("invalid record length in `{}`", line));
+    }
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if sum != 0 {
+        return Err(format!("checksum mismatch in `{}`", line));
+    }
+    Ok(bytes)
+}
+
+/// Formats one record, including its checksum and a trailing newline.
+fn format_record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+    let digits: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}\n", digits)
+}
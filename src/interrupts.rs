@@ -0,0 +1,108 @@
+/// Nesting depth beyond which interrupt entries are reported as a storm.
+const DEFAULT_STORM_DEPTH: usize = 8;
+
+/// A problem found while watching interrupt entries and returns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterruptDiagnostic {
+    /// An interrupt was taken while the handler for a previous one had not returned yet.
+    Reentered { pc: u16, depth: usize },
+    /// Interrupts kept nesting without `RTI`, growing the stack by `stack_growth` bytes.
+    Storm {
+        pc: u16,
+        depth: usize,
+        stack_growth: u16,
+    },
+}
+
+/// Tracks interrupt handlers that have been entered but not returned from.
+///
+/// Every interrupt entry pushes three bytes, so handlers that are re-entered before they reach
+/// their `RTI` slowly eat the stack until it wraps and overwrites itself. Each diagnostic is
+/// reported once per run of nested interrupts; the monitor resets once every handler has returned.
+#[derive(Clone, Debug)]
+pub struct InterruptMonitor {
+    pub storm_depth: usize, // Nesting depth reported as a storm
+    pub irqs: u64,          // IRQs taken since the CPU was created
+    pub nmis: u64,          // NMIs taken since the CPU was created
+    entry_sps: Vec<u16>,    // Stack pointer before each active handler was entered
+    diagnostics: Vec<InterruptDiagnostic>,
+    reentry_reported: bool,
+    storm_reported: bool,
+}
+
+impl Default for InterruptMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptMonitor {
+    pub fn new() -> Self {
+        InterruptMonitor {
+            storm_depth: DEFAULT_STORM_DEPTH,
+            irqs: 0,
+            nmis: 0,
+            entry_sps: Vec::new(),
+            diagnostics: Vec::new(),
+            reentry_reported: false,
+            storm_reported: false,
+        }
+    }
+
+    /// Records an interrupt being taken at `pc`, before anything is pushed for it.
+    pub fn enter(&mut self, pc: u16, sp: u16) {
+        self.entry_sps.push(sp);
+        let depth = self.entry_sps.len();
+        if depth > 1 && !self.reentry_reported {
+            self.reentry_reported = true;
+            self.diagnostics
+                .push(InterruptDiagnostic::Reentered { pc, depth });
+        }
+        if depth > self.storm_depth && !self.storm_reported {
+            self.storm_reported = true;
+            let stack_growth = self.entry_sps[0].wrapping_sub(sp) & 0x00FF;
+            self.diagnostics.push(InterruptDiagnostic::Storm {
+                pc,
+                depth,
+                stack_growth,
+            });
+        }
+    }
+
+    /// Records an `RTI`. Returns with no active handler are ignored.
+    pub fn leave(&mut self) {
+        self.entry_sps.pop();
+        if self.entry_sps.is_empty() {
+            self.reentry_reported = false;
+            self.storm_reported = false;
+        }
+    }
+
+    /// Returns the number of handlers currently entered and not yet returned from.
+    pub fn depth(&self) -> usize {
+        self.entry_sps.len()
+    }
+
+    /// Returns the diagnostics found so far, oldest first.
+    pub fn diagnostics(&self) -> &[InterruptDiagnostic] {
+        &self.diagnostics
+    }
+}
+
+/// Formats a diagnostic as a warning line for the user.
+pub fn format_diagnostic(diagnostic: &InterruptDiagnostic) -> String {
+    match diagnostic {
+        InterruptDiagnostic::Reentered { pc, depth } => format!(
+            "Interrupt taken at ${:04X} while a handler was still running (depth {})",
+            pc, depth
+        ),
+        InterruptDiagnostic::Storm {
+            pc,
+            depth,
+            stack_growth,
+        } => format!(
+            "Interrupt storm at ${:04X}: {} nested interrupts without RTI, stack grew by {} bytes",
+            pc, depth, stack_growth
+        ),
+    }
+}
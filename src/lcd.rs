@@ -0,0 +1,264 @@
+use crate::via::{PortDevice, ViaPorts};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Columns of the display.
+pub const LCD_COLUMNS: usize = 16;
+
+// Control lines on port A, as wired on Ben Eater's breadboard computer
+const E: u8 = 0x80; // Enable: commands and data are latched as it falls
+const RW: u8 = 0x40; // Set to read from the LCD
+const RS: u8 = 0x20; // Register select: set for data, clear for commands
+
+/// Length of each line of display RAM in two-line mode.
+const LINE_LENGTH: u8 = 40;
+
+/// Size of the display RAM, by address.
+pub const DDRAM_SIZE: usize = 0x80;
+
+/// What an `Lcd` shows, shared with the host: its display RAM and how often it was redrawn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LcdScreen {
+    pub ddram: [u8; DDRAM_SIZE], // Display RAM, by address, as of the last write or command
+    pub refreshes: u64,          // Times the visible text changed and the display was redrawn
+}
+
+impl Default for LcdScreen {
+    fn default() -> Self {
+        LcdScreen {
+            ddram: [b' '; DDRAM_SIZE],
+            refreshes: 0,
+        }
+    }
+}
+
+/// A 16x2 character LCD with an HD44780 controller, wired to a VIA's ports.
+///
+/// The wiring is Ben Eater's: the data lines D0-D7 on port B and the E, RW and RS lines on PA7,
+/// PA6 and PA5. The controller's 8-bit interface is modelled: clear, home, entry mode (with
+/// display shift), display on/off, cursor and display shift, function set (one or two lines) and
+/// setting the display RAM address, plus reading the busy flag (never busy) and display RAM. The
+/// 4-bit interface, custom characters in CGRAM and the cursor itself are not. Characters outside
+/// printable ASCII are shown as `?`.
+///
+/// Every time the visible text changes, the display is drawn on the output as a box of two lines.
+/// The host can follow the display RAM and the redraws through the `LcdScreen` returned by
+/// `screen`, e.g. to hash what a program displayed (see `frame_hash`).
+///
+/// # Example
+/// ```rust
+/// use r_6502::lcd::Lcd;
+/// use r_6502::via::{PortDevice, ViaPorts};
+///
+/// let mut lcd = Lcd::new(Box::new(std::io::sink()));
+/// let mut ports = ViaPorts::default();
+/// ports.a.ddr = 0xE0;
+/// ports.b.ddr = 0xFF;
+/// // Latch each byte into the LCD by pulsing E, with RS set for data
+/// let mut send = |rs: u8, byte: u8| {
+///     ports.b.output = byte;
+///     for control in [rs, rs | 0x80, rs] {
+///         ports.a.output = control;
+///         lcd.update(&mut ports);
+///     }
+/// };
+/// for command in [0x38, 0x0E, 0x06] {
+///     send(0x00, command); // 8-bit, 2 lines; display on; increment
+/// }
+/// for byte in b"Hi!" {
+///     send(0x20, *byte);
+/// }
+/// assert_eq!(lcd.text(), ["Hi!             ", "                "]);
+/// ```
+pub struct Lcd {
+    ddram: [u8; DDRAM_SIZE], // Display RAM, by address
+    address: u8,             // Address counter
+    increment: bool,         // The address counter counts up after each access
+    shift: bool,             // The display shifts after each write
+    offset: i32,             // Columns the display is shifted left by
+    display_on: bool,
+    two_lines: bool,
+    enable: bool, // Level of E at the last update
+    output: Box<dyn Write>,
+    shown: Option<[String; 2]>, // Text last drawn
+    screen: Rc<RefCell<LcdScreen>>,
+}
+
+impl Lcd {
+    /// Creates an LCD as it is at power on, with the display off, drawing itself on `output`.
+    pub fn new(output: Box<dyn Write>) -> Self {
+        let mut lcd = Lcd {
+            ddram: [b' '; DDRAM_SIZE],
+            address: 0,
+            increment: true,
+            shift: false,
+            offset: 0,
+            display_on: false,
+            two_lines: false,
+            enable: false,
+            output,
+            shown: None,
+            screen: Rc::new(RefCell::new(LcdScreen::default())),
+        };
+        lcd.shown = Some(lcd.text()); // Blank, so nothing is drawn until text appears
+        lcd
+    }
+
+    /// Returns the screen, shared with the device so the host can follow it while it is connected.
+    pub fn screen(&self) -> Rc<RefCell<LcdScreen>> {
+        Rc::clone(&self.screen)
+    }
+
+    /// Returns the visible text of both lines, blank while the display is off.
+    pub fn text(&self) -> [String; 2] {
+        let line = |line: usize| -> String {
+            (0..LCD_COLUMNS)
+                .map(|column| {
+                    let byte = match self.ddram_address(line, column) {
+                        Some(address) if self.display_on => self.ddram[address as usize],
+                        _ => b' ',
+                    };
+                    match byte {
+                        0x20..=0x7E => byte as char,
+                        _ => '?',
+                    }
+                })
+                .collect()
+        };
+        [line(0), line(1)]
+    }
+
+    /// Returns the display RAM address shown at a position, if the line is in use.
+    fn ddram_address(&self, line: usize, column: usize) -> Option<u8> {
+        if self.two_lines {
+            let index = (column as i32 + self.offset).rem_euclid(LINE_LENGTH as i32) as u8;
+            Some(line as u8 * 0x40 + index)
+        } else if line == 0 {
+            Some((column as i32 + self.offset).rem_euclid(LINE_LENGTH as i32 * 2) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the address counter one step, wrapping within the display RAM in use.
+    fn advance(&mut self) {
+        let next = if self.increment {
+            self.address.wrapping_add(1)
+        } else {
+            self.address.wrapping_sub(1)
+        };
+        self.address = if self.two_lines {
+            match next {
+                0x28 => 0x40,
+                0x3F => 0x27,
+                0x68 => 0x00,
+                0xFF => 0x67,
+                next => next,
+            }
+        } else {
+            match next {
+                0x50 => 0x00,
+                0xFF => 0x4F,
+                next => next,
+            }
+        };
+    }
+
+    /// Executes a command written with RS clear.
+    fn command(&mut self, command: u8) {
+        match command.leading_zeros() {
+            7 => {
+                // Clear display
+                self.ddram = [b' '; DDRAM_SIZE];
+                self.address = 0;
+                self.offset = 0;
+                self.increment = true;
+            }
+            6 => {
+                // Return home
+                self.address = 0;
+                self.offset = 0;
+            }
+            5 => {
+                // Entry mode set
+                self.increment = command & 0x02 != 0;
+                self.shift = command & 0x01 != 0;
+            }
+            4 => self.display_on = command & 0x04 != 0, // Display on/off control
+            3 => {
+                // Cursor or display shift
+                let right = command & 0x04 != 0;
+                if command & 0x08 != 0 {
+                    self.offset += if right { -1 } else { 1 };
+                } else {
+                    let increment = self.increment;
+                    self.increment = right;
+                    self.advance();
+                    self.increment = increment;
+                }
+            }
+            2 => self.two_lines = command & 0x08 != 0, // Function set
+            1 => {}                                    // Set CGRAM address
+            0 => self.address = command & 0x7F,        // Set DDRAM address
+            _ => {}
+        }
+    }
+
+    /// Writes a byte to display RAM with RS set.
+    fn write_data(&mut self, byte: u8) {
+        self.ddram[self.address as usize & 0x7F] = byte;
+        self.advance();
+        if self.shift {
+            self.offset += if self.increment { 1 } else { -1 };
+        }
+    }
+
+    /// Draws the display on the output if its text changed since it was last drawn.
+    fn redraw(&mut self) {
+        let text = self.text();
+        if self.shown.as_ref() == Some(&text) {
+            return;
+        }
+        let border = format!("+{}+", "-".repeat(LCD_COLUMNS));
+        let frame = format!("{}\n|{}|\n|{}|\n{}\n", border, text[0], text[1], border);
+        // Output the host can no longer take should not stop the program
+        let _ = self.output.write_all(frame.as_bytes());
+        let _ = self.output.flush();
+        self.shown = Some(text);
+        self.screen.borrow_mut().refreshes += 1;
+    }
+}
+
+impl PortDevice for Lcd {
+    fn update(&mut self, ports: &mut ViaPorts) {
+        let control = ports.a.pins();
+        let enable = control & E != 0;
+        let falling = self.enable && !enable;
+        self.enable = enable;
+        if control & RW != 0 {
+            if enable {
+                // Drive the data lines for the program to read
+                ports.b.input = if control & RS != 0 {
+                    self.ddram[self.address as usize & 0x7F]
+                } else {
+                    self.address & 0x7F // Never busy
+                };
+            } else if falling && control & RS != 0 {
+                self.advance();
+            }
+            return;
+        }
+        if !falling {
+            return;
+        }
+        let byte = ports.b.pins();
+        if control & RS != 0 {
+            self.write_data(byte);
+        } else {
+            self.command(byte);
+        }
+        self.screen.borrow_mut().ddram = self.ddram;
+        self.redraw();
+    }
+}
@@ -0,0 +1,70 @@
+//! A 6502 assembler and emulator.
+//!
+//! Programs are assembled into a `Memory` with an `Assembler` and executed on a `CPU`, either
+//! through a `Runner` or by calling the `asm_runner` functions directly.
+pub mod asm_error;
+pub mod asm_parser;
+pub mod asm_runner;
+pub mod bcd;
+pub mod bench;
+pub mod breakpoint;
+pub mod build;
+pub mod bus;
+pub mod capabilities;
+pub mod clock;
+pub mod compact_trace;
+pub mod console;
+pub mod cosim;
+pub mod cpu;
+pub mod critical_section;
+pub mod debug_info;
+pub mod debugger;
+pub mod disassembler;
+pub mod event_log;
+pub mod expr;
+pub mod formatter;
+pub mod frame_hash;
+pub mod functional_test;
+pub mod guard;
+pub mod hardware_trace;
+pub mod hazards;
+pub mod intel_hex;
+pub mod interrupts;
+pub mod lcd;
+pub mod limits;
+pub mod lockstep;
+pub mod machine;
+pub mod markers;
+pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mmu;
+pub mod opcode_table;
+pub mod patch;
+pub mod pia;
+pub mod pins;
+pub mod prestate;
+pub mod profiler;
+pub mod project;
+pub mod random_program;
+pub mod relocation;
+pub mod save_state;
+pub mod self_write;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod session;
+pub mod shadow_stack;
+pub mod stack_usage;
+pub mod symbol_map;
+pub mod taint;
+pub mod token;
+pub mod trace;
+pub mod util;
+pub mod via;
+pub mod watchpoint;
+
+pub use asm_error::AsmError;
+pub use asm_parser::Assembler;
+pub use asm_runner::Runner;
+pub use cpu::CPU;
+pub use memory::Memory;
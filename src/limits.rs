@@ -0,0 +1,134 @@
+use crate::cpu::CPU;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A resource a program can be limited in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    Cycles,         // Clock cycles executed
+    TraceBytes,     // Host memory or disk taken by traces and the bus log
+    DeviceAccesses, // Reads and writes of device registers
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Cycles => write!(f, "cycle"),
+            Limit::TraceBytes => write!(f, "trace size"),
+            Limit::DeviceAccesses => write!(f, "device I/O"),
+        }
+    }
+}
+
+/// What a program has used of each limited resource.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceUsage {
+    pub cycles: u64,
+    pub instructions: u64,
+    pub trace_bytes: u64, // Text and compact traces written, and bus accesses recorded
+    pub device_accesses: u64,
+}
+
+impl ResourceUsage {
+    /// Measures what the program running on `cpu` has used so far.
+    pub fn measure(cpu: &CPU) -> Self {
+        let compact_bytes = cpu
+            .compact_trace
+            .as_ref()
+            .map_or(0, |writer| writer.bytes_written());
+        ResourceUsage {
+            cycles: cpu.cycles,
+            instructions: cpu.instructions,
+            trace_bytes: cpu.trace_bytes + compact_bytes + cpu.memory.bus_log_bytes(),
+            device_accesses: cpu.memory.device_accesses(),
+        }
+    }
+}
+
+/// Limits on what a single emulator instance may use, for running untrusted programs such as
+/// classroom submissions or fuzzing inputs.
+///
+/// `try_execute_instruction` checks the limits before every instruction and stops with
+/// `StopReason::LimitExceeded` once one is reached, so a run ends cleanly rather than looping
+/// forever or filling the host's memory or disk. An instruction is never cut short, so a limit
+/// can be overshot by the one instruction that reaches it. Every limit is off by default.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+/// use r_6502::limits::{Limit, ResourceLimits};
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..3].copy_from_slice(&[0x4C, 0x00, 0x00]); // JMP $0000, forever
+/// cpu.limits = ResourceLimits {
+///     max_cycles: Some(1000),
+///     ..ResourceLimits::default()
+/// };
+/// let reason = try_run_until(&mut cpu, &EndConditions::default());
+/// assert_eq!(reason, StopReason::LimitExceeded(Limit::Cycles));
+/// assert_eq!(cpu.cycles, 1002);
+///
+/// let report = cpu.limits.report(&cpu, Some(Limit::Cycles));
+/// assert!(report.to_json().contains(r#""exceeded":"cycles""#));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimits {
+    #[serde(default)]
+    pub max_cycles: Option<u64>, // Clock cycles since the CPU was created
+    #[serde(default)]
+    pub max_trace_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_device_accesses: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Returns whether any limit is set.
+    pub fn is_limited(&self) -> bool {
+        *self != ResourceLimits::default()
+    }
+
+    /// Returns the first limit `cpu` has reached, if any.
+    pub fn check(&self, cpu: &CPU) -> Option<Limit> {
+        if !self.is_limited() {
+            return None;
+        }
+        let usage = ResourceUsage::measure(cpu);
+        let reached = |limit: Option<u64>, used: u64| limit.is_some_and(|limit| used >= limit);
+        if reached(self.max_cycles, usage.cycles) {
+            Some(Limit::Cycles)
+        } else if reached(self.max_trace_bytes, usage.trace_bytes) {
+            Some(Limit::TraceBytes)
+        } else if reached(self.max_device_accesses, usage.device_accesses) {
+            Some(Limit::DeviceAccesses)
+        } else {
+            None
+        }
+    }
+
+    /// Reports the limits, what `cpu` used, and the limit that stopped it, if one did.
+    pub fn report(&self, cpu: &CPU, exceeded: Option<Limit>) -> ResourceReport {
+        ResourceReport {
+            exceeded,
+            limits: *self,
+            usage: ResourceUsage::measure(cpu),
+        }
+    }
+}
+
+/// The outcome of a run under `ResourceLimits`, for graders and other tools to read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ResourceReport {
+    pub exceeded: Option<Limit>,
+    pub limits: ResourceLimits,
+    pub usage: ResourceUsage,
+}
+
+impl ResourceReport {
+    /// Formats the report as a line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
@@ -0,0 +1,152 @@
+use crate::asm_runner::execute_instruction;
+use crate::cpu::{Register, CPU};
+
+/// A function that executes exactly one instruction on a `CPU`.
+///
+/// Each side of a `Lockstep` pair is driven by one of these, so two implementations of the
+/// execution core can be compared against each other.
+pub type StepFn = fn(&mut CPU);
+
+/// Describes the first difference found between the two instances of a `Lockstep` pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub instruction: u64, // Number of instructions executed when the difference was found
+    pub pc: u16,          // Program counter of the left instance before the instruction ran
+    pub field: String,    // Register, flag or memory location that differs
+    pub left: u64,
+    pub right: u64,
+}
+
+/// Two emulator instances loaded from the same image and stepped one instruction at a time.
+///
+/// After every instruction the registers, flags, cycle counters and full memory of both instances
+/// are compared, so a refactor of the execution core can be checked against the previous one
+/// instruction by instruction.
+pub struct Lockstep {
+    pub left: CPU,
+    pub right: CPU,
+    left_step: StepFn,
+    right_step: StepFn,
+    instructions: u64,
+}
+
+/// Executes one instruction with the default `asm_runner` implementation.
+///
+/// The byte counter `execute_instruction` takes is irrelevant when stepping, so a scratch counter
+/// is used.
+///
+/// # Example
+/// ```rust
+/// use r_6502::lockstep::{run_memory_step, Lockstep};
+///
+/// let mut lockstep = Lockstep::new(&[0xE8], 0x0600, run_memory_step, run_memory_step);
+/// lockstep.step().unwrap();
+/// ```
+pub fn run_memory_step(cpu: &mut CPU) {
+    let mut data_cycle_count: u32 = u32::MAX;
+    execute_instruction(cpu, &mut data_cycle_count);
+}
+
+impl Lockstep {
+    /// Creates two instances with `image` loaded at `origin` and the program counter set to it.
+    ///
+    /// # Parameters
+    /// - `image`: The program bytes to load into both instances.
+    /// - `origin`: The address the image is loaded at and execution starts from.
+    /// - `left_step`: The function executing one instruction on the left instance.
+    /// - `right_step`: The function executing one instruction on the right instance.
+    ///
+    /// # Panics
+    /// - If the image does not fit in memory when loaded at `origin`.
+    pub fn new(image: &[u8], origin: u16, left_step: StepFn, right_step: StepFn) -> Self {
+        let mut left = CPU::new();
+        let mut right = CPU::new();
+        for cpu in [&mut left, &mut right] {
+            let start = origin as usize;
+            cpu.memory.data[start..start + image.len()].copy_from_slice(image);
+            cpu.pc = origin;
+        }
+        Lockstep {
+            left,
+            right,
+            left_step,
+            right_step,
+            instructions: 0,
+        }
+    }
+
+    /// Returns the number of instructions both instances have executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Executes one instruction on both instances and compares their state.
+    ///
+    /// # Returns
+    /// - `Ok(())`: If both instances are identical after the instruction.
+    /// - `Err(divergence)`: The first register, flag or memory byte that differs.
+    pub fn step(&mut self) -> Result<(), Divergence> {
+        let pc = self.left.pc;
+        (self.left_step)(&mut self.left);
+        (self.right_step)(&mut self.right);
+        self.instructions += 1;
+        self.compare(pc)
+    }
+
+    /// Steps both instances until they diverge or `max_instructions` have been executed.
+    ///
+    /// # Returns
+    /// - `Ok(count)`: The number of instructions executed without any difference.
+    /// - `Err(divergence)`: The first difference found.
+    pub fn run(&mut self, max_instructions: u64) -> Result<u64, Divergence> {
+        for _ in 0..max_instructions {
+            self.step()?;
+        }
+        Ok(self.instructions)
+    }
+
+    /// Compares the full state of both instances, returning the first difference found.
+    fn compare(&self, pc: u16) -> Result<(), Divergence> {
+        let (left, right) = (&self.left, &self.right);
+        for register in Register::ALL {
+            let (left_value, right_value) = (left.get(register), right.get(register));
+            if left_value != right_value {
+                return Err(self.divergence(
+                    pc,
+                    format!("{:?}", register),
+                    left_value as u64,
+                    right_value as u64,
+                ));
+            }
+        }
+        if left.cycles != right.cycles {
+            return Err(self.divergence(pc, String::from("cycles"), left.cycles, right.cycles));
+        }
+
+        let mismatch = left
+            .memory
+            .data
+            .iter()
+            .zip(right.memory.data.iter())
+            .position(|(l, r)| l != r);
+        match mismatch {
+            Some(address) => Err(self.divergence(
+                pc,
+                format!("${:04X}", address),
+                left.memory.data[address] as u64,
+                right.memory.data[address] as u64,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn divergence(&self, pc: u16, field: String, left: u64, right: u64) -> Divergence {
+        Divergence {
+            instruction: self.instructions,
+            pc,
+            field,
+            left,
+            right,
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::console::{stdin_keys, Console, ConsoleInput, Encoding, InputMode, Newline};
+use crate::cpu::CPU;
+use crate::lcd::{Lcd, LcdScreen};
+use crate::pia::{Pia, PIA_REGISTERS};
+use crate::via::{Via, VIA_REGISTERS};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+/// An error found while reading or applying a machine profile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MachineError {
+    pub line: usize, // 1-based line of the profile the error was found on, or 0 if none applies
+    pub message: String,
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+/// A ROM image loaded from a file.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomSpec {
+    pub file: String, // Relative to the profile's file, if it was loaded from one
+    pub address: u16,
+}
+
+/// A memory-mapped device, by kind.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DeviceSpec {
+    /// A `Console` on stdout at `address`, with an encoding `Encoding::parse` accepts, taking
+    /// input from stdin as `input` (`none`, `raw` or `cooked`) says. Cooked lines end with
+    /// `newline` (`cr`, `lf` or `crlf`) and are echoed as they are edited if `echo` is set.
+    Console {
+        address: u16,
+        #[serde(default = "default_encoding")]
+        encoding: String,
+        #[serde(default = "default_input")]
+        input: String,
+        #[serde(default = "default_newline")]
+        newline: String,
+        #[serde(default)]
+        echo: bool,
+    },
+    /// A 6522 `Via` with its 16 registers from `address`.
+    Via { address: u16 },
+    /// A 16x2 `Lcd` on stdout, wired to the ports of the VIA at `via`, which must be listed before
+    /// it.
+    Lcd { via: u16 },
+    /// The Apple I's keyboard and display `Pia` on the terminal, with its 4 registers from
+    /// `address`.
+    Pia { address: u16 },
+}
+
+fn default_encoding() -> String {
+    String::from("ascii")
+}
+
+fn default_input() -> String {
+    String::from("none")
+}
+
+fn default_newline() -> String {
+    String::from("cr")
+}
+
+/// A description of the hardware around the CPU: its ROMs, devices and clock.
+///
+/// Profiles are written in TOML, so new hardware can be described without writing Rust:
+///
+/// ```toml
+/// name = "My SBC"
+/// description = "32K RAM, 32K ROM, a serial console"
+/// clock_hz = 1000000
+/// load_address = 0x0200     # Where programs go unless --origin says otherwise
+///
+/// [[rom]]
+/// file = "monitor.bin"      # Relative to the profile
+/// address = 0x8000
+///
+/// [[device]]
+/// kind = "console"
+/// address = 0x7F00
+/// encoding = "ascii"
+/// input = "cooked"          # Lines typed on the terminal, edited before the program sees them
+/// ```
+///
+/// Everything not covered by a ROM or device is RAM. `MachineProfile::builtin` returns the
+/// profiles that ship with the emulator.
+///
+/// # Example
+/// ```rust
+/// use r_6502::machine::MachineProfile;
+/// use r_6502::CPU;
+///
+/// let profile = MachineProfile::from_toml(
+///     "name = \"tty\"\nclock_hz = 2000000\n[[device]]\nkind = \"console\"\naddress = 0xF001\n",
+/// )
+/// .unwrap();
+/// assert_eq!(profile.clock_hz, Some(2_000_000));
+///
+/// let mut cpu = CPU::new();
+/// profile.apply(&mut cpu).unwrap();
+/// assert!(cpu.memory.map().iter().any(|region| region.name == "Console"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineProfile {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub clock_hz: Option<u64>, // Speed of the real machine's clock, if it has a fixed one
+    pub load_address: Option<u16>, // Default address to load programs at
+    #[serde(default)]
+    pub rom: Vec<RomSpec>,
+    #[serde(default)]
+    pub device: Vec<DeviceSpec>,
+}
+
+impl MachineProfile {
+    /// Returns the names of the built-in profiles.
+    pub fn builtin_names() -> &'static [&'static str] {
+        &["generic", "eater", "apple1"]
+    }
+
+    /// Returns the built-in profile called `name`, if there is one.
+    ///
+    /// - `generic`: 64K of RAM and nothing else, the machine used when no profile is given.
+    /// - `eater`: Ben Eater's breadboard computer: RAM from `$0000`, a 6522 VIA at `$6000` with a
+    ///   16x2 LCD on its ports, and the program in the EEPROM from `$8000`, clocked at 1 MHz.
+    ///   The EEPROM image is the program itself, loaded at `$8000`, so it sets its own vectors.
+    /// - `apple1`: the Apple I: 4K of RAM from `$0000` (8K with the expansion at `$E000`), the
+    ///   keyboard and display PIA at `$D010-$D013` on the terminal, and the 256-byte Woz Monitor
+    ///   ROM at `$FF00`, clocked at about 1 MHz. The ROM is not included: programs load at `$FF00` by
+    ///   default, so `run wozmon.bin --binary --machine apple1 --cycles <n>` starts the monitor
+    ///   from a dump of it, and keys typed on the terminal go to the monitor.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "generic" => Some(MachineProfile {
+                name: String::from("generic"),
+                description: String::from("64K of RAM"),
+                clock_hz: None,
+                load_address: None,
+                rom: Vec::new(),
+                device: Vec::new(),
+            }),
+            "eater" => Some(MachineProfile {
+                name: String::from("eater"),
+                description: String::from(
+                    "Ben Eater's 6502 breadboard computer: RAM, a 6522 VIA at $6000 driving a \
+                     16x2 LCD, and a 32K EEPROM at $8000",
+                ),
+                clock_hz: Some(1_000_000),
+                load_address: Some(0x8000),
+                rom: Vec::new(),
+                device: vec![
+                    DeviceSpec::Via { address: 0x6000 },
+                    DeviceSpec::Lcd { via: 0x6000 },
+                ],
+            }),
+            "apple1" => Some(MachineProfile {
+                name: String::from("apple1"),
+                description: String::from(
+                    "Apple I: 4K RAM, keyboard and display PIA at $D010, Woz Monitor at $FF00",
+                ),
+                clock_hz: Some(1_022_727),
+                load_address: Some(0xFF00),
+                rom: Vec::new(),
+                device: vec![DeviceSpec::Pia { address: 0xD010 }],
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parses a profile from TOML.
+    ///
+    /// # Errors
+    /// Returns a `MachineError` if the text is not valid TOML, a key is missing or unknown, or a
+    /// value has the wrong type or does not fit (e.g. an address above `$FFFF`).
+    pub fn from_toml(text: &str) -> Result<Self, MachineError> {
+        toml::from_str(text).map_err(|e| MachineError {
+            line: e
+                .span()
+                .map(|span| text[..span.start].lines().count().max(1))
+                .unwrap_or(0),
+            message: e.message().to_string(),
+        })
+    }
+
+    /// Reads a profile from a TOML file. ROM paths in it are taken relative to the file.
+    ///
+    /// # Errors
+    /// Returns a `MachineError` with line 0 if the file cannot be read, or the error `from_toml`
+    /// found.
+    pub fn load_file(path: &str) -> Result<Self, MachineError> {
+        let text = fs::read_to_string(path).map_err(|e| MachineError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        let mut profile = Self::from_toml(&text)?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        for rom in &mut profile.rom {
+            rom.file = directory.join(&rom.file).display().to_string();
+        }
+        Ok(profile)
+    }
+
+    /// Sets up the CPU's memory as the profile describes: loads the ROMs and attaches the
+    /// devices.
+    ///
+    /// # Errors
+    /// Returns a `MachineError` with line 0 if a ROM cannot be read or does not fit, or a device
+    /// has an unknown setting.
+    pub fn apply(&self, cpu: &mut CPU) -> Result<(), MachineError> {
+        self.attach(cpu, || Box::new(std::io::stdout())).map(|_| ())
+    }
+
+    /// Sets up the CPU's memory like `apply`, but with the LCDs drawing nowhere, for tests that
+    /// check what a program displays.
+    ///
+    /// # Returns
+    /// The screens of the LCDs, in the order the profile lists them.
+    ///
+    /// # Errors
+    /// The same as `apply`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::machine::MachineProfile;
+    /// use r_6502::CPU;
+    ///
+    /// let mut cpu = CPU::new();
+    /// let screens = MachineProfile::builtin("eater").unwrap().apply_headless(&mut cpu).unwrap();
+    /// assert_eq!(screens.len(), 1);
+    /// assert_eq!(screens[0].borrow().refreshes, 0);
+    /// ```
+    pub fn apply_headless(
+        &self,
+        cpu: &mut CPU,
+    ) -> Result<Vec<Rc<RefCell<LcdScreen>>>, MachineError> {
+        self.attach(cpu, || Box::new(std::io::sink()))
+    }
+
+    /// Loads the ROMs and attaches the devices for `apply`, with the LCDs drawing on `lcd_output`.
+    fn attach(
+        &self,
+        cpu: &mut CPU,
+        lcd_output: fn() -> Box<dyn Write>,
+    ) -> Result<Vec<Rc<RefCell<LcdScreen>>>, MachineError> {
+        let mut screens = Vec::new();
+        let error = |message: String| MachineError { line: 0, message };
+        for rom in &self.rom {
+            let bytes = fs::read(&rom.file).map_err(|e| error(format!("{}: {}", rom.file, e)))?;
+            cpu.memory
+                .load_rom(&bytes, rom.address)
+                .map_err(|e| error(format!("{}: {}", rom.file, e)))?;
+        }
+        // VIAs are attached last, once the peripherals wired to them are connected
+        let mut vias: Vec<(u16, Via)> = Vec::new();
+        for device in &self.device {
+            match device {
+                DeviceSpec::Console {
+                    address,
+                    encoding,
+                    input,
+                    newline,
+                    echo,
+                } => {
+                    let encoding = Encoding::parse(encoding)
+                        .ok_or_else(|| error(format!("unknown encoding {}", encoding)))?;
+                    let settings = ConsoleInput {
+                        mode: InputMode::parse(input)
+                            .ok_or_else(|| error(format!("unknown console input {}", input)))?,
+                        newline: Newline::parse(newline)
+                            .ok_or_else(|| error(format!("unknown newline {}", newline)))?,
+                        echo: *echo,
+                    };
+                    let mut console = Console::new(encoding, Box::new(std::io::stdout()));
+                    if settings.mode != InputMode::None {
+                        console = console.with_input(stdin_keys(), settings);
+                    }
+                    cpu.memory
+                        .attach(*address, *address, "Console", Box::new(console));
+                }
+                DeviceSpec::Via { address } => {
+                    if address.checked_add(VIA_REGISTERS - 1).is_none() {
+                        return Err(error(format!("VIA at ${:04X} runs past $FFFF", address)));
+                    }
+                    vias.push((*address, Via::new()));
+                }
+                DeviceSpec::Pia { address } => {
+                    let end = address
+                        .checked_add(PIA_REGISTERS - 1)
+                        .ok_or_else(|| error(format!("PIA at ${:04X} runs past $FFFF", address)))?;
+                    cpu.memory
+                        .attach(*address, end, "PIA", Box::new(Pia::terminal()));
+                }
+                DeviceSpec::Lcd { via } => {
+                    let (_, via) = vias
+                        .iter_mut()
+                        .find(|(address, _)| address == via)
+                        .ok_or_else(|| error(format!("no VIA at ${:04X} for the LCD", via)))?;
+                    let lcd = Lcd::new(lcd_output());
+                    screens.push(lcd.screen());
+                    via.connect(Box::new(lcd));
+                }
+            }
+        }
+        for (address, via) in vias {
+            cpu.memory
+                .attach(address, address + VIA_REGISTERS - 1, "VIA", Box::new(via));
+        }
+        Ok(screens)
+    }
+}
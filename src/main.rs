@@ -1,11 +1,210 @@
-use asm_parser::read_asm_file;
-use cpu::CPU;
-
-mod asm_parser;
-mod cpu;
-mod memory;
-mod token;
-mod util;
+use r_6502::asm_parser::{Assembler, ZeroPageLabels};
+use r_6502::asm_runner::{try_execute_instruction, try_run_until, EndConditions, StopReason};
+use r_6502::bench::{run_benchmark, Halt};
+use r_6502::breakpoint::{Flag, FlagChange};
+use r_6502::capabilities::Capabilities;
+use r_6502::clock::{Clock, ClockSpeed, MAX_SCALE, MIN_SCALE};
+use r_6502::compact_trace::{write_text, CompactTraceReader, CompactTraceWriter};
+use r_6502::console::{stdin_keys, Console, ConsoleInput, Encoding, InputMode, Newline};
+use r_6502::cpu::{EndOfMemory, UnknownOpcode, CPU};
+use r_6502::critical_section::{check_critical_sections, format_warning};
+use r_6502::debugger::{format_memory_map, Monitor};
+use r_6502::disassembler::{disassemble_with_symbols, format_labeled_disassembly};
+use r_6502::event_log::{EventFilter, EventKind, EventLog};
+use r_6502::expr::{evaluate, format_value};
+use r_6502::formatter::format_source;
+use r_6502::functional_test::{FunctionalTest, TestOutcome};
+use r_6502::hardware_trace::Capture;
+use r_6502::hazards::HazardMonitor;
+use r_6502::intel_hex::IntelHex;
+use r_6502::interrupts::format_diagnostic;
+use r_6502::limits::ResourceLimits;
+use r_6502::machine::MachineProfile;
+use r_6502::markers;
+use r_6502::memory::Memory;
+#[cfg(feature = "metrics")]
+use r_6502::metrics::Metrics;
+use r_6502::patch::PatchSet;
+use r_6502::pins::step_pins;
+use r_6502::prestate::PreState;
+use r_6502::project::{Project, ProjectBuild, MANIFEST};
+use r_6502::random_program::{ProgramGenerator, MAX_INSTRUCTIONS};
+use r_6502::relocation::Relocatable;
+use r_6502::save_state::SaveState;
+#[cfg(feature = "server")]
+use r_6502::server::Server;
+use r_6502::session::{Session, SessionDir};
+use r_6502::stack_usage::{analyze_stack, StackMonitor};
+use r_6502::symbol_map::SymbolMap;
+use r_6502::trace::trace_line;
+use r_6502::util::convert_hex_string_to_u16;
+use r_6502::via::{Via, VIA_REGISTERS};
+use r_6502::watchpoint::WatchKind;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::time::Duration;
+
+const USAGE: &str = "Usage: cpu_6502_r <command> [options]
+
+Commands:
+  run <file>                   Assemble (or load, with --binary or a .hex file) and run a
+                               program
+  run                          Build and run the project of the r6502.toml manifest in the
+                               current directory or the nearest one above it
+  build                        Build the project of the nearest r6502.toml and write its image
+  assemble <file>              Assemble a program and print its size and labels, or
+                               write it to a binary file with --output
+  debug <file>                 Load a program and debug it interactively
+  verify <file> --expect <image>
+                               Run a program and compare memory against an expected image
+  compare-trace <file> --capture <csv>
+                               Run a program and compare its bus, cycle by cycle, with a
+                               capture from a real 6502 board
+  functest <image>             Run a self-checking test binary, such as Klaus Dormann's 6502
+                               functional test, until it traps
+  stress                       Run random programs (--programs, default 1000, of
+                               --instructions, default 64, from --seed, default 0) through
+                               to their end; with --illegal-opcodes, include undocumented ones
+  bench <file>                 Run a program until it halts (traps with JMP * or reaches a
+                               BRK) and report its cycles, its instructions and the host's
+                               speed in MIPS
+  save-state <file> -o <state> Run a program and save the machine state to <state>
+  load-state <state>           Restore a saved machine state, optionally run it further with
+                               --cycles, and print the registers (or save it again with -o)
+  pins <file>                  Run a program for --cycles (default 1000) and write the state
+                               of the CPU's pins in every clock cycle as JSON lines (to -o)
+  lint <file>                  Check a program for multi-byte values updated without SEI/CLI
+                               while an interrupt handler uses them; with --run, also run it
+                               (for --cycles if given) and report behaviour that is undefined
+                               on real hardware
+  stack <file>                 Measure the stack depth of each routine, statically and by
+                               running the program
+  disasm <file> <start> <end>  Disassemble a binary image loaded at <start>, with the labels
+                               of --labels <file> shown by name
+  trace-text <trace>           Convert a compact trace written with --trace-file to text
+  eval <expression> [file]     Evaluate an expression, with the labels of <file>
+  fmt [--check] <file>...      Format assembly files (`-` formats stdin to stdout)
+  serve <addr>                 Host emulator sessions for WebSocket clients at <addr>, e.g.
+                               0.0.0.0:6502 (needs a build with --features server)
+  map                          Print the memory map
+  --version                    Print the emulator's name and version
+  --capabilities               Print the opcodes, machines, devices, loaders and features of
+                               this build as JSON
+
+A <file> of `-` reads the program or source from stdin (except for debug, which reads its
+commands from there).
+
+Options for run, assemble and debug:
+  --origin <addr>  Address to assemble or load the program at (default $0000, or the
+                   machine's load address)
+  --machine <name> Set up the ROMs and devices of a built-in machine profile (generic,
+                   eater, apple1)
+  --machine-file <file>
+                   Set up the machine described by a TOML profile
+  --binary         Load <file> as a raw binary image instead of assembling it
+  --init <file>    Set registers and memory from a pre-state file before running
+  --patch <file>   Poke the bytes of a patch file into memory after loading, or after every
+                   instruction for patches marked always; may be repeated
+  --trace          Write a trace line for every instruction to stderr
+  --labels <file>  Load the labels of a VICE label file, e.g. for a --binary program, to show
+                   in traces and the debugger (assemble writes the file instead)
+  --trace-file <file>
+                   Write every instruction to <file> in the compact binary trace format
+  --cycles <n>     Stop after <n> clock cycles instead of at the end of the program
+  --stop-at-brk    Run until a BRK only, ignoring the end of the program (which also stops
+                   at a BRK by default)
+  --stop-at-trap   Run until the program jumps or branches to itself (JMP *)
+  --end <addr>     Run until the program counter reaches <addr>
+  --clock <speed>  Run at a real clock speed, e.g. 1MHz, 1.79MHz or 500kHz, or at the --machine
+                   profile's speed with `machine` (default unlimited)
+  --speed <factor> Scale the --clock speed by <factor>, from 0.1 to 100 (default 1)
+  --illegal-opcodes
+                   Execute the stable undocumented NMOS opcodes (LAX, SAX, DCP, ...)
+  --unknown-opcodes <trap|nop>
+                   Stop on other undocumented opcodes, or skip them (default trap)
+  --zero-page-labels <off|on|strict>
+                   Encode label operands that fit in the zero page as zero-page addresses,
+                   warning about lines kept absolute because their size changed between
+                   passes (an error with strict; default off)
+  --end-of-memory <stop|wrap>
+                   Stop when an instruction runs past $FFFF, or wrap to $0000 (default stop)
+  --relocate <file>@<addr>
+                   Load a relocatable blob (see assemble --relocatable) at <addr> as well;
+                   may be repeated
+  --console <addr> Print the bytes the program writes to <addr> on stdout
+  --console-input <none|raw|cooked>
+                   Let the program read the keys typed on stdin from the console: each byte
+                   as it arrives, or whole lines, with line editing and history, once entered
+                   (default none)
+  --console-newline <cr|lf|crlf>
+                   Ending of cooked input lines (default cr)
+  --console-echo   Show cooked input lines as they are edited, for terminals that do not
+                   echo keys themselves
+  --via <addr>     Attach a 6522 VIA (ports and timers) at <addr>-<addr>+$0F
+  --encoding <ascii|petscii>
+                   Character set of the console, with its control codes translated to
+                   terminal sequences (default ascii)
+  --max-cycles <n> Stop the program cleanly once it has run for <n> clock cycles
+  --max-trace-bytes <n>
+                   Stop it once its traces (and bus log) take <n> bytes
+  --max-device-io <n>
+                   Stop it once it has made <n> reads and writes of device registers
+  --limit-report <file>
+                   Write a JSON report of the limits, what the program used and the limit it
+                   reached, if any, to <file> (`-` for stdout)
+  --metrics <addr> Serve live metrics for Prometheus at http://<addr>/metrics, e.g.
+                   127.0.0.1:9650 (needs a build with --features metrics)
+
+Options for build, and for run without a <file>:
+  --manifest-path <file>
+                   Use the project of the manifest <file> instead of looking for r6502.toml
+  -o, --output <file>
+                   Write the image to <file> instead of the manifest's output (build)
+  --hex            Write Intel HEX instead of a raw binary (build; implied by a .hex output)
+  --labels <file>  Also write the labels and constants to <file> in VICE's label format (build)
+
+Options for debug:
+  --session <dir>  Save the session to <dir> as it goes, and resume it from there when it
+                   exists
+  --autosave <n>   Seconds between saves of the session (default 30; 0 saves after every
+                   command)
+
+Options for stack:
+  --floor <addr>   Lowest safe stack address, above any data kept in page $01 (default $0100)
+
+Options for verify:
+  --expect <image>     Expected memory contents, as a raw binary image
+  --expect-at <addr>   Address the expected image starts at (default $0000)
+  --mask <start>-<end> Ignore a range of addresses, e.g. device registers; may be repeated
+
+Options for functest (and --origin, --init, --illegal-opcodes, ...; <image> is loaded as a raw
+binary unless it is a .hex file):
+  --start <addr>       Address to start at (default $0400, as in Klaus Dormann's test)
+  --success <addr>     Address of the trap reached when every test passed (default $3469)
+  --max-instructions <n>
+                       Instructions to execute before giving up (default 100000000)
+
+Options for bench (and the options for run):
+  --max-instructions <n>
+                       Instructions to execute before giving up (default 100000000)
+
+Options for assemble:
+  --labels <file>  Also write the labels and constants to <file> in VICE's label format
+  -o, --output <file>
+                   Write the assembled bytes to <file> (`-` for stdout), starting at --origin
+                   if given or at the lowest address assembled to otherwise
+  --hex            Write Intel HEX instead of a raw binary (implied by a .hex output file)
+  --fill <byte>    Value for bytes not assembled to, such as .org gaps (default $00)
+  --relocatable    Write a relocatable blob, assembled to load at any address, for --relocate
+  --pad-to <size>  Pad the file with the fill byte to <size> bytes, e.g. $8000 for a 32K ROM";
+
+/// Prints an error followed by the usage text and exits.
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    std::process::exit(1);
+}
+
 fn print_memory_table(memory: &[u8]) {
     let mut stop: bool = false;
     let mut i: usize = 0;
@@ -29,9 +228,1696 @@ fn print_memory_table(memory: &[u8]) {
     }
 }
 
-fn main() {
+fn print_registers(cpu: &CPU) {
+    println!("#### REGISTERS #####");
+    println!(
+        "PC: 0x{:04X}, SP: 0x{:02X}, A: 0x{:02X}, X: 0x{:02X}, Y: 0x{:02X}",
+        cpu.pc, cpu.sp, cpu.a, cpu.x, cpu.y
+    );
+    println!(
+        "P: {:08b} (NV-BDIZC), cycles: {}",
+        cpu.get_status(),
+        cpu.cycles
+    );
+}
+
+/// Parses a hex address given on the command line, with or without a leading `$`.
+fn parse_address_arg(value: &str) -> u16 {
+    let hex = value.strip_prefix('$').unwrap_or(value);
+    match convert_hex_string_to_u16(hex) {
+        Ok(address) => address,
+        Err(_) => {
+            eprintln!("Invalid address {}", value);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parses a count given on the command line, exiting with a usage error if it is not a number.
+fn parse_count_arg(value: &str) -> u64 {
+    match value.parse::<u64>() {
+        Ok(count) => count,
+        Err(_) => usage_error(&format!("Invalid count {}", value)),
+    }
+}
+
+/// Reads a file given on the command line, or stdin if the path is `-`, exiting with a message if
+/// it cannot be read.
+fn read_input(path: &str) -> Vec<u8> {
+    let result = if path == "-" {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+    } else {
+        std::fs::read(path)
+    };
+    match result {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads a text file given on the command line, or stdin if the path is `-`.
+fn read_text_input(path: &str) -> String {
+    match String::from_utf8(read_input(path)) {
+        Ok(text) => text,
+        Err(_) => {
+            eprintln!("Error reading {}: not valid UTF-8 text", path);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Writes an artifact to a file given on the command line, or stdout if the path is `-`.
+fn write_output(path: &str, bytes: &[u8]) {
+    let result = if path == "-" {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(bytes).and_then(|_| stdout.flush())
+    } else {
+        std::fs::write(path, bytes)
+    };
+    if let Err(e) = result {
+        eprintln!("Error writing {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
+
+/// Assembles a source file given on the command line, or stdin if the path is `-`, exiting with a
+/// message if it does not assemble.
+fn assemble_arg(assembler: &mut Assembler, mem: &mut Memory, path: &str, origin: u16) {
+    let mut starting_add: u16 = origin;
+    let result = if path == "-" {
+        assembler.assemble(&read_text_input(path), mem, &mut starting_add)
+    } else {
+        assembler.assemble_file(path, mem, &mut starting_add)
+    };
+    if let Err(e) = result {
+        eprintln!("Error assembling {}: {}", path, e);
+        std::process::exit(1);
+    }
+    for widening in assembler.widenings() {
+        eprintln!("Warning: {}", widening);
+    }
+}
+
+/// Loads a binary image given on the command line, or stdin if the path is `-`, exiting with a
+/// message if it cannot be loaded.
+fn load_binary_arg(mem: &mut Memory, path: &str, origin: u16) -> usize {
+    let image = read_input(path);
+    match mem.load_bytes(&image, origin) {
+        Ok(()) => image.len(),
+        Err(e) => {
+            eprintln!("Error loading {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `disasm <file> <start> <end>`: loads a binary image at `start` and disassembles up to `end`.
+fn disasm_command(args: &[String]) {
+    let mut symbols = SymbolMap::default();
+    let mut positional: Vec<&String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--labels" => match args.next() {
+                Some(path) => symbols = read_labels(path),
+                None => usage_error("Missing value for --labels"),
+            },
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 3 {
+        eprintln!("Usage: disasm <file> <start> <end> [--labels <file>]");
+        std::process::exit(1);
+    }
+    let start = parse_address_arg(positional[1]);
+    let end = parse_address_arg(positional[2]);
+    let mut mem = Memory::new();
+    load_binary_arg(&mut mem, positional[0], start);
+    let instructions = disassemble_with_symbols(&mem, start, end, &symbols);
+    println!("{}", format_labeled_disassembly(&instructions, &symbols));
+}
+
+/// Reads a VICE label file given on the command line, exiting with a message if it is invalid.
+fn read_labels(path: &str) -> SymbolMap {
+    match SymbolMap::load_file(path) {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Error reading labels {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `trace-text <trace>`: converts a compact trace to text on stdout.
+fn trace_text_command(args: &[String]) {
+    if args.len() != 1 {
+        eprintln!("Usage: trace-text <trace>");
+        std::process::exit(1);
+    }
+    let path = &args[0];
+    let input: Box<dyn Read> = if path == "-" {
+        Box::new(std::io::stdin())
+    } else {
+        match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    };
+    let stdout = std::io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    let result = CompactTraceReader::new(BufReader::new(input))
+        .and_then(|reader| write_text(reader, &mut output))
+        .and_then(|_| output.flush());
+    if let Err(e) = result {
+        eprintln!("Error converting {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
+
+/// `eval <expression> [file]`: evaluates an expression, with the labels of `file` as symbols.
+fn eval_command(args: &[String]) {
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("Usage: eval <expression> [file]");
+        std::process::exit(1);
+    }
+    let mut assembler = Assembler::new();
+    if let Some(path) = args.get(1) {
+        assemble_arg(&mut assembler, &mut Memory::new(), path, 0);
+    }
+    match evaluate(&args[0], assembler.symbol_table()) {
+        Ok(value) => println!("{}", format_value(value)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `fmt [--check] <file>...`: rewrites assembly files in the canonical style.
+///
+/// With `--check`, files are left alone and the command fails if any of them is not formatted. A
+/// path of `-` formats stdin to stdout.
+fn fmt_command(args: &[String]) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() {
+        eprintln!("Usage: fmt [--check] <file>...");
+        std::process::exit(1);
+    }
+    let mut unformatted = false;
+    for path in paths {
+        let source = read_text_input(path);
+        let formatted = format_source(&source);
+        if path == "-" && !check {
+            write_output(path, formatted.as_bytes());
+            continue;
+        }
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            unformatted = true;
+        } else {
+            write_output(path, formatted.as_bytes());
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+}
+
+/// `serve <addr>`: hosts emulator sessions for WebSocket clients until interrupted.
+#[cfg(feature = "server")]
+fn serve_command(args: &[String]) {
+    let address = match args {
+        [address] => address,
+        _ => usage_error("Expected serve <addr>"),
+    };
+    let server = match Server::bind(address.as_str()) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Error listening at {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+    match server.local_addr() {
+        Ok(local) => eprintln!("Serving sessions at ws://{}", local),
+        Err(_) => eprintln!("Serving sessions at ws://{}", address),
+    }
+    server.run();
+}
+
+#[cfg(not(feature = "server"))]
+fn serve_command(_args: &[String]) {
+    eprintln!("serve needs a build with the server feature (cargo build --features server)");
+    std::process::exit(1);
+}
+
+/// Options shared by the commands that load a program.
+struct ProgramOptions {
+    file: String,
+    origin: Option<u16>,
+    binary: bool,
+    init: Option<String>,
+    trace: bool,
+    trace_file: Option<String>,
+    metrics: Option<String>,
+    limits: ResourceLimits,
+    limit_report: Option<String>,
+    cycles: Option<u64>,
+    end: EndConditions, // Ends of the program other than --cycles
+    illegal_opcodes: bool,
+    unknown_opcode: UnknownOpcode,
+    zero_page_labels: ZeroPageLabels,
+    end_of_memory: EndOfMemory,
+    output: Option<String>,
+    hex: bool,
+    fill: u8,
+    pad_to: Option<usize>,
+    floor: u16,
+    console: Option<u16>,
+    console_input: ConsoleInput,
+    via: Option<u16>,
+    encoding: Encoding,
+    session: Option<String>,
+    autosave: u64,
+    relocatable: bool,
+    relocate: Vec<(String, u16)>,
+    machine: Option<MachineProfile>,
+    patches: PatchSet,
+    labels: Option<String>, // VICE label file to write (assemble) or read (debug)
+    clock: Option<String>,  // Speed to throttle to, or `machine`
+    speed: Option<f64>,     // Factor the clock speed is scaled by
+    manifest_path: Option<String>, // Manifest of the project to build or run
+    project: Option<Project>, // Project built instead of `file`, for build and run
+}
+
+impl ProgramOptions {
+    /// Returns the address to assemble or load the program at.
+    fn origin(&self) -> u16 {
+        self.origin
+            .or(self
+                .machine
+                .as_ref()
+                .and_then(|machine| machine.load_address))
+            .unwrap_or(0)
+    }
+}
+
+fn parse_options(args: &[String]) -> ProgramOptions {
+    let mut options = ProgramOptions {
+        file: String::new(),
+        origin: None,
+        binary: false,
+        init: None,
+        trace: false,
+        trace_file: None,
+        metrics: None,
+        limits: ResourceLimits::default(),
+        limit_report: None,
+        cycles: None,
+        end: EndConditions::default(),
+        illegal_opcodes: false,
+        unknown_opcode: UnknownOpcode::Trap,
+        zero_page_labels: ZeroPageLabels::Off,
+        end_of_memory: EndOfMemory::Stop,
+        output: None,
+        hex: false,
+        fill: 0,
+        pad_to: None,
+        floor: 0x0100,
+        console: None,
+        console_input: ConsoleInput::default(),
+        via: None,
+        encoding: Encoding::ascii(),
+        session: None,
+        autosave: 30,
+        relocatable: false,
+        relocate: Vec::new(),
+        machine: None,
+        patches: PatchSet::new(),
+        labels: None,
+        clock: None,
+        speed: None,
+        manifest_path: None,
+        project: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| match args.next() {
+            Some(value) => value.clone(),
+            None => usage_error(&format!("Missing value for {}", flag)),
+        };
+        match arg.as_str() {
+            "--origin" => options.origin = Some(parse_address_arg(&value("--origin"))),
+            "--binary" => options.binary = true,
+            "--init" => options.init = Some(value("--init")),
+            "--trace" => options.trace = true,
+            "--trace-file" => options.trace_file = Some(value("--trace-file")),
+            "--metrics" => options.metrics = Some(value("--metrics")),
+            "--max-cycles" => {
+                options.limits.max_cycles = Some(parse_count_arg(&value("--max-cycles")))
+            }
+            "--max-trace-bytes" => {
+                options.limits.max_trace_bytes = Some(parse_count_arg(&value("--max-trace-bytes")))
+            }
+            "--max-device-io" => {
+                options.limits.max_device_accesses =
+                    Some(parse_count_arg(&value("--max-device-io")))
+            }
+            "--limit-report" => options.limit_report = Some(value("--limit-report")),
+            "--stop-at-brk" => options.end.brk = true,
+            "--stop-at-trap" => options.end.trap = true,
+            "--end" => options.end.end_address = Some(parse_address_arg(&value("--end"))),
+            "--cycles" => {
+                let cycles = value("--cycles");
+                match cycles.parse::<u64>() {
+                    Ok(cycles) => options.cycles = Some(cycles),
+                    Err(_) => usage_error(&format!("Invalid cycle count {}", cycles)),
+                }
+            }
+            "--console" => options.console = Some(parse_address_arg(&value("--console"))),
+            "--console-input" => {
+                let mode = value("--console-input");
+                match InputMode::parse(&mode) {
+                    Some(mode) => options.console_input.mode = mode,
+                    None => usage_error(&format!("Invalid --console-input {}", mode)),
+                }
+            }
+            "--console-newline" => {
+                let newline = value("--console-newline");
+                match Newline::parse(&newline) {
+                    Some(newline) => options.console_input.newline = newline,
+                    None => usage_error(&format!("Invalid --console-newline {}", newline)),
+                }
+            }
+            "--console-echo" => options.console_input.echo = true,
+            "--via" => {
+                let address = parse_address_arg(&value("--via"));
+                if address > 0xFFFF - (VIA_REGISTERS - 1) {
+                    usage_error(&format!("VIA at ${:04X} runs past $FFFF", address));
+                }
+                options.via = Some(address);
+            }
+            "--encoding" => {
+                let name = value("--encoding");
+                match Encoding::parse(&name) {
+                    Some(encoding) => options.encoding = encoding,
+                    None => usage_error(&format!("Unknown encoding {}", name)),
+                }
+            }
+            "--machine" => {
+                let name = value("--machine");
+                match MachineProfile::builtin(&name) {
+                    Some(machine) => options.machine = Some(machine),
+                    None => usage_error(&format!(
+                        "Unknown machine {} (built in: {})",
+                        name,
+                        MachineProfile::builtin_names().join(", ")
+                    )),
+                }
+            }
+            "--machine-file" => {
+                let path = value("--machine-file");
+                match MachineProfile::load_file(&path) {
+                    Ok(machine) => options.machine = Some(machine),
+                    Err(e) => {
+                        eprintln!("Error loading {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--patch" => {
+                let path = value("--patch");
+                match PatchSet::load_file(&path) {
+                    Ok(patches) => options.patches.patches.extend(patches.patches),
+                    Err(e) => {
+                        eprintln!("Error loading {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--relocatable" => options.relocatable = true,
+            "--relocate" => {
+                let load = value("--relocate");
+                match load.rsplit_once('@') {
+                    Some((path, address)) => options
+                        .relocate
+                        .push((path.to_string(), parse_address_arg(address))),
+                    None => usage_error(&format!("Expected <file>@<addr>, found {}", load)),
+                }
+            }
+            "--session" => options.session = Some(value("--session")),
+            "--autosave" => {
+                let seconds = value("--autosave");
+                match seconds.parse::<u64>() {
+                    Ok(seconds) => options.autosave = seconds,
+                    Err(_) => usage_error(&format!("Invalid autosave interval {}", seconds)),
+                }
+            }
+            "--floor" => options.floor = parse_address_arg(&value("--floor")),
+            "--illegal-opcodes" => options.illegal_opcodes = true,
+            "--unknown-opcodes" => {
+                options.unknown_opcode = match value("--unknown-opcodes").as_str() {
+                    "trap" => UnknownOpcode::Trap,
+                    "nop" => UnknownOpcode::Nop,
+                    other => usage_error(&format!("Invalid --unknown-opcodes {}", other)),
+                }
+            }
+            "--zero-page-labels" => {
+                options.zero_page_labels = match value("--zero-page-labels").as_str() {
+                    "off" => ZeroPageLabels::Off,
+                    "on" => ZeroPageLabels::On,
+                    "strict" => ZeroPageLabels::Strict,
+                    other => usage_error(&format!("Invalid --zero-page-labels {}", other)),
+                }
+            }
+            "--end-of-memory" => {
+                options.end_of_memory = match value("--end-of-memory").as_str() {
+                    "stop" => EndOfMemory::Stop,
+                    "wrap" => EndOfMemory::Wrap,
+                    other => usage_error(&format!("Invalid --end-of-memory {}", other)),
+                }
+            }
+            "-o" | "--output" => options.output = Some(value("--output")),
+            "--hex" => options.hex = true,
+            "--labels" => options.labels = Some(value("--labels")),
+            "--clock" => options.clock = Some(value("--clock")),
+            "--speed" => {
+                let speed = value("--speed");
+                match speed.parse::<f64>() {
+                    Ok(factor) if (MIN_SCALE..=MAX_SCALE).contains(&factor) => {
+                        options.speed = Some(factor)
+                    }
+                    _ => usage_error(&format!("Invalid --speed {}", speed)),
+                }
+            }
+            "--manifest-path" => options.manifest_path = Some(value("--manifest-path")),
+            "--fill" => {
+                let fill = value("--fill");
+                match u8::try_from(parse_address_arg(&fill)) {
+                    Ok(fill) => options.fill = fill,
+                    Err(_) => usage_error(&format!("Invalid fill byte {}", fill)),
+                }
+            }
+            "--pad-to" => {
+                let size = value("--pad-to");
+                let parsed = match size.strip_prefix('$') {
+                    Some(hex) => usize::from_str_radix(hex, 16),
+                    None => size.parse::<usize>(),
+                };
+                match parsed {
+                    Ok(size) => options.pad_to = Some(size),
+                    Err(_) => usage_error(&format!("Invalid size {}", size)),
+                }
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                usage_error(&format!("Unknown option {}", flag))
+            }
+            file if options.file.is_empty() => options.file = file.to_string(),
+            extra => usage_error(&format!("Unexpected argument {}", extra)),
+        }
+    }
+    if options.console.is_none() && options.console_input != ConsoleInput::default() {
+        usage_error("--console-input, --console-newline and --console-echo need a --console");
+    }
+    options
+}
+
+/// Parses the options of a command that loads a program file.
+fn parse_program_options(args: &[String]) -> ProgramOptions {
+    let options = parse_options(args);
+    if options.manifest_path.is_some() {
+        usage_error("--manifest-path only applies to build and run");
+    }
+    if options.file.is_empty() {
+        usage_error("Missing program file");
+    }
+    options
+}
+
+/// Parses the options of `build` or `run`. Without a program file, loads the project of
+/// `--manifest-path`, or of the nearest `r6502.toml`, and the machine it names unless one is given
+/// on the command line.
+fn parse_project_options(args: &[String]) -> ProgramOptions {
+    let mut options = parse_options(args);
+    if !options.file.is_empty() {
+        if options.manifest_path.is_some() {
+            usage_error("--manifest-path cannot be used with a program file");
+        }
+        return options;
+    }
+    if options.origin.is_some() || options.binary {
+        usage_error("--origin and --binary cannot be used with a project");
+    }
+    let path = match &options.manifest_path {
+        Some(path) => path.clone(),
+        None => match std::env::current_dir()
+            .ok()
+            .and_then(|directory| Project::find(&directory))
+        {
+            Some(path) => path.display().to_string(),
+            None => usage_error(&format!("Missing program file, and no {} found", MANIFEST)),
+        },
+    };
+    let project = match Project::load_file(&path) {
+        Ok(project) => project,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    if options.machine.is_none() {
+        match project.machine_profile() {
+            Ok(machine) => options.machine = machine,
+            Err(e) => {
+                eprintln!("Error loading {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    options.file = path;
+    options.project = Some(project);
+    options
+}
+
+/// Builds a project into `mem`, exiting with a message if it does not build.
+fn build_project_arg(
+    project: &Project,
+    assembler: &mut Assembler,
+    mem: &mut Memory,
+    path: &str,
+) -> ProjectBuild {
+    match project.build(assembler, mem) {
+        Ok(build) => {
+            for warning in &build.warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            build
+        }
+        Err(e) => {
+            eprintln!("Error building {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Assembles or loads the program, points the reset vector at it if it does not set one itself,
+/// resets the CPU and applies `--init` and `--patch`.
+///
+/// # Returns
+/// The number of program bytes, the address just past the last of them, and the assembler if the
+/// program was assembled.
+fn load_program(cpu: &mut CPU, options: &ProgramOptions) -> (u32, u16, Option<Assembler>) {
+    if options.trace {
+        cpu.trace = Some(Box::new(std::io::stderr()));
+    }
+    cpu.illegal_opcodes = options.illegal_opcodes;
+    cpu.unknown_opcode = options.unknown_opcode;
+    cpu.end_of_memory = options.end_of_memory;
+    cpu.limits = options.limits;
+    if let Some(clock) = &options.clock {
+        let speed = if clock == "machine" {
+            match options
+                .machine
+                .as_ref()
+                .and_then(|machine| machine.clock_hz)
+            {
+                Some(hz) => Some(ClockSpeed::Hz(hz)),
+                None => usage_error("--clock machine needs a --machine with a clock speed"),
+            }
+        } else {
+            ClockSpeed::parse(clock)
+        };
+        match speed {
+            Some(speed) => cpu.clock = Some(Clock::new(speed)),
+            None => usage_error(&format!("Invalid clock speed {}", clock)),
+        }
+    }
+    if let Some(factor) = options.speed {
+        match &cpu.clock {
+            Some(clock) => clock.controls().set_scale(factor),
+            None => usage_error("--speed needs a --clock to scale"),
+        }
+    }
+    if let Some(machine) = &options.machine {
+        if let Err(e) = machine.apply(cpu) {
+            eprintln!("Error setting up {}: {}", machine.name, e);
+            std::process::exit(1);
+        }
+    }
+    let (data_cycle_count, entry_point, assembler) = if let Some(project) = &options.project {
+        let mut assembler = Assembler::new();
+        assembler.set_zero_page_labels(options.zero_page_labels);
+        let build = build_project_arg(project, &mut assembler, &mut cpu.memory, &options.file);
+        let entry_point = build.entry_point.unwrap_or_default();
+        (assembler.size(), entry_point, Some(assembler))
+    } else if options.file.ends_with(".hex") {
+        let hex = match IntelHex::parse(&read_text_input(&options.file)) {
+            Ok(hex) => hex,
+            Err(e) => {
+                eprintln!("Error loading {}: {}", options.file, e);
+                std::process::exit(1);
+            }
+        };
+        let length = match hex.load(&mut cpu.memory) {
+            Ok(length) => length,
+            Err(e) => {
+                eprintln!("Error loading {}: {}", options.file, e);
+                std::process::exit(1);
+            }
+        };
+        let entry_point = hex.start.or(hex.lowest_address()).unwrap_or(0);
+        (length as u32, entry_point, None)
+    } else if options.binary {
+        let length = load_binary_arg(&mut cpu.memory, &options.file, options.origin());
+        (length as u32, options.origin(), None)
+    } else {
+        let mut assembler = Assembler::new();
+        assembler.set_zero_page_labels(options.zero_page_labels);
+        assemble_arg(
+            &mut assembler,
+            &mut cpu.memory,
+            &options.file,
+            options.origin(),
+        );
+        let entry_point = assembler.entry_point().unwrap_or(options.origin());
+        (assembler.size(), entry_point, Some(assembler))
+    };
+    if let Some(assembler) = &assembler {
+        for (address, name) in assembler.markers() {
+            cpu.markers.add(*address, name);
+        }
+    }
+    if !cpu.markers.is_empty() && cpu.events.is_none() {
+        // Only the markers, but all of them, so the timings cover the whole run
+        cpu.events = Some(EventLog::of_kinds(usize::MAX, &[EventKind::Marker]));
+    }
+    for (path, address) in &options.relocate {
+        let result = Relocatable::parse(&read_input(path))
+            .and_then(|blob| blob.load(&mut cpu.memory, *address));
+        if let Err(e) = result {
+            eprintln!("Error loading {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(address) = options.console {
+        let mut console = Console::new(options.encoding.clone(), Box::new(std::io::stdout()));
+        if options.console_input.mode != InputMode::None {
+            console = console.with_input(stdin_keys(), options.console_input);
+        }
+        cpu.memory
+            .attach(address, address, "Console", Box::new(console));
+    }
+    if let Some(address) = options.via {
+        cpu.memory.attach(
+            address,
+            address + VIA_REGISTERS - 1,
+            "VIA",
+            Box::new(Via::new()),
+        );
+    }
+    if cpu.memory.reset_vector() == 0 {
+        cpu.memory.set_reset_vector(entry_point);
+    }
+    cpu.reset();
+    if let Some(path) = &options.init {
+        match PreState::load_file(path) {
+            Ok(state) => state.apply(cpu),
+            Err(e) => {
+                eprintln!("Error loading {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    options.patches.apply_once(&mut cpu.memory);
+    cpu.patches = options.patches.clone();
+    let mut symbols = assembler
+        .as_ref()
+        .map(|assembler| assembler.symbol_table().clone())
+        .unwrap_or_default();
+    if let Some(path) = &options.labels {
+        symbols.extend(read_labels(path).to_symbols());
+    }
+    cpu.symbols = SymbolMap::from_symbols(&symbols);
+    if let Some(path) = &options.trace_file {
+        let writer = File::create(path).and_then(|file| {
+            let output: Box<dyn Write> = Box::new(BufWriter::new(file));
+            CompactTraceWriter::new(output, &symbols)
+        });
+        match writer {
+            Ok(writer) => cpu.compact_trace = Some(writer),
+            Err(e) => {
+                eprintln!("Error writing {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(address) = &options.metrics {
+        serve_metrics(cpu, address);
+    }
+    (
+        data_cycle_count,
+        entry_point.wrapping_add(data_cycle_count as u16),
+        assembler,
+    )
+}
+
+/// Starts serving the metrics of `cpu` at `address`, exiting with a message if it cannot.
+#[cfg(feature = "metrics")]
+fn serve_metrics(cpu: &mut CPU, address: &str) {
+    let metrics = Metrics::new();
+    match metrics.serve(address) {
+        Ok(local) => eprintln!("Serving metrics at http://{}/metrics", local),
+        Err(e) => {
+            eprintln!("Error serving metrics at {}: {}", address, e);
+            std::process::exit(1);
+        }
+    }
+    metrics.publish(cpu);
+    cpu.metrics = Some(metrics);
+}
+
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(_cpu: &mut CPU, _address: &str) {
+    eprintln!("--metrics needs a build with the metrics feature (cargo build --features metrics)");
+    std::process::exit(1);
+}
+
+/// Runs a loaded program until one of its end conditions (`--stop-at-brk`, `--stop-at-trap`,
+/// `--end`) or for `--cycles`, or to the end of the program or a `BRK` if none is given,
+/// reporting on stderr why it stopped early if it did, and writes the `--limit-report`.
+fn run_program(cpu: &mut CPU, options: &ProgramOptions, end_address: u16) {
+    let mut end = EndConditions {
+        cycle_budget: options.cycles,
+        ..options.end
+    };
+    if end == EndConditions::default() {
+        end = EndConditions::program_end(end_address);
+    }
+    let reason = try_run_until(cpu, &end);
+    if !reason.is_end() {
+        eprintln!("Stopped: {:?}", reason);
+    }
+    if let Some(path) = &options.limit_report {
+        let exceeded = match reason {
+            StopReason::LimitExceeded(limit) => Some(limit),
+            _ => None,
+        };
+        let report = cpu.limits.report(cpu, exceeded).to_json();
+        write_output(path, format!("{}\n", report).as_bytes());
+    }
+    if let Some(writer) = cpu.compact_trace.as_mut() {
+        if let Err(e) = writer.flush() {
+            eprintln!("Error writing trace: {}", e);
+        }
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(metrics) = &cpu.metrics {
+        metrics.publish(cpu);
+    }
+}
+
+/// `run [file]`: runs a program, or builds and runs the project, and prints the resulting memory
+/// and registers.
+fn run_command(args: &[String]) {
+    let options = parse_project_options(args);
+    let mut cpu = CPU::new();
+    let (_, end_address, _) = load_program(&mut cpu, &options);
+    run_program(&mut cpu, &options, end_address);
+    for diagnostic in cpu.interrupts.diagnostics() {
+        eprintln!("Warning: {}", format_diagnostic(diagnostic));
+    }
+    for event in cpu.self_writes.events() {
+        eprintln!("Warning: {}", event);
+    }
+    if let Some(events) = &cpu.events {
+        if !markers::timings(events).is_empty() {
+            eprint!("{}", markers::report(events));
+        }
+    }
+    print_memory_table(&cpu.memory.data[..]);
+    print_registers(&cpu);
+}
+
+/// Writes a snapshot of the CPU to `path`, exiting with a message if it cannot be written.
+fn save_state_arg(cpu: &CPU, path: &str) {
+    if let Err(e) = SaveState::capture(cpu).save(path) {
+        eprintln!("Error saving state: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// `save-state <file> -o <state>`: runs a program like `run` and saves the state it ends in.
+fn save_state_command(args: &[String]) {
+    let options = parse_program_options(args);
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => usage_error("Missing -o <state> file"),
+    };
+    let mut cpu = CPU::new();
+    let (_, end_address, _) = load_program(&mut cpu, &options);
+    run_program(&mut cpu, &options, end_address);
+    save_state_arg(&cpu, &output);
+    println!("Saved state at ${:04X} after {} cycles", cpu.pc, cpu.cycles);
+}
+
+/// `compare-trace <file> --capture <csv>`: runs a program against a bus capture from real hardware
+/// and reports the first cycle that differs.
+fn compare_trace_command(args: &[String]) {
+    let mut capture: Option<String> = None;
+    let mut rest: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--capture" => match args.next() {
+                Some(value) => capture = Some(value.clone()),
+                None => usage_error("Missing value for --capture"),
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let path = match capture {
+        Some(path) => path,
+        None => usage_error("Missing --capture <csv>"),
+    };
+    let capture = match Capture::load_file(&path) {
+        Ok(capture) => capture,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    let options = parse_program_options(&rest);
+    let mut cpu = CPU::new();
+    load_program(&mut cpu, &options);
+    match capture.compare(&mut cpu) {
+        Ok(cycles) => println!("Matched all {} cycles", cycles),
+        Err(divergence) => {
+            println!("Diverged at {}", divergence);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `pins <file>`: runs a program for `--cycles` clock cycles and writes the state of the pins in
+/// every cycle as a JSON object per line, for visualization front-ends.
+fn pins_command(args: &[String]) {
+    let options = parse_program_options(args);
+    let mut cpu = CPU::new();
+    load_program(&mut cpu, &options);
+    let limit = options.cycles.unwrap_or(1000);
+    let start = cpu.cycles;
+    let mut lines = String::new();
+    while cpu.cycles - start < limit {
+        for pin in step_pins(&mut cpu) {
+            // Serializing plain data cannot fail
+            lines.push_str(&serde_json::to_string(&pin).unwrap_or_default());
+            lines.push('\n');
+        }
+    }
+    write_output(options.output.as_deref().unwrap_or("-"), lines.as_bytes());
+}
+
+/// `load-state <state>`: restores a saved state, runs it for `--cycles` if given, and prints the
+/// registers or saves the result to `-o`.
+fn load_state_command(args: &[String]) {
+    let options = parse_program_options(args);
     let mut cpu = CPU::new();
-    let mut starting_add: u16 = 0;
-    read_asm_file("test.asm".to_string(), &mut cpu.memory, &mut starting_add);
-    print_memory_table(&cpu.memory.data);
+    match SaveState::load(&options.file) {
+        Ok(state) => state.restore(&mut cpu),
+        Err(e) => {
+            eprintln!("Error loading {}: {}", options.file, e);
+            std::process::exit(1);
+        }
+    }
+    if options.trace {
+        cpu.trace = Some(Box::new(std::io::stderr()));
+    }
+    cpu.illegal_opcodes = options.illegal_opcodes;
+    cpu.unknown_opcode = options.unknown_opcode;
+    cpu.end_of_memory = options.end_of_memory;
+    cpu.limits = options.limits;
+    if let Some(limit) = options.cycles {
+        let end = EndConditions {
+            cycle_budget: Some(limit),
+            ..options.end
+        };
+        let reason = try_run_until(&mut cpu, &end);
+        if !reason.is_end() {
+            eprintln!("Stopped: {:?}", reason);
+        }
+    }
+    match &options.output {
+        Some(output) => save_state_arg(&cpu, output),
+        None => print_registers(&cpu),
+    }
+}
+
+/// `assemble <file>`: assembles a program and prints its size, entry point and labels, or writes
+/// it to the `--output` file.
+fn assemble_command(args: &[String]) {
+    let options = parse_program_options(args);
+    if options.relocatable {
+        if options.labels.is_some() {
+            usage_error("--labels cannot be used with --relocatable");
+        }
+        if options.zero_page_labels != ZeroPageLabels::Off {
+            usage_error("--zero-page-labels cannot be used with --relocatable");
+        }
+        let path = match &options.output {
+            Some(path) => path,
+            None => usage_error("--relocatable needs an --output file"),
+        };
+        let blob = match Relocatable::assemble(&read_text_input(&options.file)) {
+            Ok(blob) => blob,
+            Err(e) => {
+                eprintln!("Error assembling {}: {}", options.file, e);
+                std::process::exit(1);
+            }
+        };
+        write_output(path, &blob.to_bytes());
+        let summary = format!(
+            "{} bytes, {} relocations",
+            blob.code.len(),
+            blob.relocations.len()
+        );
+        if path == "-" {
+            eprintln!("{}", summary);
+        } else {
+            println!("{}", summary);
+        }
+        return;
+    }
+    let mut mem = Memory::new();
+    let mut assembler = Assembler::new();
+    assembler.set_zero_page_labels(options.zero_page_labels);
+    assemble_arg(&mut assembler, &mut mem, &options.file, options.origin());
+    let summary = match assembler.entry_point() {
+        Some(entry_point) => format!(
+            "{} bytes, entry point ${:04X}",
+            assembler.size(),
+            entry_point
+        ),
+        None => String::from("0 bytes"),
+    };
+    if let Some(path) = &options.labels {
+        let map = SymbolMap::from_symbols(assembler.symbol_table());
+        write_output(path, map.format().as_bytes());
+    }
+    if let Some(path) = &options.output {
+        let mut image = assembler.image(&mem, options.origin, options.fill);
+        if let Some(size) = options.pad_to {
+            if image.len() > size {
+                eprintln!(
+                    "Program is {} bytes, larger than --pad-to {}",
+                    image.len(),
+                    size
+                );
+                std::process::exit(1);
+            }
+            image.resize(size, options.fill);
+        }
+        if options.hex || path.ends_with(".hex") {
+            let lowest = assembler
+                .debug_info()
+                .ranges()
+                .map(|(address, _)| address)
+                .min();
+            let start = options.origin.or(lowest).unwrap_or_default();
+            write_output(path, IntelHex::format(&image, start).as_bytes());
+        } else {
+            write_output(path, &image);
+        }
+        // Keep stdout clean when the artifact is written there
+        if path == "-" {
+            eprintln!("{}", summary);
+        } else {
+            println!("{}", summary);
+            println!("Wrote {} bytes to {}", image.len(), path);
+        }
+        return;
+    }
+    println!("{}", summary);
+    let mut labels: Vec<(&String, &u16)> = assembler.symbol_table().iter().collect();
+    labels.sort_by_key(|(name, address)| (**address, name.as_str()));
+    for (name, address) in labels {
+        println!("${:04X}  {}", address, name);
+    }
+}
+
+/// `build`: builds the project of the nearest `r6502.toml` (or `--manifest-path`), writes its
+/// image, and prints how much of each segment it used.
+fn build_command(args: &[String]) {
+    let options = parse_project_options(args);
+    let project = match &options.project {
+        Some(project) => project,
+        None => usage_error("build takes no program file (use assemble for a single file)"),
+    };
+    let mut mem = Memory::new();
+    let mut assembler = Assembler::new();
+    assembler.set_zero_page_labels(options.zero_page_labels);
+    let build = build_project_arg(project, &mut assembler, &mut mem, &options.file);
+    let image = assembler.image(&mem, None, project.fill);
+    let path = options.output.clone().unwrap_or_else(|| project.output());
+    if path != "-" {
+        if let Some(directory) = std::path::Path::new(&path).parent() {
+            if let Err(e) = std::fs::create_dir_all(directory) {
+                eprintln!("Error writing {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if options.hex || path.ends_with(".hex") {
+        let lowest = assembler
+            .debug_info()
+            .ranges()
+            .map(|(address, _)| address)
+            .min()
+            .unwrap_or_default();
+        write_output(&path, IntelHex::format(&image, lowest).as_bytes());
+    } else {
+        write_output(&path, &image);
+    }
+    if let Some(labels) = &options.labels {
+        let map = SymbolMap::from_symbols(assembler.symbol_table());
+        write_output(labels, map.format().as_bytes());
+    }
+    let mut summary = String::new();
+    for segment in &build.segments {
+        let size = match segment.size {
+            Some(size) => format!(" of {}", size),
+            None => String::new(),
+        };
+        summary += &format!(
+            "{:<12} ${:04X}  {} bytes{}\n",
+            segment.name, segment.address, segment.used, size
+        );
+    }
+    summary += &format!("Wrote {} bytes to {}", image.len(), path);
+    // Keep stdout clean when the image is written there
+    if path == "-" {
+        eprintln!("{}", summary);
+    } else {
+        println!("{}", summary);
+    }
+}
+
+/// `verify <file> --expect <image>`: runs a program, then compares the memory covered by the
+/// expected image against it, failing if anything outside the `--mask` ranges differs.
+fn verify_command(args: &[String]) {
+    let mut expect: Option<String> = None;
+    let mut expect_at: u16 = 0;
+    let mut masks: Vec<(u16, u16)> = Vec::new();
+    let mut rest: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| match args.next() {
+            Some(value) => value.clone(),
+            None => usage_error(&format!("Missing value for {}", flag)),
+        };
+        match arg.as_str() {
+            "--expect" => expect = Some(value("--expect")),
+            "--expect-at" => expect_at = parse_address_arg(&value("--expect-at")),
+            "--mask" => {
+                let range = value("--mask");
+                match range.split_once('-') {
+                    Some((start, end)) => {
+                        masks.push((parse_address_arg(start), parse_address_arg(end)))
+                    }
+                    None => usage_error(&format!("Invalid range {}", range)),
+                }
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let expect = match expect {
+        Some(expect) => expect,
+        None => usage_error("Missing --expect image"),
+    };
+    let options = parse_program_options(&rest);
+    let mut golden = Memory::new();
+    let length = load_binary_arg(&mut golden, &expect, expect_at);
+    // Only the addresses the image covers are compared
+    let end = expect_at as usize + length;
+    if expect_at > 0 {
+        masks.push((0, expect_at - 1));
+    }
+    if end < 0x10000 {
+        masks.push((end as u16, 0xFFFF));
+    }
+
+    let mut cpu = CPU::new();
+    let (_, end_address, _) = load_program(&mut cpu, &options);
+    run_program(&mut cpu, &options, end_address);
+    let mismatches = cpu.memory.compare(&golden, &masks);
+    for mismatch in &mismatches {
+        println!(
+            "${:04X}: expected ${:02X}, got ${:02X}",
+            mismatch.address, mismatch.expected, mismatch.actual
+        );
+    }
+    if mismatches.is_empty() {
+        println!("Memory matches {}", expect);
+    } else {
+        println!("{} bytes differ from {}", mismatches.len(), expect);
+        std::process::exit(1);
+    }
+}
+
+/// `functest <image>`: runs a self-checking test binary and reports where it trapped.
+///
+/// Exits with status 1 unless the test passed, so it can be run from CI.
+fn functest_command(args: &[String]) {
+    let mut test = FunctionalTest::klaus_dormann();
+    let mut rest: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| match args.next() {
+            Some(value) => value.clone(),
+            None => usage_error(&format!("Missing value for {}", flag)),
+        };
+        match arg.as_str() {
+            "--start" => test.start = parse_address_arg(&value("--start")),
+            "--success" => test.success = parse_address_arg(&value("--success")),
+            "--max-instructions" => {
+                let count = value("--max-instructions");
+                match count.parse::<u64>() {
+                    Ok(count) => test.max_instructions = count,
+                    Err(_) => usage_error(&format!("Invalid instruction count {}", count)),
+                }
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let mut options = parse_program_options(&rest);
+    options.binary = true;
+    let mut cpu = CPU::new();
+    load_program(&mut cpu, &options);
+    match test.run(&mut cpu) {
+        TestOutcome::Passed {
+            instructions,
+            cycles,
+        } => {
+            println!(
+                "Passed: trapped at ${:04X} after {} instructions, {} cycles",
+                test.success, instructions, cycles
+            );
+            return;
+        }
+        TestOutcome::Failed { pc, instructions } => {
+            println!(
+                "Failed: trapped at ${:04X} after {} instructions",
+                pc, instructions
+            );
+            print_registers(&cpu);
+        }
+        TestOutcome::IllegalOpcode { address, opcode } => {
+            println!("Failed: unknown opcode {:02X} at ${:04X}", opcode, address);
+            print_registers(&cpu);
+        }
+        TestOutcome::TimedOut { pc } => println!(
+            "Failed: still running at ${:04X} after {} instructions",
+            pc, test.max_instructions
+        ),
+    }
+    std::process::exit(1);
+}
+
+/// `stress`: runs randomly generated programs, each with its own seed, checking that they run
+/// through to their end without the emulator failing.
+///
+/// Exits with status 1 at the first program that fails, printing its seed so it can be rerun.
+fn stress_command(args: &[String]) {
+    let mut seed: u64 = 0;
+    let mut programs: u64 = 1000;
+    let mut length: usize = 64;
+    let mut undocumented = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| match args.next() {
+            Some(value) => value.clone(),
+            None => usage_error(&format!("Missing value for {}", flag)),
+        };
+        match arg.as_str() {
+            "--seed" => seed = parse_count_arg(&value("--seed")),
+            "--programs" => programs = parse_count_arg(&value("--programs")),
+            "--instructions" => {
+                let count = parse_count_arg(&value("--instructions"));
+                if count > MAX_INSTRUCTIONS as u64 {
+                    usage_error(&format!("At most {} instructions", MAX_INSTRUCTIONS));
+                }
+                length = count as usize;
+            }
+            "--illegal-opcodes" => undocumented = true,
+            _ => usage_error(&format!("Unknown option {}", arg)),
+        }
+    }
+    let mut instructions: u64 = 0;
+    for program_seed in (0..programs).map(|index| seed.wrapping_add(index)) {
+        let mut generator = ProgramGenerator::new(program_seed);
+        generator.instructions = length;
+        generator.undocumented = undocumented;
+        let program = generator.generate();
+        let mut cpu = CPU::new();
+        program.load(&mut cpu);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            program.functional_test().run(&mut cpu)
+        }));
+        match result {
+            Ok(TestOutcome::Passed {
+                instructions: executed,
+                ..
+            }) => instructions += executed,
+            Ok(outcome) => {
+                println!("Seed {} failed: {:?}", program_seed, outcome);
+                print_registers(&cpu);
+                std::process::exit(1);
+            }
+            Err(_) => {
+                println!("Seed {} failed: the emulator panicked", program_seed);
+                std::process::exit(1);
+            }
+        }
+    }
+    println!(
+        "Passed: {} programs, {} instructions",
+        programs, instructions
+    );
+}
+
+/// `bench <file>`: runs a program until it halts and reports its cycle and instruction counts, and
+/// how fast the host emulated them.
+///
+/// Exits with status 1 if the program could not go on, or did not halt in time.
+fn bench_command(args: &[String]) {
+    let mut max_instructions: u64 = 100_000_000;
+    let mut rest: Vec<String> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-instructions" => match args.next() {
+                Some(count) => max_instructions = parse_count_arg(count),
+                None => usage_error("Missing value for --max-instructions"),
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = parse_program_options(&rest);
+    let mut cpu = CPU::new();
+    load_program(&mut cpu, &options);
+    let result = run_benchmark(&mut cpu, max_instructions);
+    let halted = match result.halt {
+        Halt::Trap { pc } => format!("trapped at ${:04X}", pc),
+        Halt::Brk { pc } => format!("BRK at ${:04X}", pc),
+        Halt::Stopped(reason) => format!("stopped: {:?}", reason),
+        Halt::TimedOut => format!("still running after {} instructions", max_instructions),
+    };
+    println!("Halted:       {}", halted);
+    println!("Cycles:       {}", result.cycles);
+    println!("Instructions: {}", result.instructions);
+    println!(
+        "Host time:    {:.3} s ({:.2} MIPS, {:.2} MHz)",
+        result.elapsed.as_secs_f64(),
+        result.mips(),
+        result.mhz()
+    );
+    if matches!(result.halt, Halt::Stopped(_) | Halt::TimedOut) {
+        std::process::exit(1);
+    }
+}
+
+/// Returns the handlers the NMI and IRQ/BRK vectors point at, skipping vectors that are not set.
+fn interrupt_handlers(mem: &Memory) -> Vec<u16> {
+    [0xFFFA, 0xFFFE]
+        .into_iter()
+        .map(|address: usize| u16::from_le_bytes([mem.data[address], mem.data[address + 1]]))
+        .filter(|handler| *handler != 0)
+        .collect()
+}
+
+/// `lint <file>`: reports code that could misbehave if an interrupt arrives at the wrong time, and
+/// with `--run`, the portability hazards the program runs into.
+fn lint_command(args: &[String]) {
+    let run = args.iter().any(|arg| arg == "--run");
+    let rest: Vec<String> = args.iter().filter(|arg| *arg != "--run").cloned().collect();
+    let options = parse_program_options(&rest);
+    let mut cpu = CPU::new();
+    let (mut data_cycle_count, _, assembler) = load_program(&mut cpu, &options);
+    let symbols = assembler
+        .map(|assembler| assembler.symbol_table().clone())
+        .unwrap_or_default();
+    let mut warnings: Vec<String> =
+        check_critical_sections(&cpu.memory, cpu.pc, &interrupt_handlers(&cpu.memory))
+            .iter()
+            .map(|warning| format_warning(&cpu.memory, warning, &symbols))
+            .collect();
+    if run {
+        let mut monitor = HazardMonitor::new(&cpu.memory);
+        if data_cycle_count > 0 {
+            let origin = options.origin();
+            monitor.mark_initialised(origin, origin.saturating_add((data_cycle_count - 1) as u16));
+        }
+        match options.cycles {
+            Some(limit) => {
+                let start = cpu.cycles;
+                let mut unlimited = u32::MAX;
+                while cpu.cycles - start < limit {
+                    monitor.step(&mut cpu, &mut unlimited);
+                }
+            }
+            None => monitor.run(&mut cpu, &mut data_cycle_count),
+        }
+        warnings.extend(monitor.hazards().iter().map(|hazard| hazard.to_string()));
+    }
+    for warning in &warnings {
+        println!("warning: {}", warning);
+    }
+    if !warnings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// `stack <file>`: analyses the stack usage of each routine, then runs the program to measure it.
+///
+/// The interrupt handlers the IRQ/BRK and NMI vectors point at, if set, are included.
+fn stack_command(args: &[String]) {
+    let options = parse_program_options(args);
+    let mut cpu = CPU::new();
+    let (mut data_cycle_count, _, assembler) = load_program(&mut cpu, &options);
+    let symbols = assembler
+        .map(|assembler| assembler.symbol_table().clone())
+        .unwrap_or_default();
+    let handlers = interrupt_handlers(&cpu.memory);
+    let initial_sp = cpu.sp as u8;
+    let report = analyze_stack(&cpu.memory, cpu.pc, &handlers);
+    print!("{}", report.report(&symbols, initial_sp, options.floor));
+
+    let mut monitor = StackMonitor::new();
+    monitor.run(&mut cpu, &mut data_cycle_count);
+    println!("\nMeasured:");
+    for (address, depth) in monitor.depths() {
+        let name = symbols
+            .iter()
+            .filter(|(_, value)| **value == *address)
+            .map(|(name, _)| name.clone())
+            .min()
+            .unwrap_or_else(|| format!("${:04X}", address));
+        println!("{:<20} {:>10}", name, depth);
+    }
+    println!("lowest SP: ${:02X}", monitor.lowest_sp());
+}
+
+/// The number of events the debugger keeps for its `log` command.
+const EVENT_LOG_CAPACITY: usize = 10_000;
+
+/// `debug <file>`: loads a program and reads debugger commands from stdin.
+///
+/// Besides the monitor commands (see `Monitor`), the debugger supports `step [n]`, `run`, `regs`,
+/// `break <addr|label>`, `watch <flag> [set|clear|change]`,
+/// `watch <addr>[-<addr>] [read|write|access]`, `display [expression]`, `save <state>`,
+/// `load <state>`, `log [filter]`, `log export <file> [filter]`, `log clear` and `quit`.
+///
+/// `log` shows the interrupts, device register writes, traps, bank switches and markers reached
+/// since the program was loaded, stamped with their cycle. The filter picks kinds (`interrupt`,
+/// `device-write`, `trap`, `bank-switch`, `marker`) and a cycle range (`from <n>`, `to <n>`), see
+/// `EventFilter`, and `export` writes the events that pass it as JSON lines.
+///
+/// With `--session <dir>`, the breakpoints, watchpoints, displays, symbols and machine state are
+/// saved to `dir` every `--autosave` seconds and on quitting, and picked up again the next time
+/// the debugger is started with the same directory.
+fn debug_command(args: &[String]) {
+    let options = parse_program_options(args);
+    if options.file == "-" {
+        usage_error("debug reads its commands from stdin, so the program must be a file");
+    }
+    let mut cpu = CPU::new();
+    let (_, end_address, _) = load_program(&mut cpu, &options);
+    cpu.events = Some(EventLog::new(EVENT_LOG_CAPACITY));
+    let mut monitor = Monitor::new();
+    monitor.symbols = cpu.symbols.to_symbols();
+    let mut displays: Vec<String> = Vec::new();
+    let mut session_dir = options
+        .session
+        .as_ref()
+        .map(|path| SessionDir::new(path, Duration::from_secs(options.autosave)));
+    if let Some(dir) = &session_dir {
+        match dir.load() {
+            Ok(Some((session, state))) => {
+                state.restore(&mut cpu);
+                session.apply(&mut cpu);
+                monitor.symbols.extend(session.symbols);
+                displays = session.displays;
+                println!("Resumed session at ${:04X}", cpu.pc);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Error loading session: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // Monitor commands panic on bad input: report the message and keep the session going
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = match payload.downcast_ref::<String>() {
+            Some(message) => message.as_str(),
+            None => payload.downcast_ref::<&str>().copied().unwrap_or("error"),
+        };
+        eprintln!("Error: {}", message);
+    }));
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("${:04X}> ", cpu.pc);
+        let _ = std::io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some("quit") => break,
+            Some("regs") => print_registers(&cpu),
+            Some("step") => {
+                let count = words
+                    .next()
+                    .and_then(|n| n.parse::<u32>().ok())
+                    .unwrap_or(1);
+                let mut unlimited = u32::MAX;
+                for _ in 0..count {
+                    println!("{}", trace_line(&cpu));
+                    if let Err(reason) = try_execute_instruction(&mut cpu, &mut unlimited) {
+                        println!("{:?}", reason);
+                        break;
+                    }
+                }
+                print_displays(&cpu, &displays, &monitor.symbols);
+            }
+            Some("run") => {
+                match try_run_until(&mut cpu, &EndConditions::program_end(end_address)) {
+                    reason if reason.is_end() => println!("Program finished"),
+                    reason => println!("Stopped: {:?}", reason),
+                }
+                print_displays(&cpu, &displays, &monitor.symbols);
+            }
+            Some("display") => {
+                let expression = line.trim_start()["display".len()..].trim();
+                if !expression.is_empty() {
+                    displays.push(expression.to_string());
+                }
+                print_displays(&cpu, &displays, &monitor.symbols);
+            }
+            Some("break") => {
+                let location = words.next().unwrap_or("");
+                let address = match monitor.symbols.get(location) {
+                    Some(address) => Some(*address),
+                    None => evaluate(location, &monitor.symbols)
+                        .ok()
+                        .map(|address| address as u16),
+                };
+                match address {
+                    Some(address) => {
+                        cpu.breakpoints.add(address);
+                        println!("Breakpoint at ${:04X}", address);
+                    }
+                    None => println!("Unknown location {}", location),
+                }
+            }
+            Some("save") => match words.next() {
+                Some(path) => match SaveState::capture(&cpu).save(path) {
+                    Ok(()) => println!("Saved state to {}", path),
+                    Err(e) => println!("Error saving state: {}", e),
+                },
+                None => println!("Usage: save <state>"),
+            },
+            Some("load") => match words.next() {
+                Some(path) => match SaveState::load(path) {
+                    Ok(state) => {
+                        state.restore(&mut cpu);
+                        println!("Loaded state from {}", path);
+                    }
+                    Err(e) => println!("Error loading {}: {}", path, e),
+                },
+                None => println!("Usage: load <state>"),
+            },
+            Some("log") => {
+                let arguments = line.trim_start()["log".len()..].trim();
+                let (export, filter) = match arguments.split_once(' ') {
+                    Some(("export", rest)) => {
+                        let rest = rest.trim();
+                        match rest.split_once(' ') {
+                            Some((path, filter)) => (Some(path), filter),
+                            None => (Some(rest), ""),
+                        }
+                    }
+                    _ => (None, arguments),
+                };
+                let events = cpu
+                    .events
+                    .get_or_insert_with(|| EventLog::new(EVENT_LOG_CAPACITY));
+                match (EventFilter::parse(filter), export) {
+                    _ if filter == "clear" && export.is_none() => events.clear(),
+                    (Err(e), _) => println!("{}", e),
+                    (Ok(filter), Some(path)) => {
+                        match std::fs::write(path, events.to_json_lines(&filter)) {
+                            Ok(()) => println!(
+                                "Wrote {} events to {}",
+                                events.query(&filter).count(),
+                                path
+                            ),
+                            Err(e) => println!("Error writing {}: {}", path, e),
+                        }
+                    }
+                    (Ok(filter), None) => {
+                        if events.dropped() > 0 {
+                            println!("({} earlier events dropped)", events.dropped());
+                        }
+                        for event in events.query(&filter) {
+                            println!("{}", event);
+                        }
+                    }
+                }
+            }
+            Some("watch") => {
+                let target = words.next().unwrap_or("");
+                let mode = words.next();
+                if let Some(flag) = Flag::parse(target) {
+                    let change = match mode {
+                        None | Some("set") => Some(FlagChange::Set),
+                        Some("clear") => Some(FlagChange::Cleared),
+                        Some("change") => Some(FlagChange::Either),
+                        Some(_) => None,
+                    };
+                    match change {
+                        Some(change) => {
+                            cpu.breakpoints.add_flag(flag, change);
+                            println!("Breakpoint on {:?} {:?}", flag, change);
+                        }
+                        None => println!("Usage: watch <C|Z|I|D|V|N> [set|clear|change]"),
+                    }
+                } else {
+                    let address = |location: &str| match monitor.symbols.get(location) {
+                        Some(address) => Some(*address),
+                        None => evaluate(location, &monitor.symbols)
+                            .ok()
+                            .map(|address| address as u16),
+                    };
+                    let range = match target.split_once('-') {
+                        Some((start, end)) => address(start).zip(address(end)),
+                        None => address(target).map(|address| (address, address)),
+                    };
+                    let kind = match mode {
+                        None | Some("write") => Some(WatchKind::Write),
+                        Some("read") => Some(WatchKind::Read),
+                        Some("access") => Some(WatchKind::Access),
+                        Some(_) => None,
+                    };
+                    match (range, kind) {
+                        (Some((start, end)), Some(kind)) => {
+                            cpu.memory.watchpoints.add(start, end, kind);
+                            println!("Watchpoint on ${:04X}-${:04X} {:?}", start, end, kind);
+                        }
+                        _ => println!("Usage: watch <addr>[-<addr>] [read|write|access]"),
+                    }
+                }
+            }
+            Some(_) => match monitor.run_command(&cpu.memory, &line) {
+                Ok(output) => println!("{}", output),
+                Err(e) => println!("{}", e),
+            },
+        }
+        if let Some(dir) = session_dir.as_mut() {
+            let session = Session::capture(&cpu, &monitor.symbols, &displays);
+            if let Err(e) = dir.autosave(&session, &cpu) {
+                eprintln!("Error saving session: {}", e);
+            }
+        }
+    }
+    if let Some(dir) = session_dir.as_mut() {
+        let session = Session::capture(&cpu, &monitor.symbols, &displays);
+        if let Err(e) = dir.save(&session, &cpu) {
+            eprintln!("Error saving session: {}", e);
+        }
+    }
+}
+
+/// Shows the byte at the address each displayed expression evaluates to, e.g. `ptr = $0400: $12`.
+fn print_displays(cpu: &CPU, displays: &[String], symbols: &HashMap<String, u16>) {
+    for expression in displays {
+        match evaluate(expression, symbols) {
+            Ok(address) => {
+                let address = address as u16;
+                println!(
+                    "{} = ${:04X}: ${:02X}",
+                    expression, address, cpu.memory.data[address as usize]
+                );
+            }
+            Err(e) => println!("{}: {}", expression, e),
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let rest = args.get(2..).unwrap_or(&[]);
+    match args.get(1).map(String::as_str) {
+        Some("run") => run_command(rest),
+        Some("assemble") => assemble_command(rest),
+        Some("build") => build_command(rest),
+        Some("debug") => debug_command(rest),
+        Some("stack") => stack_command(rest),
+        Some("lint") => lint_command(rest),
+        Some("pins") => pins_command(rest),
+        Some("functest") => functest_command(rest),
+        Some("stress") => stress_command(rest),
+        Some("bench") => bench_command(rest),
+        Some("verify") => verify_command(rest),
+        Some("compare-trace") => compare_trace_command(rest),
+        Some("save-state") => save_state_command(rest),
+        Some("load-state") => load_state_command(rest),
+        Some("disasm") => disasm_command(rest),
+        Some("trace-text") => trace_text_command(rest),
+        Some("eval") => eval_command(rest),
+        Some("fmt") => fmt_command(rest),
+        Some("serve") => serve_command(rest),
+        Some("map") => println!("{}", format_memory_map(&CPU::new().memory)),
+        Some("help" | "--help" | "-h") => println!("{}", USAGE),
+        Some("--version" | "-V") => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+        }
+        Some("--capabilities") => println!("{}", Capabilities::of_this_build().to_json()),
+        Some(command) => usage_error(&format!("Unknown command {}", command)),
+        None => usage_error("Missing command"),
+    }
 }
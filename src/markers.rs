@@ -0,0 +1,199 @@
+use crate::event_log::{EventFilter, EventKind, EventLog};
+use std::collections::BTreeMap;
+
+/// Cycles measured between one marker and the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SectionTiming {
+    pub count: u64,
+    pub total: u64,
+    pub min: u64,
+    pub max: u64,
+}
+
+/// Execution markers set on a `CPU`.
+///
+/// A marker names an instruction address, usually placed with the assembler's `.marker "name"`
+/// directive. Markers take no space in the program and cost nothing to execute: every time an
+/// instruction at a marked address is about to run, an `EventKind::Marker` event with the marker's
+/// name is recorded in `cpu.events`, stamped with the current cycle count. Markers are only
+/// recorded while `cpu.events` is set. `timings` then measures the sections between consecutive
+/// markers in the log, e.g. from `frame_start` to `frame_end`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::event_log::{EventKind, EventLog};
+/// use r_6502::markers::timings;
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// cpu.events = Some(EventLog::of_kinds(usize::MAX, &[EventKind::Marker]));
+/// let mut end_address: u16 = 0;
+/// let mut assembler = Assembler::new();
+/// let source = ".marker \"start\"\nLDA #$01\nINX\n.marker \"end\"\nNOP";
+/// assembler.assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// for (address, name) in assembler.markers() {
+///     cpu.markers.add(*address, name);
+/// }
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+///
+/// let events = cpu.events.as_ref().unwrap();
+/// assert_eq!(events.events().next().unwrap().to_string(), "         0  $0000  marker        start");
+/// let timing = timings(events)[&(String::from("start"), String::from("end"))];
+/// assert_eq!(timing.total, 4);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Markers {
+    names: BTreeMap<u16, Vec<String>>,
+}
+
+impl Markers {
+    pub fn new() -> Self {
+        Markers {
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// Returns whether no markers are placed.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Places a marker called `name` on the instruction at `address`.
+    pub fn add(&mut self, address: u16, name: &str) {
+        self.names
+            .entry(address)
+            .or_default()
+            .push(name.to_string());
+    }
+
+    /// Returns the names of the markers on the instruction at `address`.
+    pub fn names_at(&self, address: u16) -> &[String] {
+        self.names
+            .get(&address)
+            .map_or(&[], |names| names.as_slice())
+    }
+}
+
+/// Measures the cycles between each marker reached and the next, keyed by their names.
+///
+/// Only the marker events `events` still holds are measured, so a log that lets old events go
+/// measures the latest sections only.
+pub fn timings(events: &EventLog) -> BTreeMap<(String, String), SectionTiming> {
+    let filter = EventFilter {
+        kinds: vec![EventKind::Marker],
+        ..EventFilter::default()
+    };
+    let reached: Vec<_> = events.query(&filter).collect();
+    let mut timings: BTreeMap<(String, String), SectionTiming> = BTreeMap::new();
+    for pair in reached.windows(2) {
+        let cycles = pair[1].cycle - pair[0].cycle;
+        let key = (pair[0].detail.clone(), pair[1].detail.clone());
+        let timing = timings.entry(key).or_insert(SectionTiming {
+            count: 0,
+            total: 0,
+            min: u64::MAX,
+            max: 0,
+        });
+        timing.count += 1;
+        timing.total += cycles;
+        timing.min = timing.min.min(cycles);
+        timing.max = timing.max.max(cycles);
+    }
+    timings
+}
+
+/// Formats the `timings` of the markers in `events` as a table, one section per line.
+pub fn report(events: &EventLog) -> String {
+    let mut report = format!(
+        "{:<32} {:>8} {:>10} {:>10} {:>10}\n",
+        "SECTION", "COUNT", "AVERAGE", "MIN", "MAX"
+    );
+    for ((from, to), timing) in timings(events) {
+        report.push_str(&format!(
+            "{:<32} {:>8} {:>10} {:>10} {:>10}\n",
+            format!("{} -> {}", from, to),
+            timing.count,
+            timing.total / timing.count,
+            timing.min,
+            timing.max
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm_runner::{run_until, EndConditions};
+    use crate::console::{Console, Encoding};
+    use crate::{Assembler, CPU};
+
+    /// Runs `source` with its markers placed, a console at `$F001` and `events` as the CPU's event
+    /// log.
+    fn run(source: &str, events: EventLog) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.events = Some(events);
+        let console = Console::new(Encoding::ascii(), Box::new(std::io::sink()));
+        cpu.memory
+            .attach(0xF001, 0xF001, "Console", Box::new(console));
+        let mut end_address: u16 = 0;
+        let mut assembler = Assembler::new();
+        assembler
+            .assemble(source, &mut cpu.memory, &mut end_address)
+            .unwrap();
+        for (address, name) in assembler.markers() {
+            cpu.markers.add(*address, name);
+        }
+        run_until(&mut cpu, &EndConditions::program_end(end_address));
+        cpu
+    }
+
+    /// Three times round a loop that writes the console, between `top` and `bottom`.
+    const LOOP: &str =
+        "LDX #3\n.marker \"top\"\nloop: STX $F001\n.marker \"bottom\"\nDEX\nBNE loop";
+
+    #[test]
+    fn markers_are_logged_with_other_events_and_timed_from_the_log() {
+        let cpu = run(LOOP, EventLog::new(100));
+        let events = cpu.events.as_ref().unwrap();
+        let kinds: Vec<EventKind> = events.events().take(3).map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            [EventKind::Marker, EventKind::DeviceWrite, EventKind::Marker]
+        );
+        let filter = EventFilter::parse("marker").unwrap();
+        let reached: Vec<(u16, &str)> = events
+            .query(&filter)
+            .map(|event| (event.pc, event.detail.as_str()))
+            .collect();
+        assert_eq!(reached.len(), 6);
+        assert_eq!(reached[..2], [(0x0002, "top"), (0x0005, "bottom")]);
+
+        let timings = timings(events);
+        let write = timings[&(String::from("top"), String::from("bottom"))];
+        assert_eq!((write.count, write.total), (3, 12)); // STX $F001 takes 4 cycles
+        let back = timings[&(String::from("bottom"), String::from("top"))];
+        assert_eq!((back.count, back.min, back.max), (2, 5, 5)); // DEX, then BNE taken
+        assert!(report(events).contains("top -> bottom"));
+    }
+
+    #[test]
+    fn a_marker_only_log_keeps_every_marker() {
+        let cpu = run(LOOP, EventLog::of_kinds(2, &[EventKind::Marker]));
+        let events = cpu.events.as_ref().unwrap();
+        assert!(events.events().all(|event| event.kind == EventKind::Marker));
+        assert_eq!((events.events().count(), events.dropped()), (2, 4));
+
+        let cpu = run(LOOP, EventLog::of_kinds(usize::MAX, &[EventKind::Marker]));
+        assert_eq!(cpu.events.as_ref().unwrap().events().count(), 6);
+
+        // Without a log, markers are placed but nothing is recorded
+        let mut cpu = CPU::new();
+        cpu.markers.add(0, "start");
+        cpu.memory.data[0] = 0xEA;
+        cpu.step();
+        assert!(cpu.events.is_none());
+        assert_eq!(cpu.markers.names_at(0), ["start"]);
+    }
+}
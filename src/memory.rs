@@ -1,21 +1,459 @@
+use crate::bus::{Bus, BusAccess, Device};
+use crate::watchpoint::Watchpoints;
+use std::fs;
+use std::io;
+
 const MAX_MEMORY: usize = 65536;
 
+/// A named range of the address space, as shown by the `map` command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u16,
+    pub end: u16, // Last address in the region, inclusive
+    pub name: &'static str,
+    pub kind: &'static str, // What backs the region, e.g. "RAM"
+}
+
+/// A byte that differs between two memories, as found by `Memory::compare`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub address: u16,
+    pub expected: u8, // Value in the golden image
+    pub actual: u8,
+}
+
+/// How much the program has used a device attached to the bus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceActivity {
+    pub name: &'static str,
+    pub start: u16, // First address the device is mapped at
+    pub reads: u64, // Reads of its registers through the `Bus` methods
+    pub writes: u64,
+}
+
+/// A write to a device's registers, as returned by `Memory::take_device_writes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceWrite {
+    pub name: &'static str, // The device written to
+    pub address: u16,
+    pub value: u8,
+}
+
+/// A device attached to the bus over `start..=end`.
+struct MappedDevice {
+    start: u16,
+    end: u16,
+    name: &'static str,
+    device: Box<dyn Device>,
+    reads: u64,
+    writes: u64,
+}
+
+/// The 64K address space, and the bus the CPU accesses it through.
+///
+/// `data` holds the RAM (and ROM contents) and can be inspected or patched directly without going
+/// through the bus, which is what debuggers and loaders do. The CPU instead uses the `Bus` methods,
+/// which honour ROM regions and attached devices.
 pub struct Memory {
     pub max_memory: usize,
-    pub data: [u8; MAX_MEMORY],
+    pub data: Box<[u8; MAX_MEMORY]>, // On the heap, so a `CPU` fits on small thread stacks
+    roms: Vec<(u16, u16)>,           // Read-only ranges, as `(start, end)`
+    devices: Vec<MappedDevice>,
+    pub watchpoints: Watchpoints, // Checked on every access through the `Bus` methods
+    bus_log: Option<Vec<BusAccess>>, // Accesses through the `Bus` methods, while recording
+    device_writes: Option<Vec<DeviceWrite>>, // Writes to device registers, while recording
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
         Memory {
             max_memory: MAX_MEMORY,
-            data: [0; self::MAX_MEMORY],
+            data: zeroed_address_space(),
+            roms: Vec::new(),
+            devices: Vec::new(),
+            watchpoints: Watchpoints::new(),
+            bus_log: None,
+            device_writes: None,
+        }
+    }
+
+    /// Returns the layout of the address space, lowest region first.
+    ///
+    /// The first regions mark the areas the 6502 itself gives a special meaning to. ROM regions and
+    /// attached devices follow in address order, overlaying the RAM regions they fall in.
+    pub fn map(&self) -> Vec<MemoryRegion> {
+        let mut overlays: Vec<MemoryRegion> = self
+            .roms
+            .iter()
+            .map(|(start, end)| MemoryRegion {
+                start: *start,
+                end: *end,
+                name: "ROM",
+                kind: "ROM",
+            })
+            .chain(self.devices.iter().map(|mapped| MemoryRegion {
+                start: mapped.start,
+                end: mapped.end,
+                name: mapped.name,
+                kind: "I/O",
+            }))
+            .collect();
+        overlays.sort_by_key(|region| region.start);
+        let mut regions = vec![
+            MemoryRegion {
+                start: 0x0000,
+                end: 0x00FF,
+                name: "Zero page",
+                kind: "RAM",
+            },
+            MemoryRegion {
+                start: 0x0100,
+                end: 0x01FF,
+                name: "Stack",
+                kind: "RAM",
+            },
+            MemoryRegion {
+                start: 0x0200,
+                end: 0xFFF9,
+                name: "General purpose",
+                kind: "RAM",
+            },
+            MemoryRegion {
+                start: 0xFFFA,
+                end: 0xFFFF,
+                name: "NMI/RESET/IRQ vectors",
+                kind: "RAM",
+            },
+        ];
+        regions.append(&mut overlays);
+        regions
+    }
+
+    /// Loads `bytes` at `origin` as ROM, which the CPU can read but not write.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error, leaving memory untouched, if the bytes are empty or do not
+    /// fit between `origin` and the end of memory.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::bus::Bus;
+    /// use r_6502::Memory;
+    ///
+    /// let mut memory = Memory::new();
+    /// memory.load_rom(&[0xEA, 0xEA], 0xF000).unwrap();
+    /// memory.write(0xF000, 0x00);
+    /// assert_eq!(memory.read(0xF000), 0xEA);
+    /// ```
+    pub fn load_rom(&mut self, bytes: &[u8], origin: u16) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty ROM image",
+            ));
         }
+        self.load_bytes(bytes, origin)?;
+        self.roms.push((origin, origin + (bytes.len() - 1) as u16));
+        Ok(())
+    }
+
+    /// Maps `device` over the addresses `start..=end`, in front of the RAM or ROM there.
+    ///
+    /// Devices attached later take precedence where ranges overlap. `name` is shown in the memory
+    /// map.
+    pub fn attach(&mut self, start: u16, end: u16, name: &'static str, device: Box<dyn Device>) {
+        self.devices.insert(
+            0,
+            MappedDevice {
+                start,
+                end,
+                name,
+                device,
+                reads: 0,
+                writes: 0,
+            },
+        );
+    }
+
+    /// Starts recording the accesses made through the `Bus` methods, discarding any recorded
+    /// before.
+    pub fn record_bus(&mut self) {
+        self.bus_log = Some(Vec::new());
+    }
+
+    /// Stops recording bus accesses and returns the ones recorded, in the order they were made.
+    pub fn take_bus_log(&mut self) -> Vec<BusAccess> {
+        self.bus_log.take().unwrap_or_default()
+    }
+
+    /// Returns the host memory taken by the bus accesses recorded so far.
+    pub fn bus_log_bytes(&self) -> u64 {
+        self.bus_log.as_ref().map_or(0, |log| {
+            (log.len() * std::mem::size_of::<BusAccess>()) as u64
+        })
+    }
+
+    /// Starts or stops recording the writes made to device registers through the `Bus` methods.
+    ///
+    /// Stopping discards the writes not yet taken, and starting again keeps any being recorded.
+    pub fn record_device_writes(&mut self, on: bool) {
+        match (on, self.device_writes.is_some()) {
+            (true, false) => self.device_writes = Some(Vec::new()),
+            (false, true) => self.device_writes = None,
+            _ => {}
+        }
+    }
+
+    /// Returns the device writes recorded since the last call, in the order they were made, and
+    /// carries on recording.
+    pub fn take_device_writes(&mut self) -> Vec<DeviceWrite> {
+        self.device_writes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Adds an access to the bus log, if recording.
+    fn log_access(&mut self, address: u16, value: u8, write: bool) {
+        if let Some(log) = self.bus_log.as_mut() {
+            log.push(BusAccess {
+                address,
+                value,
+                write,
+            });
+        }
+    }
+
+    /// Advances the clock of every attached device by `cycles`.
+    pub fn tick(&mut self, cycles: u64) {
+        for mapped in &mut self.devices {
+            mapped.device.tick(cycles);
+        }
+    }
+
+    /// Returns whether any attached device is asserting the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.devices.iter().any(|mapped| mapped.device.irq())
+    }
+
+    /// Returns the number of reads and writes each attached device has seen, in the order they
+    /// were attached.
+    pub fn device_activity(&self) -> Vec<DeviceActivity> {
+        self.devices
+            .iter()
+            .map(|mapped| DeviceActivity {
+                name: mapped.name,
+                start: mapped.start,
+                reads: mapped.reads,
+                writes: mapped.writes,
+            })
+            .collect()
+    }
+
+    /// Returns the number of reads and writes of device registers, over every attached device.
+    pub fn device_accesses(&self) -> u64 {
+        self.devices
+            .iter()
+            .map(|mapped| mapped.reads + mapped.writes)
+            .sum()
+    }
+
+    /// Returns whether a device is mapped over `address`, so it holds registers rather than memory.
+    pub fn is_device(&self, address: u16) -> bool {
+        self.devices
+            .iter()
+            .any(|mapped| (mapped.start..=mapped.end).contains(&address))
+    }
+
+    /// Returns the device mapped over `address`, if any.
+    fn device_at(&mut self, address: u16) -> Option<&mut MappedDevice> {
+        self.devices
+            .iter_mut()
+            .find(|mapped| (mapped.start..=mapped.end).contains(&address))
+    }
+
+    /// Copies `bytes` into memory starting at `origin`.
+    ///
+    /// # Errors
+    /// Returns an `InvalidInput` error, leaving memory untouched, if the bytes do not fit between
+    /// `origin` and the end of memory.
+    pub fn load_bytes(&mut self, bytes: &[u8], origin: u16) -> io::Result<()> {
+        let start = origin as usize;
+        if bytes.len() > self.max_memory - start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} bytes do not fit in memory at ${:04X}",
+                    bytes.len(),
+                    origin
+                ),
+            ));
+        }
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Loads a raw binary image (e.g. a `.bin` or `.rom` file) into memory at `origin`.
+    ///
+    /// # Returns
+    /// The number of bytes loaded.
+    ///
+    /// # Errors
+    /// - If the file cannot be read.
+    /// - If the image does not fit between `origin` and the end of memory.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use r_6502::Memory;
+    ///
+    /// let mut memory = Memory::new();
+    /// let length = memory.load_binary("program.bin", 0x8000).unwrap();
+    /// ```
+    pub fn load_binary(&mut self, path: &str, origin: u16) -> io::Result<usize> {
+        let image = fs::read(path)?;
+        self.load_bytes(&image, origin)?;
+        Ok(image.len())
+    }
+
+    /// Returns the address in the reset vector at `$FFFC`/`$FFFD`, where execution starts.
+    pub fn reset_vector(&self) -> u16 {
+        ((self.data[0xFFFD] as u16) << 8) | self.data[0xFFFC] as u16
+    }
+
+    /// Points the reset vector at `$FFFC`/`$FFFD` to `address`.
+    pub fn set_reset_vector(&mut self, address: u16) {
+        self.data[0xFFFC] = address as u8;
+        self.data[0xFFFD] = (address >> 8) as u8;
+    }
+
+    /// Compares memory against a golden image, byte by byte, as the RAM contents in `data`.
+    ///
+    /// Addresses inside any of the `masks` ranges (as `(start, end)`, inclusive) are skipped, so
+    /// device registers, scratch RAM and other bytes that vary from run to run do not count.
+    ///
+    /// # Returns
+    /// The differing bytes, lowest address first.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::Memory;
+    ///
+    /// let mut golden = Memory::new();
+    /// golden.data[0x0200..0x0203].copy_from_slice(&[1, 2, 3]);
+    /// let mut memory = Memory::new();
+    /// memory.data[0x0200..0x0203].copy_from_slice(&[1, 9, 3]);
+    /// memory.data[0x0300] = 0xFF; // Scratch value
+    /// let mismatches = memory.compare(&golden, &[(0x0300, 0x03FF)]);
+    /// assert_eq!(mismatches.len(), 1);
+    /// assert_eq!((mismatches[0].address, mismatches[0].expected, mismatches[0].actual), (0x0201, 2, 9));
+    /// ```
+    pub fn compare(&self, golden: &Memory, masks: &[(u16, u16)]) -> Vec<Mismatch> {
+        (0..MAX_MEMORY)
+            .filter(|address| self.data[*address] != golden.data[*address])
+            .map(|address| address as u16)
+            .filter(|address| {
+                !masks
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(address))
+            })
+            .map(|address| Mismatch {
+                address,
+                expected: golden.data[address as usize],
+                actual: self.data[address as usize],
+            })
+            .collect()
     }
 
     pub fn initialise(&mut self) {
-        for i in 0..self::MAX_MEMORY {
-            self.data[i] = 0;
+        self.data.fill(0);
+    }
+}
+
+/// Allocates the 64K of `Memory::data` on the heap, already zeroed.
+///
+/// The array is never built on the stack first, as `Box::new([0; MAX_MEMORY])` can be in debug
+/// builds, and the allocator hands out zeroed pages rather than the bytes being cleared one by one.
+fn zeroed_address_space() -> Box<[u8; MAX_MEMORY]> {
+    match vec![0; MAX_MEMORY].into_boxed_slice().try_into() {
+        Ok(data) => data,
+        Err(_) => unreachable!("the vector has exactly MAX_MEMORY bytes"),
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = match self.device_at(address) {
+            Some(mapped) => {
+                mapped.reads += 1;
+                mapped.device.read(address - mapped.start)
+            }
+            None => self.data[address as usize],
+        };
+        self.watchpoints.check(address, value, false);
+        self.log_access(address, value, false);
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.watchpoints.check(address, value, true);
+        self.log_access(address, value, true);
+        if let Some(mapped) = self.device_at(address) {
+            mapped.writes += 1;
+            mapped.device.write(address - mapped.start, value);
+            let name = mapped.name;
+            if let Some(writes) = self.device_writes.as_mut() {
+                writes.push(DeviceWrite {
+                    name,
+                    address,
+                    value,
+                });
+            }
+            return;
         }
+        if self
+            .roms
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&address))
+        {
+            return;
+        }
+        self.data[address as usize] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use std::thread;
+
+    /// Less than the 64K address space, so building one on the stack would overflow it.
+    const SMALL_STACK: usize = 32 * 1024;
+
+    #[test]
+    fn cpu_construction_fits_on_a_small_stack() {
+        assert!(std::mem::size_of::<CPU>() < SMALL_STACK / 4);
+        assert!(std::mem::size_of::<Memory>() < SMALL_STACK / 4);
+        let built = thread::Builder::new()
+            .stack_size(SMALL_STACK)
+            .spawn(|| {
+                let mut cpu = CPU::new();
+                cpu.memory.data[0xFFFF] = 0xEA;
+                let memory = Memory::default();
+                (
+                    cpu.memory.data[0xFFFF],
+                    memory.data.iter().all(|&byte| byte == 0),
+                )
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(built, (0xEA, true));
     }
 }
@@ -0,0 +1,388 @@
+use crate::cpu::CPU;
+use crate::memory::DeviceActivity;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Instructions executed between two publications of `cpu.metrics`.
+pub const PUBLISH_INTERVAL: u64 = 4096;
+
+/// Shortest time the rates are measured over.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Longest request head the server reads; anything longer is answered from what was read.
+const MAX_REQUEST: usize = 8192;
+
+/// The state of a running emulation, as last published.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub irqs: u64,
+    pub nmis: u64,
+    pub instructions_per_second: f64, // Over the last second or so of wall-clock time
+    pub cycles_per_second: f64,
+    pub irqs_per_second: f64,
+    pub devices: Vec<DeviceActivity>,
+}
+
+impl MetricsSnapshot {
+    /// Formats the snapshot in the Prometheus text exposition format.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::metrics::MetricsSnapshot;
+    ///
+    /// let snapshot = MetricsSnapshot {
+    ///     instructions: 1200,
+    ///     ..MetricsSnapshot::default()
+    /// };
+    /// assert!(snapshot.render().contains("\nr6502_instructions_total 1200\n"));
+    /// ```
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        };
+        let single = |value: String| [(String::new(), value)];
+        metric(
+            "r6502_instructions_total",
+            "counter",
+            "Instructions executed.",
+            &single(self.instructions.to_string()),
+        );
+        metric(
+            "r6502_cycles_total",
+            "counter",
+            "Clock cycles elapsed.",
+            &single(self.cycles.to_string()),
+        );
+        metric(
+            "r6502_irqs_total",
+            "counter",
+            "IRQs taken.",
+            &single(self.irqs.to_string()),
+        );
+        metric(
+            "r6502_nmis_total",
+            "counter",
+            "NMIs taken.",
+            &single(self.nmis.to_string()),
+        );
+        metric(
+            "r6502_instructions_per_second",
+            "gauge",
+            "Instructions executed per second of wall-clock time, over the last second.",
+            &single(self.instructions_per_second.to_string()),
+        );
+        metric(
+            "r6502_cycles_per_second",
+            "gauge",
+            "Effective clock rate in Hz, over the last second.",
+            &single(self.cycles_per_second.to_string()),
+        );
+        metric(
+            "r6502_irqs_per_second",
+            "gauge",
+            "IRQs taken per second of wall-clock time, over the last second.",
+            &single(self.irqs_per_second.to_string()),
+        );
+        let device_samples = |count: fn(&DeviceActivity) -> u64| -> Vec<(String, String)> {
+            self.devices
+                .iter()
+                .map(|device| {
+                    (
+                        format!(
+                            "{{device=\"{}\",address=\"{:04X}\"}}",
+                            device.name, device.start
+                        ),
+                        count(device).to_string(),
+                    )
+                })
+                .collect()
+        };
+        metric(
+            "r6502_device_reads_total",
+            "counter",
+            "Reads of a device's registers.",
+            &device_samples(|device| device.reads),
+        );
+        metric(
+            "r6502_device_writes_total",
+            "counter",
+            "Writes to a device's registers.",
+            &device_samples(|device| device.writes),
+        );
+        output
+    }
+}
+
+/// Counts at the start of the window the rates are measured over.
+struct RateWindow {
+    started: Instant,
+    instructions: u64,
+    cycles: u64,
+    irqs: u64,
+}
+
+struct State {
+    snapshot: MetricsSnapshot,
+    window: Option<RateWindow>,
+}
+
+/// Live metrics of an emulation, for running it as a long-lived service.
+///
+/// Set as `cpu.metrics`, the CPU publishes its counters every `PUBLISH_INTERVAL` instructions,
+/// and `serve` answers `GET /metrics` over HTTP with the last ones published, for Prometheus to
+/// scrape. Clones share the same metrics, so the server runs on its own thread without holding
+/// up the CPU.
+///
+/// # Example
+/// ```rust
+/// use r_6502::metrics::Metrics;
+/// use r_6502::{Assembler, CPU};
+/// use std::io::{Read, Write};
+/// use std::net::TcpStream;
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// Assembler::new()
+///     .assemble("loop:\nINX\nJMP loop", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// let metrics = Metrics::new();
+/// let address = metrics.serve("127.0.0.1:0").unwrap();
+/// cpu.metrics = Some(metrics.clone());
+/// cpu.run_for_cycles(100_000);
+/// metrics.publish(&cpu);
+/// assert_eq!(metrics.snapshot().instructions, cpu.instructions);
+///
+/// let mut stream = TcpStream::connect(address).unwrap();
+/// stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+/// let mut response = String::new();
+/// stream.read_to_string(&mut response).unwrap();
+/// assert!(response.starts_with("HTTP/1.1 200 OK"));
+/// assert!(response.contains(&format!("r6502_cycles_total {}", cpu.cycles)));
+/// ```
+#[derive(Clone)]
+pub struct Metrics {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            state: Arc::new(Mutex::new(State {
+                snapshot: MetricsSnapshot::default(),
+                window: None,
+            })),
+        }
+    }
+
+    /// Publishes the counters of `cpu`, updating the rates once a second has passed since they
+    /// were last measured.
+    pub fn publish(&self, cpu: &CPU) {
+        let mut guard = match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let state = &mut *guard;
+        state.snapshot.instructions = cpu.instructions;
+        state.snapshot.cycles = cpu.cycles;
+        state.snapshot.irqs = cpu.interrupts.irqs;
+        state.snapshot.nmis = cpu.interrupts.nmis;
+        state.snapshot.devices = cpu.memory.device_activity();
+        let now = Instant::now();
+        if let Some(window) = &state.window {
+            let elapsed = now.duration_since(window.started);
+            if elapsed < RATE_WINDOW {
+                return;
+            }
+            let seconds = elapsed.as_secs_f64();
+            let rate = |now: u64, then: u64| now.wrapping_sub(then) as f64 / seconds;
+            state.snapshot.instructions_per_second = rate(cpu.instructions, window.instructions);
+            state.snapshot.cycles_per_second = rate(cpu.cycles, window.cycles);
+            state.snapshot.irqs_per_second = rate(cpu.interrupts.irqs, window.irqs);
+        }
+        state.window = Some(RateWindow {
+            started: now,
+            instructions: cpu.instructions,
+            cycles: cpu.cycles,
+            irqs: cpu.interrupts.irqs,
+        });
+    }
+
+    /// Returns the metrics last published.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        match self.state.lock() {
+            Ok(state) => state.snapshot.clone(),
+            Err(poisoned) => poisoned.into_inner().snapshot.clone(),
+        }
+    }
+
+    /// Serves the metrics over HTTP at `address` (e.g. `127.0.0.1:9650`), on a thread of its own.
+    ///
+    /// `GET /metrics` returns `MetricsSnapshot::render` of the last metrics published; any other
+    /// path is not found. Requests are answered one at a time, each on a fresh connection.
+    ///
+    /// # Returns
+    /// The address listened on, which tells the port chosen when `address` gives port 0.
+    ///
+    /// # Errors
+    /// Returns any error binding to `address`.
+    pub fn serve<A: ToSocketAddrs>(&self, address: A) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let local = listener.local_addr()?;
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A client that goes away only loses its own response
+                let _ = metrics.respond(stream);
+            }
+        });
+        Ok(local)
+    }
+
+    /// Reads a request from `stream` and answers it.
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|bytes| bytes == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let read = stream.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+        let path = parts
+            .nth(1)
+            .map(|path| path.split('?').next().unwrap_or(path));
+        let (status, body) = match (request.starts_with("GET "), path) {
+            (true, Some("/metrics")) => ("200 OK", self.snapshot().render()),
+            (true, _) => ("404 Not Found", String::from("Not found; try /metrics\n")),
+            (false, _) => (
+                "405 Method Not Allowed",
+                String::from("Only GET is supported\n"),
+            ),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::{Console, Encoding};
+    use crate::Assembler;
+
+    /// Checks `text` is in the exposition format: each metric has a `# HELP` and `# TYPE` line
+    /// before its samples, and each sample a name, optional labels and a number.
+    ///
+    /// # Returns
+    /// The value of each sample, by its name and labels.
+    fn parse_exposition(text: &str) -> Vec<(String, f64)> {
+        assert!(text.ends_with('\n'));
+        let mut samples = Vec::new();
+        let mut described = None; // Name of the metric whose samples follow
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                let name = help.split(' ').next().unwrap();
+                let kind = lines.next().unwrap().strip_prefix("# TYPE ").unwrap();
+                assert_eq!(kind.split(' ').next(), Some(name), "{}", line);
+                let kind = &kind[name.len() + 1..];
+                assert!(kind == "counter" || kind == "gauge", "{}", kind);
+                assert_eq!(kind == "counter", name.ends_with("_total"), "{}", name);
+                described = Some(name);
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let name = series.split('{').next().unwrap();
+            assert_eq!(described, Some(name), "{}", line);
+            if series.len() > name.len() {
+                let labels = &series[name.len()..];
+                assert!(labels.starts_with('{') && labels.ends_with('}'), "{}", line);
+                for label in labels[1..labels.len() - 1].split(',') {
+                    let (key, value) = label.split_once('=').unwrap();
+                    assert!(key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+                    assert!(value.len() >= 2 && value.starts_with('"') && value.ends_with('"'));
+                }
+            }
+            samples.push((series.to_string(), value.parse::<f64>().unwrap()));
+        }
+        samples
+    }
+
+    /// Returns the value of the sample `series`.
+    fn sample(samples: &[(String, f64)], series: &str) -> f64 {
+        let found = samples.iter().find(|(name, _)| name == series);
+        found.unwrap_or_else(|| panic!("no sample {}", series)).1
+    }
+
+    #[test]
+    fn a_short_run_renders_advancing_counters() {
+        let mut cpu = CPU::new();
+        let console = Console::new(Encoding::ascii(), Box::new(io::sink()));
+        cpu.memory
+            .attach(0xF001, 0xF001, "Console", Box::new(console));
+        let mut end_address: u16 = 0;
+        Assembler::new()
+            .assemble(
+                "loop:\nINX\nSTX $F001\nJMP loop",
+                &mut cpu.memory,
+                &mut end_address,
+            )
+            .unwrap();
+        let metrics = Metrics::new();
+        cpu.metrics = Some(metrics.clone());
+
+        // The CPU publishes on its own every `PUBLISH_INTERVAL` instructions
+        cpu.run_for_cycles(100_000);
+        let published = metrics.snapshot().instructions;
+        assert!(published > 0 && published.is_multiple_of(PUBLISH_INTERVAL));
+        metrics.publish(&cpu);
+        let first = parse_exposition(&metrics.snapshot().render());
+        assert_eq!(first.len(), 9);
+        let writes = "r6502_device_writes_total{device=\"Console\",address=\"F001\"}";
+        assert_eq!(sample(&first, "r6502_cycles_total"), cpu.cycles as f64);
+        assert_eq!(
+            sample(&first, "r6502_instructions_total"),
+            cpu.instructions as f64
+        );
+        assert_eq!(sample(&first, writes), (cpu.instructions / 3) as f64);
+
+        cpu.run_for_cycles(1_000);
+        metrics.publish(&cpu);
+        let second = parse_exposition(&metrics.snapshot().render());
+        for series in ["r6502_instructions_total", "r6502_cycles_total", writes] {
+            assert!(
+                sample(&second, series) > sample(&first, series),
+                "{}",
+                series
+            );
+        }
+        assert_eq!(sample(&second, "r6502_irqs_total"), 0.0);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::memory::Memory;
+use crate::util::convert_hex_string_to_u16;
+use std::fmt;
+
+/// Size of a page the MMU can remap.
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// Number of pages in the 64K address space.
+pub const PAGE_COUNT: usize = 16;
+
+/// Bit set in a page register to map a bank into that page.
+const MAPPED: u8 = 0x80;
+
+/// A paged MMU mapping 4K banks of a larger backing store into the 64K address space.
+///
+/// The MMU has sixteen registers starting at `registers`, one per 4K page of the address space.
+/// Writing `$80 | bank` to a page's register maps that bank of the backing store into the page, and
+/// writing `$00` puts back the RAM that was there before. Banks are paged in by copying, so the
+/// backing store of a mapped bank only catches up with the program's writes when it is mapped out
+/// again (`bank` accounts for this). A bank mapped into two pages at once holds two separate copies.
+///
+/// The page holding the registers cannot be remapped, as that would page the registers out too.
+///
+/// # Example
+/// ```rust
+/// use r_6502::mmu::Mmu;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// let mut mmu = Mmu::new(0xFE00, 4);
+/// mmu.bank_mut(2)[0] = 0x42;
+/// mem.data[0xFE08] = 0x82; // Map bank 2 at $8000
+/// mmu.sync(&mut mem);
+/// assert_eq!(mem.data[0x8000], 0x42);
+/// assert_eq!(mmu.mapping()[8], Some(2));
+///
+/// mem.data[0x8001] = 0x99;
+/// mem.data[0xFE08] = 0x00; // Back to plain RAM
+/// mmu.sync(&mut mem);
+/// assert_eq!(mem.data[0x8000], 0x00);
+/// assert_eq!(mmu.bank(&mem, 2)[1], 0x99);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Mmu {
+    registers: u16,
+    backing: Vec<u8>,
+    mapping: [Option<u8>; PAGE_COUNT],
+    saved_ram: Vec<Option<Vec<u8>>>, // RAM paged out while a bank is mapped over it
+}
+
+impl Mmu {
+    /// Creates an MMU with `banks` 4K banks of zeroed backing store and its registers at
+    /// `registers..registers + 16`. Every page starts out as plain RAM.
+    ///
+    /// # Panics
+    /// - If `banks` is more than 128, the most a page register can select.
+    /// - If the registers would straddle two pages.
+    pub fn new(registers: u16, banks: usize) -> Self {
+        if registers as usize % PAGE_SIZE > PAGE_SIZE - PAGE_COUNT {
+            panic!("MMU registers at ${:04X} straddle two pages", registers);
+        }
+        if banks > MAPPED as usize {
+            panic!("An MMU can have at most {} banks, not {}", MAPPED, banks);
+        }
+        Mmu {
+            registers,
+            backing: vec![0; banks * PAGE_SIZE],
+            mapping: [None; PAGE_COUNT],
+            saved_ram: vec![None; PAGE_COUNT],
+        }
+    }
+
+    /// Returns the number of banks in the backing store.
+    pub fn banks(&self) -> usize {
+        self.backing.len() / PAGE_SIZE
+    }
+
+    /// Returns the bank mapped into each page, lowest page first, or `None` for plain RAM.
+    pub fn mapping(&self) -> [Option<u8>; PAGE_COUNT] {
+        self.mapping
+    }
+
+    /// Returns the bank mapped over `address`, or `None` if it is plain RAM.
+    pub fn bank_at(&self, address: u16) -> Option<u8> {
+        self.mapping[address as usize / PAGE_SIZE]
+    }
+
+    /// Returns the contents of a bank, including writes made while it is mapped into `mem`.
+    ///
+    /// # Panics
+    /// - If `bank` is not in the backing store.
+    pub fn bank(&self, mem: &Memory, bank: u8) -> Vec<u8> {
+        match self.mapping.iter().position(|mapped| *mapped == Some(bank)) {
+            Some(page) => mem.data[page * PAGE_SIZE..(page + 1) * PAGE_SIZE].to_vec(),
+            None => self.bank_slice(bank).to_vec(),
+        }
+    }
+
+    /// Returns a bank of the backing store for loading it, e.g. with a ROM image.
+    ///
+    /// Changes to a bank that is currently mapped show up the next time it is mapped in.
+    ///
+    /// # Panics
+    /// - If `bank` is not in the backing store.
+    pub fn bank_mut(&mut self, bank: u8) -> &mut [u8] {
+        let start = self.bank_start(bank);
+        &mut self.backing[start..start + PAGE_SIZE]
+    }
+
+    /// Applies the values in the page registers, remapping every page whose register changed.
+    ///
+    /// Registers selecting a bank past the end of the backing store, or remapping the page the
+    /// registers live in, are ignored.
+    pub fn sync(&mut self, mem: &mut Memory) {
+        for page in 0..PAGE_COUNT {
+            let register = mem.data[self.registers as usize + page];
+            let bank = if register & MAPPED != 0 {
+                Some(register & !MAPPED)
+            } else {
+                None
+            };
+            if bank != self.mapping[page] {
+                self.map(mem, page, bank);
+            }
+        }
+    }
+
+    /// Maps `bank` into `page` (`None` for plain RAM), writing the page register to match.
+    ///
+    /// # Returns
+    /// Whether the page was remapped. Pages are left alone if `bank` is not in the backing store or
+    /// `page` holds the registers.
+    pub fn map(&mut self, mem: &mut Memory, page: usize, bank: Option<u8>) -> bool {
+        let registers_page = self.registers as usize / PAGE_SIZE;
+        let valid_bank = bank.is_none_or(|bank| (bank as usize) < self.banks());
+        if page >= PAGE_COUNT || page == registers_page || !valid_bank {
+            return false;
+        }
+        let range = page * PAGE_SIZE..(page + 1) * PAGE_SIZE;
+        // Page out whatever is there now
+        match self.mapping[page] {
+            Some(old) => {
+                let start = self.bank_start(old);
+                self.backing[start..start + PAGE_SIZE].copy_from_slice(&mem.data[range.clone()]);
+            }
+            None => self.saved_ram[page] = Some(mem.data[range.clone()].to_vec()),
+        }
+        match bank {
+            Some(bank) => mem.data[range].copy_from_slice(self.bank_slice(bank)),
+            None => {
+                if let Some(ram) = self.saved_ram[page].take() {
+                    mem.data[range].copy_from_slice(&ram);
+                }
+            }
+        }
+        self.mapping[page] = bank;
+        mem.data[self.registers as usize + page] = bank.map_or(0, |bank| MAPPED | bank);
+        true
+    }
+
+    fn bank_start(&self, bank: u8) -> usize {
+        if bank as usize >= self.banks() {
+            let banks = self.banks();
+            panic!("Bank {} is not in the {}-bank backing store", bank, banks);
+        }
+        bank as usize * PAGE_SIZE
+    }
+
+    fn bank_slice(&self, bank: u8) -> &[u8] {
+        let start = self.bank_start(bank);
+        &self.backing[start..start + PAGE_SIZE]
+    }
+}
+
+/// An address qualified by the bank that must be mapped over it, written `bank:address`.
+///
+/// Code in different banks can share an address range, so a bare address is ambiguous while banks
+/// are switched. A banked address only refers to its location while its bank is mapped there; an
+/// address without a bank always does.
+///
+/// # Example
+/// ```rust
+/// use r_6502::mmu::{BankedAddress, Mmu};
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// let mut mmu = Mmu::new(0xFE00, 4);
+/// let location = BankedAddress::parse("3:$8000").unwrap();
+/// assert_eq!(location.to_string(), "3:$8000");
+///
+/// mmu.map(&mut mem, 0x8, Some(1));
+/// assert!(!location.is_active(Some(&mmu)));
+/// mmu.map(&mut mem, 0x8, Some(3));
+/// assert!(location.is_active(Some(&mmu)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BankedAddress {
+    pub bank: Option<u8>, // Bank that must be mapped over `address`, if any
+    pub address: u16,
+}
+
+impl BankedAddress {
+    /// Creates an address that refers to its location whatever is mapped there.
+    pub fn unbanked(address: u16) -> Self {
+        BankedAddress {
+            bank: None,
+            address,
+        }
+    }
+
+    /// Parses `bank:$address` (bank in decimal) or a bare `$address`.
+    ///
+    /// # Returns
+    /// - `Some(address)`: If the text is well formed.
+    /// - `None`: If the bank is not a decimal number or the address is not `$`-prefixed hex.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (bank, address) = match text.trim().split_once(':') {
+            Some((bank, address)) => (Some(bank.trim().parse::<u8>().ok()?), address),
+            None => (None, text),
+        };
+        let address = address
+            .trim()
+            .strip_prefix('$')
+            .and_then(|hex| convert_hex_string_to_u16(hex).ok())?;
+        Some(BankedAddress { bank, address })
+    }
+
+    /// Checks whether the address currently refers to its location, given the CPU's MMU.
+    ///
+    /// Without an MMU nothing is ever mapped, so only unbanked addresses are active.
+    pub fn is_active(&self, mmu: Option<&Mmu>) -> bool {
+        match self.bank {
+            Some(bank) => mmu.is_some_and(|mmu| mmu.bank_at(self.address) == Some(bank)),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{}:${:04X}", bank, self.address),
+            None => write!(f, "${:04X}", self.address),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use crate::asm_runner::{
+    adc, and, asl, bcc, bcs, beq, bit, bmi, bne, bpl, brk, bvc, bvs, clc, cld, cli, clv, cmp, cpx,
+    cpy, dec, dex, dey, eor, inc, inx, iny, jmp, jsr, lda, ldx, ldy, lsr, nop, ora, pha, php, pla,
+    plp, rol, ror, rti, rts, sbc, sec, sed, sei, sta, stx, sty, tax, tay, tsx, txa, txs, tya,
+};
+use crate::cpu::CPU;
+use crate::token::{AddressingMode, Token};
+use AddressingMode::*;
+
+/// Executes an instruction whose opcode has been fetched, in the given addressing mode.
+///
+/// Returns the cycles it took on top of its base cycle count, such as those of a taken branch.
+pub type Execute = fn(&mut CPU, AddressingMode, &mut u32) -> u64;
+
+/// Everything the assembler and the runner need to know about one opcode.
+#[derive(Clone, Copy, Debug)]
+pub struct OpcodeInfo {
+    pub token: Token,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub length: u16, // Bytes, including the opcode
+    pub cycles: u64, // Base cycle count, before page-crossing and branch penalties
+    pub execute: Execute,
+}
+
+/// Builds a table entry, working out the length from the addressing mode.
+const fn op(
+    token: Token,
+    mnemonic: &'static str,
+    mode: AddressingMode,
+    cycles: u64,
+    execute: Execute,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        token,
+        mnemonic,
+        mode,
+        length: 1 + mode.operand_size(),
+        cycles,
+        execute,
+    }
+}
+
+/// The documented opcodes, grouped by instruction. Adding an instruction here is enough for the
+/// assembler to accept it and the runner to execute it.
+const DOCUMENTED: [OpcodeInfo; 151] = [
+    op(Token::LDA, "LDA", Immediate, 2, lda),
+    op(Token::LdaZP, "LDA", ZeroPage, 3, lda),
+    op(Token::LdaZPX, "LDA", ZeroPageX, 4, lda),
+    op(Token::LdaAP, "LDA", Absolute, 4, lda),
+    op(Token::LdaAPX, "LDA", AbsoluteX, 4, lda),
+    op(Token::LdaAPY, "LDA", AbsoluteY, 4, lda),
+    op(Token::LdaIDX, "LDA", IndexedIndirect, 6, lda),
+    op(Token::LdaIDY, "LDA", IndirectIndexed, 5, lda),
+    op(Token::LDX, "LDX", Immediate, 2, ldx),
+    op(Token::LdxZP, "LDX", ZeroPage, 3, ldx),
+    op(Token::LdxZPY, "LDX", ZeroPageY, 4, ldx),
+    op(Token::LdxAP, "LDX", Absolute, 4, ldx),
+    op(Token::LdxAPY, "LDX", AbsoluteY, 4, ldx),
+    op(Token::LDY, "LDY", Immediate, 2, ldy),
+    op(Token::LdyZP, "LDY", ZeroPage, 3, ldy),
+    op(Token::LdyZPX, "LDY", ZeroPageX, 4, ldy),
+    op(Token::LdyAP, "LDY", Absolute, 4, ldy),
+    op(Token::LdyAPX, "LDY", AbsoluteX, 4, ldy),
+    op(Token::STA, "STA", ZeroPage, 3, sta),
+    op(Token::StaZPX, "STA", ZeroPageX, 4, sta),
+    op(Token::StaAP, "STA", Absolute, 4, sta),
+    op(Token::StaAPX, "STA", AbsoluteX, 5, sta),
+    op(Token::StaAPY, "STA", AbsoluteY, 5, sta),
+    op(Token::StaIDX, "STA", IndexedIndirect, 6, sta),
+    op(Token::StaIDY, "STA", IndirectIndexed, 6, sta),
+    op(Token::STX, "STX", ZeroPage, 3, stx),
+    op(Token::StxZPY, "STX", ZeroPageY, 4, stx),
+    op(Token::StxAP, "STX", Absolute, 4, stx),
+    op(Token::STY, "STY", ZeroPage, 3, sty),
+    op(Token::StyZPX, "STY", ZeroPageX, 4, sty),
+    op(Token::StyAP, "STY", Absolute, 4, sty),
+    op(Token::ADC, "ADC", Immediate, 2, adc),
+    op(Token::AdcZP, "ADC", ZeroPage, 3, adc),
+    op(Token::AdcZPX, "ADC", ZeroPageX, 4, adc),
+    op(Token::AdcAP, "ADC", Absolute, 4, adc),
+    op(Token::AdcAPX, "ADC", AbsoluteX, 4, adc),
+    op(Token::AdcAPY, "ADC", AbsoluteY, 4, adc),
+    op(Token::AdcIDX, "ADC", IndexedIndirect, 6, adc),
+    op(Token::AdcIDY, "ADC", IndirectIndexed, 5, adc),
+    op(Token::SBC, "SBC", Immediate, 2, sbc),
+    op(Token::SbcZP, "SBC", ZeroPage, 3, sbc),
+    op(Token::SbcZPX, "SBC", ZeroPageX, 4, sbc),
+    op(Token::SbcAP, "SBC", Absolute, 4, sbc),
+    op(Token::SbcAPX, "SBC", AbsoluteX, 4, sbc),
+    op(Token::SbcAPY, "SBC", AbsoluteY, 4, sbc),
+    op(Token::SbcIDX, "SBC", IndexedIndirect, 6, sbc),
+    op(Token::SbcIDY, "SBC", IndirectIndexed, 5, sbc),
+    op(Token::AND, "AND", Immediate, 2, and),
+    op(Token::AndZP, "AND", ZeroPage, 3, and),
+    op(Token::AndZPX, "AND", ZeroPageX, 4, and),
+    op(Token::AndAP, "AND", Absolute, 4, and),
+    op(Token::AndAPX, "AND", AbsoluteX, 4, and),
+    op(Token::AndAPY, "AND", AbsoluteY, 4, and),
+    op(Token::AndIDX, "AND", IndexedIndirect, 6, and),
+    op(Token::AndIDY, "AND", IndirectIndexed, 5, and),
+    op(Token::ORA, "ORA", Immediate, 2, ora),
+    op(Token::OraZP, "ORA", ZeroPage, 3, ora),
+    op(Token::OraZPX, "ORA", ZeroPageX, 4, ora),
+    op(Token::OraAP, "ORA", Absolute, 4, ora),
+    op(Token::OraAPX, "ORA", AbsoluteX, 4, ora),
+    op(Token::OraAPY, "ORA", AbsoluteY, 4, ora),
+    op(Token::OraIDX, "ORA", IndexedIndirect, 6, ora),
+    op(Token::OraIDY, "ORA", IndirectIndexed, 5, ora),
+    op(Token::EOR, "EOR", Immediate, 2, eor),
+    op(Token::EorZP, "EOR", ZeroPage, 3, eor),
+    op(Token::EorZPX, "EOR", ZeroPageX, 4, eor),
+    op(Token::EorAP, "EOR", Absolute, 4, eor),
+    op(Token::EorAPX, "EOR", AbsoluteX, 4, eor),
+    op(Token::EorAPY, "EOR", AbsoluteY, 4, eor),
+    op(Token::EorIDX, "EOR", IndexedIndirect, 6, eor),
+    op(Token::EorIDY, "EOR", IndirectIndexed, 5, eor),
+    op(Token::BIT, "BIT", ZeroPage, 3, bit),
+    op(Token::BitAP, "BIT", Absolute, 4, bit),
+    op(Token::CMP, "CMP", Immediate, 2, cmp),
+    op(Token::CmpZP, "CMP", ZeroPage, 3, cmp),
+    op(Token::CmpZPX, "CMP", ZeroPageX, 4, cmp),
+    op(Token::CmpAP, "CMP", Absolute, 4, cmp),
+    op(Token::CmpAPX, "CMP", AbsoluteX, 4, cmp),
+    op(Token::CmpAPY, "CMP", AbsoluteY, 4, cmp),
+    op(Token::CmpIDX, "CMP", IndexedIndirect, 6, cmp),
+    op(Token::CmpIDY, "CMP", IndirectIndexed, 5, cmp),
+    op(Token::CPX, "CPX", Immediate, 2, cpx),
+    op(Token::CpxZP, "CPX", ZeroPage, 3, cpx),
+    op(Token::CpxAP, "CPX", Absolute, 4, cpx),
+    op(Token::CPY, "CPY", Immediate, 2, cpy),
+    op(Token::CpyZP, "CPY", ZeroPage, 3, cpy),
+    op(Token::CpyAP, "CPY", Absolute, 4, cpy),
+    op(Token::ASL, "ASL", Accumulator, 2, asl),
+    op(Token::AslZP, "ASL", ZeroPage, 5, asl),
+    op(Token::AslZPX, "ASL", ZeroPageX, 6, asl),
+    op(Token::AslAP, "ASL", Absolute, 6, asl),
+    op(Token::AslAPX, "ASL", AbsoluteX, 7, asl),
+    op(Token::LSR, "LSR", Accumulator, 2, lsr),
+    op(Token::LsrZP, "LSR", ZeroPage, 5, lsr),
+    op(Token::LsrZPX, "LSR", ZeroPageX, 6, lsr),
+    op(Token::LsrAP, "LSR", Absolute, 6, lsr),
+    op(Token::LsrAPX, "LSR", AbsoluteX, 7, lsr),
+    op(Token::ROL, "ROL", Accumulator, 2, rol),
+    op(Token::RolZP, "ROL", ZeroPage, 5, rol),
+    op(Token::RolZPX, "ROL", ZeroPageX, 6, rol),
+    op(Token::RolAP, "ROL", Absolute, 6, rol),
+    op(Token::RolAPX, "ROL", AbsoluteX, 7, rol),
+    op(Token::ROR, "ROR", Accumulator, 2, ror),
+    op(Token::RorZP, "ROR", ZeroPage, 5, ror),
+    op(Token::RorZPX, "ROR", ZeroPageX, 6, ror),
+    op(Token::RorAP, "ROR", Absolute, 6, ror),
+    op(Token::RorAPX, "ROR", AbsoluteX, 7, ror),
+    op(Token::INC, "INC", ZeroPage, 5, inc),
+    op(Token::IncZPX, "INC", ZeroPageX, 6, inc),
+    op(Token::IncAP, "INC", Absolute, 6, inc),
+    op(Token::IncAPX, "INC", AbsoluteX, 7, inc),
+    op(Token::DEC, "DEC", ZeroPage, 5, dec),
+    op(Token::DecZPX, "DEC", ZeroPageX, 6, dec),
+    op(Token::DecAP, "DEC", Absolute, 6, dec),
+    op(Token::DecAPX, "DEC", AbsoluteX, 7, dec),
+    op(Token::INX, "INX", Implied, 2, inx),
+    op(Token::INY, "INY", Implied, 2, iny),
+    op(Token::DEX, "DEX", Implied, 2, dex),
+    op(Token::DEY, "DEY", Implied, 2, dey),
+    op(Token::BCC, "BCC", Relative, 2, bcc),
+    op(Token::BCS, "BCS", Relative, 2, bcs),
+    op(Token::BEQ, "BEQ", Relative, 2, beq),
+    op(Token::BNE, "BNE", Relative, 2, bne),
+    op(Token::BMI, "BMI", Relative, 2, bmi),
+    op(Token::BPL, "BPL", Relative, 2, bpl),
+    op(Token::BVS, "BVS", Relative, 2, bvs),
+    op(Token::BVC, "BVC", Relative, 2, bvc),
+    op(Token::JMP, "JMP", Absolute, 3, jmp),
+    op(Token::JmpID, "JMP", Indirect, 5, jmp),
+    op(Token::JSR, "JSR", Absolute, 6, jsr),
+    op(Token::RTS, "RTS", Implied, 6, rts),
+    op(Token::RTI, "RTI", Implied, 6, rti),
+    op(Token::BRK, "BRK", Implied, 7, brk),
+    op(Token::PHA, "PHA", Implied, 3, pha),
+    op(Token::PHP, "PHP", Implied, 3, php),
+    op(Token::PLA, "PLA", Implied, 4, pla),
+    op(Token::PLP, "PLP", Implied, 4, plp),
+    op(Token::TAX, "TAX", Implied, 2, tax),
+    op(Token::TAY, "TAY", Implied, 2, tay),
+    op(Token::TXA, "TXA", Implied, 2, txa),
+    op(Token::TYA, "TYA", Implied, 2, tya),
+    op(Token::TSX, "TSX", Implied, 2, tsx),
+    op(Token::TXS, "TXS", Implied, 2, txs),
+    op(Token::CLC, "CLC", Implied, 2, clc),
+    op(Token::CLD, "CLD", Implied, 2, cld),
+    op(Token::CLI, "CLI", Implied, 2, cli),
+    op(Token::CLV, "CLV", Implied, 2, clv),
+    op(Token::SEC, "SEC", Implied, 2, sec),
+    op(Token::SED, "SED", Implied, 2, sed),
+    op(Token::SEI, "SEI", Implied, 2, sei),
+    op(Token::NOP, "NOP", Implied, 2, nop),
+];
+
+/// Places each documented opcode at its byte value.
+const fn build_table() -> [Option<OpcodeInfo>; 256] {
+    let mut table: [Option<OpcodeInfo>; 256] = [None; 256];
+    let mut index = 0;
+    while index < DOCUMENTED.len() {
+        let info = DOCUMENTED[index];
+        table[info.token as usize] = Some(info);
+        index += 1;
+    }
+    table
+}
+
+/// Every opcode byte, with the information about it if it is a documented 6502 opcode.
+///
+/// This is the single description of the instruction set: the assembler picks the opcode for a
+/// mnemonic and addressing mode here, and the runner decodes the byte at the program counter here
+/// and calls its `execute`. Undocumented opcodes are not in the table; the runner handles them
+/// separately, as `cpu.illegal_opcodes` says.
+///
+/// # Example
+/// ```rust
+/// use r_6502::opcode_table::OPCODES;
+/// use r_6502::token::AddressingMode;
+///
+/// let info = OPCODES[0xBD].unwrap();
+/// assert_eq!((info.mnemonic, info.mode), ("LDA", AddressingMode::AbsoluteX));
+/// assert_eq!((info.length, info.cycles), (3, 4));
+/// assert!(OPCODES[0x02].is_none());
+/// assert_eq!(OPCODES.iter().flatten().count(), 151);
+/// ```
+pub static OPCODES: [Option<OpcodeInfo>; 256] = build_table();
+
+/// Returns the table entry for an opcode byte, or `None` if it is not a documented opcode.
+pub fn opcode_info(opcode: u8) -> Option<&'static OpcodeInfo> {
+    OPCODES[opcode as usize].as_ref()
+}
+
+/// Finds the opcode of an instruction in an addressing mode.
+///
+/// # Returns
+/// - `Some(info)`: If the instruction exists in that mode.
+/// - `None`: If the mnemonic is unknown or the instruction has no such mode.
+///
+/// # Example
+/// ```rust
+/// use r_6502::opcode_table::find_opcode;
+/// use r_6502::token::{AddressingMode, Token};
+///
+/// let info = find_opcode("STX", AddressingMode::ZeroPageY).unwrap();
+/// assert_eq!(info.token, Token::StxZPY);
+/// assert!(find_opcode("STX", AddressingMode::AbsoluteY).is_none());
+/// ```
+pub fn find_opcode(mnemonic: &str, mode: AddressingMode) -> Option<&'static OpcodeInfo> {
+    OPCODES
+        .iter()
+        .flatten()
+        .find(|info| info.mnemonic == mnemonic && info.mode == mode)
+}
+
+/// Checks whether a mnemonic names a documented instruction.
+pub fn is_mnemonic(mnemonic: &str) -> bool {
+    OPCODES
+        .iter()
+        .flatten()
+        .any(|info| info.mnemonic == mnemonic)
+}
+
+/// Returns a mnemonic written in any case as the table spells it, if it names a documented
+/// instruction.
+///
+/// # Example
+/// ```rust
+/// use r_6502::opcode_table::canonical_mnemonic;
+///
+/// assert_eq!(canonical_mnemonic("lda"), Some("LDA"));
+/// assert_eq!(canonical_mnemonic("Bne"), Some("BNE"));
+/// assert_eq!(canonical_mnemonic("LDZ"), None);
+/// ```
+pub fn canonical_mnemonic(mnemonic: &str) -> Option<&'static str> {
+    OPCODES
+        .iter()
+        .flatten()
+        .find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic))
+        .map(|info| info.mnemonic)
+}
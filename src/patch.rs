@@ -0,0 +1,215 @@
+use crate::memory::Memory;
+use crate::prestate::{parse_number, strip_comment};
+use std::fmt;
+use std::fs;
+
+/// A test a patch's condition makes on a byte of memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatchCondition {
+    pub address: u16,
+    pub value: u8,
+    pub equal: bool, // `true` for `==`, `false` for `!=`
+}
+
+impl PatchCondition {
+    /// Checks whether the condition holds for the current contents of memory.
+    pub fn matches(&self, mem: &Memory) -> bool {
+        (mem.data[self.address as usize] == self.value) == self.equal
+    }
+}
+
+/// Bytes to poke into memory, once or continuously, optionally only while a condition holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Patch {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub condition: Option<PatchCondition>,
+    pub always: bool, // Reapplied after every instruction rather than once at load
+}
+
+/// An error found while reading a patch file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatchError {
+    pub line: usize, // 1-based line the error was found on, or 0 if the file could not be read
+    pub message: String,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// A set of patches ("cheats") applied to memory from the host side.
+///
+/// Patch files hold one patch per line, with `#` starting a comment. Numbers are decimal, `$` hex
+/// or `0x` hex, as in pre-state files:
+///
+/// ```text
+/// $E0A3 = $EA $EA $EA               # once at load: NOP out the boot delay
+/// $0042 = $09 always                # after every instruction: infinite lives
+/// $C010 = $60 if $C010 == $20       # only if the ROM has the expected byte there
+/// $0300 = $01 always if $00FF != $00
+/// ```
+///
+/// Patches without `always` are applied by `apply_once`, typically right after the program is
+/// loaded. Patches with it are applied by `apply_always`, which the CPU calls after every
+/// instruction when the set is in `cpu.patches`. A condition compares a byte of memory with a
+/// value, as in Game Genie codes; a patch whose condition does not hold is skipped.
+///
+/// Patches write `memory.data` directly, bypassing ROM protection and devices, so they can patch
+/// ROMs.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::patch::PatchSet;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// // DEC $10 three times, with $10 held at 5
+/// cpu.memory.data[0..6].copy_from_slice(&[0xC6, 0x10, 0xC6, 0x10, 0xC6, 0x10]);
+/// let patches = PatchSet::parse("$0010 = 5 always\n$0004 = $EA $EA if $0004 == $C6").unwrap();
+/// patches.apply_once(&mut cpu.memory);
+/// cpu.patches = patches;
+/// run_until(&mut cpu, &EndConditions::program_end(6));
+/// assert_eq!(cpu.memory.data[0x10], 5);
+/// assert_eq!(cpu.memory.data[0x04], 0xEA);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PatchSet {
+    pub patches: Vec<Patch>,
+}
+
+impl PatchSet {
+    pub fn new() -> Self {
+        PatchSet {
+            patches: Vec::new(),
+        }
+    }
+
+    /// Parses the text of a patch file.
+    ///
+    /// # Errors
+    /// Returns a `PatchError` for the first line that is not a valid patch, or a patch running
+    /// past the end of memory.
+    pub fn parse(text: &str) -> Result<Self, PatchError> {
+        let mut patches: Vec<Patch> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            patches.push(parse_patch(line).map_err(|message| PatchError {
+                line: index + 1,
+                message,
+            })?);
+        }
+        Ok(PatchSet { patches })
+    }
+
+    /// Reads and parses a patch file.
+    ///
+    /// # Errors
+    /// Returns a `PatchError` with line 0 if the file cannot be read, or the first parse error.
+    pub fn load_file(path: &str) -> Result<Self, PatchError> {
+        let text = fs::read_to_string(path).map_err(|e| PatchError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        Self::parse(&text)
+    }
+
+    /// Applies the patches without `always` whose condition holds.
+    ///
+    /// # Returns
+    /// The number of patches applied.
+    pub fn apply_once(&self, mem: &mut Memory) -> usize {
+        self.apply(mem, false)
+    }
+
+    /// Applies the patches with `always` whose condition holds.
+    pub fn apply_always(&self, mem: &mut Memory) {
+        self.apply(mem, true);
+    }
+
+    fn apply(&self, mem: &mut Memory, always: bool) -> usize {
+        let mut applied = 0;
+        for patch in self.patches.iter().filter(|patch| patch.always == always) {
+            if patch
+                .condition
+                .is_some_and(|condition| !condition.matches(mem))
+            {
+                continue;
+            }
+            let start = patch.address as usize;
+            mem.data[start..start + patch.bytes.len()].copy_from_slice(&patch.bytes);
+            applied += 1;
+        }
+        applied
+    }
+}
+
+/// Parses one patch, e.g. `$0042 = $09 always if $0043 == $00`.
+fn parse_patch(line: &str) -> Result<Patch, String> {
+    let (address, rest) = line
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<address> = <bytes>`, found `{}`", line))?;
+    let address = parse_number(address)?;
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut condition: Option<PatchCondition> = None;
+    let mut always = false;
+    let mut words = rest.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "always" => always = true,
+            "if" => {
+                let (target, operator, value) = (words.next(), words.next(), words.next());
+                let (target, operator, value) = match (target, operator, value) {
+                    (Some(target), Some(operator), Some(value)) => (target, operator, value),
+                    _ => return Err(String::from("expected `if <address> == <value>`")),
+                };
+                let equal = match operator {
+                    "==" => true,
+                    "!=" => false,
+                    other => return Err(format!("invalid comparison `{}`", other)),
+                };
+                condition = Some(PatchCondition {
+                    address: parse_number(target)?,
+                    value: parse_byte(value)?,
+                    equal,
+                });
+            }
+            value if condition.is_none() && !always => bytes.push(parse_byte(value)?),
+            other => {
+                return Err(format!(
+                    "unexpected `{}` after `always` or the condition",
+                    other
+                ))
+            }
+        }
+    }
+    if bytes.is_empty() {
+        return Err(String::from("no bytes to patch"));
+    }
+    if address as usize + bytes.len() > 0x10000 {
+        return Err(format!("patch at ${:04X} runs past $FFFF", address));
+    }
+    Ok(Patch {
+        address,
+        bytes,
+        condition,
+        always,
+    })
+}
+
+/// Parses a number that must fit in a byte.
+fn parse_byte(value: &str) -> Result<u8, String> {
+    u8::try_from(parse_number(value)?).map_err(|_| format!("`{}` does not fit in a byte", value))
+}
@@ -0,0 +1,114 @@
+use crate::bus::Device;
+use crate::console::stdin_keys;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+
+/// Number of registers the PIA decodes.
+pub const PIA_REGISTERS: u16 = 4;
+
+// Register offsets
+const KBD: u16 = 0x0; // Keyboard data, with bit 7 set
+const KBDCR: u16 = 0x1; // Keyboard control: bit 7 is set while a key is waiting
+const DSP: u16 = 0x2; // Display data: bit 7 is set while the display is busy
+
+/// Control register bit selecting the data register rather than the data direction register.
+const CR_DATA: u8 = 0x04;
+
+/// The Apple I's 6821 PIA, with the keyboard on port A and the terminal display on port B.
+///
+/// Mapped at `$D010-$D013` on the Apple I: `KBD`, `KBDCR`, `DSP` and `DSPCR`. Keys come from
+/// `input`: lowercase letters are turned into uppercase, as the Apple I keyboard has no lowercase,
+/// and a line feed into the carriage return the Woz Monitor expects. Characters written to `DSP`
+/// go to `output` with bit 7 dropped and carriage returns as line feeds. The display is never
+/// busy. Writes to a data register while its control register selects the data direction
+/// register, as the Woz Monitor does when it starts, are ignored.
+///
+/// # Example
+/// ```rust
+/// use r_6502::bus::Device;
+/// use r_6502::pia::Pia;
+/// use std::sync::mpsc;
+///
+/// let (keys, input) = mpsc::channel();
+/// let mut pia = Pia::new(input, Box::new(std::io::sink()));
+/// assert_eq!(pia.read(1) & 0x80, 0x00); // No key waiting
+/// keys.send(b'a').unwrap();
+/// assert_eq!(pia.read(1) & 0x80, 0x80);
+/// assert_eq!(pia.read(0), 0x80 | b'A');
+/// assert_eq!(pia.read(1) & 0x80, 0x00);
+/// ```
+pub struct Pia {
+    input: Receiver<u8>,
+    output: Box<dyn Write>,
+    key: Option<u8>,  // Key waiting to be read, already translated
+    control: [u8; 2], // KBDCR and DSPCR
+}
+
+impl Pia {
+    /// Creates a PIA reading keys from `input` and writing the display to `output`.
+    pub fn new(input: Receiver<u8>, output: Box<dyn Write>) -> Self {
+        Pia {
+            input,
+            output,
+            key: None,
+            control: [0; 2],
+        }
+    }
+
+    /// Creates a PIA on the terminal, with the keyboard read from stdin on a background thread so
+    /// the program keeps running while no key is pressed.
+    pub fn terminal() -> Self {
+        Self::new(stdin_keys(), Box::new(std::io::stdout()))
+    }
+
+    /// Takes the next key from the input if none is waiting.
+    fn poll(&mut self) {
+        while self.key.is_none() {
+            match self.input.try_recv() {
+                Ok(b'\r') => {} // Terminals sending CR LF give one return
+                Ok(b'\n') => self.key = Some(0x0D),
+                Ok(byte) => self.key = Some(byte.to_ascii_uppercase() & 0x7F),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Device for Pia {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset % PIA_REGISTERS {
+            KBD => {
+                self.poll();
+                self.key.take().map_or(0, |key| key | 0x80)
+            }
+            KBDCR => {
+                self.poll();
+                let ready = if self.key.is_some() { 0x80 } else { 0x00 };
+                self.control[0] | ready
+            }
+            DSP => 0x00, // Never busy
+            _ => self.control[1],
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset % PIA_REGISTERS {
+            KBD => {}
+            KBDCR => self.control[0] = value & 0x3F,
+            DSP => {
+                if self.control[1] & CR_DATA == 0 {
+                    return;
+                }
+                let text = match value & 0x7F {
+                    0x0D => String::from("\n"),
+                    byte @ 0x20..=0x7E => (byte as char).to_string(),
+                    _ => String::new(),
+                };
+                // Output the host can no longer take should not stop the program
+                let _ = self.output.write_all(text.as_bytes());
+                let _ = self.output.flush();
+            }
+            _ => self.control[1] = value & 0x3F,
+        }
+    }
+}
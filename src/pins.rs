@@ -0,0 +1,87 @@
+use crate::bus::BusAccess;
+use crate::cpu::CPU;
+use serde::Serialize;
+
+/// The levels on the CPU's pins during one clock cycle, as a visualization would show them.
+///
+/// Levels are given by meaning rather than voltage: `read` is the R/W pin high, and `irq`, `nmi`
+/// and `rdy` are `true` when the line is asserted (for IRQ and NMI, pulled low).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct PinState {
+    pub cycle: u64,     // Value of `cpu.cycles` at the start of the cycle
+    pub address: u16,   // Address bus
+    pub data: u8,       // Data bus
+    pub read: bool,     // R/W: `true` while reading, `false` while writing
+    pub sync: bool,     // Set during an opcode fetch
+    pub internal: bool, // A cycle with no access in the emulator, see `step_pins`
+    pub irq: bool,
+    pub nmi: bool,
+    pub rdy: bool,
+}
+
+/// Executes one instruction and returns the state of the pins for each of its clock cycles.
+///
+/// Instructions are emulated as a whole rather than cycle by cycle, so this is a behavioural
+/// picture of the bus, like Visual6502 shows but without the transistors: the reads and writes
+/// the instruction makes through the bus are reported in order, the first one (the opcode fetch)
+/// with `sync` set. Cycles in which the real chip makes a dummy read or works internally are
+/// added at the end as `internal` reads of the last address accessed, with the byte in
+/// `memory.data` on the data bus, so there is one state per cycle of the instruction. An IRQ
+/// taken after the instruction adds the cycles of its stack pushes and vector fetch.
+///
+/// `irq` is the level of the IRQ line the attached devices drive at the start of the
+/// instruction. Nothing drives NMI or RDY, so `nmi` is always `false` and `rdy` always `true`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::pins::step_pins;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..3].copy_from_slice(&[0x8D, 0x00, 0x02]); // STA $0200
+/// cpu.a = 0x42;
+/// let pins = step_pins(&mut cpu);
+/// let bus: Vec<(u16, u8, bool, bool)> =
+///     pins.iter().map(|pin| (pin.address, pin.data, pin.read, pin.sync)).collect();
+/// assert_eq!(
+///     bus,
+///     [(0x0000, 0x8D, true, true), (0x0001, 0x00, true, false), (0x0002, 0x02, true, false),
+///      (0x0200, 0x42, false, false)]
+/// );
+/// ```
+pub fn step_pins(cpu: &mut CPU) -> Vec<PinState> {
+    let start = cpu.cycles;
+    let irq = cpu.memory.irq();
+    cpu.memory.record_bus();
+    cpu.step();
+    let mut accesses: Vec<BusAccess> = cpu.memory.take_bus_log();
+    // Immediate operands are read once when fetched and again as the operand; on the chip that
+    // is a single cycle
+    accesses.dedup_by(|next, previous| !next.write && next == previous);
+    let state = |index: usize, address: u16, data: u8, read: bool, internal: bool| PinState {
+        cycle: start + index as u64,
+        address,
+        data,
+        read,
+        sync: index == 0 && !internal,
+        internal,
+        irq,
+        nmi: false,
+        rdy: true,
+    };
+    let mut pins: Vec<PinState> = accesses
+        .iter()
+        .enumerate()
+        .map(|(index, access)| state(index, access.address, access.value, !access.write, false))
+        .collect();
+    let last_address = accesses
+        .last()
+        .map(|access| access.address)
+        .unwrap_or(cpu.pc);
+    let cycles = (cpu.cycles - start) as usize;
+    for index in pins.len()..cycles {
+        let data = cpu.memory.data[last_address as usize];
+        pins.push(state(index, last_address, data, true, true));
+    }
+    pins
+}
@@ -0,0 +1,214 @@
+use crate::cpu::{Register, CPU};
+use std::fmt;
+use std::fs;
+
+/// One assignment in a pre-state file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Assignment {
+    Register(Register, u16),
+    Memory { start: u16, bytes: Vec<u8> },
+}
+
+/// An error found while reading a pre-state file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreStateError {
+    pub line: usize, // 1-based line the error was found on, or 0 if the file could not be read
+    pub message: String,
+}
+
+impl fmt::Display for PreStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for PreStateError {}
+
+/// Initial register values and memory contents to set up before running a program.
+///
+/// Pre-state files hold one or more comma-separated assignments per line, with `#` starting a
+/// comment. Numbers are decimal, `$` hex or `0x` hex:
+///
+/// ```text
+/// A=0x10, X=$05, PC=$0600       # registers: A, X, Y, SP, PC and P
+/// $0300 = $01 $02 $03           # bytes from an address onwards
+/// $0200..$020F = "HELLO"        # a string, padded with zeros to fill the range
+/// $0400..$04FF = $EA            # a single byte repeated over the range
+/// ```
+///
+/// # Example
+/// ```rust
+/// use r_6502::prestate::PreState;
+/// use r_6502::CPU;
+///
+/// let state = PreState::parse("A=0x10, $0200..$0207 = \"HELLO\"\n$0300 = $01 2").unwrap();
+/// let mut cpu = CPU::new();
+/// state.apply(&mut cpu);
+/// assert_eq!(cpu.a, 0x10);
+/// assert_eq!(&cpu.memory.data[0x0200..0x0208], b"HELLO\0\0\0");
+/// assert_eq!(&cpu.memory.data[0x0300..0x0302], &[0x01, 0x02]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PreState {
+    pub assignments: Vec<Assignment>,
+}
+
+impl PreState {
+    /// Parses a pre-state description.
+    ///
+    /// # Errors
+    /// Returns a `PreStateError` for the first assignment that is malformed, names an unknown
+    /// register, has a value that does not fit, or does not fit in its range or in memory.
+    pub fn parse(text: &str) -> Result<PreState, PreStateError> {
+        let mut assignments: Vec<Assignment> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| PreStateError {
+                line: index + 1,
+                message,
+            };
+            for item in split_items(strip_comment(line)) {
+                let item = item.trim();
+                if item.is_empty() {
+                    continue;
+                }
+                assignments.push(parse_assignment(item).map_err(error)?);
+            }
+        }
+        Ok(PreState { assignments })
+    }
+
+    /// Reads and parses a pre-state file.
+    ///
+    /// # Errors
+    /// Returns a `PreStateError` with line 0 if the file cannot be read, or the first parse error.
+    pub fn load_file(path: &str) -> Result<PreState, PreStateError> {
+        let text = fs::read_to_string(path).map_err(|e| PreStateError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        PreState::parse(&text)
+    }
+
+    /// Sets the registers and memory of `cpu`, applying assignments in file order.
+    pub fn apply(&self, cpu: &mut CPU) {
+        for assignment in &self.assignments {
+            match assignment {
+                Assignment::Register(register, value) => cpu.set(*register, *value),
+                Assignment::Memory { start, bytes } => {
+                    let start = *start as usize;
+                    cpu.memory.data[start..start + bytes.len()].copy_from_slice(bytes);
+                }
+            }
+        }
+    }
+}
+
+/// Removes a `#` comment from a line, leaving `#` inside strings alone.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits a line on the commas separating assignments, leaving commas inside strings alone.
+fn split_items(line: &str) -> Vec<&str> {
+    let mut items: Vec<&str> = Vec::new();
+    let mut in_string = false;
+    let mut start = 0;
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(&line[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&line[start..]);
+    items
+}
+
+fn parse_assignment(item: &str) -> Result<Assignment, String> {
+    let (target, value) = match item.split_once('=') {
+        Some((target, value)) => (target.trim(), value.trim()),
+        None => return Err(format!("expected `target = value`, found `{}`", item)),
+    };
+    if !target.starts_with('$') && !target.starts_with("0x") {
+        let register = match Register::parse(target) {
+            Some(register) => register,
+            None => return Err(format!("unknown register `{}`", target)),
+        };
+        let value = parse_number(value)?;
+        if register.is_8_bit() && value > 0xFF {
+            return Err(format!("`{}` does not fit in {}", value, target));
+        }
+        return Ok(Assignment::Register(register, value));
+    }
+
+    let (start, end) = match target.split_once("..") {
+        Some((start, end)) => (parse_number(start)?, Some(parse_number(end)?)),
+        None => (parse_number(target)?, None),
+    };
+    let mut bytes = parse_bytes(value)?;
+    if let Some(end) = end {
+        if end < start {
+            return Err(format!("range `{}` ends before it starts", target));
+        }
+        let length = (end - start) as usize + 1;
+        if bytes.len() > length {
+            return Err(format!("{} bytes do not fit in `{}`", bytes.len(), target));
+        }
+        if bytes.len() == 1 && !value.starts_with('"') {
+            bytes = vec![bytes[0]; length];
+        } else {
+            bytes.resize(length, 0);
+        }
+    }
+    if bytes.len() > 0x10000 - start as usize {
+        return Err(format!(
+            "{} bytes do not fit in memory at `{}`",
+            bytes.len(),
+            target
+        ));
+    }
+    Ok(Assignment::Memory { start, bytes })
+}
+
+/// Parses the bytes on the right of a memory assignment: a quoted string or a list of numbers.
+fn parse_bytes(value: &str) -> Result<Vec<u8>, String> {
+    if let Some(text) = value.strip_prefix('"') {
+        return match text.strip_suffix('"') {
+            Some(text) if text.is_ascii() => Ok(text.as_bytes().to_vec()),
+            Some(_) => Err(format!("string {} is not ASCII", value)),
+            None => Err(format!("unterminated string {}", value)),
+        };
+    }
+    value
+        .split_whitespace()
+        .map(|word| {
+            let number = parse_number(word)?;
+            u8::try_from(number).map_err(|_| format!("`{}` does not fit in a byte", word))
+        })
+        .collect()
+}
+
+/// Parses a decimal, `$` hex or `0x` hex number.
+pub(crate) fn parse_number(value: &str) -> Result<u16, String> {
+    let value = value.trim();
+    let parsed = match value.strip_prefix('$').or_else(|| value.strip_prefix("0x")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse::<u16>(),
+    };
+    parsed.map_err(|_| format!("invalid number `{}`", value))
+}
@@ -0,0 +1,169 @@
+use crate::asm_runner::execute_instruction;
+use crate::cpu::CPU;
+use crate::shadow_stack::ShadowStack;
+use crate::token::Token;
+use std::collections::HashMap;
+
+/// Cycles measured for one routine over a run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoutineProfile {
+    pub calls: u64,
+    pub inclusive: u64, // Cycles spent in the routine and everything it called
+    pub exclusive: u64, // Cycles spent in the routine's own instructions
+}
+
+/// A routine that has been entered through `JSR` and not yet returned from.
+struct Frame {
+    address: u16,
+    start_cycles: u64,
+    child_cycles: u64,
+}
+
+/// Measures how many cycles each labelled routine takes while a program runs.
+///
+/// Calls are tracked through `JSR` and `RTS`: a `JSR` opens a frame for its target and the matching
+/// `RTS` closes it. The code the run starts in is treated as a routine of its own, so every cycle is
+/// attributed to some routine. Routines are named after the label defined at their address, or
+/// shown as `$XXXX` when they have none.
+///
+/// # Example
+/// ```rust
+/// use r_6502::profiler::Profiler;
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// let mut assembler = Assembler::new();
+/// assembler
+///     .assemble("main:\nJSR work\nJMP end\nwork:\nINX\nRTS\nend:\nNOP", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// let mut profiler = Profiler::new(assembler.symbol_table());
+/// let mut data_cycle_count = end_address as u32;
+/// profiler.run(&mut cpu, &mut data_cycle_count);
+/// assert_eq!(profiler.profile("work").unwrap().calls, 1);
+/// assert_eq!(profiler.profile("work").unwrap().exclusive, 8);
+/// ```
+pub struct Profiler {
+    names: HashMap<u16, String>,
+    frames: Vec<Frame>,
+    calls: ShadowStack,
+    profiles: HashMap<u16, RoutineProfile>,
+}
+
+impl Profiler {
+    /// Creates a profiler naming routines after the labels in `symbol_table`.
+    pub fn new(symbol_table: &HashMap<String, u16>) -> Self {
+        let mut names: HashMap<u16, String> = HashMap::new();
+        for (label, address) in symbol_table {
+            // Keep the alphabetically first label when several share an address
+            let name = names.entry(*address).or_insert_with(|| label.clone());
+            if label < name {
+                *name = label.clone();
+            }
+        }
+        Profiler {
+            names,
+            frames: Vec::new(),
+            calls: ShadowStack::new(),
+            profiles: HashMap::new(),
+        }
+    }
+
+    /// Executes the program like `run_memory`, accounting every instruction's cycles to a routine.
+    ///
+    /// Routines still open when the program runs out (including the one the run started in) are
+    /// closed at that point, so their cycles are included in the report.
+    pub fn run(&mut self, cpu: &mut CPU, data_cycle_count: &mut u32) {
+        self.enter(cpu.pc, cpu.cycles);
+        while *data_cycle_count > 0 {
+            self.step(cpu, data_cycle_count);
+        }
+        while !self.frames.is_empty() {
+            self.leave(cpu.cycles);
+        }
+    }
+
+    /// Executes one instruction, opening a frame on `JSR` and closing the frames an `RTS` leaves.
+    ///
+    /// Calls are matched through a `ShadowStack`, so RTS-trick jumps and discarded return addresses
+    /// do not throw the accounting out of step.
+    fn step(&mut self, cpu: &mut CPU, data_cycle_count: &mut u32) {
+        let pc = cpu.pc;
+        let sp = cpu.sp as u8;
+        let opcode = Token::from_opcode(cpu.memory.data[pc as usize]);
+        execute_instruction(cpu, data_cycle_count);
+        match opcode {
+            Some(Token::JSR) => {
+                self.calls.call(cpu.pc, pc.wrapping_add(3), cpu.sp as u8);
+                self.enter(cpu.pc, cpu.cycles);
+            }
+            Some(Token::RTS) => {
+                for _ in self.calls.ret(sp) {
+                    self.leave(cpu.cycles);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, address: u16, cycles: u64) {
+        self.frames.push(Frame {
+            address,
+            start_cycles: cycles,
+            child_cycles: 0,
+        });
+    }
+
+    fn leave(&mut self, cycles: u64) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let inclusive = cycles - frame.start_cycles;
+        let profile = self.profiles.entry(frame.address).or_default();
+        profile.calls += 1;
+        profile.inclusive += inclusive;
+        profile.exclusive += inclusive - frame.child_cycles;
+        if let Some(parent) = self.frames.last_mut() {
+            parent.child_cycles += inclusive;
+        }
+    }
+
+    /// Returns the name a routine is reported under.
+    fn name(&self, address: u16) -> String {
+        match self.names.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    /// Returns the measured cycles for a routine, by label or `$XXXX` name.
+    pub fn profile(&self, name: &str) -> Option<&RoutineProfile> {
+        self.profiles
+            .iter()
+            .find(|(address, _)| self.name(**address) == name)
+            .map(|(_, profile)| profile)
+    }
+
+    /// Formats the measured cycles as a table, most expensive routine (by inclusive cycles) first.
+    pub fn report(&self) -> String {
+        let mut rows: Vec<(String, &RoutineProfile)> = self
+            .profiles
+            .iter()
+            .map(|(address, profile)| (self.name(*address), profile))
+            .collect();
+        rows.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+
+        let mut report = format!(
+            "{:<20} {:>8} {:>12} {:>12}\n",
+            "ROUTINE", "CALLS", "INCLUSIVE", "EXCLUSIVE"
+        );
+        for (name, profile) in rows {
+            report.push_str(&format!(
+                "{:<20} {:>8} {:>12} {:>12}\n",
+                name, profile.calls, profile.inclusive, profile.exclusive
+            ));
+        }
+        report
+    }
+}
@@ -0,0 +1,615 @@
+use crate::asm_error::AsmError;
+use crate::asm_parser::{Assembler, Widening};
+use crate::machine::MachineProfile;
+use crate::memory::Memory;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of a project manifest, looked for in the current directory and the ones above it.
+pub const MANIFEST: &str = "r6502.toml";
+
+/// Deepest `.include`s may nest, which stops a file that includes itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An error found while reading or building a project.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProjectError {
+    pub line: usize, // 1-based line of the manifest the error was found on, or 0 if none applies
+    pub message: String,
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+/// A block of the program placed at a fixed address.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Segment {
+    pub name: String,
+    pub address: u16,
+    pub size: Option<u32>, // Most bytes the segment may take, e.g. 0x8000 for a 32K ROM
+    pub files: Vec<String>, // Assembled in order, one after the other; relative to the manifest
+}
+
+/// How much of a segment a build used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentUsage {
+    pub name: String,
+    pub address: u16,
+    pub used: u32, // Bytes from `address` through the last one the segment emitted
+    pub size: Option<u32>, // The segment's limit, if it has one
+}
+
+/// The result of building a project.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProjectBuild {
+    pub entry_point: Option<u16>, // Address of the `entry` label, or of the first byte emitted
+    pub segments: Vec<SegmentUsage>,
+    pub warnings: Vec<String>, // Widened zero-page operands, as `<file>:<widening>`
+}
+
+/// A multi-file 6502 program, described by an `r6502.toml` manifest, so it can be built and run
+/// without a long command line:
+///
+/// ```toml
+/// name = "blink"
+/// machine = "eater"            # A built-in profile, or machine_file = "board.toml"
+/// entry = "reset"              # Label the program starts at (default: its first byte)
+/// output = "build/blink.bin"   # The default is build/<name>.bin
+/// fill = 0xFF                  # Value of the bytes between segments (default 0)
+/// include = ["lib"]            # Where `.include "file"` looks after the including file's directory
+///
+/// [[segment]]
+/// name = "code"
+/// address = 0x8000
+/// size = 0x7FFA                # Optional; building fails if the segment outgrows it
+/// files = ["src/main.asm", "src/lcd.asm"]
+///
+/// [[segment]]
+/// name = "vectors"
+/// address = 0xFFFA
+/// files = ["src/vectors.asm"]
+/// ```
+///
+/// The segments are assembled as one program, each starting with an `.org` at its address, so
+/// labels defined in any file can be used in all the others. A line `.include "file"` in a source
+/// file is replaced by the lines of that file. Paths are relative to the manifest.
+///
+/// # Example
+/// ```rust
+/// use r_6502::project::Project;
+/// use r_6502::{Assembler, Memory};
+///
+/// let name = format!("r6502-project-example-{}", std::process::id());
+/// let directory = std::env::temp_dir().join(name);
+/// std::fs::create_dir_all(directory.join("lib")).unwrap();
+/// std::fs::write(directory.join("main.asm"), "start:\nJSR inc_x\nJMP start\n").unwrap();
+/// std::fs::write(directory.join("io.asm"), ".include \"util.asm\" ; From lib\n").unwrap();
+/// std::fs::write(directory.join("lib/util.asm"), "inc_x:\nINX\nRTS\n").unwrap();
+/// std::fs::write(directory.join("vectors.asm"), ".word start\n.word start\n").unwrap();
+/// std::fs::write(
+///     directory.join("r6502.toml"),
+///     "name = \"demo\"\nentry = \"start\"\ninclude = [\"lib\"]\n\
+///      [[segment]]\nname = \"code\"\naddress = 0x8000\nfiles = [\"main.asm\", \"io.asm\"]\n\
+///      [[segment]]\nname = \"vectors\"\naddress = 0xFFFC\nfiles = [\"vectors.asm\"]\n",
+/// )
+/// .unwrap();
+///
+/// let project = Project::load_file(directory.join("r6502.toml").to_str().unwrap()).unwrap();
+/// let mut memory = Memory::new();
+/// let mut assembler = Assembler::new();
+/// let build = project.build(&mut assembler, &mut memory).unwrap();
+/// assert_eq!(build.entry_point, Some(0x8000));
+/// assert_eq!(assembler.symbol_table()["inc_x"], 0x8006);
+/// assert_eq!((build.segments[0].used, build.segments[1].used), (8, 4));
+/// assert_eq!(memory.reset_vector(), 0x8000);
+/// std::fs::remove_dir_all(&directory).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub name: String,
+    pub machine: Option<String>, // Name of a built-in machine profile
+    pub machine_file: Option<String>, // TOML machine profile, relative to the manifest
+    pub entry: Option<String>,   // Label the program starts at
+    pub output: Option<String>,  // Image `build` writes, relative to the manifest
+    #[serde(default)]
+    pub fill: u8,
+    #[serde(default)]
+    pub include: Vec<String>, // Directories `.include` searches, relative to the manifest
+    pub segment: Vec<Segment>,
+}
+
+impl Project {
+    /// Parses a manifest from TOML. Paths in it are left as they are.
+    ///
+    /// # Errors
+    /// Returns a `ProjectError` if the text is not valid TOML, a key is missing or unknown, a value
+    /// has the wrong type or does not fit, there are no segments or a segment has no files, or both
+    /// `machine` and `machine_file` are given.
+    pub fn from_toml(text: &str) -> Result<Self, ProjectError> {
+        let project: Project = toml::from_str(text).map_err(|e| ProjectError {
+            line: e
+                .span()
+                .map(|span| text[..span.start].lines().count().max(1))
+                .unwrap_or(0),
+            message: e.message().to_string(),
+        })?;
+        let error = |message: String| ProjectError { line: 0, message };
+        if project.segment.is_empty() {
+            return Err(error(String::from("the project has no [[segment]]")));
+        }
+        if let Some(segment) = project
+            .segment
+            .iter()
+            .find(|segment| segment.files.is_empty())
+        {
+            return Err(error(format!("segment `{}` has no files", segment.name)));
+        }
+        if project.machine.is_some() && project.machine_file.is_some() {
+            return Err(error(String::from(
+                "only one of machine and machine_file can be given",
+            )));
+        }
+        Ok(project)
+    }
+
+    /// Reads a manifest file. Source files, include directories, the machine file and the output
+    /// in it are taken relative to the manifest.
+    ///
+    /// # Errors
+    /// Returns a `ProjectError` with line 0 if the file cannot be read, or the error `from_toml`
+    /// found.
+    pub fn load_file(path: &str) -> Result<Self, ProjectError> {
+        let text = fs::read_to_string(path).map_err(|e| ProjectError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        let mut project = Self::from_toml(&text)?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let resolve = |file: &String| directory.join(file).display().to_string();
+        for segment in &mut project.segment {
+            segment.files = segment.files.iter().map(resolve).collect();
+        }
+        project.include = project.include.iter().map(resolve).collect();
+        project.machine_file = project.machine_file.as_ref().map(resolve);
+        project.output = Some(resolve(&project.output()));
+        Ok(project)
+    }
+
+    /// Returns the path of the manifest in `directory` or the nearest directory above it that has
+    /// one, if any does.
+    pub fn find(directory: &Path) -> Option<PathBuf> {
+        directory
+            .ancestors()
+            .map(|directory| directory.join(MANIFEST))
+            .find(|path| path.is_file())
+    }
+
+    /// Returns the path of the image `build` writes: `output`, or `build/<name>.bin`.
+    pub fn output(&self) -> String {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => format!("build/{}.bin", self.name),
+        }
+    }
+
+    /// Returns the machine profile the project runs on, if it names one.
+    ///
+    /// # Errors
+    /// Returns a `ProjectError` if `machine` is not a built-in profile, or the `machine_file`
+    /// cannot be loaded.
+    pub fn machine_profile(&self) -> Result<Option<MachineProfile>, ProjectError> {
+        let error = |message: String| ProjectError { line: 0, message };
+        if let Some(name) = &self.machine {
+            return match MachineProfile::builtin(name) {
+                Some(machine) => Ok(Some(machine)),
+                None => Err(error(format!(
+                    "unknown machine {} (built in: {})",
+                    name,
+                    MachineProfile::builtin_names().join(", ")
+                ))),
+            };
+        }
+        match &self.machine_file {
+            Some(path) => MachineProfile::load_file(path)
+                .map(Some)
+                .map_err(|e| error(format!("{}: {}", path, e))),
+            None => Ok(None),
+        }
+    }
+
+    /// Assembles the project's segments into `mem` with `assembler`, which keeps the symbol
+    /// table, markers and debug info of the whole program. The debug info's lines are those of
+    /// the combined source, not of the files.
+    ///
+    /// # Errors
+    /// Returns a `ProjectError` with line 0 if a file cannot be read or does not assemble (the
+    /// message giving its file and line), `entry` is not a label, a segment outgrows its `size`,
+    /// or two segments overlap.
+    pub fn build(
+        &self,
+        assembler: &mut Assembler,
+        mem: &mut Memory,
+    ) -> Result<ProjectBuild, ProjectError> {
+        let error = |message: String| ProjectError { line: 0, message };
+        let mut source = Source::default();
+        let mut segment_lines = Vec::new();
+        for segment in &self.segment {
+            let first = source.lines.len();
+            source.push(format!(".org ${:04X}", segment.address), None);
+            for file in &segment.files {
+                self.read_source(Path::new(file), None, 0, &mut source)?;
+            }
+            segment_lines.push(first + 1..source.lines.len() + 1);
+        }
+
+        let mut curr_mem_add = self.segment[0].address;
+        if let Err(e) = assembler.assemble(&source.lines.join("\n"), mem, &mut curr_mem_add) {
+            return Err(error(match e {
+                AsmError::Syntax {
+                    kind,
+                    line,
+                    column,
+                    text,
+                } => {
+                    let (file, line) = source.locate(line);
+                    format!("{}:{}:{}: {} `{}`", file, line, column, kind, text)
+                }
+                io => io.to_string(),
+            }));
+        }
+        let warnings = assembler
+            .widenings()
+            .iter()
+            .map(|widening| {
+                let (file, line) = source.locate(widening.line);
+                let widening = Widening {
+                    line,
+                    ..widening.clone()
+                };
+                format!("{}:{}", file, widening)
+            })
+            .collect();
+
+        let mut segments = Vec::new();
+        for (segment, lines) in self.segment.iter().zip(segment_lines) {
+            let end = assembler
+                .debug_info()
+                .lines()
+                .filter(|(line, _, _)| lines.contains(line))
+                .map(|(_, address, length)| address as u32 + length as u32)
+                .max()
+                .unwrap_or(segment.address as u32);
+            let used = end.saturating_sub(segment.address as u32);
+            if let Some(size) = segment.size {
+                if used > size {
+                    return Err(error(format!(
+                        "segment `{}` is {} bytes, larger than its size {}",
+                        segment.name, used, size
+                    )));
+                }
+            }
+            let start = segment.address as u32;
+            if let Some(other) = segments.iter().find(|other: &&SegmentUsage| {
+                let other_start = other.address as u32;
+                start < other_start + other.used && other_start < end
+            }) {
+                return Err(error(format!(
+                    "segment `{}` overlaps segment `{}`",
+                    segment.name, other.name
+                )));
+            }
+            segments.push(SegmentUsage {
+                name: segment.name.clone(),
+                address: segment.address,
+                used,
+                size: segment.size,
+            });
+        }
+
+        let entry_point = match &self.entry {
+            Some(label) => match assembler.symbol_table().get(label) {
+                Some(address) => Some(*address),
+                None => return Err(error(format!("entry point `{}` is not a label", label))),
+            },
+            None => assembler.entry_point(),
+        };
+        if let Some(entry_point) = entry_point {
+            if mem.reset_vector() == 0 {
+                mem.set_reset_vector(entry_point);
+            }
+        }
+        Ok(ProjectBuild {
+            entry_point,
+            segments,
+            warnings,
+        })
+    }
+
+    /// Appends the lines of the source file at `path` to `source`, replacing `.include` lines with
+    /// the files they name. `from` is the file and line that included it, if one did.
+    fn read_source(
+        &self,
+        path: &Path,
+        from: Option<(usize, usize)>,
+        depth: usize,
+        source: &mut Source,
+    ) -> Result<(), ProjectError> {
+        let error = |message: String| ProjectError { line: 0, message };
+        let located = |message: String| match from {
+            Some((file, line)) => error(format!("{}:{}: {}", source.files[file], line, message)),
+            None => error(message),
+        };
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(located(format!(
+                "includes nested more than {} deep",
+                MAX_INCLUDE_DEPTH
+            )));
+        }
+        let text =
+            fs::read_to_string(path).map_err(|e| located(format!("{}: {}", path.display(), e)))?;
+        let file = source.files.len();
+        source.files.push(path.display().to_string());
+        for (index, line) in text.lines().enumerate() {
+            let included = match include_directive(line) {
+                Some(operand) => operand,
+                None => {
+                    source.push(line.to_string(), Some((file, index + 1)));
+                    continue;
+                }
+            };
+            let location = format!("{}:{}", source.files[file], index + 1);
+            let name = match included
+                .strip_prefix('"')
+                .and_then(|name| name.strip_suffix('"'))
+            {
+                Some(name) if !name.is_empty() => name,
+                _ => {
+                    return Err(error(format!(
+                        "{}: expected `.include \"<file>\"`, found `{}`",
+                        location,
+                        line.trim()
+                    )))
+                }
+            };
+            let directory = path.parent().unwrap_or(Path::new(""));
+            let found = std::iter::once(directory)
+                .chain(self.include.iter().map(Path::new))
+                .map(|directory| directory.join(name))
+                .find(|candidate| candidate.is_file());
+            match found {
+                Some(found) => {
+                    self.read_source(&found, Some((file, index + 1)), depth + 1, source)?
+                }
+                None => {
+                    return Err(error(format!(
+                        "{}: cannot find `{}` to include",
+                        location, name
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The combined source of a project, with the file and line each line came from.
+#[derive(Default)]
+struct Source {
+    files: Vec<String>,
+    lines: Vec<String>,
+    origins: Vec<Option<(usize, usize)>>, // Index in `files` and 1-based line, or `None` if generated
+}
+
+impl Source {
+    fn push(&mut self, line: String, origin: Option<(usize, usize)>) {
+        self.lines.push(line);
+        self.origins.push(origin);
+    }
+
+    /// Returns the file and line the 1-based combined `line` came from, or `("<project>", 0)` for
+    /// a generated line.
+    fn locate(&self, line: usize) -> (&str, usize) {
+        match self.origins.get(line.wrapping_sub(1)) {
+            Some(Some((file, line))) => (&self.files[*file], *line),
+            _ => ("<project>", 0),
+        }
+    }
+}
+
+/// Returns the operand of an `.include` line (in any case), if `line` is one.
+fn include_directive(line: &str) -> Option<&str> {
+    let code = line.split(';').next().unwrap_or("").trim();
+    if code.len() > 8 && code[..8].eq_ignore_ascii_case(".include") {
+        let operand = &code[8..];
+        if operand.starts_with(char::is_whitespace) {
+            return Some(operand.trim());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A project in a temporary directory of its own, removed when dropped.
+    struct TempProject {
+        directory: PathBuf,
+    }
+
+    impl TempProject {
+        /// Creates the directory for the test called `name`, with `files` as (path, contents).
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let directory =
+                std::env::temp_dir().join(format!("r6502-project-{}-{}", name, std::process::id()));
+            for (path, contents) in files {
+                let path = directory.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            TempProject { directory }
+        }
+
+        /// Loads the manifest and builds the project into fresh memory.
+        fn build(&self) -> Result<ProjectBuild, ProjectError> {
+            let manifest = self.directory.join(MANIFEST);
+            let project = Project::load_file(manifest.to_str().unwrap())?;
+            project.build(&mut Assembler::new(), &mut Memory::new())
+        }
+
+        /// Builds the project, expecting an error, and returns its message with the directory
+        /// taken out.
+        fn build_error(&self) -> String {
+            let message = self.build().unwrap_err().message;
+            let directory = format!("{}{}", self.directory.display(), std::path::MAIN_SEPARATOR);
+            message.replace(&directory, "")
+        }
+    }
+
+    impl Drop for TempProject {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    /// A manifest with one segment at `$8000` assembling `files`.
+    fn manifest(files: &str) -> String {
+        format!(
+            "name = \"test\"\n[[segment]]\nname = \"code\"\naddress = 0x8000\nfiles = [{}]\n",
+            files
+        )
+    }
+
+    #[test]
+    fn a_missing_source_file_is_an_error() {
+        let project = TempProject::new("missing", &[(MANIFEST, &manifest("\"main.asm\""))]);
+        assert!(project.build_error().starts_with("main.asm: "));
+    }
+
+    #[test]
+    fn a_recursive_include_is_an_error() {
+        let project = TempProject::new(
+            "recursive",
+            &[
+                (MANIFEST, &manifest("\"main.asm\"")),
+                ("main.asm", "NOP\n.include \"loop.asm\"\n"),
+                ("loop.asm", "INX\n.include \"loop.asm\" ; again\n"),
+            ],
+        );
+        assert_eq!(
+            project.build_error(),
+            "loop.asm:2: includes nested more than 16 deep"
+        );
+
+        let project = TempProject::new(
+            "unfound",
+            &[
+                (MANIFEST, &manifest("\"main.asm\"")),
+                ("main.asm", "NOP\n.include \"lib.asm\"\n"),
+            ],
+        );
+        assert_eq!(
+            project.build_error(),
+            "main.asm:2: cannot find `lib.asm` to include"
+        );
+    }
+
+    #[test]
+    fn errors_in_sources_name_their_file_and_line() {
+        let project = TempProject::new(
+            "syntax",
+            &[
+                (MANIFEST, &manifest("\"main.asm\", \"lib.asm\"")),
+                ("main.asm", "NOP\n"),
+                ("lib.asm", "INX\nLDQ #1\n"),
+            ],
+        );
+        assert!(project.build_error().starts_with("lib.asm:2:1: "));
+    }
+
+    #[test]
+    fn overlapping_segments_are_an_error() {
+        let manifest = "name = \"test\"\n\
+            [[segment]]\nname = \"code\"\naddress = 0x8000\nfiles = [\"code.asm\"]\n\
+            [[segment]]\nname = \"data\"\naddress = 0x8002\nfiles = [\"data.asm\"]\n";
+        let project = TempProject::new(
+            "overlap",
+            &[
+                (MANIFEST, manifest),
+                ("code.asm", "LDA $1234\n"),
+                ("data.asm", ".byte 1\n"),
+            ],
+        );
+        assert_eq!(
+            project.build_error(),
+            "segment `data` overlaps segment `code`"
+        );
+
+        // Touching is fine
+        let project = TempProject::new(
+            "touching",
+            &[
+                (MANIFEST, &manifest.replace("0x8002", "0x8003")),
+                ("code.asm", "LDA $1234\n"),
+                ("data.asm", ".byte 1\n"),
+            ],
+        );
+        let segments = project.build().unwrap().segments;
+        assert_eq!((segments[0].used, segments[1].used), (3, 1));
+    }
+
+    #[test]
+    fn a_segment_larger_than_its_size_is_an_error() {
+        let manifest = manifest("\"main.asm\"").replace("address", "size = 2\naddress");
+        let project = TempProject::new(
+            "overflow",
+            &[(MANIFEST, &manifest), ("main.asm", "JMP $8000\n")],
+        );
+        assert_eq!(
+            project.build_error(),
+            "segment `code` is 3 bytes, larger than its size 2"
+        );
+    }
+
+    #[test]
+    fn an_unknown_entry_label_is_an_error() {
+        let manifest =
+            manifest("\"main.asm\"").replace("[[segment]]", "entry = \"main\"\n[[segment]]");
+        let project = TempProject::new(
+            "entry",
+            &[(MANIFEST, &manifest), ("main.asm", "start:\nNOP\n")],
+        );
+        assert_eq!(project.build_error(), "entry point `main` is not a label");
+    }
+
+    #[test]
+    fn manifests_without_segments_or_with_two_machines_are_errors() {
+        let error = |text: &str| Project::from_toml(text).unwrap_err().message;
+        assert_eq!(
+            error("name = \"test\"\nsegment = []\n"),
+            "the project has no [[segment]]"
+        );
+        assert_eq!(error(&manifest("")), "segment `code` has no files");
+        assert_eq!(
+            error(&format!(
+                "machine = \"eater\"\nmachine_file = \"m.toml\"\n{}",
+                manifest("\"a\"")
+            )),
+            "only one of machine and machine_file can be given"
+        );
+        assert_eq!(Project::from_toml("name = 1\n").unwrap_err().line, 1);
+    }
+}
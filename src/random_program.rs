@@ -0,0 +1,387 @@
+use crate::asm_runner::undocumented_mode;
+use crate::cpu::CPU;
+use crate::functional_test::FunctionalTest;
+use crate::opcode_table::opcode_info;
+use crate::token::AddressingMode;
+
+/// Address generated programs are loaded at and start from.
+pub const ORIGIN: u16 = 0x0600;
+
+/// Page the generated loads and stores address, so they never reach the program itself. Indexed
+/// and indirect accesses stay within this page and the next.
+pub const DATA_PAGE: u8 = 0x02;
+
+/// Most random instructions a program can have, so that it fits below the vectors.
+pub const MAX_INSTRUCTIONS: usize = 4096;
+
+/// Most instructions a branch or jump skips, which keeps every branch offset in range.
+const MAX_SKIP: usize = 8;
+
+const PHA: u8 = 0x48;
+const PLA: u8 = 0x68;
+const PLP: u8 = 0x28;
+const LDA_IMMEDIATE: u8 = 0xA9;
+const LDX_IMMEDIATE: u8 = 0xA2;
+const LDY_IMMEDIATE: u8 = 0xA0;
+const STA_ZERO_PAGE: u8 = 0x85;
+const STA_ZERO_PAGE_X: u8 = 0x95;
+const JMP_ABSOLUTE: u8 = 0x4C;
+
+/// The SplitMix64 generator: small, fast, and good enough to pick opcodes and operands.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.next() as u8
+    }
+
+    /// Returns a number in `0..bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+/// One generated instruction, with any setup it needs.
+struct Item {
+    bytes: Vec<u8>,
+    instructions: u64,     // Instructions in `bytes`, setup included
+    target: Option<usize>, // Item a branch or jump ending `bytes` goes to
+}
+
+/// A generated program, ending in a `JMP *` trap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RandomProgram {
+    pub bytes: Vec<u8>,        // Loaded at `ORIGIN`
+    pub trap: u16,             // Address of the final `JMP *`
+    pub max_instructions: u64, // Most instructions executed up to and including the trap
+    pub undocumented: bool,    // Whether the program uses stable undocumented opcodes
+}
+
+impl RandomProgram {
+    /// Loads the program into `cpu` and points the program counter at it, enabling undocumented
+    /// opcodes if the program uses them.
+    pub fn load(&self, cpu: &mut CPU) {
+        let start = ORIGIN as usize;
+        cpu.memory.data[start..start + self.bytes.len()].copy_from_slice(&self.bytes);
+        cpu.pc = ORIGIN;
+        if self.undocumented {
+            cpu.illegal_opcodes = true;
+        }
+    }
+
+    /// Returns a test that passes when the program runs through to its trap.
+    pub fn functional_test(&self) -> FunctionalTest {
+        FunctionalTest {
+            start: ORIGIN,
+            success: self.trap,
+            max_instructions: self.max_instructions,
+        }
+    }
+}
+
+/// Generates random but valid programs from a fixed seed, for stress tests and for differential
+/// testing with `Lockstep`.
+///
+/// Every opcode is equally likely, so rarely used instructions, addressing modes and flag
+/// combinations (decimal mode, page-crossing indexes, `PLP` of arbitrary status bytes) get as much
+/// exercise as common ones. Programs start by setting `A`, `X`, `Y` and `P` to random values, and
+/// are guaranteed to run through to the `JMP *` trap at their end:
+///
+/// - Opcodes that halt the CPU, `BRK`, `JSR`, `RTS`, `RTI` and `JMP ($nnnn)` are never generated.
+/// - Branches and `JMP`s only go forward, to the start of a later instruction or to the trap.
+/// - Loads and stores address the zero page, the stack, or `DATA_PAGE`; indirect accesses have
+///   their pointer's high byte set to `DATA_PAGE` first. Nothing writes to the program.
+///
+/// The same seed and settings always generate the same programs.
+///
+/// # Example
+/// ```rust
+/// use r_6502::functional_test::TestOutcome;
+/// use r_6502::lockstep::{run_memory_step, Lockstep};
+/// use r_6502::random_program::{ProgramGenerator, ORIGIN};
+/// use r_6502::CPU;
+///
+/// let program = ProgramGenerator::new(42).generate();
+/// assert_eq!(program, ProgramGenerator::new(42).generate());
+///
+/// let mut cpu = CPU::new();
+/// program.load(&mut cpu);
+/// let outcome = program.functional_test().run(&mut cpu);
+/// assert!(matches!(outcome, TestOutcome::Passed { .. }));
+///
+/// let mut lockstep = Lockstep::new(&program.bytes, ORIGIN, run_memory_step, run_memory_step);
+/// lockstep.run(program.max_instructions).unwrap();
+/// assert_eq!(lockstep.left.pc, program.trap);
+/// ```
+pub struct ProgramGenerator {
+    pub instructions: usize, // Random instructions per program, not counting setup
+    pub undocumented: bool,  // Also generate the stable undocumented opcodes
+    rng: SplitMix64,
+}
+
+impl ProgramGenerator {
+    /// Creates a generator of 64-instruction programs of documented opcodes.
+    pub fn new(seed: u64) -> Self {
+        ProgramGenerator {
+            instructions: 64,
+            undocumented: false,
+            rng: SplitMix64(seed),
+        }
+    }
+
+    /// Generates the next program.
+    ///
+    /// # Panics
+    /// - If `instructions` is more than `MAX_INSTRUCTIONS`.
+    pub fn generate(&mut self) -> RandomProgram {
+        assert!(
+            self.instructions <= MAX_INSTRUCTIONS,
+            "Programs are limited to {} instructions",
+            MAX_INSTRUCTIONS
+        );
+        let opcodes = self.opcodes();
+        let mut program = vec![
+            LDA_IMMEDIATE,
+            self.rng.byte(),
+            PHA,
+            LDA_IMMEDIATE,
+            self.rng.byte(),
+            LDX_IMMEDIATE,
+            self.rng.byte(),
+            LDY_IMMEDIATE,
+            self.rng.byte(),
+            PLP,
+        ];
+        let mut max_instructions = 6;
+        let items: Vec<Item> = (0..self.instructions)
+            .map(|index| {
+                let opcode = opcodes[self.rng.below(opcodes.len())];
+                self.item(opcode, index)
+            })
+            .collect();
+
+        // Lay the items out, then point branches and jumps at the items they go to
+        let mut addresses = Vec::with_capacity(items.len() + 1);
+        let mut address = ORIGIN + program.len() as u16;
+        for item in &items {
+            addresses.push(address);
+            address += item.bytes.len() as u16;
+        }
+        let trap = address;
+        addresses.push(trap);
+        for (index, item) in items.into_iter().enumerate() {
+            let mut bytes = item.bytes;
+            if let Some(target) = item.target {
+                let target = addresses[target.min(self.instructions)];
+                let end = addresses[index] + bytes.len() as u16;
+                if bytes[0] == JMP_ABSOLUTE {
+                    bytes[1..].copy_from_slice(&target.to_le_bytes());
+                } else {
+                    bytes[1] = (target - end) as u8; // Forward, so at most 127
+                }
+            }
+            program.extend_from_slice(&bytes);
+            max_instructions += item.instructions;
+        }
+        program.push(JMP_ABSOLUTE);
+        program.extend_from_slice(&trap.to_le_bytes());
+        RandomProgram {
+            bytes: program,
+            trap,
+            max_instructions: max_instructions + 1,
+            undocumented: self.undocumented,
+        }
+    }
+
+    /// Returns the opcodes programs are made of.
+    fn opcodes(&self) -> Vec<u8> {
+        (0..=0xFF)
+            .filter(|&opcode| match opcode_info(opcode) {
+                Some(info) => {
+                    !matches!(info.mnemonic, "BRK" | "JSR" | "RTS" | "RTI")
+                        && info.mode != AddressingMode::Indirect
+                }
+                None => self.undocumented && undocumented_mode(opcode).is_some(),
+            })
+            .collect()
+    }
+
+    /// Generates an instruction with the given opcode, the `index`th of the program.
+    fn item(&mut self, opcode: u8, index: usize) -> Item {
+        let mode = match opcode_info(opcode) {
+            Some(info) => info.mode,
+            None => undocumented_mode(opcode).unwrap_or(AddressingMode::Implied),
+        };
+        let mut item = Item {
+            bytes: vec![opcode],
+            instructions: 1,
+            target: None,
+        };
+        match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => {}
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY => item.bytes.push(self.rng.byte()),
+            AddressingMode::Absolute if opcode == JMP_ABSOLUTE => {
+                item.bytes.extend_from_slice(&[0, 0]);
+                item.target = Some(index + 1 + self.rng.below(MAX_SKIP + 1));
+            }
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                item.bytes.extend_from_slice(&[self.rng.byte(), DATA_PAGE]);
+            }
+            AddressingMode::Relative => {
+                item.bytes.push(0);
+                item.target = Some(index + 1 + self.rng.below(MAX_SKIP + 1));
+            }
+            AddressingMode::IndexedIndirect | AddressingMode::IndirectIndexed => {
+                // Point the pointer's high byte at the data page, keeping A
+                let pointer = self.rng.byte();
+                let store = if mode == AddressingMode::IndexedIndirect {
+                    STA_ZERO_PAGE_X
+                } else {
+                    STA_ZERO_PAGE
+                };
+                item.bytes = vec![
+                    PHA,
+                    LDA_IMMEDIATE,
+                    DATA_PAGE,
+                    store,
+                    pointer.wrapping_add(1),
+                    PLA,
+                    opcode,
+                    pointer,
+                ];
+                item.instructions = 5;
+            }
+            AddressingMode::Indirect => unreachable!("JMP ($nnnn) is never generated"),
+        }
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functional_test::TestOutcome;
+    use std::collections::BTreeSet;
+
+    /// Returns the addressing mode of a generated opcode.
+    fn mode(opcode: u8) -> AddressingMode {
+        match opcode_info(opcode) {
+            Some(info) => info.mode,
+            None => undocumented_mode(opcode).unwrap(),
+        }
+    }
+
+    /// Decodes `program` into the offset and bytes of each instruction after the setup, checking
+    /// the setup and the trap on the way.
+    fn decode(program: &RandomProgram) -> Vec<(usize, &[u8])> {
+        let bytes = &program.bytes;
+        let setup = [
+            LDA_IMMEDIATE,
+            PHA,
+            LDA_IMMEDIATE,
+            LDX_IMMEDIATE,
+            LDY_IMMEDIATE,
+            PLP,
+        ];
+        assert_eq!([0, 2, 3, 5, 7, 9].map(|offset| bytes[offset]), setup);
+        let trap = (program.trap - ORIGIN) as usize;
+        assert_eq!(
+            bytes[trap..],
+            [JMP_ABSOLUTE, bytes[trap + 1], bytes[trap + 2]]
+        );
+        assert_eq!(
+            u16::from_le_bytes([bytes[trap + 1], bytes[trap + 2]]),
+            program.trap
+        );
+
+        let mut instructions = Vec::new();
+        let mut offset = 10;
+        while offset < trap {
+            let length = 1 + mode(bytes[offset]).operand_size() as usize;
+            instructions.push((offset, &bytes[offset..offset + length]));
+            offset += length;
+        }
+        assert_eq!(offset, trap, "the last instruction runs into the trap");
+        instructions
+    }
+
+    #[test]
+    fn generated_programs_keep_off_the_program() {
+        // Indexed data accesses reach into the page after `DATA_PAGE`, still below the program
+        assert!(((DATA_PAGE as u16 + 2) << 8) <= ORIGIN);
+        let mut indirect = 0;
+        for seed in 0..500 {
+            let mut generator = ProgramGenerator::new(seed);
+            generator.instructions = 200;
+            generator.undocumented = seed % 2 == 1;
+            let program = generator.generate();
+            let instructions = decode(&program);
+            let starts: BTreeSet<u16> = instructions
+                .iter()
+                .map(|(offset, _)| ORIGIN + *offset as u16)
+                .chain([program.trap])
+                .collect();
+
+            for (offset, bytes) in &instructions {
+                let address = ORIGIN + *offset as u16;
+                let end = address + bytes.len() as u16;
+                let context = format!("seed {}, ${:04X}: {:02X?}", seed, address, bytes);
+                match mode(bytes[0]) {
+                    AddressingMode::Absolute if bytes[0] == JMP_ABSOLUTE => {
+                        let target = u16::from_le_bytes([bytes[1], bytes[2]]);
+                        assert!(target >= end && starts.contains(&target), "{}", context);
+                    }
+                    AddressingMode::Absolute
+                    | AddressingMode::AbsoluteX
+                    | AddressingMode::AbsoluteY => {
+                        assert_eq!(bytes[2], DATA_PAGE, "{}", context);
+                    }
+                    AddressingMode::Relative => {
+                        let target = end.wrapping_add(bytes[1] as i8 as u16);
+                        assert!(target >= end && starts.contains(&target), "{}", context);
+                    }
+                    AddressingMode::IndexedIndirect | AddressingMode::IndirectIndexed => {
+                        let store = match mode(bytes[0]) {
+                            AddressingMode::IndexedIndirect => STA_ZERO_PAGE_X,
+                            _ => STA_ZERO_PAGE,
+                        };
+                        let pointer_high = bytes[1].wrapping_add(1);
+                        let setup = [PHA, LDA_IMMEDIATE, DATA_PAGE, store, pointer_high, PLA];
+                        assert_eq!(program.bytes[offset - 6..*offset], setup, "{}", context);
+                        indirect += 1;
+                    }
+                    AddressingMode::Indirect => panic!("JMP ($nnnn) in {}", context),
+                    _ => {}
+                }
+            }
+
+            let mut cpu = CPU::new();
+            program.load(&mut cpu);
+            let outcome = program.functional_test().run(&mut cpu);
+            assert!(
+                matches!(outcome, TestOutcome::Passed { .. }),
+                "seed {}",
+                seed
+            );
+            let start = ORIGIN as usize;
+            assert_eq!(
+                cpu.memory.data[start..start + program.bytes.len()],
+                program.bytes,
+                "seed {}",
+                seed
+            );
+        }
+        assert!(indirect > 1000);
+    }
+}
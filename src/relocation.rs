@@ -0,0 +1,208 @@
+use crate::asm_error::AsmError;
+use crate::asm_parser::Assembler;
+use crate::memory::Memory;
+use std::fmt;
+
+/// First bytes of a relocatable file.
+pub const RELOCATABLE_MAGIC: &[u8; 4] = b"R65\x01";
+
+/// Second origin a relocatable blob is assembled at, to find the bytes that depend on its
+/// address. Both bytes differ from `$0000` so low and high bytes of addresses show up.
+const SHIFTED_ORIGIN: u16 = 0x0101;
+
+/// An error found while building or loading a relocatable blob.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelocationError {
+    Asm(AsmError),  // The source does not assemble
+    Format(String), // The file or the code cannot be relocated
+}
+
+impl fmt::Display for RelocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelocationError::Asm(e) => write!(f, "{}", e),
+            RelocationError::Format(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RelocationError {}
+
+/// Position-independent code with a table of the absolute addresses in it, so a loader can place
+/// it at any free address.
+///
+/// Small utilities such as monitor extensions and drivers are assembled at `$0000`; each entry of
+/// `relocations` is the offset of a 16-bit little-endian address within `code` that refers to the
+/// blob itself, and gets the load address added to it. Addresses outside the blob, such as ROM
+/// routines or I/O registers, are left alone.
+///
+/// The file format is the `RELOCATABLE_MAGIC` bytes, the length of the code and the number of
+/// relocations (both 16-bit little-endian), the code, then the relocation offsets, 16-bit
+/// little-endian each.
+///
+/// # Example
+/// ```rust
+/// use r_6502::relocation::Relocatable;
+/// use r_6502::Memory;
+///
+/// // Calls a ROM routine at $FFD2 and jumps within itself
+/// let blob = Relocatable::assemble("loop:\nJSR $FFD2\nJMP loop").unwrap();
+/// assert_eq!(blob.relocations, vec![4]);
+///
+/// let mut memory = Memory::new();
+/// blob.load(&mut memory, 0xC000).unwrap();
+/// assert_eq!(&memory.data[0xC000..0xC006], &[0x20, 0xD2, 0xFF, 0x4C, 0x00, 0xC0]);
+///
+/// let bytes = blob.to_bytes();
+/// assert_eq!(Relocatable::parse(&bytes).unwrap(), blob);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Relocatable {
+    pub code: Vec<u8>,         // The code as assembled at `$0000`
+    pub relocations: Vec<u16>, // Offsets of the absolute addresses to relocate
+}
+
+impl Relocatable {
+    /// Assembles source into a relocatable blob.
+    ///
+    /// The source is assembled twice, at `$0000` and at `$0101`, and the relocations are the
+    /// 16-bit words that differ by exactly `$0101` between the two.
+    ///
+    /// # Errors
+    /// - `RelocationError::Asm`: If the source does not assemble.
+    /// - `RelocationError::Format`: If the source uses `.org`, or a byte depends on where the blob
+    ///   is loaded without being part of a whole address (e.g. the high byte of a label on its
+    ///   own), which a word relocation cannot fix up.
+    pub fn assemble(source: &str) -> Result<Self, RelocationError> {
+        let base = assemble_at(source, 0x0000)?;
+        let shifted = assemble_at(source, SHIFTED_ORIGIN)?;
+        Self::from_images(&base, &shifted)
+    }
+
+    /// Builds a relocatable blob from the same code assembled at `$0000` and at `$0101`.
+    ///
+    /// # Errors
+    /// Returns a `RelocationError::Format` if the images differ in length or a differing byte is
+    /// not part of an address.
+    pub fn from_images(base: &[u8], shifted: &[u8]) -> Result<Self, RelocationError> {
+        if base.len() != shifted.len() {
+            return Err(RelocationError::Format(format!(
+                "the code is {} bytes at $0000 but {} bytes at ${:04X}",
+                base.len(),
+                shifted.len(),
+                SHIFTED_ORIGIN
+            )));
+        }
+        let mut relocations = Vec::new();
+        let mut offset = 0;
+        while offset < base.len() {
+            if base[offset] == shifted[offset] {
+                offset += 1;
+                continue;
+            }
+            let word = |image: &[u8]| {
+                image
+                    .get(offset + 1)
+                    .map(|high| u16::from_le_bytes([image[offset], *high]))
+            };
+            match (word(base), word(shifted)) {
+                (Some(from), Some(to)) if to.wrapping_sub(from) == SHIFTED_ORIGIN => {
+                    relocations.push(offset as u16);
+                    offset += 2;
+                }
+                _ => {
+                    return Err(RelocationError::Format(format!(
+                        "the byte at offset ${:04X} depends on the load address but is not \
+                         part of an address",
+                        offset
+                    )))
+                }
+            }
+        }
+        Ok(Relocatable {
+            code: base.to_vec(),
+            relocations,
+        })
+    }
+
+    /// Serializes the blob in the relocatable file format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = RELOCATABLE_MAGIC.to_vec();
+        bytes.extend_from_slice(&(self.code.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.relocations.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.code);
+        for offset in &self.relocations {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parses a file in the relocatable format.
+    ///
+    /// # Errors
+    /// Returns a `RelocationError::Format` if the file does not start with `RELOCATABLE_MAGIC`,
+    /// is truncated, or has a relocation outside the code.
+    pub fn parse(bytes: &[u8]) -> Result<Self, RelocationError> {
+        let error = |message: &str| RelocationError::Format(message.to_string());
+        let header = RELOCATABLE_MAGIC.len() + 4;
+        if bytes.len() < header || &bytes[..RELOCATABLE_MAGIC.len()] != RELOCATABLE_MAGIC {
+            return Err(error("not a relocatable file"));
+        }
+        let word = |index: usize| u16::from_le_bytes([bytes[index], bytes[index + 1]]) as usize;
+        let length = word(RELOCATABLE_MAGIC.len());
+        let count = word(RELOCATABLE_MAGIC.len() + 2);
+        if bytes.len() != header + length + count * 2 {
+            return Err(error("relocatable file is truncated or has trailing bytes"));
+        }
+        let relocations: Vec<u16> = (0..count)
+            .map(|index| word(header + length + index * 2) as u16)
+            .collect();
+        if relocations
+            .iter()
+            .any(|offset| *offset as usize + 1 >= length)
+        {
+            return Err(error("relocation outside the code"));
+        }
+        Ok(Relocatable {
+            code: bytes[header..header + length].to_vec(),
+            relocations,
+        })
+    }
+
+    /// Returns the code fixed up to run at `address`.
+    pub fn relocate(&self, address: u16) -> Vec<u8> {
+        let mut code = self.code.clone();
+        for offset in &self.relocations {
+            let offset = *offset as usize;
+            let value = u16::from_le_bytes([code[offset], code[offset + 1]]).wrapping_add(address);
+            code[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        code
+    }
+
+    /// Relocates the code to `address` and copies it into memory there.
+    ///
+    /// # Errors
+    /// Returns a `RelocationError::Format`, leaving memory untouched, if the code does not fit
+    /// between `address` and the end of memory.
+    pub fn load(&self, mem: &mut Memory, address: u16) -> Result<(), RelocationError> {
+        mem.load_bytes(&self.relocate(address), address)
+            .map_err(|e| RelocationError::Format(e.to_string()))
+    }
+}
+
+/// Assembles source at `origin`, returning the bytes from `origin` onwards.
+fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, RelocationError> {
+    let mut mem = Memory::new();
+    let mut assembler = Assembler::new();
+    let mut curr_mem_add = origin;
+    assembler
+        .assemble(source, &mut mem, &mut curr_mem_add)
+        .map_err(RelocationError::Asm)?;
+    if assembler.entry_point().unwrap_or(origin) != origin {
+        return Err(RelocationError::Format(String::from(
+            "relocatable code cannot move itself with .org",
+        )));
+    }
+    Ok(assembler.image(&mem, Some(origin), 0x00))
+}
@@ -0,0 +1,184 @@
+use crate::cpu::CPU;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+
+/// Version of the save-state format written by `SaveState::to_json`.
+pub const SAVE_STATE_VERSION: u32 = 1;
+
+/// Bytes of memory per line of hex in a saved state.
+const ROW_LENGTH: usize = 64;
+
+/// An error found while reading or writing a saved state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveStateError {
+    pub line: usize, // 1-based line the error was found on, or 0 if it is not about a line
+    pub message: String,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// A snapshot of the machine: registers, flags, cycle count and the full 64K of memory.
+///
+/// Saved states are JSON, with memory written as rows of hex so they stay readable and diff well.
+/// Only the machine itself is saved: breakpoints, guards, traces and attached devices belong to
+/// the session and are left as they are when a state is restored.
+///
+/// # Example
+/// ```rust
+/// use r_6502::save_state::SaveState;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.a = 0x42;
+/// cpu.pc = 0x0600;
+/// cpu.memory.data[0x0200] = 0xEA;
+/// let json = SaveState::capture(&cpu).to_json();
+///
+/// let mut restored = CPU::new();
+/// SaveState::from_json(&json).unwrap().restore(&mut restored);
+/// assert_eq!((restored.a, restored.pc), (0x42, 0x0600));
+/// assert_eq!(restored.memory.data[0x0200], 0xEA);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveState {
+    pub version: u32,
+    pub pc: u16,
+    pub sp: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub cycles: u64,
+    #[serde(with = "hex_rows")]
+    pub memory: Vec<u8>,
+}
+
+impl SaveState {
+    /// Takes a snapshot of the CPU.
+    pub fn capture(cpu: &CPU) -> Self {
+        SaveState {
+            version: SAVE_STATE_VERSION,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.p,
+            cycles: cpu.cycles,
+            memory: cpu.memory.data.to_vec(),
+        }
+    }
+
+    /// Puts the CPU back in the saved state.
+    ///
+    /// Memory is restored directly, without going through the bus, so ROM regions are restored
+    /// too and attached devices see no writes.
+    pub fn restore(&self, cpu: &mut CPU) {
+        cpu.pc = self.pc;
+        cpu.sp = self.sp;
+        cpu.a = self.a;
+        cpu.x = self.x;
+        cpu.y = self.y;
+        cpu.p = self.p;
+        cpu.cycles = self.cycles;
+        cpu.memory.data.copy_from_slice(&self.memory);
+    }
+
+    /// Serializes the state as JSON.
+    pub fn to_json(&self) -> String {
+        // Serializing plain integers and strings cannot fail
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// Parses a state serialized by `to_json`.
+    ///
+    /// # Errors
+    /// Returns a `SaveStateError` if the text is not a saved state, was written by a newer
+    /// version, or does not hold exactly 64K of memory.
+    pub fn from_json(text: &str) -> Result<Self, SaveStateError> {
+        let state: SaveState = serde_json::from_str(text).map_err(|e| SaveStateError {
+            line: e.line(),
+            message: e.to_string(),
+        })?;
+        if state.version > SAVE_STATE_VERSION {
+            return Err(SaveStateError {
+                line: 0,
+                message: format!("unsupported save-state version {}", state.version),
+            });
+        }
+        if state.memory.len() != 0x10000 {
+            return Err(SaveStateError {
+                line: 0,
+                message: format!(
+                    "{} bytes of memory saved instead of 65536",
+                    state.memory.len()
+                ),
+            });
+        }
+        Ok(state)
+    }
+
+    /// Writes the state to a file.
+    ///
+    /// # Errors
+    /// Returns a `SaveStateError` with line 0 if the file cannot be written.
+    pub fn save(&self, path: &str) -> Result<(), SaveStateError> {
+        fs::write(path, self.to_json()).map_err(|e| SaveStateError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })
+    }
+
+    /// Reads a state from a file.
+    ///
+    /// # Errors
+    /// Returns a `SaveStateError` with line 0 if the file cannot be read, or the error
+    /// `from_json` found.
+    pub fn load(path: &str) -> Result<Self, SaveStateError> {
+        let text = fs::read_to_string(path).map_err(|e| SaveStateError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        Self::from_json(&text)
+    }
+}
+
+/// Serializes bytes as a list of hex strings of `ROW_LENGTH` bytes each.
+mod hex_rows {
+    use super::ROW_LENGTH;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(bytes.chunks(ROW_LENGTH).map(|row| {
+            row.iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<String>()
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let rows: Vec<String> = Vec::deserialize(deserializer)?;
+        let mut bytes = Vec::new();
+        for row in rows {
+            if row.len() % 2 != 0 || !row.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(D::Error::custom(format!("invalid memory row `{}`", row)));
+            }
+            for index in (0..row.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(&row[index..index + 2], 16).unwrap_or(0));
+            }
+        }
+        Ok(bytes)
+    }
+}
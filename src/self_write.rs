@@ -0,0 +1,88 @@
+use std::fmt;
+
+/// A write an instruction made to its own bytes, while it was executing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfWrite {
+    pub pc: u16,      // Address of the instruction
+    pub address: u16, // Address written, within the instruction's bytes
+    pub value: u8,
+}
+
+impl fmt::Display for SelfWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "${:04X}: writes ${:02X} to ${:04X}, inside the instruction itself",
+            self.pc, self.value, self.address
+        )
+    }
+}
+
+/// Watches for instructions that write over their own opcode or operand bytes.
+///
+/// Self-modifying code patches instructions that run later; an instruction that stores into its
+/// own bytes is almost always an off-by-one address, such as a table placed one byte too early.
+/// `execute_instruction` tells the monitor which bytes the current instruction occupies, and
+/// writes made by the instruction, including pushes, are checked against them. Each instruction
+/// address is reported once.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0x0600;
+/// // `table` was meant to be $0605, after the code, but lands on the store's own operand
+/// let source = "table = $0603\nLDA #$00\nSTA table\nNOP";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// cpu.pc = 0x0600;
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// let event = cpu.self_writes.events()[0];
+/// assert_eq!((event.pc, event.address, event.value), (0x0602, 0x0603, 0x00));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SelfWriteMonitor {
+    footprint: Option<(u16, u16)>, // Address and length of the instruction executing
+    events: Vec<SelfWrite>,
+}
+
+impl SelfWriteMonitor {
+    pub fn new() -> Self {
+        SelfWriteMonitor {
+            footprint: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Records that the instruction occupying `length` bytes from `pc` is starting.
+    pub fn begin(&mut self, pc: u16, length: u16) {
+        self.footprint = Some((pc, length));
+    }
+
+    /// Records that the current instruction has finished, so later writes (such as an interrupt's
+    /// pushes) are not checked.
+    pub fn end(&mut self) {
+        self.footprint = None;
+    }
+
+    /// Checks a write made by the program, recording it if it falls within the current
+    /// instruction's bytes.
+    pub fn check(&mut self, address: u16, value: u8) {
+        if let Some((pc, length)) = self.footprint {
+            if address.wrapping_sub(pc) < length && !self.events.iter().any(|e| e.pc == pc) {
+                self.events.push(SelfWrite { pc, address, value });
+            }
+        }
+    }
+
+    /// Returns the writes recorded so far, oldest first.
+    pub fn events(&self) -> &[SelfWrite] {
+        &self.events
+    }
+
+    /// Forgets the writes recorded so far.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
@@ -0,0 +1,619 @@
+use crate::asm_parser::Assembler;
+use crate::asm_runner::try_execute_instruction;
+use crate::console::{Console, Encoding};
+use crate::cpu::CPU;
+use crate::limits::ResourceLimits;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Error, Message};
+
+/// Most clock cycles a single `run` request executes, so one session cannot hold up the others.
+pub const MAX_RUN_CYCLES: u64 = 10_000_000;
+
+/// Most sessions a server hosts at once.
+pub const MAX_SESSIONS: usize = 256;
+
+/// Most console output kept for a session while no client is attached to it.
+const MAX_CONSOLE_BACKLOG: usize = 64 * 1024;
+
+/// How often a connection checks for events to send while its client is quiet.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A request from a client, as a JSON object with a `command` and an optional `id` that is
+/// echoed in the reply.
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+enum Command {
+    Create {
+        console: Option<u16>, // Address to attach a console device at
+        #[serde(default)]
+        limits: ResourceLimits,
+    },
+    List,
+    Load {
+        session: u32,
+        source: Option<String>, // Assembly source, or
+        bytes: Option<Vec<u8>>, // a binary image
+        origin: Option<u16>,
+    },
+    Run {
+        session: u32,
+        cycles: u64,
+    },
+    Attach {
+        session: u32,
+    },
+    Detach {
+        session: u32,
+    },
+    Destroy {
+        session: u32,
+    },
+}
+
+/// Collects what a session's console device prints until it is sent to the attached clients.
+#[derive(Clone, Default)]
+struct ConsoleBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for ConsoleBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// One emulated machine hosted by the server.
+struct Instance {
+    cpu: CPU,
+    console: ConsoleBuffer,
+    attached: Vec<u64>, // Clients receiving the console output
+}
+
+/// The emulator sessions hosted by a server, and the clients using them.
+///
+/// Requests and replies are JSON objects. Each request names a `command`, and the reply echoes
+/// its `id`, if it has one, with `"ok": true` and the command's results, or `"ok": false` and an
+/// `error`:
+///
+/// - `{"command": "create", "console": 61441}`: creates a session, optionally with a console
+///   device at an address and `limits` (e.g. `{"max_cycles": 50000000}`, see `ResourceLimits`),
+///   and replies with its `session` number.
+/// - `{"command": "list"}`: replies with the `sessions` hosted.
+/// - `{"command": "load", "session": 1, "source": "...", "origin": 1536}`: assembles source (or
+///   loads an array of `bytes`) at `origin`, points the reset vector at it if it is not set, and
+///   resets the CPU.
+/// - `{"command": "run", "session": 1, "cycles": 100000}`: runs for up to `cycles` clock cycles
+///   (at most `MAX_RUN_CYCLES`) and replies with the `cycles` run, the `registers`, and why it
+///   `stopped` early, if it did.
+/// - `{"command": "attach", "session": 1}` and `detach`: start and stop sending the session's
+///   console output to the client, as `{"event": "console", "session": 1, "text": "..."}`
+///   messages. Output printed while no client was attached is sent on attaching.
+/// - `{"command": "destroy", "session": 1}`: removes a session.
+///
+/// Sessions are independent machines; any client can use any session, so a class can share a
+/// server with a session each.
+///
+/// # Example
+/// ```rust
+/// use r_6502::server::SessionManager;
+/// use std::sync::mpsc;
+///
+/// let mut manager = SessionManager::new();
+/// let (events, received) = mpsc::channel();
+/// let client = manager.connect(events);
+/// let reply = manager.handle(client, r#"{"id": 1, "command": "create", "console": 61441}"#);
+/// assert_eq!(reply, r#"{"id":1,"ok":true,"session":1}"#);
+/// let source = "LDA #$48\nSTA $F001\nLDA #$49\nSTA $F001\nloop:\nJMP loop";
+/// let load = serde_json::json!({"command": "load", "session": 1, "source": source, "origin": 1536});
+/// assert!(manager.handle(client, &load.to_string()).contains(r#""ok":true"#));
+/// manager.handle(client, r#"{"command": "attach", "session": 1}"#);
+/// let reply = manager.handle(client, r#"{"command": "run", "session": 1, "cycles": 100}"#);
+/// assert!(reply.contains(r#""stopped":null"#));
+/// assert_eq!(
+///     received.try_recv().unwrap(),
+///     r#"{"event":"console","session":1,"text":"HI"}"#
+/// );
+/// ```
+pub struct SessionManager {
+    sessions: BTreeMap<u32, Instance>,
+    clients: HashMap<u64, Sender<String>>, // Where to send each client's events
+    next_session: u32,
+    next_client: u64,
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        SessionManager {
+            sessions: BTreeMap::new(),
+            clients: HashMap::new(),
+            next_session: 1,
+            next_client: 1,
+        }
+    }
+
+    /// Registers a client, which is sent the console output of the sessions it attaches to
+    /// through `events`.
+    ///
+    /// # Returns
+    /// The number identifying the client in `handle` and `disconnect`.
+    pub fn connect(&mut self, events: Sender<String>) -> u64 {
+        let client = self.next_client;
+        self.next_client += 1;
+        self.clients.insert(client, events);
+        client
+    }
+
+    /// Forgets a client, detaching it from every session. The sessions it used live on.
+    pub fn disconnect(&mut self, client: u64) {
+        self.clients.remove(&client);
+        for instance in self.sessions.values_mut() {
+            instance.attached.retain(|attached| *attached != client);
+        }
+    }
+
+    /// Handles a request from a client and returns the reply, after sending any console output
+    /// the request caused to the attached clients.
+    pub fn handle(&mut self, client: u64, request: &str) -> String {
+        let reply = match serde_json::from_str::<Request>(request) {
+            Ok(request) => {
+                let mut reply = match self.execute(client, request.command) {
+                    Ok(reply) => reply,
+                    Err(message) => json!({"ok": false, "error": message}),
+                };
+                if !request.id.is_null() {
+                    reply["id"] = request.id;
+                }
+                reply
+            }
+            Err(e) => json!({"ok": false, "error": format!("invalid request: {}", e)}),
+        };
+        self.send_console_output();
+        reply.to_string()
+    }
+
+    fn execute(&mut self, client: u64, command: Command) -> Result<Value, String> {
+        match command {
+            Command::Create { console, limits } => {
+                if self.sessions.len() >= MAX_SESSIONS {
+                    return Err(format!("at most {} sessions can be hosted", MAX_SESSIONS));
+                }
+                let mut instance = Instance {
+                    cpu: CPU::new(),
+                    console: ConsoleBuffer::default(),
+                    attached: Vec::new(),
+                };
+                instance.cpu.limits = limits;
+                if let Some(address) = console {
+                    let output = Box::new(instance.console.clone());
+                    let device = Console::new(Encoding::ascii(), output);
+                    instance
+                        .cpu
+                        .memory
+                        .attach(address, address, "Console", Box::new(device));
+                }
+                let session = self.next_session;
+                self.next_session += 1;
+                self.sessions.insert(session, instance);
+                Ok(json!({"ok": true, "session": session}))
+            }
+            Command::List => {
+                let sessions: Vec<u32> = self.sessions.keys().copied().collect();
+                Ok(json!({"ok": true, "sessions": sessions}))
+            }
+            Command::Load {
+                session,
+                source,
+                bytes,
+                origin,
+            } => {
+                let instance = self.session(session)?;
+                let origin = origin.unwrap_or(0);
+                let (entry_point, size) = match (source, bytes) {
+                    (Some(source), None) => {
+                        let mut assembler = Assembler::new();
+                        let mut address = origin;
+                        assembler
+                            .assemble(&source, &mut instance.cpu.memory, &mut address)
+                            .map_err(|e| e.to_string())?;
+                        (
+                            assembler.entry_point().unwrap_or(origin),
+                            assembler.size() as usize,
+                        )
+                    }
+                    (None, Some(bytes)) => {
+                        instance
+                            .cpu
+                            .memory
+                            .load_bytes(&bytes, origin)
+                            .map_err(|e| e.to_string())?;
+                        (origin, bytes.len())
+                    }
+                    _ => return Err(String::from("give either source or bytes to load")),
+                };
+                let cpu = &mut instance.cpu;
+                if cpu.memory.reset_vector() == 0 {
+                    cpu.memory.set_reset_vector(entry_point);
+                }
+                cpu.reset();
+                Ok(json!({"ok": true, "bytes": size, "entry_point": entry_point}))
+            }
+            Command::Run { session, cycles } => {
+                let cpu = &mut self.session(session)?.cpu;
+                let start = cpu.cycles;
+                let limit = cycles.min(MAX_RUN_CYCLES);
+                let mut unlimited = u32::MAX;
+                let mut stopped = None;
+                while cpu.cycles - start < limit {
+                    if let Err(reason) = try_execute_instruction(cpu, &mut unlimited) {
+                        stopped = Some(format!("{:?}", reason));
+                        break;
+                    }
+                }
+                Ok(json!({
+                    "ok": true,
+                    "cycles": cpu.cycles - start,
+                    "stopped": stopped,
+                    "registers": {
+                        "pc": cpu.pc,
+                        "a": cpu.a,
+                        "x": cpu.x,
+                        "y": cpu.y,
+                        "p": cpu.get_status(),
+                        "sp": cpu.sp & 0x00FF,
+                    },
+                }))
+            }
+            Command::Attach { session } => {
+                let instance = self.session(session)?;
+                if !instance.attached.contains(&client) {
+                    instance.attached.push(client);
+                }
+                Ok(json!({"ok": true}))
+            }
+            Command::Detach { session } => {
+                self.session(session)?
+                    .attached
+                    .retain(|attached| *attached != client);
+                Ok(json!({"ok": true}))
+            }
+            Command::Destroy { session } => match self.sessions.remove(&session) {
+                Some(_) => Ok(json!({"ok": true})),
+                None => Err(format!("no session {}", session)),
+            },
+        }
+    }
+
+    fn session(&mut self, session: u32) -> Result<&mut Instance, String> {
+        self.sessions
+            .get_mut(&session)
+            .ok_or_else(|| format!("no session {}", session))
+    }
+
+    /// Sends the console output of each session to the clients attached to it. Output of
+    /// sessions with no client attached is kept, up to `MAX_CONSOLE_BACKLOG` bytes.
+    fn send_console_output(&mut self) {
+        for (session, instance) in &mut self.sessions {
+            let mut buffer = instance.console.0.borrow_mut();
+            if buffer.is_empty() {
+                continue;
+            }
+            if instance.attached.is_empty() {
+                let excess = buffer.len().saturating_sub(MAX_CONSOLE_BACKLOG);
+                buffer.drain(..excess);
+                continue;
+            }
+            let event = json!({
+                "event": "console",
+                "session": session,
+                "text": String::from_utf8_lossy(&buffer),
+            })
+            .to_string();
+            buffer.clear();
+            for client in &instance.attached {
+                if let Some(events) = self.clients.get(client) {
+                    let _ = events.send(event.clone());
+                }
+            }
+        }
+    }
+}
+
+/// What a connection tells the thread running the sessions.
+enum ManagerMessage {
+    Connect(u64, Sender<String>),
+    Request(u64, String),
+    Disconnect(u64),
+}
+
+/// Hosts emulator sessions for clients connecting over WebSocket.
+///
+/// Each text message a client sends is a request for the `SessionManager`, and it receives the
+/// replies and console events as text messages. Every connection is served on a thread of its
+/// own, while the sessions all run on one thread, one request at a time.
+///
+/// # Example
+/// ```rust
+/// use r_6502::server::Server;
+/// use std::thread;
+/// use tungstenite::Message;
+///
+/// let server = Server::bind("127.0.0.1:0").unwrap();
+/// let address = server.local_addr().unwrap();
+/// thread::spawn(move || server.run());
+///
+/// let (mut socket, _) = tungstenite::connect(format!("ws://{}", address)).unwrap();
+/// let mut request = |text: &str| {
+///     socket.send(Message::text(text)).unwrap();
+///     socket.read().unwrap().into_text().unwrap()
+/// };
+/// assert_eq!(request(r#"{"command": "create", "console": 61441}"#), r#"{"ok":true,"session":1}"#);
+/// request(r#"{"command": "load", "session": 1, "source": "LDA #$41\nSTA $F001"}"#);
+/// request(r#"{"command": "attach", "session": 1}"#);
+/// let event = request(r#"{"command": "run", "session": 1, "cycles": 6}"#);
+/// assert_eq!(event, r#"{"event":"console","session":1,"text":"A"}"#);
+/// ```
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    /// Creates a server listening at `address`, e.g. `0.0.0.0:6502`.
+    ///
+    /// # Errors
+    /// Returns any error binding to `address`.
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(address)?,
+        })
+    }
+
+    /// Returns the address listened on, which tells the port chosen when binding to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections until the listener fails.
+    pub fn run(self) {
+        let (manager, inbox) = mpsc::channel();
+        thread::spawn(move || manage_sessions(inbox));
+        for (client, stream) in (1_u64..).zip(self.listener.incoming().flatten()) {
+            let manager = manager.clone();
+            thread::spawn(move || {
+                // A client that goes away only ends its own connection
+                let _ = serve_connection(stream, client, &manager);
+                let _ = manager.send(ManagerMessage::Disconnect(client));
+            });
+        }
+    }
+}
+
+/// Runs the sessions, handling the messages of every connection in turn.
+///
+/// The sessions live on this thread alone, so their CPUs and devices need not be shared between
+/// threads.
+fn manage_sessions(inbox: Receiver<ManagerMessage>) {
+    let mut manager = SessionManager::new();
+    // Each connection's client number in the manager, and where to send its replies
+    let mut clients: HashMap<u64, (u64, Sender<String>)> = HashMap::new();
+    for message in inbox {
+        match message {
+            ManagerMessage::Connect(connection, events) => {
+                let client = manager.connect(events.clone());
+                clients.insert(connection, (client, events));
+            }
+            ManagerMessage::Request(connection, request) => {
+                if let Some((client, replies)) = clients.get(&connection) {
+                    let _ = replies.send(manager.handle(*client, &request));
+                }
+            }
+            ManagerMessage::Disconnect(connection) => {
+                if let Some((client, _)) = clients.remove(&connection) {
+                    manager.disconnect(client);
+                }
+            }
+        }
+    }
+}
+
+/// Performs the WebSocket handshake on `stream`, then passes the client's requests to the
+/// session thread and sends it back what that thread sends, until the client disconnects.
+fn serve_connection(
+    stream: TcpStream,
+    connection: u64,
+    manager: &Sender<ManagerMessage>,
+) -> io::Result<()> {
+    let mut socket = tungstenite::accept(stream).map_err(io::Error::other)?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    let (events, outbox) = mpsc::channel();
+    let _ = manager.send(ManagerMessage::Connect(connection, events));
+    loop {
+        match socket.read() {
+            Ok(Message::Text(request)) => {
+                let _ = manager.send(ManagerMessage::Request(connection, request));
+            }
+            Ok(_) => {} // Pings are answered by `read`, and binary messages are ignored
+            Err(Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(io_error(e)),
+        }
+        while let Ok(event) = outbox.try_recv() {
+            socket.send(Message::text(event)).map_err(io_error)?;
+        }
+    }
+}
+
+fn io_error(error: Error) -> io::Error {
+    match error {
+        Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `request` for `client` and parses the reply.
+    fn request(manager: &mut SessionManager, client: u64, request: &str) -> Value {
+        serde_json::from_str(&manager.handle(client, request)).unwrap()
+    }
+
+    /// Creates a session with a console at `$F001` and loads `source` into it at `$0600`.
+    fn loaded_session(manager: &mut SessionManager, client: u64, source: &str) -> u64 {
+        let reply = request(
+            manager,
+            client,
+            r#"{"command": "create", "console": 61441}"#,
+        );
+        let session = reply["session"].as_u64().unwrap();
+        let load = json!({"command": "load", "session": session, "source": source, "origin": 1536});
+        assert_eq!(request(manager, client, &load.to_string())["ok"], true);
+        session
+    }
+
+    #[test]
+    fn sessions_are_isolated_from_each_other() {
+        let mut manager = SessionManager::new();
+        let (first_events, first_received) = mpsc::channel();
+        let (second_events, second_received) = mpsc::channel();
+        let first = manager.connect(first_events);
+        let second = manager.connect(second_events);
+        let writer = loaded_session(
+            &mut manager,
+            first,
+            "LDA #$42\nSTA $0200\nSTA $F001\nloop:\nJMP loop",
+        );
+        let reader = loaded_session(&mut manager, second, "LDA $0200\nloop:\nJMP loop");
+        request(
+            &mut manager,
+            first,
+            &json!({"command": "attach", "session": writer}).to_string(),
+        );
+        request(
+            &mut manager,
+            second,
+            &json!({"command": "attach", "session": reader}).to_string(),
+        );
+
+        let run = |session: u64| json!({"command": "run", "session": session, "cycles": 20});
+        let reply = request(&mut manager, first, &run(writer).to_string());
+        assert_eq!(reply["registers"]["a"], 0x42);
+        assert_eq!(
+            first_received.try_recv().unwrap(),
+            json!({"event": "console", "session": writer, "text": "B"}).to_string()
+        );
+        assert!(second_received.try_recv().is_err());
+
+        // The reader's memory and registers are its own
+        let reply = request(&mut manager, second, &run(reader).to_string());
+        assert_eq!(reply["registers"]["a"], 0);
+        assert!(first_received.try_recv().is_err());
+        assert!(second_received.try_recv().is_err());
+
+        // Destroying one session leaves the other
+        let destroy = json!({"command": "destroy", "session": writer}).to_string();
+        assert_eq!(request(&mut manager, second, &destroy)["ok"], true);
+        let reply = request(&mut manager, first, r#"{"command": "list"}"#);
+        assert_eq!(reply["sessions"], json!([reader]));
+    }
+
+    #[test]
+    fn bad_requests_are_answered_with_an_error() {
+        let mut manager = SessionManager::new();
+        let (events, _received) = mpsc::channel();
+        let client = manager.connect(events);
+        for (bad, error) in [
+            ("not json", "invalid request: "),
+            (
+                r#"{"id": 7, "command": "fly"}"#,
+                "invalid request: unknown variant `fly`",
+            ),
+            (
+                r#"{"command": "run", "session": 1}"#,
+                "invalid request: missing field",
+            ),
+            (
+                r#"{"id": 8, "command": "run", "session": 9, "cycles": 1}"#,
+                "no session 9",
+            ),
+            (r#"{"command": "load", "session": 9}"#, "no session 9"),
+        ] {
+            let reply = request(&mut manager, client, bad);
+            assert_eq!(reply["ok"], false, "{}", bad);
+            assert!(
+                reply["error"].as_str().unwrap().starts_with(error),
+                "{}",
+                reply
+            );
+        }
+        // The id is echoed once the request parses
+        let reply = request(
+            &mut manager,
+            client,
+            r#"{"id": 8, "command": "detach", "session": 9}"#,
+        );
+        assert_eq!(reply["id"], 8);
+
+        // The client carries on
+        let reply = request(&mut manager, client, r#"{"command": "create"}"#);
+        assert_eq!(reply, json!({"ok": true, "session": 1}));
+        let reply = request(&mut manager, client, r#"{"command": "load", "session": 1}"#);
+        assert_eq!(reply["error"], "give either source or bytes to load");
+        let reply = request(
+            &mut manager,
+            client,
+            r#"{"command": "load", "session": 1, "source": "LDQ #1"}"#,
+        );
+        assert_eq!(reply["ok"], false);
+    }
+
+    #[test]
+    fn the_session_thread_replies_to_bad_requests() {
+        let (manager, inbox) = mpsc::channel();
+        thread::spawn(move || manage_sessions(inbox));
+        let (replies, outbox) = mpsc::channel();
+        manager.send(ManagerMessage::Connect(1, replies)).unwrap();
+        for text in ["{", r#"{"command": "create"}"#] {
+            let request = ManagerMessage::Request(1, String::from(text));
+            manager.send(request).unwrap();
+        }
+        let reply = outbox.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(
+            reply.starts_with(r#"{"error":"invalid request: "#),
+            "{}",
+            reply
+        );
+        let reply = outbox.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(reply, r#"{"ok":true,"session":1}"#);
+    }
+}
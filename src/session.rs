@@ -0,0 +1,166 @@
+use crate::breakpoint::{Breakpoint, FlagBreakpoint};
+use crate::cpu::CPU;
+use crate::save_state::{SaveState, SaveStateError};
+use crate::watchpoint::Watchpoint;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The debugging setup of a session: everything besides the machine state needed to pick it up
+/// again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub breakpoints: Vec<Breakpoint>,
+    pub flag_breakpoints: Vec<FlagBreakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub displays: Vec<String>, // Expressions shown every time execution stops
+    pub symbols: HashMap<String, u16>,
+}
+
+impl Session {
+    /// Collects the breakpoints and watchpoints set on the CPU, with the session's symbols and
+    /// displayed expressions.
+    pub fn capture(cpu: &CPU, symbols: &HashMap<String, u16>, displays: &[String]) -> Self {
+        Session {
+            breakpoints: cpu.breakpoints.list().to_vec(),
+            flag_breakpoints: cpu.breakpoints.flag_list().to_vec(),
+            watchpoints: cpu.memory.watchpoints.list().to_vec(),
+            displays: displays.to_vec(),
+            symbols: symbols.clone(),
+        }
+    }
+
+    /// Sets the session's breakpoints and watchpoints on the CPU, on top of any already set.
+    pub fn apply(&self, cpu: &mut CPU) {
+        for breakpoint in &self.breakpoints {
+            cpu.breakpoints.insert(*breakpoint);
+        }
+        for breakpoint in &self.flag_breakpoints {
+            cpu.breakpoints.add_flag(breakpoint.flag, breakpoint.change);
+        }
+        for watchpoint in &self.watchpoints {
+            cpu.memory
+                .watchpoints
+                .add(watchpoint.start, watchpoint.end, watchpoint.kind);
+        }
+    }
+}
+
+/// A directory a debugging session is saved to, so it can be resumed after the debugger is
+/// interrupted.
+///
+/// The directory holds `session.json`, the `Session`, and `state.json`, the latest `SaveState`.
+/// Both are written to a temporary file first and then renamed over the old one, so a crash
+/// while saving leaves the previous save intact.
+///
+/// # Example
+/// ```rust,no_run
+/// use r_6502::session::{Session, SessionDir};
+/// use r_6502::CPU;
+/// use std::collections::HashMap;
+/// use std::time::Duration;
+///
+/// let mut cpu = CPU::new();
+/// let mut dir = SessionDir::new("debug-session", Duration::from_secs(30));
+/// if let Some((session, state)) = dir.load().unwrap() {
+///     state.restore(&mut cpu);
+///     session.apply(&mut cpu);
+/// }
+/// // After every debugger command:
+/// let session = Session::capture(&cpu, &HashMap::new(), &[]);
+/// dir.autosave(&session, &cpu).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct SessionDir {
+    path: PathBuf,
+    interval: Duration,
+    last_save: Option<Instant>,
+}
+
+impl SessionDir {
+    /// Creates a session directory at `path` (created on the first save), autosaved at most once
+    /// per `interval`.
+    pub fn new(path: impl AsRef<Path>, interval: Duration) -> Self {
+        SessionDir {
+            path: path.as_ref().to_path_buf(),
+            interval,
+            last_save: None,
+        }
+    }
+
+    /// Loads the saved session and machine state, if the directory holds one.
+    ///
+    /// # Errors
+    /// Returns a `SaveStateError` if a saved file cannot be read or parsed.
+    pub fn load(&self) -> Result<Option<(Session, SaveState)>, SaveStateError> {
+        let session_path = self.path.join("session.json");
+        let state_path = self.path.join("state.json");
+        if !session_path.exists() || !state_path.exists() {
+            return Ok(None);
+        }
+        let text = read_file(&session_path)?;
+        let session: Session = serde_json::from_str(&text).map_err(|e| SaveStateError {
+            line: e.line(),
+            message: format!("{}: {}", session_path.display(), e),
+        })?;
+        let state = SaveState::from_json(&read_file(&state_path)?)?;
+        Ok(Some((session, state)))
+    }
+
+    /// Saves the session and machine state now.
+    ///
+    /// # Errors
+    /// Returns a `SaveStateError` with line 0 if the directory or a file cannot be written.
+    pub fn save(&mut self, session: &Session, cpu: &CPU) -> Result<(), SaveStateError> {
+        fs::create_dir_all(&self.path).map_err(|e| SaveStateError {
+            line: 0,
+            message: format!("{}: {}", self.path.display(), e),
+        })?;
+        // Serializing plain data cannot fail
+        let text = serde_json::to_string_pretty(session).unwrap_or_default();
+        write_file(&self.path.join("session.json"), &text)?;
+        write_file(
+            &self.path.join("state.json"),
+            &SaveState::capture(cpu).to_json(),
+        )?;
+        self.last_save = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Saves the session and machine state if the autosave interval has passed since the last
+    /// save (or nothing has been saved yet).
+    ///
+    /// # Returns
+    /// Whether the session was saved.
+    ///
+    /// # Errors
+    /// Returns the error `save` found.
+    pub fn autosave(&mut self, session: &Session, cpu: &CPU) -> Result<bool, SaveStateError> {
+        match self.last_save {
+            Some(last_save) if last_save.elapsed() < self.interval => Ok(false),
+            _ => self.save(session, cpu).map(|_| true),
+        }
+    }
+}
+
+/// Reads a text file, reporting errors with its path.
+fn read_file(path: &Path) -> Result<String, SaveStateError> {
+    fs::read_to_string(path).map_err(|e| SaveStateError {
+        line: 0,
+        message: format!("{}: {}", path.display(), e),
+    })
+}
+
+/// Replaces a file with `text`, writing it next to the file first so the old contents survive a
+/// crash while writing.
+fn write_file(path: &Path, text: &str) -> Result<(), SaveStateError> {
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, text)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|e| SaveStateError {
+            line: 0,
+            message: format!("{}: {}", path.display(), e),
+        })
+}
@@ -0,0 +1,79 @@
+/// A subroutine call recorded by the shadow stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+    pub routine: u16,        // Address the `JSR` jumped to
+    pub return_address: u16, // Address of the instruction after the `JSR`
+    pub sp: u8,              // Stack pointer right after the `JSR` pushed its return address
+}
+
+/// Keeps track of the subroutine calls in progress, independently of the 6502 stack contents.
+///
+/// Real code does not always pair `JSR` with `RTS`. Two idioms are recognised from the stack
+/// pointer at the `RTS`, so backtraces stay correct instead of drifting out of step:
+/// - Pushing an address and executing `RTS` to jump to it (the "RTS trick", common in jump-table
+///   dispatch). The stack pointer is below the innermost call's, so the `RTS` returns from nothing.
+/// - Discarding return addresses with `PLA`/`PLA` or `TXS` before returning to an outer caller. The
+///   stack pointer is above the innermost call's, so every call whose return address has been
+///   discarded is closed along with the one returned to.
+///
+/// # Example
+/// ```rust
+/// use r_6502::shadow_stack::ShadowStack;
+///
+/// let mut stack = ShadowStack::new();
+/// stack.call(0x0700, 0x0603, 0xFD);
+/// stack.call(0x0800, 0x0703, 0xFB);
+/// // The inner routine discarded its own return address before returning
+/// assert_eq!(stack.ret(0xFD).len(), 2);
+/// assert!(stack.backtrace().is_empty());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ShadowStack {
+    frames: Vec<CallFrame>,
+}
+
+impl ShadowStack {
+    pub fn new() -> Self {
+        ShadowStack { frames: Vec::new() }
+    }
+
+    /// Records a `JSR` to `routine`, with `sp` the stack pointer after the return address was pushed.
+    pub fn call(&mut self, routine: u16, return_address: u16, sp: u8) {
+        self.frames.push(CallFrame {
+            routine,
+            return_address,
+            sp,
+        });
+    }
+
+    /// Records an `RTS`, with `sp` the stack pointer before the return address is pulled.
+    ///
+    /// # Returns
+    /// The calls the `RTS` leaves, innermost first. This is empty for an RTS trick or an `RTS`
+    /// outside any recorded call, and holds more than one call when return addresses were discarded.
+    pub fn ret(&mut self, sp: u8) -> Vec<CallFrame> {
+        let mut closed: Vec<CallFrame> = Vec::new();
+        while let Some(frame) = self.frames.last() {
+            if sp < frame.sp {
+                // More has been pushed since the call: the RTS jumps to a pushed address
+                break;
+            }
+            let matched = sp == frame.sp;
+            closed.extend(self.frames.pop());
+            if matched {
+                break;
+            }
+        }
+        closed
+    }
+
+    /// Returns the calls in progress, innermost first.
+    pub fn backtrace(&self) -> Vec<CallFrame> {
+        self.frames.iter().rev().copied().collect()
+    }
+
+    /// Returns the number of calls in progress.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+}
@@ -0,0 +1,448 @@
+use crate::asm_runner::execute_instruction;
+use crate::cpu::CPU;
+use crate::memory::Memory;
+use crate::shadow_stack::ShadowStack;
+use crate::token::{AddressingMode, Token};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Bytes an interrupt pushes before its handler runs: the return address and the status.
+const INTERRUPT_FRAME: u16 = 3;
+
+/// The stack usage found for one routine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoutineStack {
+    pub address: u16,
+    pub depth: Option<u16>, // Bytes the routine and its callees push, `None` if unbounded
+    pub entry_depth: u16, // Deepest the stack is when the routine is entered, return address included
+}
+
+/// The result of `analyze_stack`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StackReport {
+    pub main: Option<u16>, // Bytes the code at the entry point pushes, `None` if unbounded
+    pub interrupts: Option<u16>, // Bytes the interrupt handlers can add on top of that
+    pub routines: Vec<RoutineStack>, // Every routine called, by address
+}
+
+impl StackReport {
+    /// Returns the most bytes the program can have on the stack at once, interrupts included, or
+    /// `None` if that cannot be bounded.
+    pub fn worst_case(&self) -> Option<u16> {
+        Some(self.main? + self.interrupts?)
+    }
+
+    /// Returns the routines whose worst case, with interrupts arriving at their deepest point,
+    /// takes the stack pointer below `floor` when the program starts with the stack pointer at
+    /// `initial_sp`. Routines that cannot be bounded are always at risk.
+    ///
+    /// `floor` is the lowest stack address that is safe to use, i.e. one past the end of any data
+    /// kept at the bottom of page `$01`.
+    pub fn at_risk(&self, initial_sp: u8, floor: u16) -> Vec<RoutineStack> {
+        let available = (0x0100 + initial_sp as u16 + 1).saturating_sub(floor);
+        self.routines
+            .iter()
+            .filter(|routine| match (routine.depth, self.interrupts) {
+                (Some(depth), Some(interrupts)) => {
+                    routine.entry_depth + depth + interrupts > available
+                }
+                _ => true,
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Formats the report as a table, one routine per line, naming routines after the labels in
+    /// `symbol_table`. Routines at risk (see `at_risk`) are flagged.
+    pub fn report(
+        &self,
+        symbol_table: &HashMap<String, u16>,
+        initial_sp: u8,
+        floor: u16,
+    ) -> String {
+        let names = routine_names(symbol_table);
+        let name = |address: u16| match names.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("${:04X}", address),
+        };
+        let bytes = |depth: Option<u16>| match depth {
+            Some(depth) => depth.to_string(),
+            None => String::from("unbounded"),
+        };
+        let at_risk: HashSet<u16> = self
+            .at_risk(initial_sp, floor)
+            .iter()
+            .map(|routine| routine.address)
+            .collect();
+
+        let mut report = format!(
+            "{:<20} {:>10} {:>10} {:>10}\n",
+            "ROUTINE", "DEPTH", "ENTRY", "WORST"
+        );
+        for routine in &self.routines {
+            let worst = routine.depth.map(|depth| routine.entry_depth + depth);
+            report.push_str(&format!(
+                "{:<20} {:>10} {:>10} {:>10}{}\n",
+                name(routine.address),
+                bytes(routine.depth),
+                routine.entry_depth,
+                bytes(worst),
+                if at_risk.contains(&routine.address) {
+                    "  AT RISK"
+                } else {
+                    ""
+                }
+            ));
+        }
+        report.push_str(&format!(
+            "main: {} bytes, interrupts: {} bytes, worst case: {} bytes of {} available\n",
+            bytes(self.main),
+            bytes(self.interrupts),
+            bytes(self.worst_case()),
+            (0x0100 + initial_sp as u16 + 1).saturating_sub(floor)
+        ));
+        report
+    }
+}
+
+/// Measures statically how deep each routine can take the stack.
+///
+/// Starting from the entry point (and each interrupt handler), the control flow is followed the
+/// way `find_code` follows it, keeping count of the bytes on the stack: `PHA`/`PHP` push one,
+/// `PLA`/`PLP` pull one and `JSR` pushes two plus whatever the called routine pushes. `RTS`, `RTI`
+/// and `BRK` end a path. `TXS` is assumed to set up a fresh stack, so the count starts again from
+/// zero.
+///
+/// A routine's depth is unbounded (`None`) if it is recursive, if a loop pushes more than it
+/// pulls, or if it makes a computed `JMP ($xxxx)`, which cannot be followed. Each interrupt
+/// handler adds 3 bytes plus its own depth, as interrupts can arrive at the program's deepest point
+/// and an NMI can arrive during an IRQ.
+///
+/// # Example
+/// ```rust
+/// use r_6502::stack_usage::analyze_stack;
+/// use r_6502::{Assembler, Memory};
+///
+/// let mut memory = Memory::new();
+/// let mut end_address: u16 = 0x0600;
+/// let mut assembler = Assembler::new();
+/// let source = "JSR outer\nBRK\nouter:\nPHA\nJSR inner\nPLA\nRTS\ninner:\nPHA\nPHA\nPLA\nPLA\nRTS";
+/// assembler.assemble(source, &mut memory, &mut end_address).unwrap();
+/// let inner = assembler.symbol_table()["inner"];
+/// let report = analyze_stack(&memory, 0x0600, &[inner]);
+/// assert_eq!(report.main, Some(7)); // outer: 2 + 1 + inner: 2 + 2
+/// assert_eq!(report.interrupts, Some(5)); // 3 + 2
+/// assert_eq!(report.worst_case(), Some(12));
+/// let inner_stack = report.routines.iter().find(|r| r.address == inner).unwrap();
+/// assert_eq!(inner_stack.entry_depth, 5);
+/// assert!(report.at_risk(0xFF, 0x01F8).iter().any(|r| r.address == inner));
+/// ```
+pub fn analyze_stack(mem: &Memory, entry: u16, interrupt_handlers: &[u16]) -> StackReport {
+    let mut analyzer = StackAnalyzer {
+        mem,
+        depths: HashMap::new(),
+        calls: HashMap::new(),
+        active: HashSet::new(),
+    };
+    let main = analyzer.routine_depth(entry);
+    let mut interrupts = Some(0);
+    for handler in interrupt_handlers {
+        let depth = analyzer.routine_depth(*handler);
+        interrupts = interrupts.and_then(|total: u16| Some(total + INTERRUPT_FRAME + depth?));
+    }
+
+    // Propagate the depth each routine is entered at down the call graph
+    let mut entry_depths: BTreeMap<u16, u16> = BTreeMap::new();
+    let mut pending: Vec<(u16, u16)> = vec![(entry, 0)];
+    pending.extend(interrupt_handlers.iter().map(|handler| (*handler, 0)));
+    while let Some((routine, depth)) = pending.pop() {
+        for (site_depth, callee) in analyzer.calls.get(&routine).into_iter().flatten() {
+            let callee_depth = depth + site_depth + 2;
+            let known = entry_depths.entry(*callee).or_insert(0);
+            if callee_depth > *known {
+                *known = callee_depth;
+                // Recursive routines are unbounded anyway, and would raise the depth forever
+                if analyzer.depths.get(callee) != Some(&None) {
+                    pending.push((*callee, callee_depth));
+                }
+            }
+        }
+    }
+    let routines = entry_depths
+        .into_iter()
+        .map(|(address, entry_depth)| RoutineStack {
+            address,
+            depth: analyzer.depths.get(&address).copied().flatten(),
+            entry_depth,
+        })
+        .collect();
+    StackReport {
+        main,
+        interrupts,
+        routines,
+    }
+}
+
+struct StackAnalyzer<'a> {
+    mem: &'a Memory,
+    depths: HashMap<u16, Option<u16>>,
+    calls: HashMap<u16, Vec<(u16, u16)>>, // Per routine: stack depth at each `JSR` and its target
+    active: HashSet<u16>,                 // Routines being analysed, to detect recursion
+}
+
+impl StackAnalyzer<'_> {
+    /// Returns the most bytes `routine` and its callees push, not counting its return address.
+    fn routine_depth(&mut self, routine: u16) -> Option<u16> {
+        if let Some(depth) = self.depths.get(&routine) {
+            return *depth;
+        }
+        if !self.active.insert(routine) {
+            return None;
+        }
+        let depth = self.walk(routine);
+        self.active.remove(&routine);
+        self.depths.insert(routine, depth);
+        depth
+    }
+
+    fn walk(&mut self, routine: u16) -> Option<u16> {
+        let mut seen: HashMap<u16, u16> = HashMap::new();
+        let mut pending: Vec<(u16, u16)> = vec![(routine, 0)];
+        let mut deepest: u16 = 0;
+        while let Some((address, depth)) = pending.pop() {
+            match seen.get(&address) {
+                Some(previous) if depth > *previous => return None, // A loop that keeps pushing
+                Some(_) => continue,
+                None => {
+                    seen.insert(address, depth);
+                }
+            }
+            deepest = deepest.max(depth);
+            let token = match Token::from_opcode(self.mem.data[address as usize]) {
+                Some(token) => token,
+                None => continue,
+            };
+            let next = address.wrapping_add(1 + token.addressing_mode().operand_size());
+            let byte = self.mem.data[address.wrapping_add(1) as usize];
+            let word =
+                ((self.mem.data[address.wrapping_add(2) as usize] as u16) << 8) | byte as u16;
+            match (token, token.addressing_mode()) {
+                (_, AddressingMode::Relative) => {
+                    pending.push((next.wrapping_add(byte as i8 as u16), depth));
+                    pending.push((next, depth));
+                }
+                (Token::JMP, _) => pending.push((word, depth)),
+                (Token::JSR, _) => {
+                    // Recorded first, so unbounded routines are reported too
+                    self.calls.entry(routine).or_default().push((depth, word));
+                    let callee = self.routine_depth(word)?;
+                    deepest = deepest.max(depth + 2 + callee);
+                    pending.push((next, depth));
+                }
+                (Token::JmpID, _) => return None,
+                (Token::RTS | Token::RTI | Token::BRK, _) => {}
+                (Token::PHA | Token::PHP, _) => pending.push((next, depth + 1)),
+                (Token::PLA | Token::PLP, _) => pending.push((next, depth.saturating_sub(1))),
+                (Token::TXS, _) => pending.push((next, 0)),
+                _ => pending.push((next, depth)),
+            }
+        }
+        Some(deepest)
+    }
+}
+
+/// Measures how deep each routine takes the stack while a program runs.
+///
+/// Calls are tracked through `JSR` and `RTS` with a `ShadowStack`, like `Profiler` does. A
+/// routine's depth is the most bytes on the stack below its return address at any point during a
+/// call, so it includes the routines it calls and any interrupt taken while it runs.
+///
+/// # Example
+/// ```rust
+/// use r_6502::stack_usage::StackMonitor;
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// let mut assembler = Assembler::new();
+/// assembler
+///     .assemble("JSR work\nJMP end\nwork:\nPHA\nPHA\nPLA\nPLA\nRTS\nend:\nNOP", &mut cpu.memory, &mut end_address)
+///     .unwrap();
+/// cpu.sp = 0xFF;
+/// let mut monitor = StackMonitor::new();
+/// let mut data_cycle_count = end_address as u32;
+/// monitor.run(&mut cpu, &mut data_cycle_count);
+/// assert_eq!(monitor.depth(assembler.symbol_table()["work"]), Some(2));
+/// assert_eq!(monitor.lowest_sp(), 0xFB);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StackMonitor {
+    calls: ShadowStack,
+    depths: BTreeMap<u16, u16>,
+    lowest_sp: Option<u8>,
+}
+
+impl StackMonitor {
+    pub fn new() -> Self {
+        StackMonitor {
+            calls: ShadowStack::new(),
+            depths: BTreeMap::new(),
+            lowest_sp: None,
+        }
+    }
+
+    /// Executes the program like `run_memory`, measuring the stack after every instruction.
+    pub fn run(&mut self, cpu: &mut CPU, data_cycle_count: &mut u32) {
+        while *data_cycle_count > 0 {
+            self.step(cpu, data_cycle_count);
+        }
+    }
+
+    /// Executes one instruction and updates the depth of every routine in progress.
+    pub fn step(&mut self, cpu: &mut CPU, data_cycle_count: &mut u32) {
+        let pc = cpu.pc;
+        let sp = cpu.sp as u8;
+        let opcode = Token::from_opcode(cpu.memory.data[pc as usize]);
+        execute_instruction(cpu, data_cycle_count);
+        match opcode {
+            Some(Token::JSR) => {
+                self.calls.call(cpu.pc, pc.wrapping_add(3), cpu.sp as u8);
+                self.depths.entry(cpu.pc).or_insert(0);
+            }
+            Some(Token::RTS) => {
+                self.calls.ret(sp);
+            }
+            _ => {}
+        }
+        let sp = cpu.sp as u8;
+        self.lowest_sp = Some(self.lowest_sp.map_or(sp, |lowest| lowest.min(sp)));
+        for frame in self.calls.backtrace() {
+            let depth = self.depths.entry(frame.routine).or_insert(0);
+            *depth = (*depth).max(frame.sp.saturating_sub(sp) as u16);
+        }
+    }
+
+    /// Returns the most bytes a routine had on the stack below its return address, or `None` if
+    /// it was never called.
+    pub fn depth(&self, routine: u16) -> Option<u16> {
+        self.depths.get(&routine).copied()
+    }
+
+    /// Returns the measured depth of every routine called, by address.
+    pub fn depths(&self) -> &BTreeMap<u16, u16> {
+        &self.depths
+    }
+
+    /// Returns the lowest the stack pointer went, or `$FF` if nothing ran.
+    pub fn lowest_sp(&self) -> u8 {
+        self.lowest_sp.unwrap_or(0xFF)
+    }
+}
+
+/// Names routine addresses after the alphabetically first label defined at each.
+fn routine_names(symbol_table: &HashMap<String, u16>) -> HashMap<u16, String> {
+    let mut names: HashMap<u16, String> = HashMap::new();
+    for (label, address) in symbol_table {
+        let name = names.entry(*address).or_insert_with(|| label.clone());
+        if label < name {
+            *name = label.clone();
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assembler;
+
+    /// Assembles `source` at `$0600`, analyses it from there and runs it with a `StackMonitor`
+    /// until it reaches its `end` label.
+    ///
+    /// # Returns
+    /// The static report, the monitor and the address of each label.
+    fn analyze_and_measure(source: &str) -> (StackReport, StackMonitor, HashMap<String, u16>) {
+        let mut cpu = CPU::new();
+        let mut assembler = Assembler::new();
+        let mut end_address: u16 = 0x0600;
+        assembler
+            .assemble(source, &mut cpu.memory, &mut end_address)
+            .unwrap();
+        let symbols = assembler.symbol_table().clone();
+        let report = analyze_stack(&cpu.memory, 0x0600, &[]);
+
+        cpu.pc = 0x0600;
+        cpu.sp = 0xFF;
+        let mut monitor = StackMonitor::new();
+        let mut unlimited = u32::MAX;
+        for _ in 0..10_000 {
+            if cpu.pc == symbols["end"] {
+                return (report, monitor, symbols);
+            }
+            monitor.step(&mut cpu, &mut unlimited);
+        }
+        panic!("the program did not reach its end");
+    }
+
+    /// Returns the static stack usage of the routine at `address`.
+    fn routine(report: &StackReport, address: u16) -> RoutineStack {
+        *report
+            .routines
+            .iter()
+            .find(|routine| routine.address == address)
+            .unwrap()
+    }
+
+    #[test]
+    fn recursion_is_unbounded() {
+        let (report, monitor, symbols) = analyze_and_measure(
+            "LDX #3\nJSR count\nJMP end\n\
+             count:\nDEX\nBEQ done\nJSR count\ndone:\nRTS\nend:\nNOP",
+        );
+        let count = routine(&report, symbols["count"]);
+        assert_eq!((count.depth, count.entry_depth), (None, 2));
+        assert_eq!((report.main, report.worst_case()), (None, None));
+        assert_eq!(report.at_risk(0xFF, 0x0100), vec![count]);
+        let table = report.report(&symbols, 0xFF, 0x0100);
+        assert!(table.contains("count                 unbounded          2  unbounded  AT RISK"));
+
+        // Three calls deep, so the outermost has two more return addresses below its own
+        assert_eq!(monitor.depth(symbols["count"]), Some(4));
+        assert_eq!(monitor.lowest_sp(), 0xF9);
+    }
+
+    #[test]
+    fn indirect_jumps_are_unbounded() {
+        let (report, monitor, symbols) = analyze_and_measure(
+            "JSR dispatch\nJMP end\n\
+             dispatch:\nPHA\nJMP (vector)\n\
+             handler:\nPHA\nPLA\nPLA\nRTS\n\
+             vector:\n.word handler\nend:\nNOP",
+        );
+        let dispatch = routine(&report, symbols["dispatch"]);
+        assert_eq!((dispatch.depth, dispatch.entry_depth), (None, 2));
+        assert_eq!(report.main, None);
+        assert_eq!(report.routines.len(), 1); // The handler cannot be found statically
+
+        assert_eq!(monitor.depth(symbols["dispatch"]), Some(2));
+        assert_eq!(monitor.depth(symbols["handler"]), None); // Jumped to, not called
+    }
+
+    #[test]
+    fn static_and_measured_depths_can_disagree() {
+        let (report, monitor, symbols) = analyze_and_measure(
+            "LDA #0\nJSR rare\nJSR reserve\nJSR plain\nJMP end\n\
+             rare:\nBEQ skip\nPHA\nPHA\nPHA\nPLA\nPLA\nPLA\nskip:\nRTS\n\
+             reserve:\nTSX\nTXA\nSEC\nSBC #4\nTAX\nTXS\nJSR plain\nTXA\nCLC\nADC #4\nTAX\nTXS\nRTS\n\
+             plain:\nPHA\nPLA\nRTS\nend:\nNOP",
+        );
+        let depths = |name: &str| {
+            let address = symbols[name];
+            (routine(&report, address).depth, monitor.depth(address))
+        };
+        // A path the run never took counts statically
+        assert_eq!(depths("rare"), (Some(3), Some(0)));
+        // Reserving space by moving the stack pointer is taken for setting up a fresh stack
+        assert_eq!(depths("reserve"), (Some(3), Some(7)));
+        assert_eq!(depths("plain"), (Some(1), Some(1)));
+    }
+}
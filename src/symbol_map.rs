@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+/// An error found while reading a label file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolMapError {
+    pub line: usize, // 1-based line the error was found on, or 0 if the file could not be read
+    pub message: String,
+}
+
+impl fmt::Display for SymbolMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for SymbolMapError {}
+
+/// The labels of a program and their addresses, in VICE's label file format.
+///
+/// VICE's monitor loads label files with `load_labels` (or `-moncommands` on the command line) to
+/// show symbolic names; each line is an `al` command giving an address and a name starting with
+/// `.`, such as `al C:0600 .start`. Most 6502 toolchains can write and read the format, so it is
+/// also how labels are passed to other debuggers and back into this crate.
+///
+/// # Example
+/// ```rust
+/// use r_6502::symbol_map::SymbolMap;
+/// use r_6502::{Assembler, Memory};
+///
+/// let mut memory = Memory::new();
+/// let mut address: u16 = 0x0600;
+/// let mut assembler = Assembler::new();
+/// assembler
+///     .assemble("start:\nLDX #$00\nloop:\nINX\nBNE loop", &mut memory, &mut address)
+///     .unwrap();
+/// let map = SymbolMap::from_symbols(assembler.symbol_table());
+/// assert_eq!(map.format(), "al C:0600 .start\nal C:0602 .loop\n");
+///
+/// let parsed = SymbolMap::parse(&map.format()).unwrap();
+/// assert_eq!(&parsed.to_symbols(), assembler.symbol_table());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    pub labels: Vec<(u16, String)>, // Sorted by address, then name
+}
+
+impl SymbolMap {
+    /// Creates a map of the labels and constants in an assembler's symbol table.
+    pub fn from_symbols(symbols: &HashMap<String, u16>) -> Self {
+        let mut labels: Vec<(u16, String)> = symbols
+            .iter()
+            .map(|(name, address)| (*address, name.clone()))
+            .collect();
+        labels.sort();
+        SymbolMap { labels }
+    }
+
+    /// Returns the labels as a symbol table, keyed by name.
+    pub fn to_symbols(&self) -> HashMap<String, u16> {
+        self.labels
+            .iter()
+            .map(|(address, name)| (name.clone(), *address))
+            .collect()
+    }
+
+    /// Returns the name to show for `address`: the alphabetically first label there, if any.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::symbol_map::SymbolMap;
+    ///
+    /// let map = SymbolMap::parse("al C:8F3A .print_char\nal C:8F3A .chrout").unwrap();
+    /// assert_eq!(map.name_at(0x8F3A), Some("chrout"));
+    /// assert_eq!(map.name_at(0x8F3B), None);
+    /// ```
+    pub fn name_at(&self, address: u16) -> Option<&str> {
+        let index = self.labels.partition_point(|(label, _)| *label < address);
+        match self.labels.get(index) {
+            Some((label, name)) if *label == address => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Formats the labels as a VICE label file, one `al C:<address> .<name>` line each.
+    pub fn format(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(address, name)| format!("al C:{:04X} .{}\n", address, name))
+            .collect()
+    }
+
+    /// Parses a VICE label file.
+    ///
+    /// The `C:` memory space prefix and the `.` before the name are optional, as some tools leave
+    /// them out. Blank lines and lines starting with `#` or `;` are skipped.
+    ///
+    /// # Errors
+    /// Returns a `SymbolMapError` if a line is not an `al` command, or its address is not a hex
+    /// number of at most four digits.
+    pub fn parse(text: &str) -> Result<Self, SymbolMapError> {
+        let mut labels = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let error = |message: String| SymbolMapError {
+                line: index + 1,
+                message,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (address, name) = match words[..] {
+                ["al", address, name] => (address, name),
+                _ => {
+                    return Err(error(format!(
+                        "expected `al <address> <label>`: `{}`",
+                        line
+                    )))
+                }
+            };
+            let digits = address
+                .strip_prefix("C:")
+                .or_else(|| address.strip_prefix("c:"))
+                .unwrap_or(address);
+            let address = match u16::from_str_radix(digits, 16) {
+                Ok(address) if digits.len() <= 4 => address,
+                _ => return Err(error(format!("invalid address `{}`", address))),
+            };
+            let name = name.strip_prefix('.').unwrap_or(name);
+            if name.is_empty() {
+                return Err(error(String::from("missing label name")));
+            }
+            labels.push((address, name.to_string()));
+        }
+        labels.sort();
+        Ok(SymbolMap { labels })
+    }
+
+    /// Reads and parses a VICE label file.
+    ///
+    /// # Errors
+    /// Returns a `SymbolMapError` with line 0 if the file cannot be read, or the error `parse`
+    /// found.
+    pub fn load_file(path: &str) -> Result<Self, SymbolMapError> {
+        let text = fs::read_to_string(path).map_err(|e| SymbolMapError {
+            line: 0,
+            message: format!("{}: {}", path, e),
+        })?;
+        Self::parse(&text)
+    }
+}
@@ -0,0 +1,215 @@
+use crate::cpu::CPU;
+use crate::token::{AddressingMode, Token};
+
+/// A control transfer whose destination was computed from tainted bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaintedJump {
+    pub address: u16, // Address of the jumping instruction
+    pub target: u16,  // Address it jumped to
+}
+
+/// Tracks which values in a running program were derived from input.
+///
+/// Addresses registered with `add_input` stand for input device registers: every byte read from
+/// them is tainted. Marks then follow the data as the program moves it around:
+/// - Loads and transfers copy the mark of their source into the register, stores copy the mark of
+///   the register into memory, and pushes and pulls do the same through the stack.
+/// - `ADC`, `SBC`, `AND`, `ORA` and `EOR` taint the accumulator if either operand is tainted.
+///   Shifts, rotates, increments and decrements keep the mark of the value they modify.
+/// - Immediate operands and values pushed by `JSR`, `BRK` and `PHP` are clean.
+///
+/// Flags are not tracked, so a branch on a tainted comparison does not taint anything. A
+/// `JMP ($xxxx)`, `RTS` or `RTI` whose destination is read from tainted bytes is recorded as a
+/// `TaintedJump`, which answers "does user input ever reach a jump target?".
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{run_until, EndConditions};
+/// use r_6502::taint::{TaintTracker, TaintedJump};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// // Copy a byte from the keyboard register into a jump vector, then jump through it
+/// let source = "LDA $D010\nSTA $0300\nLDA #$06\nSTA $0301\nJMP ($0300)";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// cpu.memory.data[0xD010] = 0x20;
+/// let mut taint = TaintTracker::new();
+/// taint.add_input(0xD010, 0xD010);
+/// cpu.taint = Some(taint);
+/// run_until(&mut cpu, &EndConditions::program_end(end_address));
+///
+/// let taint = cpu.taint.as_ref().unwrap();
+/// assert!(taint.is_tainted(0x0300));
+/// assert!(!taint.is_tainted(0x0301));
+/// assert_eq!(
+///     taint.tainted_jumps(),
+///     &[TaintedJump { address: 0x000B, target: 0x0620 }]
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct TaintTracker {
+    pub a: bool, // Accumulator holds a tainted value
+    pub x: bool, // X holds a tainted value
+    pub y: bool, // Y holds a tainted value
+    memory: Vec<bool>,
+    inputs: Vec<(u16, u16)>,
+    jumps: Vec<TaintedJump>,
+}
+
+impl Default for TaintTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaintTracker {
+    pub fn new() -> Self {
+        TaintTracker {
+            a: false,
+            x: false,
+            y: false,
+            memory: vec![false; 0x10000],
+            inputs: Vec::new(),
+            jumps: Vec::new(),
+        }
+    }
+
+    /// Registers `start..=end` as input device addresses, whose bytes are always tainted.
+    pub fn add_input(&mut self, start: u16, end: u16) {
+        self.inputs.push((start, end));
+    }
+
+    /// Marks the byte at `address` as tainted, e.g. for data copied in from outside the emulator.
+    pub fn mark(&mut self, address: u16) {
+        self.memory[address as usize] = true;
+    }
+
+    /// Returns whether the byte at `address` is tainted.
+    pub fn is_tainted(&self, address: u16) -> bool {
+        self.memory[address as usize]
+            || self
+                .inputs
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&address))
+    }
+
+    /// Returns the jumps whose destination came from tainted bytes, oldest first.
+    pub fn tainted_jumps(&self) -> &[TaintedJump] {
+        &self.jumps
+    }
+
+    /// Updates the marks for the instruction at the program counter, before it executes.
+    ///
+    /// Everything an instruction reads is still in place before it runs, so the whole effect on
+    /// the marks can be worked out from the CPU state at that point.
+    pub fn propagate(&mut self, cpu: &CPU) {
+        let token = match Token::from_opcode(read(cpu, cpu.pc)) {
+            Some(token) => token,
+            None => return,
+        };
+        let mode = token.addressing_mode();
+        let operand = match mode {
+            AddressingMode::Implied
+            | AddressingMode::Accumulator
+            | AddressingMode::Relative
+            | AddressingMode::Immediate => None,
+            _ => Some(effective_address(cpu, mode)),
+        };
+        let source = operand.is_some_and(|address| self.is_tainted(address));
+        let stack_top = 0x0100 | (cpu.sp & 0x00FF);
+        let stack = |offset: u16| 0x0100 | (stack_top.wrapping_add(offset) & 0x00FF);
+
+        match token.mnemonic().as_str() {
+            "LDA" => self.a = source,
+            "LDX" => self.x = source,
+            "LDY" => self.y = source,
+            "STA" => self.store(operand, self.a),
+            "STX" => self.store(operand, self.x),
+            "STY" => self.store(operand, self.y),
+            "ADC" | "SBC" | "AND" | "ORA" | "EOR" => self.a |= source,
+            "TAX" => self.x = self.a,
+            "TAY" => self.y = self.a,
+            "TXA" => self.a = self.x,
+            "TYA" => self.a = self.y,
+            "TSX" => self.x = false,
+            "PHA" => self.memory[stack_top as usize] = self.a,
+            "PHP" => self.memory[stack_top as usize] = false,
+            "PLA" => self.a = self.is_tainted(stack(1)),
+            "JSR" => {
+                self.memory[stack_top as usize] = false;
+                self.memory[stack(0xFF) as usize] = false;
+            }
+            "BRK" => {
+                for offset in [0, 0xFF, 0xFE] {
+                    self.memory[stack(offset) as usize] = false;
+                }
+            }
+            "JMP" if mode == AddressingMode::Indirect => {
+                let pointer = read_word(cpu, cpu.pc.wrapping_add(1));
+                let h_pointer = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+                self.check_jump(cpu, &[pointer, h_pointer], operand.unwrap_or(0));
+            }
+            "RTS" => {
+                let (low, high) = (stack(1), stack(2));
+                let target = word(cpu, low, high).wrapping_add(1);
+                self.check_jump(cpu, &[low, high], target);
+            }
+            "RTI" => {
+                let (low, high) = (stack(2), stack(3));
+                let target = word(cpu, low, high);
+                self.check_jump(cpu, &[low, high], target);
+            }
+            _ => {}
+        }
+    }
+
+    fn store(&mut self, address: Option<u16>, tainted: bool) {
+        if let Some(address) = address {
+            self.memory[address as usize] = tainted;
+        }
+    }
+
+    fn check_jump(&mut self, cpu: &CPU, sources: &[u16], target: u16) {
+        if sources.iter().any(|address| self.is_tainted(*address)) {
+            self.jumps.push(TaintedJump {
+                address: cpu.pc,
+                target,
+            });
+        }
+    }
+}
+
+fn read(cpu: &CPU, address: u16) -> u8 {
+    cpu.memory.data[address as usize]
+}
+
+fn word(cpu: &CPU, low: u16, high: u16) -> u16 {
+    ((read(cpu, high) as u16) << 8) | read(cpu, low) as u16
+}
+
+fn read_word(cpu: &CPU, address: u16) -> u16 {
+    word(cpu, address, address.wrapping_add(1))
+}
+
+/// Works out the address a memory operand refers to, from the operand bytes after the opcode.
+fn effective_address(cpu: &CPU, mode: AddressingMode) -> u16 {
+    let byte = read(cpu, cpu.pc.wrapping_add(1));
+    let word_operand = read_word(cpu, cpu.pc.wrapping_add(1));
+    let zero_page_word = |pointer: u8| word(cpu, pointer as u16, pointer.wrapping_add(1) as u16);
+    match mode {
+        AddressingMode::ZeroPage => byte as u16,
+        AddressingMode::ZeroPageX => byte.wrapping_add(cpu.x) as u16,
+        AddressingMode::ZeroPageY => byte.wrapping_add(cpu.y) as u16,
+        AddressingMode::Absolute => word_operand,
+        AddressingMode::AbsoluteX => word_operand.wrapping_add(cpu.x as u16),
+        AddressingMode::AbsoluteY => word_operand.wrapping_add(cpu.y as u16),
+        AddressingMode::Indirect => {
+            let h_pointer = (word_operand & 0xFF00) | (word_operand.wrapping_add(1) & 0x00FF);
+            word(cpu, word_operand, h_pointer)
+        }
+        AddressingMode::IndexedIndirect => zero_page_word(byte.wrapping_add(cpu.x)),
+        AddressingMode::IndirectIndexed => zero_page_word(byte).wrapping_add(cpu.y as u16),
+        _ => cpu.pc.wrapping_add(1),
+    }
+}
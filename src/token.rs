@@ -1,37 +1,111 @@
-// TODO: REFACTOR THE TOKENS THAT DO NOT HAVE IMMEDIATE COMMAND TO HAVE ZERO PAGE AS DEFAULT
+use crate::opcode_table::opcode_info;
+
+/// The ways an instruction can locate the value it operates on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,         // No operand (e.g. `INX`)
+    Accumulator,     // Operates on the accumulator (e.g. `ASL`)
+    Immediate,       // `#$10`
+    ZeroPage,        // `$10`
+    ZeroPageX,       // `$10,X`
+    ZeroPageY,       // `$10,Y`
+    Absolute,        // `$1234`
+    AbsoluteX,       // `$1234,X`
+    AbsoluteY,       // `$1234,Y`
+    Indirect,        // `($1234)`, only used by `JMP`
+    IndexedIndirect, // `($10,X)`
+    IndirectIndexed, // `($10),Y`
+    Relative,        // Signed offset used by branches
+}
+
+impl AddressingMode {
+    /// Returns how many operand bytes follow the opcode in this addressing mode.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::token::{AddressingMode, Token};
+    ///
+    /// assert_eq!(AddressingMode::Implied.operand_size(), 0);
+    /// assert_eq!(AddressingMode::ZeroPageX.operand_size(), 1);
+    /// assert_eq!(AddressingMode::AbsoluteY.operand_size(), 2);
+    /// ```
+    pub const fn operand_size(&self) -> u16 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndexedIndirect
+            | AddressingMode::IndirectIndexed
+            | AddressingMode::Relative => 1,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+        }
+    }
+}
 
 #[repr(u8)] // Optional, specifies the underlying representation of the enum (e.g., as a number)
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
-    LDA = 0x89,
+    LDA = 0xA9,
     LdaZP = 0xA5,
     LdaAP = 0xAD,
+    LdaZPX = 0xB5,
+    LdaAPX = 0xBD,
+    LdaAPY = 0xB9,
+    LdaIDX = 0xA1,
+    LdaIDY = 0xB1,
     LDX = 0xA2,
     LdxZP = 0xA6,
     LdxAP = 0xAE,
+    LdxZPY = 0xB6,
+    LdxAPY = 0xBE,
     LDY = 0xA0,
     LdyZP = 0xA4,
     LdyAP = 0xAC,
+    LdyZPX = 0xB4,
+    LdyAPX = 0xBC,
     ADC = 0x69,
     AdcZP = 0x65,
     AdcAP = 0x6D,
-    STA = 0x95,
+    AdcZPX = 0x75,
+    AdcAPX = 0x7D,
+    AdcAPY = 0x79,
+    AdcIDX = 0x61,
+    AdcIDY = 0x71,
+    STA = 0x85,
     StaAP = 0x8D,
-    STX = 0x01,
-    StxZP = 0x86,
-    StxAP = 0x96,
-    STY = 0x02,
-    StyZP = 0x84,
-    StyAP = 0x94,
+    StaZPX = 0x95,
+    StaAPX = 0x9D,
+    StaAPY = 0x99,
+    StaIDX = 0x81,
+    StaIDY = 0x91,
+    STX = 0x86,
+    StxAP = 0x8E,
+    StxZPY = 0x96,
+    STY = 0x84,
+    StyAP = 0x8C,
+    StyZPX = 0x94,
     JMP = 0x4C,
     JmpID = 0x6C,
     JSR = 0x20,
     AND = 0x29,
     AndZP = 0x25,
     AndAP = 0x2D,
+    AndZPX = 0x35,
+    AndAPX = 0x3D,
+    AndAPY = 0x39,
+    AndIDX = 0x21,
+    AndIDY = 0x31,
     ASL = 0x0A,
     AslZP = 0x06,
     AslAP = 0x0E,
+    AslZPX = 0x16,
+    AslAPX = 0x1E,
     BCC = 0x90,
     BCS = 0xB0,
     BEQ = 0xF0,
@@ -50,6 +124,11 @@ pub enum Token {
     CMP = 0xC9,
     CmpZP = 0xC5,
     CmpAP = 0xCD,
+    CmpZPX = 0xD5,
+    CmpAPX = 0xDD,
+    CmpAPY = 0xD9,
+    CmpIDX = 0xC1,
+    CmpIDY = 0xD1,
     CPX = 0xE0,
     CpxZP = 0xE4,
     CpxAP = 0xEC,
@@ -58,22 +137,38 @@ pub enum Token {
     CpyAP = 0xCC,
     DEC = 0xC6,
     DecAP = 0xCE,
+    DecZPX = 0xD6,
+    DecAPX = 0xDE,
     DEX = 0xCA,
     DEY = 0x88,
     EOR = 0x49,
     EorZP = 0x45,
     EorAP = 0x4D,
+    EorZPX = 0x55,
+    EorAPX = 0x5D,
+    EorAPY = 0x59,
+    EorIDX = 0x41,
+    EorIDY = 0x51,
     INC = 0xE6,
     IncAP = 0xEE,
+    IncZPX = 0xF6,
+    IncAPX = 0xFE,
     INX = 0xE8,
     INY = 0xC8,
     LSR = 0x4A,
     LsrZP = 0x46,
     LsrAP = 0x4E,
+    LsrZPX = 0x56,
+    LsrAPX = 0x5E,
     NOP = 0xEA,
     ORA = 0x09,
     OraZP = 0x05,
     OraAP = 0x0D,
+    OraZPX = 0x15,
+    OraAPX = 0x1D,
+    OraAPY = 0x19,
+    OraIDX = 0x01,
+    OraIDY = 0x11,
     PHA = 0x48,
     PHP = 0x08,
     PLA = 0x68,
@@ -81,14 +176,23 @@ pub enum Token {
     ROL = 0x2A,
     RolZP = 0x26,
     RolAP = 0x2E,
+    RolZPX = 0x36,
+    RolAPX = 0x3E,
     ROR = 0x6A,
     RorZP = 0x66,
     RorAP = 0x6E,
+    RorZPX = 0x76,
+    RorAPX = 0x7E,
     RTI = 0x40,
     RTS = 0x60,
     SBC = 0xE9,
     SbcZP = 0xE5,
     SbcAP = 0xED,
+    SbcZPX = 0xF5,
+    SbcAPX = 0xFD,
+    SbcAPY = 0xF9,
+    SbcIDX = 0xE1,
+    SbcIDY = 0xF1,
     SEC = 0x38,
     SED = 0xF8,
     SEI = 0x78,
@@ -99,3 +203,61 @@ pub enum Token {
     TXS = 0x9A,
     TYA = 0x98,
 }
+
+impl Token {
+    /// Decodes an opcode byte into the `Token` it represents.
+    ///
+    /// This is the inverse of casting a `Token` to `u8`, and is used by the runner to turn the byte
+    /// at the program counter back into an instruction.
+    ///
+    /// # Parameters
+    /// - `opcode`: The opcode byte read from memory.
+    ///
+    /// # Returns
+    /// - `Some(token)`: If the byte is one of the documented 6502 opcodes.
+    /// - `None`: If the byte is not a known opcode.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::token::{AddressingMode, Token};
+    ///
+    /// assert_eq!(Token::from_opcode(0xA9), Some(Token::LDA));
+    /// assert_eq!(Token::from_opcode(0x02), None);
+    /// ```
+    pub fn from_opcode(opcode: u8) -> Option<Token> {
+        opcode_info(opcode).map(|info| info.token)
+    }
+
+    /// Returns the assembly mnemonic of the instruction, e.g. `LDA` for `Token::LdaAPX`.
+    ///
+    /// Variants are named after their mnemonic, followed by the addressing mode for all but one of
+    /// an instruction's opcodes, so the mnemonic is always the first three letters of the name.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::token::Token;
+    ///
+    /// assert_eq!(Token::LdaAPX.mnemonic(), "LDA");
+    /// assert_eq!(Token::JmpID.mnemonic(), "JMP");
+    /// ```
+    pub fn mnemonic(&self) -> String {
+        format!("{:?}", self)[..3].to_ascii_uppercase()
+    }
+
+    /// Returns the addressing mode this opcode uses.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::token::{AddressingMode, Token};
+    ///
+    /// assert_eq!(Token::LDA.addressing_mode(), AddressingMode::Immediate);
+    /// assert_eq!(Token::LdaAPX.addressing_mode(), AddressingMode::AbsoluteX);
+    /// assert_eq!(Token::INX.addressing_mode(), AddressingMode::Implied);
+    /// ```
+    pub fn addressing_mode(&self) -> AddressingMode {
+        match opcode_info(*self as u8) {
+            Some(info) => info.mode,
+            None => unreachable!("{:?} is missing from the opcode table", self),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::cpu::CPU;
+use crate::disassembler::{disassemble_bytes_with_symbols, encoded_length};
+use crate::symbol_map::SymbolMap;
+use std::fmt;
+use std::io::Write;
+
+/// The instruction at the program counter and the CPU state before it runs, as traced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub bytes: Vec<u8>, // Opcode followed by its operand bytes, as `disassemble_instruction` reads them
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8, // As `get_status` returns it
+    pub sp: u8,
+    pub cycles: u64,
+}
+
+impl TraceRecord {
+    /// Records the instruction at the program counter and the registers.
+    pub fn capture(cpu: &CPU) -> Self {
+        let opcode = cpu.memory.data[cpu.pc as usize];
+        TraceRecord {
+            pc: cpu.pc,
+            bytes: (0..encoded_length(opcode))
+                .map(|offset| cpu.memory.data[cpu.pc.wrapping_add(offset) as usize])
+                .collect(),
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.get_status(),
+            sp: (cpu.sp & 0x00FF) as u8,
+            cycles: cpu.cycles,
+        }
+    }
+
+    /// Formats the record as a trace line, with the addresses the instruction uses shown by
+    /// name where `symbols` has a label.
+    pub fn format(&self, symbols: &SymbolMap) -> String {
+        let instruction = disassemble_bytes_with_symbols(self.pc, &self.bytes, symbols);
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        format!(
+            "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.pc,
+            bytes.join(" "),
+            instruction.text,
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.sp,
+            self.cycles
+        )
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(&SymbolMap::default()))
+    }
+}
+
+/// Formats the instruction at the program counter and the CPU state before it runs.
+///
+/// Lines follow the layout of Nintendulator logs (as used by the `nestest` reference traces), so a
+/// trace can be diffed against a known-good one:
+///
+/// ```text
+/// 0600  A9 01     LDA #$01                        A:00 X:00 Y:00 P:20 SP:00 CYC:0
+/// ```
+///
+/// Addresses with a label in `cpu.symbols` are shown by name, e.g. `JSR print_char`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::trace::trace_line;
+/// use r_6502::CPU;
+///
+/// let mut cpu = CPU::new();
+/// cpu.memory.data[0..2].copy_from_slice(&[0xA9, 0x01]);
+/// assert!(trace_line(&cpu).starts_with("0000  A9 01     LDA #$01"));
+/// assert!(trace_line(&cpu).ends_with("A:00 X:00 Y:00 P:20 SP:00 CYC:0"));
+/// ```
+pub fn trace_line(cpu: &CPU) -> String {
+    TraceRecord::capture(cpu).format(&cpu.symbols)
+}
+
+/// Traces the instruction at the program counter to `cpu.trace` and `cpu.compact_trace`, if set.
+///
+/// Traces that can no longer be written to are ignored rather than stopping the program.
+pub fn write_trace(cpu: &mut CPU) {
+    if cpu.trace.is_some() {
+        let line = trace_line(cpu);
+        if let Some(trace) = cpu.trace.as_mut() {
+            let _ = writeln!(trace, "{}", line);
+        }
+        cpu.trace_bytes += line.len() as u64 + 1;
+    }
+    if let Some(mut writer) = cpu.compact_trace.take() {
+        let _ = writer.record(&TraceRecord::capture(cpu));
+        cpu.compact_trace = Some(writer);
+    }
+}
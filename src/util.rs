@@ -1,22 +1,191 @@
-pub fn convert_string_to_u8(value: &str) -> u8 {
-    match value.parse::<u8>() {
-        Ok(parsed_value) => return parsed_value,
-        Err(_) => panic!("Failed to parse value as u8: {}", value),
+use crate::memory::Memory;
+use std::num::ParseIntError;
+
+pub fn convert_string_to_u8(value: &str) -> Result<u8, ParseIntError> {
+    value.parse::<u8>()
+}
+pub fn convert_hex_string_to_u8(value: &str) -> Result<u8, ParseIntError> {
+    u8::from_str_radix(value, 16)
+}
+pub fn convert_hex_string_to_u16(value: &str) -> Result<u16, ParseIntError> {
+    u16::from_str_radix(value, 16)
+}
+pub fn is_zero_page(value: &str) -> Result<bool, ParseIntError> {
+    Ok(convert_hex_string_to_u16(value)? < 256)
+}
+pub fn convert_string_to_u16(value: &str) -> Result<u16, ParseIntError> {
+    value.parse::<u16>()
+}
+
+/// Character sets used to interpret bytes in memory as text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    Ascii,      // Plain 7-bit ASCII
+    Petscii,    // Commodore PETSCII, unshifted (upper case/graphics) character set
+    ScreenCode, // Commodore screen codes, as stored in video memory
+}
+
+/// Decodes a single byte into the character it represents in the given encoding.
+///
+/// PETSCII is decoded using the unshifted character set, so letters come back upper case and
+/// `0x0D` (RETURN) becomes a newline. Screen codes with bit 7 set are reverse-video versions of
+/// the same characters and decode to the same character. Graphics and control codes that have no
+/// sensible text equivalent return `None`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::util::*;
+/// use r_6502::Memory;
+///
+/// assert_eq!(decode_text_byte(0x41, TextEncoding::Ascii), Some('A'));
+/// assert_eq!(decode_text_byte(0xC1, TextEncoding::Petscii), Some('A'));
+/// assert_eq!(decode_text_byte(0x01, TextEncoding::ScreenCode), Some('A'));
+/// ```
+pub fn decode_text_byte(value: u8, encoding: TextEncoding) -> Option<char> {
+    match encoding {
+        TextEncoding::Ascii => match value {
+            0x20..=0x7E => Some(value as char),
+            0x0A => Some('\n'),
+            _ => None,
+        },
+        TextEncoding::Petscii => match value {
+            0x0D => Some('\n'),
+            0x20..=0x5B | 0x5D => Some(value as char),
+            0x5C => Some('£'),
+            0x5E => Some('↑'),
+            0x5F => Some('←'),
+            0xC1..=0xDA => Some((value - 0x80) as char),
+            _ => None,
+        },
+        TextEncoding::ScreenCode => match value & 0x7F {
+            0x00 => Some('@'),
+            code @ 0x01..=0x1A => Some((code + 0x40) as char),
+            0x1B => Some('['),
+            0x1C => Some('£'),
+            0x1D => Some(']'),
+            0x1E => Some('↑'),
+            0x1F => Some('←'),
+            code @ 0x20..=0x3F => Some(code as char),
+            _ => None,
+        },
+    }
+}
+
+/// Encodes a single character into a byte in the given encoding.
+///
+/// Lower case letters are stored as their upper case equivalents in PETSCII and screen codes,
+/// since the unshifted character set has no lower case.
+///
+/// # Example
+/// ```rust
+/// use r_6502::util::*;
+/// use r_6502::Memory;
+///
+/// assert_eq!(encode_text_char('a', TextEncoding::Petscii), Some(0x41));
+/// assert_eq!(encode_text_char('A', TextEncoding::ScreenCode), Some(0x01));
+/// assert_eq!(encode_text_char('é', TextEncoding::Ascii), None);
+/// ```
+pub fn encode_text_char(c: char, encoding: TextEncoding) -> Option<u8> {
+    match encoding {
+        TextEncoding::Ascii => match c {
+            ' '..='~' | '\n' => Some(c as u8),
+            _ => None,
+        },
+        TextEncoding::Petscii => match c.to_ascii_uppercase() {
+            '\n' => Some(0x0D),
+            c @ (' '..='[' | ']') => Some(c as u8),
+            '£' => Some(0x5C),
+            '↑' => Some(0x5E),
+            '←' => Some(0x5F),
+            _ => None,
+        },
+        TextEncoding::ScreenCode => match c.to_ascii_uppercase() {
+            '@' => Some(0x00),
+            c @ 'A'..='Z' => Some(c as u8 - 0x40),
+            '[' => Some(0x1B),
+            '£' => Some(0x1C),
+            ']' => Some(0x1D),
+            '↑' => Some(0x1E),
+            '←' => Some(0x1F),
+            c @ ' '..='?' => Some(c as u8),
+            _ => None,
+        },
     }
 }
-pub fn convert_hex_string_to_u8(value: &str) -> u8 {
-    let u8_value = u8::from_str_radix(value, 16)
-        .unwrap_or_else(|_| panic!("Failed to parse hex value: {}", value));
-    u8_value
+
+/// Renders a slice of memory as text, showing bytes with no text equivalent as `.`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::util::*;
+/// use r_6502::Memory;
+///
+/// let text = bytes_to_text(&[0x48, 0x49, 0x00], TextEncoding::Ascii);
+/// assert_eq!(text, "HI.");
+/// ```
+pub fn bytes_to_text(data: &[u8], encoding: TextEncoding) -> String {
+    data.iter()
+        .map(|value| decode_text_byte(*value, encoding).unwrap_or('.'))
+        .collect()
 }
-pub fn is_zero_page(value: &str) -> bool {
-    let converted_value = u16::from_str_radix(value, 16)
-        .unwrap_or_else(|_| panic!("Failed to parse hex value: {}", value));
-    return converted_value < 256;
+
+/// Reads `length` bytes of memory starting at `address` and renders them as text.
+///
+/// Reading past `$FFFF` wraps around to `$0000`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::util::*;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// write_text_to_memory(&mut mem, 0x0400, "HELLO", TextEncoding::ScreenCode);
+/// assert_eq!(read_memory_as_text(&mem, 0x0400, 5, TextEncoding::ScreenCode), "HELLO");
+/// ```
+pub fn read_memory_as_text(
+    mem: &Memory,
+    address: u16,
+    length: usize,
+    encoding: TextEncoding,
+) -> String {
+    let data: Vec<u8> = (0..length)
+        .map(|offset| mem.data[address.wrapping_add(offset as u16) as usize])
+        .collect();
+    bytes_to_text(&data, encoding)
 }
-pub fn convert_string_to_u16(value: &str) -> u16 {
-    match value.parse::<u16>() {
-        Ok(parsed_value) => return parsed_value,
-        Err(_) => panic!("Failed to parse value as u8: {}", value),
+
+/// Encodes `text` and writes it into memory starting at `address`.
+///
+/// Writing past `$FFFF` wraps around to `$0000`.
+///
+/// # Returns
+/// The number of bytes written.
+///
+/// # Panics
+/// - If `text` contains a character that cannot be represented in `encoding`.
+///
+/// # Example
+/// ```rust
+/// use r_6502::util::*;
+/// use r_6502::Memory;
+///
+/// let mut mem = Memory::new();
+/// let written = write_text_to_memory(&mut mem, 0x0200, "HI", TextEncoding::Petscii);
+/// assert_eq!(written, 2);
+/// assert_eq!(mem.data[0x0200], 0x48);
+/// ```
+pub fn write_text_to_memory(
+    mem: &mut Memory,
+    address: u16,
+    text: &str,
+    encoding: TextEncoding,
+) -> usize {
+    let mut written: usize = 0;
+    for c in text.chars() {
+        let value = encode_text_char(c, encoding)
+            .unwrap_or_else(|| panic!("Cannot encode {:?} as {:?}", c, encoding));
+        mem.data[address.wrapping_add(written as u16) as usize] = value;
+        written += 1;
     }
+    written
 }
@@ -0,0 +1,297 @@
+use crate::bus::Device;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Number of registers a VIA decodes; it is mirrored every 16 bytes of its range.
+pub const VIA_REGISTERS: u16 = 16;
+
+// Register offsets
+const ORB: u16 = 0x0;
+const ORA: u16 = 0x1;
+const DDRB: u16 = 0x2;
+const DDRA: u16 = 0x3;
+const T1C_L: u16 = 0x4;
+const T1C_H: u16 = 0x5;
+const T1L_L: u16 = 0x6;
+const T1L_H: u16 = 0x7;
+const T2C_L: u16 = 0x8;
+const T2C_H: u16 = 0x9;
+const SR: u16 = 0xA;
+const ACR: u16 = 0xB;
+const PCR: u16 = 0xC;
+const IFR: u16 = 0xD;
+const IER: u16 = 0xE; // $F is ORA without the handshake
+
+// Interrupt flag and enable bits
+const IRQ_T2: u8 = 0x20;
+const IRQ_T1: u8 = 0x40;
+const IRQ_ANY: u8 = 0x80;
+
+const ACR_T1_FREE_RUN: u8 = 0x40;
+const ACR_T2_PULSE_COUNT: u8 = 0x20;
+
+/// An 8-bit I/O port: the output register, the data direction register and the levels the
+/// outside world drives on the pins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Port {
+    pub output: u8, // Output register, written by the program
+    pub ddr: u8,    // Data direction register: set bits are outputs
+    pub input: u8,  // Levels driven onto the pins from outside, seen on the input bits
+}
+
+impl Port {
+    /// Returns the levels on the pins: the output register on output bits, the external input on
+    /// the others.
+    pub fn pins(&self) -> u8 {
+        (self.output & self.ddr) | (self.input & !self.ddr)
+    }
+}
+
+/// The two ports of a VIA, shared between the device and the host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ViaPorts {
+    pub a: Port,
+    pub b: Port,
+}
+
+/// A peripheral wired to a VIA's ports, such as an LCD, seeing every change the program makes to
+/// them.
+pub trait PortDevice {
+    /// Reacts to the program writing a port or data direction register. The device reads the
+    /// levels on the pins and drives the input bits it is wired to.
+    fn update(&mut self, ports: &mut ViaPorts);
+}
+
+/// A MOS 6522 Versatile Interface Adapter: two 8-bit I/O ports and two 16-bit timers.
+///
+/// The VIA decodes 16 registers, so it is usually attached over `VIA_REGISTERS` bytes, e.g.
+/// `$6000-$600F` on Ben Eater's breadboard computer. Timer 1 counts down once per CPU clock cycle
+/// and either fires once or reloads from its latch and runs freely (`ACR` bit 6); timer 2 fires
+/// once. A timer setting its flag in `IFR` asserts the IRQ line while the flag is enabled in
+/// `IER`, until the program clears it by reading the timer's low counter, writing its high
+/// counter or writing the flag to `IFR`.
+///
+/// The host drives and reads the port pins through the `ViaPorts` returned by `ports`, and
+/// peripherals wired to them are attached with `connect`. The
+/// handshake lines CA1/CA2/CB1/CB2, the shift register, timer 2's pulse counting and timer 1's
+/// PB7 output are not emulated: `SR` and `PCR` read back what was written.
+///
+/// # Example
+/// ```rust
+/// use r_6502::via::{Via, VIA_REGISTERS};
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let via = Via::new();
+/// let ports = via.ports();
+/// cpu.memory.attach(0x6000, 0x6000 + VIA_REGISTERS - 1, "VIA", Box::new(via));
+///
+/// // Port B all outputs, write $55 to it, then read port A into $10
+/// let source = "LDA #$FF\nSTA $6002\nLDA #$55\nSTA $6000\nLDA $6001\nSTA $10";
+/// let mut end_address: u16 = 0;
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// ports.borrow_mut().a.input = 0x81;
+/// while cpu.pc < end_address {
+///     cpu.step();
+/// }
+/// assert_eq!(ports.borrow().b.pins(), 0x55);
+/// assert_eq!(cpu.memory.data[0x10], 0x81);
+/// ```
+pub struct Via {
+    ports: Rc<RefCell<ViaPorts>>,
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool, // Cleared once a one-shot timer 1 has fired, until it is restarted
+    t2_counter: u16,
+    t2_latch_low: u8,
+    t2_armed: bool,
+    sr: u8,
+    acr: u8,
+    pcr: u8,
+    ifr: u8, // Interrupt flags, without the "any" bit
+    ier: u8, // Interrupt enables, without the set/clear bit
+    connected: Vec<Box<dyn PortDevice>>,
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Via {
+    /// Creates a VIA as it is after reset: all pins inputs, timers stopped, interrupts disabled.
+    pub fn new() -> Self {
+        Via {
+            ports: Rc::new(RefCell::new(ViaPorts::default())),
+            t1_counter: 0xFFFF,
+            t1_latch: 0xFFFF,
+            t1_armed: false,
+            t2_counter: 0xFFFF,
+            t2_latch_low: 0xFF,
+            t2_armed: false,
+            sr: 0,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+            connected: Vec::new(),
+        }
+    }
+
+    /// Wires a peripheral to the ports, to be updated whenever the program writes them.
+    pub fn connect(&mut self, device: Box<dyn PortDevice>) {
+        self.connected.push(device);
+    }
+
+    /// Returns the ports, shared with the device so the host can drive and read the pins while it
+    /// is attached.
+    pub fn ports(&self) -> Rc<RefCell<ViaPorts>> {
+        Rc::clone(&self.ports)
+    }
+
+    /// Returns the interrupt flag register as the program reads it, with bit 7 set if any enabled
+    /// flag is set.
+    fn ifr(&self) -> u8 {
+        if self.ifr & self.ier != 0 {
+            self.ifr | IRQ_ANY
+        } else {
+            self.ifr
+        }
+    }
+
+    /// Counts timer 1 down by `cycles`, setting its flag when it passes zero.
+    ///
+    /// The counter goes from its value down to 0, then to `$FFFF`, where it fires. In free-running
+    /// mode it is reloaded from the latch on the next cycle, for a period of the latch plus 2
+    /// cycles; otherwise it keeps counting down from `$FFFF`.
+    fn tick_t1(&mut self, cycles: u64) {
+        let free_run = self.acr & ACR_T1_FREE_RUN != 0;
+        let reloading = free_run && self.t1_counter == 0xFFFF;
+        let until_fire = if reloading {
+            self.t1_latch as u64 + 2
+        } else {
+            self.t1_counter as u64 + 1
+        };
+        if cycles == 0 {
+            return;
+        }
+        if cycles < until_fire {
+            self.t1_counter = if reloading {
+                self.t1_latch - (cycles - 1) as u16
+            } else {
+                self.t1_counter - cycles as u16
+            };
+            return;
+        }
+        if self.t1_armed {
+            self.ifr |= IRQ_T1;
+        }
+        if free_run {
+            let position = (cycles - until_fire) % (self.t1_latch as u64 + 2);
+            self.t1_counter = if position == 0 {
+                0xFFFF
+            } else {
+                self.t1_latch - (position - 1) as u16
+            };
+        } else {
+            self.t1_armed = false;
+            self.t1_counter = self.t1_counter.wrapping_sub(cycles as u16);
+        }
+    }
+
+    /// Counts timer 2 down by `cycles`, setting its flag the first time it passes zero.
+    fn tick_t2(&mut self, cycles: u64) {
+        if self.acr & ACR_T2_PULSE_COUNT != 0 {
+            return;
+        }
+        if self.t2_armed && cycles > self.t2_counter as u64 {
+            self.ifr |= IRQ_T2;
+            self.t2_armed = false;
+        }
+        self.t2_counter = self.t2_counter.wrapping_sub(cycles as u16);
+    }
+}
+
+impl Device for Via {
+    fn read(&mut self, offset: u16) -> u8 {
+        match offset % VIA_REGISTERS {
+            ORB => self.ports.borrow().b.pins(),
+            ORA => self.ports.borrow().a.pins(),
+            DDRB => self.ports.borrow().b.ddr,
+            DDRA => self.ports.borrow().a.ddr,
+            T1C_L => {
+                self.ifr &= !IRQ_T1;
+                self.t1_counter as u8
+            }
+            T1C_H => (self.t1_counter >> 8) as u8,
+            T1L_L => self.t1_latch as u8,
+            T1L_H => (self.t1_latch >> 8) as u8,
+            T2C_L => {
+                self.ifr &= !IRQ_T2;
+                self.t2_counter as u8
+            }
+            T2C_H => (self.t2_counter >> 8) as u8,
+            SR => self.sr,
+            ACR => self.acr,
+            PCR => self.pcr,
+            IFR => self.ifr(),
+            IER => self.ier | IRQ_ANY,
+            _ => self.ports.borrow().a.pins(),
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset % VIA_REGISTERS {
+            ORB => self.ports.borrow_mut().b.output = value,
+            ORA => self.ports.borrow_mut().a.output = value,
+            DDRB => self.ports.borrow_mut().b.ddr = value,
+            DDRA => self.ports.borrow_mut().a.ddr = value,
+            T1C_L | T1L_L => self.t1_latch = (self.t1_latch & 0xFF00) | value as u16,
+            T1C_H => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (value as u16) << 8;
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.ifr &= !IRQ_T1;
+            }
+            T1L_H => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | (value as u16) << 8;
+                self.ifr &= !IRQ_T1;
+            }
+            T2C_L => self.t2_latch_low = value,
+            T2C_H => {
+                self.t2_counter = (value as u16) << 8 | self.t2_latch_low as u16;
+                self.t2_armed = true;
+                self.ifr &= !IRQ_T2;
+            }
+            SR => self.sr = value,
+            ACR => self.acr = value,
+            PCR => self.pcr = value,
+            IFR => self.ifr &= !value,
+            IER => {
+                // Bit 7 says whether the other set bits are enabled or disabled
+                if value & IRQ_ANY != 0 {
+                    self.ier |= value & !IRQ_ANY;
+                } else {
+                    self.ier &= !value;
+                }
+            }
+            _ => self.ports.borrow_mut().a.output = value,
+        }
+        if matches!(offset % VIA_REGISTERS, ORB | ORA | DDRB | DDRA | 0xF) {
+            let mut ports = self.ports.borrow_mut();
+            for device in &mut self.connected {
+                device.update(&mut ports);
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        self.tick_t1(cycles);
+        self.tick_t2(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.ifr & self.ier != 0
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+/// The accesses a watchpoint stops on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // Reads and writes
+}
+
+impl WatchKind {
+    /// Checks whether an access (a write if `write` is set) is one this kind watches for.
+    pub fn matches(self, write: bool) -> bool {
+        match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// A watchpoint on the addresses `start..=end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+/// An access to a watched address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub value: u8,   // Value read, or value written
+    pub write: bool, // `true` for a write, `false` for a read
+}
+
+/// A function called with every watched access, set with `Watchpoints::set_callback`.
+pub type WatchCallback = Box<dyn FnMut(&WatchHit)>;
+
+/// Read and write watchpoints on the bus.
+///
+/// `Memory` checks every access made through the `Bus` methods, including instruction fetches,
+/// stack operations and accesses to devices; loaders and debuggers poking `memory.data` directly
+/// are not seen. The first access to a watched address is remembered until it is taken with
+/// `take_hit`, which `try_run_until` does after every instruction to stop with
+/// `StopReason::Watchpoint`. A callback set with `set_callback` is called with every hit instead,
+/// and execution carries on.
+///
+/// # Example
+/// ```rust
+/// use r_6502::asm_runner::{try_run_until, EndConditions, StopReason};
+/// use r_6502::watchpoint::WatchKind;
+/// use r_6502::{Assembler, CPU};
+///
+/// let mut cpu = CPU::new();
+/// let mut end_address: u16 = 0;
+/// let source = "LDA $0300\nLDA #$07\nSTA $0301\nINX";
+/// Assembler::new().assemble(source, &mut cpu.memory, &mut end_address).unwrap();
+/// cpu.memory.watchpoints.add(0x0300, 0x03FF, WatchKind::Write);
+/// let reason = try_run_until(&mut cpu, &EndConditions::program_end(end_address));
+/// assert_eq!(
+///     reason,
+///     StopReason::Watchpoint { pc: 0x0005, address: 0x0301, value: 0x07, write: true }
+/// );
+/// ```
+#[derive(Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    hit: Option<WatchHit>,
+    callback: Option<WatchCallback>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            watchpoints: Vec::new(),
+            hit: None,
+            callback: None,
+        }
+    }
+
+    /// Watches the addresses `start..=end` for accesses of `kind`.
+    pub fn add(&mut self, start: u16, end: u16, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { start, end, kind });
+    }
+
+    /// Removes every watchpoint starting at `start`.
+    ///
+    /// # Returns
+    /// - `true`: If a watchpoint was removed.
+    /// - `false`: If no watchpoint starts at `start`.
+    pub fn remove(&mut self, start: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.start != start);
+        self.watchpoints.len() != count
+    }
+
+    /// Returns all watchpoints in the order they were added.
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Calls `callback` with every watched access instead of stopping execution.
+    ///
+    /// # Example
+    /// ```rust
+    /// use r_6502::watchpoint::{WatchHit, WatchKind};
+    /// use r_6502::CPU;
+    /// use std::sync::mpsc;
+    ///
+    /// let mut cpu = CPU::new();
+    /// let (sender, receiver) = mpsc::channel::<WatchHit>();
+    /// cpu.memory.watchpoints.add(0x0010, 0x0010, WatchKind::Access);
+    /// cpu.memory.watchpoints.set_callback(move |hit| {
+    ///     let _ = sender.send(*hit);
+    /// });
+    /// cpu.memory.data[0..4].copy_from_slice(&[0xE6, 0x10, 0xE6, 0x10]); // INC $10 twice
+    /// cpu.step();
+    /// cpu.step();
+    /// // Each INC reads the old value and writes the new one
+    /// let values: Vec<u8> = receiver.try_iter().map(|hit| hit.value).collect();
+    /// assert_eq!(values, [0x00, 0x01, 0x01, 0x02]);
+    /// assert_eq!(cpu.memory.watchpoints.take_hit(), None);
+    /// ```
+    pub fn set_callback(&mut self, callback: impl FnMut(&WatchHit) + 'static) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Stops calling the callback, so that watched accesses stop execution again.
+    pub fn clear_callback(&mut self) {
+        self.callback = None;
+    }
+
+    /// Checks an access made through the bus, recording it or passing it to the callback if it
+    /// is watched.
+    pub fn check(&mut self, address: u16, value: u8, write: bool) {
+        if !self
+            .watchpoints
+            .iter()
+            .any(|w| (w.start..=w.end).contains(&address) && w.kind.matches(write))
+        {
+            return;
+        }
+        let hit = WatchHit {
+            address,
+            value,
+            write,
+        };
+        match self.callback.as_mut() {
+            Some(callback) => callback(&hit),
+            None => {
+                if self.hit.is_none() {
+                    self.hit = Some(hit);
+                }
+            }
+        }
+    }
+
+    /// Returns and forgets the first watched access made since the last call, if any.
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}